@@ -0,0 +1,427 @@
+//! Output to an audio device through cpal.
+//!
+//! cpal streams cannot move between threads on every platform, so each
+//! [`CpalStream`] owns a supervisor thread that builds the device stream,
+//! keeps it alive and rebuilds it when the device goes away. The engine sits
+//! in a mutex shared with the device callback, which only ever `try_lock`s
+//! it: the supervisor holds the lock just long enough to swap devices, and
+//! the callback plays silence rather than wait.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use ::cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use ::cpal::{
+    BufferSize, Device, FromSample, OutputCallbackInfo, SampleFormat, SampleRate, SizedSample,
+    StreamConfig, StreamError, SupportedBufferSize,
+};
+
+use super::{Backend, BackendError, Callback, DeviceConfig};
+use crate::engine::Engine;
+
+/// Sample formats the backend writes, best first.
+const FORMATS: [SampleFormat; 4] = [
+    SampleFormat::F32,
+    SampleFormat::I32,
+    SampleFormat::I16,
+    SampleFormat::U16,
+];
+
+/// How long to wait between attempts to reopen a lost device.
+const RETRY_INTERVAL: Duration = Duration::from_millis(500);
+
+/// An output device opened through cpal.
+#[derive(Clone, Debug)]
+pub struct CpalBackend {
+    /// The device's name, or `None` to follow the host's default device.
+    name: Option<String>,
+    requested: DeviceConfig,
+    config: DeviceConfig,
+    format: SampleFormat,
+}
+
+impl CpalBackend {
+    /// Opens the host's default output device. If it is lost while
+    /// playing, the stream moves to whatever the default device is then.
+    ///
+    /// A device that cannot play at the requested rate is opened at its
+    /// default or nearest supported one; [`config`](Backend::config) reports
+    /// the rate to create the engine at.
+    pub fn open(requested: DeviceConfig) -> Result<Self, BackendError> {
+        Self::open_device(None, requested)
+    }
+
+    /// Opens the output device called `name`, as listed by
+    /// [`device_names`](CpalBackend::device_names).
+    pub fn open_named(name: &str, requested: DeviceConfig) -> Result<Self, BackendError> {
+        Self::open_device(Some(name.to_owned()), requested)
+    }
+
+    /// Names of the host's output devices.
+    pub fn device_names() -> Result<Vec<String>, BackendError> {
+        let devices = ::cpal::default_host()
+            .output_devices()
+            .map_err(device_error)?;
+        Ok(devices.filter_map(|d| d.name().ok()).collect())
+    }
+
+    fn open_device(name: Option<String>, requested: DeviceConfig) -> Result<Self, BackendError> {
+        let device = find_device(name.as_deref())?;
+        let (config, format) = negotiate(&device, &requested, false)?;
+        Ok(CpalBackend {
+            name,
+            requested,
+            config,
+            format,
+        })
+    }
+
+    /// Builds and starts a stream feeding `callback`. A lost device is
+    /// reported on `lost`, tagged with `generation`.
+    fn build(
+        &self,
+        device: &Device,
+        callback: &Arc<Mutex<Option<Callback>>>,
+        lost: Sender<Event>,
+        generation: u64,
+        errors: &Arc<Mutex<Vec<BackendError>>>,
+    ) -> Result<::cpal::Stream, BackendError> {
+        let config = StreamConfig {
+            channels: self.config.channels as u16,
+            sample_rate: SampleRate(self.config.sample_rate),
+            buffer_size: match self.config.buffer_frames {
+                Some(frames) => BufferSize::Fixed(frames as u32),
+                None => BufferSize::Default,
+            },
+        };
+        let errors = Arc::clone(errors);
+        let on_error = move |error: StreamError| match error {
+            StreamError::DeviceNotAvailable => {
+                let _ = lost.send(Event::Lost(generation));
+            }
+            StreamError::BackendSpecific { err } => {
+                lock(&errors).push(BackendError::Device(err.to_string()));
+            }
+        };
+        let callback = Arc::clone(callback);
+        let stream = match self.format {
+            SampleFormat::F32 => build_stream::<f32>(device, &config, callback, on_error),
+            SampleFormat::I32 => build_stream::<i32>(device, &config, callback, on_error),
+            SampleFormat::I16 => build_stream::<i16>(device, &config, callback, on_error),
+            SampleFormat::U16 => build_stream::<u16>(device, &config, callback, on_error),
+            _ => unreachable!("negotiate only picks formats from FORMATS"),
+        }?;
+        stream
+            .play()
+            .map_err(|e| BackendError::Device(e.to_string()))?;
+        Ok(stream)
+    }
+}
+
+impl Backend for CpalBackend {
+    type Stream = CpalStream;
+
+    fn config(&self) -> DeviceConfig {
+        self.config
+    }
+
+    fn start(self, engine: Engine) -> Result<CpalStream, BackendError> {
+        let callback = Arc::new(Mutex::new(Some(Callback::new(engine, &self.config))));
+        let errors = Arc::new(Mutex::new(Vec::new()));
+        let reconnects = Arc::new(AtomicU64::new(0));
+        let (events, events_rx) = mpsc::channel();
+        let (ready, ready_rx) = mpsc::channel();
+
+        let supervisor = Supervisor {
+            backend: self,
+            callback: Arc::clone(&callback),
+            errors: Arc::clone(&errors),
+            reconnects: Arc::clone(&reconnects),
+            events: events.clone(),
+        };
+        let thread = thread::Builder::new()
+            .name("samplerust-output".into())
+            .spawn(move || supervisor.run(events_rx, ready))
+            .map_err(|e| BackendError::Device(e.to_string()))?;
+
+        let config = match ready_rx.recv() {
+            Ok(Ok(config)) => config,
+            Ok(Err(e)) => {
+                let _ = thread.join();
+                return Err(e);
+            }
+            Err(_) => return Err(BackendError::Device("output thread exited".into())),
+        };
+        Ok(CpalStream {
+            config,
+            events,
+            thread: Some(thread),
+            callback,
+            errors,
+            reconnects,
+        })
+    }
+}
+
+/// Messages to a stream's supervisor thread.
+enum Event {
+    /// The device of the given stream generation went away.
+    Lost(u64),
+    Stop,
+}
+
+/// Keeps a device stream running on its own thread.
+struct Supervisor {
+    backend: CpalBackend,
+    callback: Arc<Mutex<Option<Callback>>>,
+    errors: Arc<Mutex<Vec<BackendError>>>,
+    reconnects: Arc<AtomicU64>,
+    events: Sender<Event>,
+}
+
+impl Supervisor {
+    fn run(
+        mut self,
+        events: mpsc::Receiver<Event>,
+        ready: Sender<Result<DeviceConfig, BackendError>>,
+    ) {
+        let mut generation = 0;
+        let first = find_device(self.backend.name.as_deref()).and_then(|device| {
+            self.backend.build(
+                &device,
+                &self.callback,
+                self.events.clone(),
+                generation,
+                &self.errors,
+            )
+        });
+        let mut stream = match first {
+            Ok(stream) => {
+                let _ = ready.send(Ok(self.backend.config));
+                Some(stream)
+            }
+            Err(e) => {
+                let _ = ready.send(Err(e));
+                return;
+            }
+        };
+
+        loop {
+            let event = if stream.is_some() {
+                events.recv().map_err(|_| RecvTimeoutError::Disconnected)
+            } else {
+                events.recv_timeout(RETRY_INTERVAL)
+            };
+            match event {
+                Ok(Event::Stop) | Err(RecvTimeoutError::Disconnected) => return,
+                Ok(Event::Lost(lost)) if lost == generation && stream.is_some() => {
+                    lock(&self.errors).push(BackendError::Disconnected);
+                    stream = None;
+                }
+                Ok(Event::Lost(_)) => {}
+                Err(RecvTimeoutError::Timeout) => {
+                    generation += 1;
+                    match self.reconnect(generation) {
+                        Ok(new) => {
+                            stream = Some(new);
+                            self.reconnects.fetch_add(1, Ordering::Relaxed);
+                        }
+                        Err(e) => lock(&self.errors).push(e),
+                    }
+                }
+            }
+        }
+    }
+
+    /// Reopens the device, renegotiating everything but the sample rate,
+    /// which the engine was built for.
+    fn reconnect(&mut self, generation: u64) -> Result<::cpal::Stream, BackendError> {
+        let device = find_device(self.backend.name.as_deref())?;
+        let requested = DeviceConfig {
+            sample_rate: self.backend.config.sample_rate,
+            ..self.backend.requested
+        };
+        let (config, format) = negotiate(&device, &requested, true)?;
+        if let Some(callback) = lock(&self.callback).as_mut() {
+            callback.reconfigure(&config);
+        }
+        self.backend.config = config;
+        self.backend.format = format;
+        self.backend.build(
+            &device,
+            &self.callback,
+            self.events.clone(),
+            generation,
+            &self.errors,
+        )
+    }
+}
+
+/// A [`CpalBackend`] playing an engine.
+///
+/// Dropping it stops the output; [`stop`](CpalStream::stop) also hands the
+/// engine back.
+pub struct CpalStream {
+    config: DeviceConfig,
+    events: Sender<Event>,
+    thread: Option<JoinHandle<()>>,
+    callback: Arc<Mutex<Option<Callback>>>,
+    errors: Arc<Mutex<Vec<BackendError>>>,
+    reconnects: Arc<AtomicU64>,
+}
+
+impl CpalStream {
+    /// The configuration the device was first opened with. After a
+    /// reconnection the channel count and buffer size may differ.
+    pub fn config(&self) -> DeviceConfig {
+        self.config
+    }
+
+    /// Errors since the last call, including lost devices and failed
+    /// attempts to reopen them.
+    pub fn take_errors(&self) -> Vec<BackendError> {
+        std::mem::take(&mut *lock(&self.errors))
+    }
+
+    /// Number of times the stream has been rebuilt after losing its device.
+    pub fn reconnects(&self) -> u64 {
+        self.reconnects.load(Ordering::Relaxed)
+    }
+
+    /// Stops the output and gives the engine back.
+    pub fn stop(mut self) -> Engine {
+        self.shut_down();
+        lock(&self.callback)
+            .take()
+            .expect("engine is only taken once")
+            .into_engine()
+    }
+
+    fn shut_down(&mut self) {
+        let _ = self.events.send(Event::Stop);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for CpalStream {
+    fn drop(&mut self) {
+        self.shut_down();
+    }
+}
+
+fn build_stream<T>(
+    device: &Device,
+    config: &StreamConfig,
+    callback: Arc<Mutex<Option<Callback>>>,
+    on_error: impl FnMut(StreamError) + Send + 'static,
+) -> Result<::cpal::Stream, BackendError>
+where
+    T: SizedSample + FromSample<f32>,
+{
+    device
+        .build_output_stream(
+            config,
+            move |data: &mut [T], _: &OutputCallbackInfo| match callback.try_lock() {
+                Ok(mut guard) => match guard.as_mut() {
+                    Some(callback) => callback.process_with(data, T::from_sample),
+                    None => data.fill(T::EQUILIBRIUM),
+                },
+                Err(_) => data.fill(T::EQUILIBRIUM),
+            },
+            on_error,
+            None,
+        )
+        .map_err(|e| BackendError::Device(e.to_string()))
+}
+
+fn find_device(name: Option<&str>) -> Result<Device, BackendError> {
+    let host = ::cpal::default_host();
+    match name {
+        None => host.default_output_device().ok_or(BackendError::NoDevice),
+        Some(name) => host
+            .output_devices()
+            .map_err(device_error)?
+            .find(|d| d.name().is_ok_and(|n| n == name))
+            .ok_or(BackendError::NoDevice),
+    }
+}
+
+/// Picks the supported configuration that comes closest to the requested
+/// channel count, preferring better sample formats, and clamps the buffer
+/// size to what it allows.
+///
+/// The requested rate is used if the device supports it. Otherwise, unless
+/// `exact_rate` is set, the device's default rate is used, or failing that
+/// the supported rate nearest the requested one.
+fn negotiate(
+    device: &Device,
+    requested: &DeviceConfig,
+    exact_rate: bool,
+) -> Result<(DeviceConfig, SampleFormat), BackendError> {
+    let usable: Vec<_> = device
+        .supported_output_configs()
+        .map_err(device_error)?
+        .filter(|range| FORMATS.contains(&range.sample_format()))
+        .collect();
+    if usable.is_empty() {
+        return Err(BackendError::UnsupportedFormat);
+    }
+    let supports = |rate: SampleRate| {
+        usable
+            .iter()
+            .any(|range| range.min_sample_rate() <= rate && rate <= range.max_sample_rate())
+    };
+    let mut rate = SampleRate(requested.sample_rate);
+    if !supports(rate) {
+        if exact_rate {
+            return Err(BackendError::UnsupportedRate(requested.sample_rate));
+        }
+        rate = match device.default_output_config() {
+            Ok(default) if supports(default.sample_rate()) => default.sample_rate(),
+            _ => usable
+                .iter()
+                .map(|range| rate.clamp(range.min_sample_rate(), range.max_sample_rate()))
+                .min_by_key(|nearest| nearest.0.abs_diff(rate.0))
+                .expect("usable is not empty"),
+        };
+    }
+    let best = usable
+        .into_iter()
+        .filter(|range| range.min_sample_rate() <= rate && rate <= range.max_sample_rate())
+        .min_by_key(|range| {
+            let channels = usize::from(range.channels());
+            let format = FORMATS.iter().position(|&f| f == range.sample_format());
+            (
+                channels.abs_diff(requested.channels),
+                channels < requested.channels,
+                format,
+            )
+        })
+        .expect("the rate is in a usable range");
+
+    let buffer_frames = match (requested.buffer_frames, best.buffer_size()) {
+        (Some(frames), &SupportedBufferSize::Range { min, max }) => {
+            Some((frames as u32).clamp(min, max) as usize)
+        }
+        _ => None,
+    };
+    let config = DeviceConfig {
+        sample_rate: rate.0,
+        channels: usize::from(best.channels()),
+        buffer_frames,
+    };
+    Ok((config, best.sample_format()))
+}
+
+fn device_error(e: impl std::fmt::Display) -> BackendError {
+    BackendError::Device(e.to_string())
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
@@ -0,0 +1,175 @@
+//! Audio output backends that drive an [`Engine`] from a device callback.
+//!
+//! A backend is opened with the [`DeviceConfig`] the application would like
+//! and reports the one it negotiated, which is what the engine should be
+//! created with. [`Backend::start`] then hands the engine to the device,
+//! whose callback runs it through a [`Callback`].
+//!
+//! [`NullBackend`] is always available and renders into memory, so code and
+//! tests written against [`Backend`] run without audio hardware. Output to a
+//! real device goes through `CpalBackend`, behind the `cpal` feature.
+
+use std::fmt;
+
+use crate::engine::Engine;
+
+#[cfg(feature = "cpal")]
+mod cpal;
+mod null;
+
+#[cfg(feature = "cpal")]
+pub use self::cpal::{CpalBackend, CpalStream};
+pub use null::{NullBackend, NullStream};
+
+/// The shape of a device's output stream.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DeviceConfig {
+    /// Frames per second.
+    pub sample_rate: u32,
+    /// Interleaved output channels.
+    pub channels: usize,
+    /// Frames per callback, or `None` for the device's default. Devices may
+    /// still vary the size from one callback to the next.
+    pub buffer_frames: Option<usize>,
+}
+
+impl Default for DeviceConfig {
+    fn default() -> Self {
+        DeviceConfig {
+            sample_rate: 48_000,
+            channels: 2,
+            buffer_frames: None,
+        }
+    }
+}
+
+/// Something that can play an [`Engine`].
+pub trait Backend {
+    /// A running output, which hands the engine back when stopped.
+    type Stream;
+
+    /// The configuration the device agreed to. Create the engine with this
+    /// sample rate; a different channel count is mapped by the [`Callback`].
+    fn config(&self) -> DeviceConfig;
+
+    /// Starts calling [`Engine::render`] for the device.
+    fn start(self, engine: Engine) -> Result<Self::Stream, BackendError>;
+}
+
+/// Errors reported by an output backend.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum BackendError {
+    /// There is no output device, or the named one was not found.
+    NoDevice,
+    /// The device can no longer play at the rate the engine was created
+    /// for.
+    UnsupportedRate(u32),
+    /// The device offers no sample format the backend can write.
+    UnsupportedFormat,
+    /// The device or the host's audio API failed.
+    Device(String),
+    /// The device went away; the backend is trying to reconnect.
+    Disconnected,
+}
+
+impl fmt::Display for BackendError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BackendError::NoDevice => write!(f, "no output device"),
+            BackendError::UnsupportedRate(rate) => {
+                write!(f, "device does not support {rate} Hz")
+            }
+            BackendError::UnsupportedFormat => write!(f, "device has no usable sample format"),
+            BackendError::Device(e) => write!(f, "audio device error: {e}"),
+            BackendError::Disconnected => write!(f, "audio device disconnected"),
+        }
+    }
+}
+
+impl std::error::Error for BackendError {}
+
+/// Largest block rendered in one go when the device does not say how large
+/// its callbacks are.
+const DEFAULT_BLOCK: usize = 4096;
+
+/// Renders an [`Engine`] into device buffers: the code every backend runs
+/// in its callback.
+///
+/// The engine renders into a scratch buffer sized up front, in pieces if a
+/// callback asks for more frames than it holds, and the result is mapped
+/// onto the device's channels the way voices map sample channels: a mono
+/// engine feeds every device channel, and surplus engine channels fold back
+/// onto the available ones. Nothing here allocates after [`new`](Callback::new).
+pub struct Callback {
+    engine: Engine,
+    scratch: Vec<f32>,
+    engine_channels: usize,
+    device_channels: usize,
+}
+
+impl Callback {
+    /// Prepares to play `engine` on a device configured as `device`.
+    pub fn new(engine: Engine, device: &DeviceConfig) -> Self {
+        assert!(device.channels > 0, "device needs at least one channel");
+        let engine_channels = engine.config().channels;
+        let block = device.buffer_frames.unwrap_or(DEFAULT_BLOCK).max(1);
+        Callback {
+            engine,
+            scratch: vec![0.0; block * engine_channels],
+            engine_channels,
+            device_channels: device.channels,
+        }
+    }
+
+    /// Adapts to a device that reopened with a different configuration.
+    /// Allocates, so it must not run on the audio thread.
+    #[cfg_attr(not(feature = "cpal"), allow(dead_code))]
+    pub(super) fn reconfigure(&mut self, device: &DeviceConfig) {
+        let block = device.buffer_frames.unwrap_or(DEFAULT_BLOCK).max(1);
+        self.scratch = vec![0.0; block * self.engine_channels];
+        self.device_channels = device.channels;
+    }
+
+    /// Fills an interleaved `f32` device buffer.
+    pub fn process(&mut self, out: &mut [f32]) {
+        self.process_with(out, |v| v);
+    }
+
+    /// Fills an interleaved device buffer of any sample type, converting
+    /// each value with `convert`. A trailing partial frame is silenced.
+    pub fn process_with<T: Copy>(&mut self, out: &mut [T], convert: impl Fn(f32) -> T) {
+        let (engine_channels, device_channels) = (self.engine_channels, self.device_channels);
+        let block = self.scratch.len() / engine_channels;
+        let mut frames = out.chunks_exact_mut(device_channels);
+        loop {
+            let n = frames.len().min(block);
+            if n == 0 {
+                break;
+            }
+            let scratch = &mut self.scratch[..n * engine_channels];
+            self.engine.render(scratch);
+            for (src, dst) in scratch.chunks_exact(engine_channels).zip(frames.by_ref()) {
+                for (d, v) in dst.iter_mut().enumerate() {
+                    let sum: f32 = if engine_channels <= device_channels {
+                        src[d % engine_channels]
+                    } else {
+                        src.iter().skip(d).step_by(device_channels).sum()
+                    };
+                    *v = convert(sum);
+                }
+            }
+        }
+        let whole = out.len() / device_channels * device_channels;
+        out[whole..].fill(convert(0.0));
+    }
+
+    /// The engine being played.
+    pub fn engine(&self) -> &Engine {
+        &self.engine
+    }
+
+    /// Gives the engine back.
+    pub fn into_engine(self) -> Engine {
+        self.engine
+    }
+}
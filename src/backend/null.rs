@@ -0,0 +1,94 @@
+//! A backend without a device, for tests and offline use.
+
+use super::{Backend, BackendError, Callback, DeviceConfig};
+use crate::engine::Engine;
+
+/// Callback size when the requested configuration leaves it open.
+const DEFAULT_BUFFER: usize = 512;
+
+/// Plays into memory instead of a device.
+///
+/// It accepts any configuration, and its stream only advances when asked
+/// to, one device-sized callback at a time, so tests see exactly what a
+/// device would have been given.
+#[derive(Clone, Debug)]
+pub struct NullBackend {
+    config: DeviceConfig,
+}
+
+impl NullBackend {
+    pub fn new(requested: DeviceConfig) -> Self {
+        assert!(requested.channels > 0, "device needs at least one channel");
+        NullBackend {
+            config: DeviceConfig {
+                buffer_frames: Some(requested.buffer_frames.unwrap_or(DEFAULT_BUFFER).max(1)),
+                ..requested
+            },
+        }
+    }
+}
+
+impl Backend for NullBackend {
+    type Stream = NullStream;
+
+    fn config(&self) -> DeviceConfig {
+        self.config
+    }
+
+    fn start(self, engine: Engine) -> Result<NullStream, BackendError> {
+        let frames = self.config.buffer_frames.unwrap_or(DEFAULT_BUFFER);
+        Ok(NullStream {
+            callback: Callback::new(engine, &self.config),
+            buffer: vec![0.0; frames * self.config.channels],
+            callbacks: 0,
+        })
+    }
+}
+
+/// A running [`NullBackend`].
+pub struct NullStream {
+    callback: Callback,
+    /// One callback's worth of device output.
+    buffer: Vec<f32>,
+    callbacks: u64,
+}
+
+impl NullStream {
+    /// Runs one device callback and returns what it produced.
+    pub fn tick(&mut self) -> &[f32] {
+        self.callback.process(&mut self.buffer);
+        self.callbacks += 1;
+        &self.buffer
+    }
+
+    /// Runs callbacks until at least `frames` frames have been produced and
+    /// returns them all, interleaved.
+    pub fn run(&mut self, frames: usize) -> Vec<f32> {
+        let channels = self.callback.device_channels;
+        let mut out = Vec::with_capacity(frames.next_multiple_of(self.buffer_frames()) * channels);
+        while out.len() < frames * channels {
+            out.extend_from_slice(self.tick());
+        }
+        out
+    }
+
+    /// Frames produced by each callback.
+    pub fn buffer_frames(&self) -> usize {
+        self.buffer.len() / self.callback.device_channels
+    }
+
+    /// Number of callbacks run so far.
+    pub fn callbacks(&self) -> u64 {
+        self.callbacks
+    }
+
+    /// The engine being played.
+    pub fn engine(&self) -> &Engine {
+        self.callback.engine()
+    }
+
+    /// Stops the stream and gives the engine back.
+    pub fn stop(self) -> Engine {
+        self.callback.into_engine()
+    }
+}
@@ -0,0 +1,210 @@
+//! Offline rendering.
+//!
+//! A [`Bounce`] owns an engine that no audio device drives. Load samples
+//! and instruments through its [`Controller`], queue timed MIDI messages
+//! or a whole MIDI file, and [`render`](Bounce::render) plays them all as
+//! fast as the machine allows, stopping once the last voice has died away.
+//!
+//! Nothing in the result depends on timing, so the same setup renders to
+//! the same bits on every run: events land on their exact frames, streamed
+//! samples are read before every block rather than raced against, and
+//! [`Dither::Tpdf`] draws its noise from a seeded generator. That makes
+//! bounced files usable as golden files in tests.
+
+use std::fmt;
+use std::io;
+use std::path::Path;
+
+use crate::codec::wav::{self, BitDepth, Dither};
+use crate::controller::Controller;
+use crate::engine::{Engine, EngineConfig, EngineError};
+use crate::instrument::Instrument;
+use crate::midi::{MidiMapper, MidiMessage, Smf};
+use crate::sample::Sample;
+use crate::stream::Streamer;
+
+/// Most frames rendered between two looks at the event list.
+const BLOCK: usize = 512;
+
+/// Errors from [`Bounce::render`] and [`Bounce::save_wav`].
+#[derive(Debug)]
+pub enum BounceError {
+    /// The engine refused a command.
+    Engine(EngineError),
+    /// The output file could not be written.
+    Io(io::Error),
+}
+
+impl fmt::Display for BounceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BounceError::Engine(e) => write!(f, "{e}"),
+            BounceError::Io(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl std::error::Error for BounceError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            BounceError::Engine(e) => Some(e),
+            BounceError::Io(e) => Some(e),
+        }
+    }
+}
+
+impl From<EngineError> for BounceError {
+    fn from(e: EngineError) -> Self {
+        BounceError::Engine(e)
+    }
+}
+
+impl From<io::Error> for BounceError {
+    fn from(e: io::Error) -> Self {
+        BounceError::Io(e)
+    }
+}
+
+/// An offline render: instruments, the MIDI that plays them, and the engine
+/// they play in.
+pub struct Bounce {
+    engine: Engine,
+    ctl: Controller,
+    streamer: Option<Streamer>,
+    mappers: Vec<MidiMapper>,
+    /// Messages and the output frames they are due on.
+    events: Vec<(u64, MidiMessage)>,
+    tail: f64,
+}
+
+impl Bounce {
+    /// Sets up an engine for offline rendering. Its streamer, if streaming
+    /// is enabled, stays with the bounce.
+    pub fn new(config: EngineConfig) -> Self {
+        let (engine, mut ctl) = Engine::new(config);
+        let streamer = ctl.streamer();
+        Bounce {
+            engine,
+            ctl,
+            streamer,
+            mappers: Vec::new(),
+            events: Vec::new(),
+            tail: 30.0,
+        }
+    }
+
+    /// The configuration the engine was created with.
+    pub fn config(&self) -> &EngineConfig {
+        self.engine.config()
+    }
+
+    /// The engine's controller, for loading samples and instruments.
+    pub fn controller(&mut self) -> &mut Controller {
+        &mut self.ctl
+    }
+
+    /// Adds an instrument that plays MIDI on `channel`, or on every channel
+    /// with `None`.
+    pub fn add_instrument(&mut self, instrument: Instrument, channel: Option<u8>) {
+        let mut mapper = MidiMapper::new(instrument);
+        mapper.set_channel(channel);
+        self.mappers.push(mapper);
+    }
+
+    /// Adds a mapper configured by hand, for a bend range or velocity curve
+    /// other than the defaults. Every message goes to every mapper.
+    pub fn add_mapper(&mut self, mapper: MidiMapper) {
+        self.mappers.push(mapper);
+    }
+
+    /// Queues `message` for output frame `frame`. Messages due on the same
+    /// frame are handled in the order they were queued.
+    pub fn push(&mut self, frame: u64, message: MidiMessage) {
+        self.events.push((frame, message));
+    }
+
+    /// Queues every message of an event list.
+    pub fn extend(&mut self, events: impl IntoIterator<Item = (u64, MidiMessage)>) {
+        self.events.extend(events);
+    }
+
+    /// Queues every MIDI message of a file, timed at the engine's rate.
+    pub fn add_smf(&mut self, smf: &Smf) {
+        let events = smf.events(self.engine.config().sample_rate);
+        self.extend(events);
+    }
+
+    /// Sets the longest time, in seconds, to keep rendering after the last
+    /// event while voices ring out. Defaults to 30; a looping voice that is
+    /// never released stops the render at this point.
+    pub fn set_tail(&mut self, seconds: f64) {
+        self.tail = seconds;
+    }
+
+    /// Plays every queued event and returns the output as an interleaved
+    /// sample at the engine's rate.
+    ///
+    /// The render covers the last event and then runs on until every voice
+    /// is silent, or for the [tail](Bounce::set_tail) at most. Silence after
+    /// the last event is trimmed.
+    pub fn render(mut self) -> Result<Sample, BounceError> {
+        let channels = self.engine.config().channels;
+        let sample_rate = self.engine.config().sample_rate;
+        let mut events = std::mem::take(&mut self.events);
+        events.sort_by_key(|(frame, _)| *frame);
+        let end = events.last().map_or(0, |(frame, _)| *frame);
+        let limit = end + (self.tail * f64::from(sample_rate)).round() as u64;
+
+        let mut out = Vec::new();
+        let mut events = events.into_iter().peekable();
+        loop {
+            let now = self.engine.frame();
+            while let Some((_, message)) = events.next_if(|(frame, _)| *frame <= now) {
+                for mapper in &mut self.mappers {
+                    mapper.handle(&mut self.ctl, now, &message)?;
+                }
+                // An empty render takes in what was just sent and applies
+                // whatever is due, so the queue never fills and the voices
+                // count before the silence check below.
+                self.engine.render(&mut []);
+            }
+            let run = match events.peek() {
+                Some((frame, _)) => (frame - now).min(BLOCK as u64),
+                None if now >= limit || (now >= end && self.is_silent()) => break,
+                None => (limit - now).min(BLOCK as u64),
+            } as usize;
+            if let Some(streamer) = &mut self.streamer {
+                while streamer.service() > 0 {}
+            }
+            let start = out.len();
+            out.resize(start + run * channels, 0.0);
+            self.engine.render(&mut out[start..]);
+            self.ctl.collect_garbage();
+        }
+        // Voices fall silent part of the way through the last block.
+        let end = end as usize * channels;
+        let sounding = out.iter().rposition(|&v| v != 0.0).map_or(0, |i| i + 1);
+        out.truncate(sounding.max(end).next_multiple_of(channels));
+        Ok(Sample::from_interleaved(out, channels, sample_rate)
+            .expect("engine has channels and a sample rate"))
+    }
+
+    /// Renders and writes the result to `path` as a WAV file.
+    pub fn save_wav(
+        self,
+        path: impl AsRef<Path>,
+        depth: BitDepth,
+        dither: Dither,
+    ) -> Result<Sample, BounceError> {
+        let sample = self.render()?;
+        wav::save(path, &sample, depth, dither)?;
+        Ok(sample)
+    }
+
+    /// No voice is sounding, stolen voices included, and no event waits.
+    fn is_silent(&self) -> bool {
+        self.engine.active_voices() == 0
+            && self.engine.pool().fading() == 0
+            && self.engine.pending_events() == 0
+    }
+}
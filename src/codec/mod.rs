@@ -0,0 +1,232 @@
+//! Audio file decoders that produce [`Sample`](crate::Sample) buffers.
+//!
+//! [`load`] and [`decode`] pick the decoder from the file contents, so a
+//! file's extension does not matter. The format modules can also be used
+//! directly. FLAC support is behind the `flac` feature, enabled by default;
+//! Ogg Vorbis and MP3 are behind the optional `vorbis` and `mp3` features.
+//!
+//! The compressed formats can also be decoded a block at a time through
+//! [`StreamDecoder`], for files too long to hold in memory.
+//!
+//! Only [`wav`] writes files as well.
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::sample::{Sample, SampleError};
+
+#[cfg(feature = "flac")]
+pub mod flac;
+#[cfg(feature = "flac")]
+mod md5;
+#[cfg(feature = "mp3")]
+pub mod mp3;
+#[cfg(feature = "vorbis")]
+mod ogg;
+#[cfg(feature = "vorbis")]
+pub mod vorbis;
+pub mod wav;
+
+/// Container formats recognised by [`detect`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FileFormat {
+    Wav,
+    Flac,
+    /// Vorbis in an Ogg container.
+    Vorbis,
+    /// MPEG audio layer III.
+    Mp3,
+}
+
+/// Identifies the format of an encoded file from its first bytes.
+pub fn detect(bytes: &[u8]) -> Option<FileFormat> {
+    if bytes.len() >= 12 && &bytes[..4] == b"RIFF" && &bytes[8..12] == b"WAVE" {
+        Some(FileFormat::Wav)
+    } else if bytes.starts_with(b"fLaC") {
+        Some(FileFormat::Flac)
+    } else if bytes.starts_with(b"OggS") {
+        // The first page holds only the codec's identification header.
+        let body = 27 + usize::from(*bytes.get(26)?);
+        bytes
+            .get(body..)?
+            .starts_with(b"\x01vorbis")
+            .then_some(FileFormat::Vorbis)
+    } else if bytes.starts_with(b"ID3")
+        || (bytes.len() >= 2 && bytes[0] == 0xff && bytes[1] & 0xe6 == 0xe2)
+    {
+        // An ID3v2 tag, or a frame sync with layer bits `01`.
+        Some(FileFormat::Mp3)
+    } else {
+        None
+    }
+}
+
+/// Reads a file from disk and decodes it with the decoder matching its
+/// contents.
+pub fn load(path: impl AsRef<Path>) -> Result<Sample, DecodeError> {
+    decode(&fs::read(path)?)
+}
+
+/// Decodes an in-memory file with the decoder matching its contents.
+pub fn decode(bytes: &[u8]) -> Result<Sample, DecodeError> {
+    match detect(bytes) {
+        Some(FileFormat::Wav) => wav::decode(bytes),
+        #[cfg(feature = "flac")]
+        Some(FileFormat::Flac) => flac::decode(bytes),
+        #[cfg(not(feature = "flac"))]
+        Some(FileFormat::Flac) => Err(DecodeError::Unsupported(
+            "FLAC file (enable the `flac` feature)".into(),
+        )),
+        #[cfg(feature = "vorbis")]
+        Some(FileFormat::Vorbis) => vorbis::decode(bytes),
+        #[cfg(not(feature = "vorbis"))]
+        Some(FileFormat::Vorbis) => Err(DecodeError::Unsupported(
+            "Ogg Vorbis file (enable the `vorbis` feature)".into(),
+        )),
+        #[cfg(feature = "mp3")]
+        Some(FileFormat::Mp3) => mp3::decode(bytes),
+        #[cfg(not(feature = "mp3"))]
+        Some(FileFormat::Mp3) => Err(DecodeError::Unsupported(
+            "MP3 file (enable the `mp3` feature)".into(),
+        )),
+        None => Err(DecodeError::Unsupported("file format".into())),
+    }
+}
+
+/// A decoder that produces audio a block at a time.
+pub trait StreamDecoder {
+    fn channels(&self) -> usize;
+
+    fn sample_rate(&self) -> u32;
+
+    /// Total frames after trimming, if the file states it up front.
+    fn frames(&self) -> Option<u64>;
+
+    /// Decodes interleaved frames into `out`, filling it unless the stream
+    /// ends first. Returns the number of frames written; 0 means the end of
+    /// the stream.
+    fn read(&mut self, out: &mut [f32]) -> Result<usize, DecodeError>;
+}
+
+/// Decodes the rest of a stream into a sample.
+///
+/// The output grows as it is decoded rather than being sized from
+/// [`StreamDecoder::frames`], which comes from an unchecked header.
+#[cfg_attr(not(any(feature = "vorbis", feature = "mp3")), allow(dead_code))]
+pub(crate) fn read_to_end(decoder: &mut impl StreamDecoder) -> Result<Sample, DecodeError> {
+    let channels = decoder.channels();
+    let mut data = Vec::new();
+    let mut block = vec![0.0; 4096 * channels];
+    loop {
+        let frames = decoder.read(&mut block)?;
+        if frames == 0 {
+            break;
+        }
+        data.extend_from_slice(&block[..frames * channels]);
+    }
+    Ok(Sample::from_interleaved(
+        data,
+        channels,
+        decoder.sample_rate(),
+    )?)
+}
+
+/// Errors produced while decoding an audio file.
+#[derive(Debug)]
+pub enum DecodeError {
+    /// The underlying reader failed.
+    Io(io::Error),
+    /// The input ended in the middle of the named structure.
+    Truncated(&'static str),
+    /// A structure was present but its contents are invalid.
+    Malformed(&'static str),
+    /// A required chunk or block is missing.
+    MissingChunk(&'static str),
+    /// The file is valid but uses an encoding this decoder does not handle.
+    Unsupported(String),
+    /// The decoded data could not form a valid sample.
+    Sample(SampleError),
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::Io(e) => write!(f, "i/o error: {e}"),
+            DecodeError::Truncated(what) => write!(f, "truncated {what}"),
+            DecodeError::Malformed(what) => write!(f, "malformed {what}"),
+            DecodeError::MissingChunk(what) => write!(f, "missing {what}"),
+            DecodeError::Unsupported(what) => write!(f, "unsupported {what}"),
+            DecodeError::Sample(e) => write!(f, "invalid sample: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            DecodeError::Io(e) => Some(e),
+            DecodeError::Sample(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for DecodeError {
+    fn from(e: io::Error) -> Self {
+        DecodeError::Io(e)
+    }
+}
+
+impl From<SampleError> for DecodeError {
+    fn from(e: SampleError) -> Self {
+        DecodeError::Sample(e)
+    }
+}
+
+/// Little-endian cursor over a byte slice that reports truncation by name.
+pub(crate) struct Cursor<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Cursor<'a> {
+    pub(crate) fn new(data: &'a [u8]) -> Self {
+        Cursor { data, pos: 0 }
+    }
+
+    pub(crate) fn remaining(&self) -> usize {
+        self.data.len() - self.pos
+    }
+
+    pub(crate) fn take(&mut self, n: usize, what: &'static str) -> Result<&'a [u8], DecodeError> {
+        if self.remaining() < n {
+            return Err(DecodeError::Truncated(what));
+        }
+        let bytes = &self.data[self.pos..self.pos + n];
+        self.pos += n;
+        Ok(bytes)
+    }
+
+    pub(crate) fn skip(&mut self, n: usize, what: &'static str) -> Result<(), DecodeError> {
+        self.take(n, what).map(|_| ())
+    }
+
+    pub(crate) fn array<const N: usize>(
+        &mut self,
+        what: &'static str,
+    ) -> Result<[u8; N], DecodeError> {
+        let mut out = [0; N];
+        out.copy_from_slice(self.take(N, what)?);
+        Ok(out)
+    }
+
+    pub(crate) fn u16_le(&mut self, what: &'static str) -> Result<u16, DecodeError> {
+        self.array(what).map(u16::from_le_bytes)
+    }
+
+    pub(crate) fn u32_le(&mut self, what: &'static str) -> Result<u32, DecodeError> {
+        self.array(what).map(u32::from_le_bytes)
+    }
+}
@@ -0,0 +1,42 @@
+//! MSB-first bit reader over MP3 side information and main data.
+
+/// Reading past the end returns zeros; callers bound their reads with the
+/// lengths the side information gives.
+pub(super) struct BitReader<'a> {
+    data: &'a [u8],
+    /// Position in bits.
+    pos: usize,
+}
+
+impl<'a> BitReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        BitReader { data, pos: 0 }
+    }
+
+    /// Position in bits from the start of the data.
+    pub fn position(&self) -> usize {
+        self.pos
+    }
+
+    pub fn seek(&mut self, pos: usize) {
+        self.pos = pos;
+    }
+
+    /// Reads `n` bits (up to 32), most significant first.
+    pub fn read(&mut self, n: u32) -> u32 {
+        let mut value = 0u32;
+        for _ in 0..n {
+            value = (value << 1) | u32::from(self.bit());
+        }
+        value
+    }
+
+    pub fn bit(&mut self) -> bool {
+        let bit = self
+            .data
+            .get(self.pos / 8)
+            .is_some_and(|b| b & (0x80 >> (self.pos % 8)) != 0);
+        self.pos += 1;
+        bit
+    }
+}
@@ -0,0 +1,216 @@
+//! Frame headers and Layer III side information.
+
+use super::bits::BitReader;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(super) enum Version {
+    Mpeg1,
+    Mpeg2,
+    /// The unofficial low-rate extension of MPEG-2.
+    Mpeg25,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(super) enum Mode {
+    Stereo,
+    JointStereo,
+    DualChannel,
+    Mono,
+}
+
+/// A Layer III frame header.
+#[derive(Clone, Copy, Debug)]
+pub(super) struct Header {
+    pub version: Version,
+    /// A CRC-16 follows the header.
+    pub protected: bool,
+    /// Bit rate in kbit/s.
+    pub bitrate: u32,
+    pub sample_rate: u32,
+    /// Index into the scalefactor band tables, 0 to 8.
+    pub rate_index: usize,
+    pub padding: bool,
+    pub mode: Mode,
+    pub mode_extension: u8,
+}
+
+impl Header {
+    /// Parses a Layer III header. Other layers, free-format bit rates and
+    /// reserved values are rejected.
+    pub fn parse(b: [u8; 4]) -> Option<Header> {
+        if b[0] != 0xff || b[1] & 0xe0 != 0xe0 || (b[1] >> 1) & 3 != 1 {
+            return None;
+        }
+        let version = match (b[1] >> 3) & 3 {
+            0 => Version::Mpeg25,
+            2 => Version::Mpeg2,
+            3 => Version::Mpeg1,
+            _ => return None,
+        };
+        const MPEG1_RATES: [u32; 15] = [
+            0, 32, 40, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320,
+        ];
+        const MPEG2_RATES: [u32; 15] =
+            [0, 8, 16, 24, 32, 40, 48, 56, 64, 80, 96, 112, 128, 144, 160];
+        let bitrate_index = usize::from(b[2] >> 4);
+        let rate = usize::from((b[2] >> 2) & 3);
+        if bitrate_index == 0 || bitrate_index == 15 || rate == 3 {
+            return None;
+        }
+        let (bitrate, base) = match version {
+            Version::Mpeg1 => (MPEG1_RATES[bitrate_index], 0),
+            Version::Mpeg2 => (MPEG2_RATES[bitrate_index], 3),
+            Version::Mpeg25 => (MPEG2_RATES[bitrate_index], 6),
+        };
+        const SAMPLE_RATES: [u32; 9] =
+            [44100, 48000, 32000, 22050, 24000, 16000, 11025, 12000, 8000];
+        Some(Header {
+            version,
+            protected: b[1] & 1 == 0,
+            bitrate,
+            sample_rate: SAMPLE_RATES[base + rate],
+            rate_index: base + rate,
+            padding: b[2] & 2 != 0,
+            mode: match b[3] >> 6 {
+                0 => Mode::Stereo,
+                1 => Mode::JointStereo,
+                2 => Mode::DualChannel,
+                _ => Mode::Mono,
+            },
+            mode_extension: (b[3] >> 4) & 3,
+        })
+    }
+
+    pub fn channels(&self) -> usize {
+        if self.mode == Mode::Mono {
+            1
+        } else {
+            2
+        }
+    }
+
+    /// Granules per frame: two in MPEG-1, one otherwise.
+    pub fn granules(&self) -> usize {
+        if self.version == Version::Mpeg1 {
+            2
+        } else {
+            1
+        }
+    }
+
+    /// Frame length in bytes, header included.
+    pub fn frame_size(&self) -> usize {
+        let per_granule = 72 * self.granules() as u32;
+        (per_granule * self.bitrate * 1000 / self.sample_rate) as usize + usize::from(self.padding)
+    }
+
+    pub fn side_info_size(&self) -> usize {
+        match (self.version, self.channels()) {
+            (Version::Mpeg1, 1) => 17,
+            (Version::Mpeg1, _) => 32,
+            (_, 1) => 9,
+            _ => 17,
+        }
+    }
+
+    /// Offset of the side information from the start of the frame.
+    pub fn side_info_start(&self) -> usize {
+        if self.protected {
+            6
+        } else {
+            4
+        }
+    }
+
+    /// Whether `other` can belong to the same stream.
+    pub fn matches(&self, other: &Header) -> bool {
+        self.version == other.version
+            && self.sample_rate == other.sample_rate
+            && self.channels() == other.channels()
+    }
+}
+
+/// Side information for one channel of one granule.
+#[derive(Clone, Copy, Debug, Default)]
+pub(super) struct Granule {
+    /// Bits of scalefactors and Huffman data.
+    pub part2_3_length: usize,
+    /// Number of value pairs in the big-values region.
+    pub big_values: usize,
+    pub global_gain: i32,
+    pub scalefac_compress: usize,
+    /// 0 normal, 1 start, 2 short, 3 stop.
+    pub block_type: u8,
+    pub mixed: bool,
+    pub table_select: [usize; 3],
+    pub subblock_gain: [i32; 3],
+    /// Ends of the first two big-values regions, in spectral lines.
+    pub region_ends: [usize; 2],
+    pub preflag: bool,
+    pub scalefac_scale: bool,
+    pub count1_table: bool,
+}
+
+/// The side information of one frame.
+#[derive(Debug, Default)]
+pub(super) struct SideInfo {
+    /// How many bytes before this frame's main data its granules start.
+    pub main_data_begin: usize,
+    /// MPEG-1 scalefactor reuse flags per channel and band group.
+    pub scfsi: [[bool; 4]; 2],
+    pub granules: [[Granule; 2]; 2],
+}
+
+impl SideInfo {
+    pub fn read(header: &Header, data: &[u8]) -> SideInfo {
+        let mut r = BitReader::new(data);
+        let mpeg1 = header.version == Version::Mpeg1;
+        let channels = header.channels();
+        let mut side = SideInfo::default();
+        if mpeg1 {
+            side.main_data_begin = r.read(9) as usize;
+            r.read(if channels == 1 { 5 } else { 3 });
+            for scfsi in side.scfsi.iter_mut().take(channels) {
+                for flag in scfsi.iter_mut() {
+                    *flag = r.bit();
+                }
+            }
+        } else {
+            side.main_data_begin = r.read(8) as usize;
+            r.read(channels as u32);
+        }
+        let long_bands = &super::layer3::LONG_BANDS[header.rate_index];
+        for gr in 0..header.granules() {
+            for ch in 0..channels {
+                let g = &mut side.granules[gr][ch];
+                g.part2_3_length = r.read(12) as usize;
+                g.big_values = (r.read(9) as usize).min(288);
+                g.global_gain = r.read(8) as i32;
+                g.scalefac_compress = r.read(if mpeg1 { 4 } else { 9 }) as usize;
+                if r.bit() {
+                    g.block_type = r.read(2) as u8;
+                    g.mixed = r.bit();
+                    g.table_select = [r.read(5) as usize, r.read(5) as usize, 0];
+                    for gain in &mut g.subblock_gain {
+                        *gain = r.read(3) as i32;
+                    }
+                    let end = if g.block_type == 2 { 36 } else { long_bands[8] };
+                    g.region_ends = [end, 576];
+                } else {
+                    g.block_type = 0;
+                    g.table_select = [r.read(5) as usize, r.read(5) as usize, r.read(5) as usize];
+                    let region0 = r.read(4) as usize;
+                    let region1 = r.read(3) as usize;
+                    g.region_ends = [
+                        long_bands[(region0 + 1).min(22)],
+                        long_bands[(region0 + region1 + 2).min(22)],
+                    ];
+                }
+                g.preflag = mpeg1 && r.bit();
+                g.scalefac_scale = r.bit();
+                g.count1_table = r.bit();
+            }
+        }
+        side
+    }
+}
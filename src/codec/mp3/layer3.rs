@@ -0,0 +1,657 @@
+//! Layer III granule decoding: scalefactors, Huffman data, requantisation,
+//! stereo processing and the hybrid filterbank.
+
+use std::f64::consts::PI;
+use std::ops::Range;
+use std::sync::OnceLock;
+
+use super::bits::BitReader;
+use super::frame::{Granule, Header, Mode, SideInfo, Version};
+use super::huffman;
+use super::synth::Synth;
+
+/// Long-block scalefactor band boundaries by sample rate: 44.1, 48 and
+/// 32 kHz, then their MPEG-2 and MPEG-2.5 halves and quarters.
+pub(super) const LONG_BANDS: [[usize; 23]; 9] = [
+    [
+        0, 4, 8, 12, 16, 20, 24, 30, 36, 44, 52, 62, 74, 90, 110, 134, 162, 196, 238, 288, 342,
+        418, 576,
+    ],
+    [
+        0, 4, 8, 12, 16, 20, 24, 30, 36, 42, 50, 60, 72, 88, 106, 128, 156, 190, 230, 276, 330,
+        384, 576,
+    ],
+    [
+        0, 4, 8, 12, 16, 20, 24, 30, 36, 44, 54, 66, 82, 102, 126, 156, 194, 240, 296, 364, 448,
+        550, 576,
+    ],
+    [
+        0, 6, 12, 18, 24, 30, 36, 44, 54, 66, 80, 96, 116, 140, 168, 200, 238, 284, 336, 396, 464,
+        522, 576,
+    ],
+    [
+        0, 6, 12, 18, 24, 30, 36, 44, 54, 66, 80, 96, 114, 136, 162, 194, 232, 278, 332, 394, 464,
+        540, 576,
+    ],
+    [
+        0, 6, 12, 18, 24, 30, 36, 44, 54, 66, 80, 96, 116, 140, 168, 200, 238, 284, 336, 396, 464,
+        522, 576,
+    ],
+    [
+        0, 6, 12, 18, 24, 30, 36, 44, 54, 66, 80, 96, 116, 140, 168, 200, 238, 284, 336, 396, 464,
+        522, 576,
+    ],
+    [
+        0, 6, 12, 18, 24, 30, 36, 44, 54, 66, 80, 96, 116, 140, 168, 200, 238, 284, 336, 396, 464,
+        522, 576,
+    ],
+    [
+        0, 12, 24, 36, 48, 60, 72, 88, 108, 132, 160, 192, 232, 280, 336, 400, 476, 566, 568, 570,
+        572, 574, 576,
+    ],
+];
+
+/// Short-block scalefactor band boundaries within one window.
+const SHORT_BANDS: [[usize; 14]; 9] = [
+    [0, 4, 8, 12, 16, 22, 30, 40, 52, 66, 84, 106, 136, 192],
+    [0, 4, 8, 12, 16, 22, 28, 38, 50, 64, 80, 100, 126, 192],
+    [0, 4, 8, 12, 16, 22, 30, 42, 58, 78, 104, 138, 180, 192],
+    [0, 4, 8, 12, 18, 24, 32, 42, 56, 74, 100, 132, 174, 192],
+    [0, 4, 8, 12, 18, 26, 36, 48, 62, 80, 104, 136, 180, 192],
+    [0, 4, 8, 12, 18, 26, 36, 48, 62, 80, 104, 134, 174, 192],
+    [0, 4, 8, 12, 18, 26, 36, 48, 62, 80, 104, 134, 174, 192],
+    [0, 4, 8, 12, 18, 26, 36, 48, 62, 80, 104, 134, 174, 192],
+    [0, 8, 16, 24, 36, 52, 72, 96, 124, 160, 162, 164, 166, 192],
+];
+
+/// Extra attenuation of the upper long bands when `preflag` is set.
+const PRETAB: [i32; 22] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 3, 3, 3, 2, 0,
+];
+
+#[derive(Clone, Copy, Default)]
+struct Scalefactors {
+    long: [i32; 22],
+    short: [[i32; 3]; 13],
+    preflag: bool,
+    /// The intensity position that marks a band as not intensity coded.
+    illegal_long: [i32; 22],
+    illegal_short: [i32; 13],
+}
+
+/// Decoder state carried from granule to granule.
+pub(super) struct Layer3 {
+    scalefactors: [Scalefactors; 2],
+    /// Quantised values, then requantised spectra, of the current granule.
+    values: [[i32; 576]; 2],
+    xr: [[f32; 576]; 2],
+    /// The second half of each subband's last IMDCT output.
+    overlap: [[f32; 576]; 2],
+    synth: [Synth; 2],
+    pcm: [[f32; 576]; 2],
+}
+
+impl Layer3 {
+    pub fn new() -> Self {
+        Layer3 {
+            scalefactors: [Scalefactors::default(); 2],
+            values: [[0; 576]; 2],
+            xr: [[0.0; 576]; 2],
+            overlap: [[0.0; 576]; 2],
+            synth: [Synth::new(), Synth::new()],
+            pcm: [[0.0; 576]; 2],
+        }
+    }
+
+    /// Decodes one frame and appends its interleaved samples to `out`.
+    /// `main` is the frame's main data, starting at its first granule, or
+    /// `None` when the bit reservoir cannot supply it; the frame then
+    /// decodes as silence.
+    pub fn decode(
+        &mut self,
+        header: &Header,
+        side: &SideInfo,
+        main: Option<&[u8]>,
+        out: &mut Vec<f32>,
+    ) {
+        let channels = header.channels();
+        let mut r = main.map(BitReader::new);
+        let mut pos = 0;
+        for (gr, granules) in side.granules.iter().enumerate().take(header.granules()) {
+            for (ch, g) in granules.iter().enumerate().take(channels) {
+                self.values[ch] = [0; 576];
+                if let Some(r) = r.as_mut() {
+                    r.seek(pos);
+                    let end = pos + g.part2_3_length;
+                    if header.version == Version::Mpeg1 {
+                        self.read_scalefactors(r, g, &side.scfsi[ch], gr == 1, ch);
+                    } else {
+                        let intensity_right = ch == 1
+                            && header.mode == Mode::JointStereo
+                            && header.mode_extension & 1 != 0;
+                        self.read_lsf_scalefactors(r, g, intensity_right, ch);
+                    }
+                    self.read_huffman(r, g, end, ch);
+                    pos = end;
+                }
+                self.requantise(header, g, ch);
+            }
+            if header.mode == Mode::JointStereo && channels == 2 {
+                self.stereo(header, &granules[1]);
+            }
+            for (ch, g) in granules.iter().enumerate().take(channels) {
+                self.hybrid(header, g, ch);
+            }
+            for i in 0..576 {
+                for ch in 0..channels {
+                    out.push(self.pcm[ch][i]);
+                }
+            }
+        }
+    }
+
+    fn read_scalefactors(
+        &mut self,
+        r: &mut BitReader<'_>,
+        g: &Granule,
+        scfsi: &[bool; 4],
+        second: bool,
+        ch: usize,
+    ) {
+        const SLEN: [[u32; 16]; 2] = [
+            [0, 0, 0, 0, 3, 1, 1, 1, 2, 2, 2, 3, 3, 3, 4, 4],
+            [0, 1, 2, 3, 0, 1, 2, 3, 1, 2, 3, 1, 2, 3, 2, 3],
+        ];
+        let sf = &mut self.scalefactors[ch];
+        let slen = [SLEN[0][g.scalefac_compress], SLEN[1][g.scalefac_compress]];
+        sf.preflag = g.preflag;
+        sf.illegal_long = [7; 22];
+        sf.illegal_short = [7; 13];
+        if g.block_type == 2 {
+            let mut first = 0;
+            if g.mixed {
+                for value in &mut sf.long[..8] {
+                    *value = r.read(slen[0]) as i32;
+                }
+                first = 3;
+            }
+            for sfb in first..12 {
+                let bits = slen[usize::from(sfb >= 6)];
+                for value in &mut sf.short[sfb] {
+                    *value = r.read(bits) as i32;
+                }
+            }
+            sf.short[12] = [0; 3];
+        } else {
+            const GROUPS: [Range<usize>; 4] = [0..6, 6..11, 11..16, 16..21];
+            for (group, bands) in GROUPS.into_iter().enumerate() {
+                // The second granule may reuse the first's scalefactors.
+                if second && scfsi[group] {
+                    continue;
+                }
+                let bits = slen[usize::from(group >= 2)];
+                for value in &mut sf.long[bands] {
+                    *value = r.read(bits) as i32;
+                }
+            }
+            sf.long[21] = 0;
+        }
+    }
+
+    /// MPEG-2 scalefactors, whose bit widths and band groups both come from
+    /// `scalefac_compress`.
+    fn read_lsf_scalefactors(
+        &mut self,
+        r: &mut BitReader<'_>,
+        g: &Granule,
+        intensity_right: bool,
+        ch: usize,
+    ) {
+        const BANDS: [[[usize; 4]; 3]; 6] = [
+            [[6, 5, 5, 5], [9, 9, 9, 9], [6, 9, 9, 9]],
+            [[6, 5, 7, 3], [9, 9, 12, 6], [6, 9, 12, 6]],
+            [[11, 10, 0, 0], [18, 18, 0, 0], [15, 18, 0, 0]],
+            [[7, 7, 7, 0], [12, 12, 12, 0], [6, 15, 12, 0]],
+            [[6, 6, 6, 3], [12, 9, 9, 6], [6, 12, 9, 6]],
+            [[8, 8, 5, 0], [15, 12, 9, 0], [6, 18, 9, 0]],
+        ];
+        let sfc = g.scalefac_compress;
+        let (slen, table, preflag) = if intensity_right {
+            let sfc = sfc >> 1;
+            if sfc < 180 {
+                ([sfc / 36, sfc % 36 / 6, sfc % 6, 0], 3, false)
+            } else if sfc < 244 {
+                let sfc = sfc - 180;
+                ([(sfc & 63) >> 4, (sfc & 15) >> 2, sfc & 3, 0], 4, false)
+            } else {
+                let sfc = sfc - 244;
+                ([sfc / 3, sfc % 3, 0, 0], 5, false)
+            }
+        } else if sfc < 400 {
+            (
+                [(sfc >> 4) / 5, (sfc >> 4) % 5, (sfc & 15) >> 2, sfc & 3],
+                0,
+                false,
+            )
+        } else if sfc < 500 {
+            let sfc = sfc - 400;
+            ([(sfc >> 2) / 5, (sfc >> 2) % 5, sfc & 3, 0], 1, false)
+        } else {
+            let sfc = sfc - 500;
+            ([sfc / 3, sfc % 3, 0, 0], 2, true)
+        };
+        let block = match (g.block_type, g.mixed) {
+            (2, false) => 1,
+            (2, true) => 2,
+            _ => 0,
+        };
+
+        let sf = &mut self.scalefactors[ch];
+        *sf = Scalefactors {
+            preflag,
+            ..Scalefactors::default()
+        };
+        // Values run through the bands in order; short bands take three
+        // values, one per window, and mixed blocks start with six long bands.
+        let mut k = 0;
+        for (&count, &bits) in BANDS[table][block].iter().zip(&slen) {
+            let illegal = (1 << bits) - 1;
+            for _ in 0..count {
+                let value = r.read(bits as u32) as i32;
+                let (long, short) = match block {
+                    0 => (Some(k), None),
+                    1 => (None, Some(k)),
+                    _ if k < 6 => (Some(k), None),
+                    _ => (None, Some(k + 3)),
+                };
+                if let Some(sfb) = long {
+                    sf.long[sfb] = value;
+                    sf.illegal_long[sfb] = illegal;
+                }
+                if let Some(k) = short {
+                    sf.short[k / 3][k % 3] = value;
+                    sf.illegal_short[k / 3] = illegal;
+                }
+                k += 1;
+            }
+        }
+    }
+
+    fn read_huffman(&mut self, r: &mut BitReader<'_>, g: &Granule, end: usize, ch: usize) {
+        let values = &mut self.values[ch];
+        let big = g.big_values * 2;
+        let mut i = 0;
+        for (region, &region_end) in [g.region_ends[0], g.region_ends[1], 576].iter().enumerate() {
+            let region_end = region_end.min(big);
+            let Some(table) = huffman::pair_table(g.table_select[region]) else {
+                i = i.max(region_end);
+                continue;
+            };
+            while i < region_end {
+                let symbol = table.tree.decode(r);
+                for (j, mut v) in [symbol / table.width, symbol % table.width]
+                    .into_iter()
+                    .enumerate()
+                {
+                    if table.linbits > 0 && v == 15 {
+                        v += r.read(table.linbits) as usize;
+                    }
+                    values[i + j] = match (v, v != 0 && r.bit()) {
+                        (v, true) => -(v as i32),
+                        (v, false) => v as i32,
+                    };
+                }
+                i += 2;
+            }
+        }
+
+        // The count1 region: quadruples of -1, 0 and 1 until the granule's
+        // bits run out.
+        let quad = huffman::quad_table();
+        while i + 4 <= 576 && r.position() < end {
+            let symbol = if g.count1_table {
+                15 - r.read(4) as usize
+            } else {
+                quad.decode(r)
+            };
+            for j in 0..4 {
+                values[i + j] = if symbol & (8 >> j) == 0 {
+                    0
+                } else if r.bit() {
+                    -1
+                } else {
+                    1
+                };
+            }
+            if r.position() > end {
+                // The last quadruple overran the granule: discard it.
+                values[i..i + 4].fill(0);
+                break;
+            }
+            i += 4;
+        }
+    }
+
+    fn requantise(&mut self, header: &Header, g: &Granule, ch: usize) {
+        let pow43 = pow43();
+        let sf = &self.scalefactors[ch];
+        let values = &self.values[ch];
+        let xr = &mut self.xr[ch];
+        let long = &LONG_BANDS[header.rate_index];
+        let short = &SHORT_BANDS[header.rate_index];
+        let shift = if g.scalefac_scale { 4 } else { 2 };
+        let base = g.global_gain - 210;
+        let mut scale = |lines: Range<usize>, exponent: i32| {
+            let gain = 2f32.powf(exponent as f32 * 0.25);
+            for i in lines {
+                let v = values[i];
+                let magnitude = pow43[(v.unsigned_abs() as usize).min(pow43.len() - 1)] * gain;
+                xr[i] = if v < 0 { -magnitude } else { magnitude };
+            }
+        };
+
+        let long_end = match (g.block_type, g.mixed) {
+            (2, false) => 0,
+            (2, true) => 3 * short[3],
+            _ => 576,
+        };
+        for sfb in 0..22 {
+            if long[sfb] >= long_end {
+                break;
+            }
+            let pre = if sf.preflag { PRETAB[sfb] } else { 0 };
+            let exponent = base - shift * (sf.long[sfb] + pre);
+            scale(long[sfb]..long[sfb + 1].min(long_end), exponent);
+        }
+        if g.block_type == 2 {
+            let first = if g.mixed { 3 } else { 0 };
+            for sfb in first..13 {
+                let width = short[sfb + 1] - short[sfb];
+                for w in 0..3 {
+                    let start = 3 * short[sfb] + w * width;
+                    let exponent = base - 8 * g.subblock_gain[w] - shift * sf.short[sfb][w];
+                    scale(start..start + width, exponent);
+                }
+            }
+        }
+    }
+
+    /// Mid/side and intensity stereo. Intensity coding covers the bands
+    /// above the last non-zero value of the right channel.
+    fn stereo(&mut self, header: &Header, right: &Granule) {
+        let mid_side = header.mode_extension & 2 != 0;
+        let intensity = header.mode_extension & 1 != 0;
+        if !intensity {
+            if mid_side {
+                self.mid_side(0..576);
+            }
+            return;
+        }
+
+        let sf = self.scalefactors[1];
+        if right.block_type == 2 {
+            let short = &SHORT_BANDS[header.rate_index];
+            let first = if right.mixed { 3 } else { 0 };
+            if right.mixed && mid_side {
+                self.mid_side(0..3 * short[3]);
+            }
+            for w in 0..3 {
+                let lines = |sfb: usize| {
+                    let width = short[sfb + 1] - short[sfb];
+                    let start = 3 * short[sfb] + w * width;
+                    start..start + width
+                };
+                let bound = (first..13)
+                    .rev()
+                    .find(|&sfb| self.xr[1][lines(sfb)].iter().any(|&x| x != 0.0))
+                    .map_or(first, |sfb| sfb + 1);
+                for sfb in first..13 {
+                    if sfb < bound {
+                        if mid_side {
+                            self.mid_side(lines(sfb));
+                        }
+                    } else {
+                        let band = sfb.min(11);
+                        let position = sf.short[band][w];
+                        self.intensity(
+                            header,
+                            right,
+                            lines(sfb),
+                            position,
+                            sf.illegal_short[band],
+                            mid_side,
+                        );
+                    }
+                }
+            }
+        } else {
+            let long = &LONG_BANDS[header.rate_index];
+            let last = self.xr[1]
+                .iter()
+                .rposition(|&x| x != 0.0)
+                .map_or(0, |i| i + 1);
+            for sfb in 0..22 {
+                let lines = long[sfb]..long[sfb + 1];
+                if long[sfb] < last {
+                    if mid_side {
+                        self.mid_side(lines);
+                    }
+                } else {
+                    let band = sfb.min(20);
+                    let position = sf.long[band];
+                    self.intensity(
+                        header,
+                        right,
+                        lines,
+                        position,
+                        sf.illegal_long[band],
+                        mid_side,
+                    );
+                }
+            }
+        }
+    }
+
+    fn mid_side(&mut self, lines: Range<usize>) {
+        let [left, right] = &mut self.xr;
+        for i in lines {
+            let (m, s) = (left[i], right[i]);
+            left[i] = (m + s) * std::f32::consts::FRAC_1_SQRT_2;
+            right[i] = (m - s) * std::f32::consts::FRAC_1_SQRT_2;
+        }
+    }
+
+    fn intensity(
+        &mut self,
+        header: &Header,
+        right: &Granule,
+        lines: Range<usize>,
+        position: i32,
+        illegal: i32,
+        mid_side: bool,
+    ) {
+        let (kl, kr) = if header.version == Version::Mpeg1 {
+            if position >= 7 {
+                (None, None)
+            } else {
+                let angle = f64::from(position) * PI / 12.0;
+                let (s, c) = angle.sin_cos();
+                (Some((s / (s + c)) as f32), Some((c / (s + c)) as f32))
+            }
+        } else if position == illegal {
+            (None, None)
+        } else {
+            let io: f32 = if right.scalefac_compress & 1 == 1 {
+                std::f32::consts::FRAC_1_SQRT_2
+            } else {
+                2f32.powf(-0.25)
+            };
+            if position == 0 {
+                (Some(1.0), Some(1.0))
+            } else if position % 2 == 1 {
+                (Some(io.powi((position + 1) / 2)), Some(1.0))
+            } else {
+                (Some(1.0), Some(io.powi(position / 2)))
+            }
+        };
+        let (Some(kl), Some(kr)) = (kl, kr) else {
+            if mid_side {
+                self.mid_side(lines);
+            }
+            return;
+        };
+        let [left, right] = &mut self.xr;
+        for i in lines {
+            let l = left[i];
+            left[i] = l * kl;
+            right[i] = l * kr;
+        }
+    }
+
+    /// Reordering, alias reduction, IMDCT and polyphase synthesis for one
+    /// channel, leaving 576 samples in `pcm`.
+    fn hybrid(&mut self, header: &Header, g: &Granule, ch: usize) {
+        let tables = imdct_tables();
+        let xr = &mut self.xr[ch];
+        let short = &SHORT_BANDS[header.rate_index];
+        let long_subbands = match (g.block_type, g.mixed) {
+            (2, false) => 0,
+            (2, true) => 3 * short[3] / 18,
+            _ => 32,
+        };
+
+        if g.block_type == 2 {
+            // Short values arrive band by band, window by window; the IMDCT
+            // wants each subband's six lines per window interleaved.
+            let mut reordered = *xr;
+            let first = if g.mixed { 3 } else { 0 };
+            for sfb in first..13 {
+                let width = short[sfb + 1] - short[sfb];
+                for w in 0..3 {
+                    for j in 0..width {
+                        let line = short[sfb] + j;
+                        reordered[line / 6 * 18 + line % 6 * 3 + w] =
+                            xr[3 * short[sfb] + w * width + j];
+                    }
+                }
+            }
+            *xr = reordered;
+        }
+
+        for sb in 1..long_subbands {
+            for (i, &(cs, ca)) in tables.alias.iter().enumerate() {
+                let (a, b) = (xr[18 * sb - 1 - i], xr[18 * sb + i]);
+                xr[18 * sb - 1 - i] = a * cs - b * ca;
+                xr[18 * sb + i] = b * cs + a * ca;
+            }
+        }
+
+        let overlap = &mut self.overlap[ch];
+        let mut time = [0.0f32; 576];
+        for sb in 0..32 {
+            let input = &xr[18 * sb..18 * sb + 18];
+            let mut y = [0.0f32; 36];
+            let block_type = match (sb < long_subbands, g.block_type) {
+                (true, 2) => 0,
+                (true, t) => t,
+                (false, _) => 2,
+            };
+            if block_type == 2 {
+                for w in 0..3 {
+                    for (i, row) in tables.short.iter().enumerate() {
+                        let sum: f32 = (0..6).map(|k| input[3 * k + w] * row[k]).sum();
+                        y[6 + 6 * w + i] += sum * tables.short_window[i];
+                    }
+                }
+            } else {
+                let window = &tables.long_windows[usize::from(block_type)];
+                for (i, row) in tables.long.iter().enumerate() {
+                    let sum: f32 = input.iter().zip(row).map(|(x, c)| x * c).sum();
+                    y[i] = sum * window[i];
+                }
+            }
+            for i in 0..18 {
+                let mut v = y[i] + overlap[18 * sb + i];
+                // Odd subbands of the polyphase bank are frequency inverted.
+                if sb % 2 == 1 && i % 2 == 1 {
+                    v = -v;
+                }
+                time[18 * sb + i] = v;
+                overlap[18 * sb + i] = y[18 + i];
+            }
+        }
+
+        let synth = &mut self.synth[ch];
+        for t in 0..18 {
+            let subbands: [f32; 32] = std::array::from_fn(|sb| time[18 * sb + t]);
+            synth.process(&subbands, &mut self.pcm[ch][32 * t..32 * t + 32]);
+        }
+    }
+}
+
+/// `|v|^(4/3)` for every value the Huffman data can code.
+fn pow43() -> &'static [f32] {
+    static TABLE: OnceLock<Vec<f32>> = OnceLock::new();
+    TABLE.get_or_init(|| {
+        (0..8207)
+            .map(|v| (v as f64).powf(4.0 / 3.0) as f32)
+            .collect()
+    })
+}
+
+struct ImdctTables {
+    /// `cos(π/72 (2i + 1 + 18)(2k + 1))` for the 36-point IMDCT.
+    long: [[f32; 18]; 36],
+    /// `cos(π/24 (2i + 1 + 6)(2k + 1))` for the 12-point IMDCT.
+    short: [[f32; 6]; 12],
+    /// Windows for block types 0, 1 and 3; index 2 is unused.
+    long_windows: [[f32; 36]; 4],
+    short_window: [f32; 12],
+    /// Alias-reduction butterfly coefficients `(cs, ca)`.
+    alias: [(f32, f32); 8],
+}
+
+fn imdct_tables() -> &'static ImdctTables {
+    static TABLES: OnceLock<ImdctTables> = OnceLock::new();
+    TABLES.get_or_init(|| {
+        let sine = |i: usize, n: usize| ((i as f64 + 0.5) * PI / n as f64).sin() as f32;
+        // Start and stop windows bridge to the short window's slope.
+        let long_windows = [
+            std::array::from_fn(|i| sine(i, 36)),
+            std::array::from_fn(|i| match i {
+                0..=17 => sine(i, 36),
+                18..=23 => 1.0,
+                24..=29 => sine(i - 18, 12),
+                _ => 0.0,
+            }),
+            std::array::from_fn(|i| sine(i, 36)),
+            std::array::from_fn(|i| match i {
+                0..=5 => 0.0,
+                6..=11 => sine(i - 6, 12),
+                12..=17 => 1.0,
+                _ => sine(i, 36),
+            }),
+        ];
+        const ALIAS: [f64; 8] = [
+            -0.6, -0.535, -0.33, -0.185, -0.095, -0.041, -0.0142, -0.0037,
+        ];
+        ImdctTables {
+            long: std::array::from_fn(|i| {
+                std::array::from_fn(|k| {
+                    (PI / 72.0 * (2 * i + 1 + 18) as f64 * (2 * k + 1) as f64).cos() as f32
+                })
+            }),
+            short: std::array::from_fn(|i| {
+                std::array::from_fn(|k| {
+                    (PI / 24.0 * (2 * i + 1 + 6) as f64 * (2 * k + 1) as f64).cos() as f32
+                })
+            }),
+            long_windows,
+            short_window: std::array::from_fn(|i| sine(i, 12)),
+            alias: ALIAS.map(|c| {
+                let norm = (1.0 + c * c).sqrt();
+                ((1.0 / norm) as f32, (c / norm) as f32)
+            }),
+        }
+    })
+}
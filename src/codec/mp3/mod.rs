@@ -0,0 +1,333 @@
+//! MPEG-1, MPEG-2 and MPEG-2.5 Layer III decoder.
+//!
+//! Handles every stereo mode, block switching, mixed blocks and the bit
+//! reservoir. A leading Xing/Info frame is not decoded as audio; when it
+//! carries a LAME tag, the encoder delay and padding it records are trimmed
+//! so loops stay gapless.
+
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::Path;
+
+use super::{DecodeError, StreamDecoder};
+use crate::sample::Sample;
+
+mod bits;
+mod frame;
+mod huffman;
+mod layer3;
+mod synth;
+
+use frame::{Header, SideInfo};
+use layer3::Layer3;
+
+/// Samples of delay the decoder's filterbanks add, which LAME's delay
+/// figure leaves out.
+const DECODER_DELAY: usize = 529;
+
+/// The largest `main_data_begin`, and so the most reservoir to keep.
+const MAX_RESERVOIR: usize = 511;
+
+/// Reads and decodes an MP3 file from disk.
+pub fn load(path: impl AsRef<Path>) -> Result<Sample, DecodeError> {
+    let mut decoder = Decoder::new(BufReader::new(File::open(path)?))?;
+    super::read_to_end(&mut decoder)
+}
+
+/// Decodes an in-memory MP3 file into an interleaved [`Sample`].
+pub fn decode(bytes: &[u8]) -> Result<Sample, DecodeError> {
+    super::read_to_end(&mut Decoder::new(bytes)?)
+}
+
+/// Incremental MP3 decoder.
+pub struct Decoder<R> {
+    frames: FrameReader<R>,
+    /// Header of the first frame.
+    header: Header,
+    /// The first audio frame, read while looking for a Xing frame.
+    pending: Option<(Header, Vec<u8>)>,
+    reservoir: Vec<u8>,
+    layer3: Box<Layer3>,
+
+    /// Frames still to drop from the start of the output.
+    skip: usize,
+    /// Frames still to output, when the LAME tag gives the length.
+    remaining: Option<u64>,
+    total: Option<u64>,
+    /// Decoded, trimmed, interleaved frames not yet read.
+    output: Vec<f32>,
+    output_read: usize,
+    finished: bool,
+}
+
+impl<R: Read> Decoder<R> {
+    /// Skips any ID3v2 tag and reads the first frame, taking gapless
+    /// information from it if it is a Xing/Info frame.
+    pub fn new(reader: R) -> Result<Self, DecodeError> {
+        let mut frames = FrameReader {
+            reader,
+            input: Vec::new(),
+            pos: 0,
+            first: None,
+        };
+        frames.skip_id3()?;
+        let Some((header, frame)) = frames.next()? else {
+            return Err(DecodeError::Malformed("MP3 stream: no Layer III frames"));
+        };
+        let mut decoder = Decoder {
+            frames,
+            header,
+            pending: None,
+            reservoir: Vec::new(),
+            layer3: Box::new(Layer3::new()),
+            skip: 0,
+            remaining: None,
+            total: None,
+            output: Vec::new(),
+            output_read: 0,
+            finished: false,
+        };
+        match info_frame(&header, &frame) {
+            Some(InfoFrame {
+                frames,
+                gapless: Some((delay, padding)),
+            }) => {
+                decoder.skip = delay + DECODER_DELAY;
+                if let Some(frames) = frames {
+                    let samples = frames * (576 * header.granules()) as u64;
+                    let total = samples.saturating_sub((delay + padding) as u64);
+                    decoder.remaining = Some(total);
+                    decoder.total = Some(total);
+                }
+            }
+            Some(_) => {}
+            None => decoder.pending = Some((header, frame)),
+        }
+        Ok(decoder)
+    }
+
+    /// Decodes frames until there is output or the stream ends.
+    fn fill(&mut self) -> Result<(), DecodeError> {
+        self.output.clear();
+        self.output_read = 0;
+        let channels = self.header.channels();
+        while self.output.is_empty() && !self.finished {
+            let next = match self.pending.take() {
+                Some(frame) => Some(frame),
+                None => self.frames.next()?,
+            };
+            let Some((header, frame)) = next else {
+                self.finished = true;
+                break;
+            };
+            let side_start = header.side_info_start();
+            let main_start = side_start + header.side_info_size();
+            if frame.len() < main_start {
+                return Err(DecodeError::Malformed(
+                    "MP3 frame: too short for side information",
+                ));
+            }
+            let side = SideInfo::read(&header, &frame[side_start..main_start]);
+
+            // Main data can begin in earlier frames: append this frame's to
+            // the reservoir and look back `main_data_begin` bytes.
+            let available = self.reservoir.len();
+            self.reservoir.extend_from_slice(&frame[main_start..]);
+            let main = available
+                .checked_sub(side.main_data_begin)
+                .map(|start| &self.reservoir[start..]);
+            self.layer3.decode(&header, &side, main, &mut self.output);
+            let excess = self.reservoir.len().saturating_sub(MAX_RESERVOIR);
+            self.reservoir.drain(..excess);
+
+            let skip = self.skip.min(self.output.len() / channels);
+            self.output.drain(..skip * channels);
+            self.skip -= skip;
+            if let Some(remaining) = self.remaining.as_mut() {
+                let frames = (self.output.len() / channels).min(*remaining as usize);
+                self.output.truncate(frames * channels);
+                *remaining -= frames as u64;
+                if *remaining == 0 {
+                    self.finished = true;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Splits the input into frames.
+struct FrameReader<R> {
+    reader: R,
+    /// Bytes read and not yet consumed, from `pos`.
+    input: Vec<u8>,
+    pos: usize,
+    /// Header of the first frame; later frames must match it.
+    first: Option<Header>,
+}
+
+impl<R: Read> FrameReader<R> {
+    fn skip_id3(&mut self) -> Result<(), DecodeError> {
+        if !self.fill(10)? || &self.input[self.pos..self.pos + 3] != b"ID3" {
+            return Ok(());
+        }
+        let header = &self.input[self.pos..self.pos + 10];
+        let size = header[6..10]
+            .iter()
+            .fold(0usize, |size, &b| (size << 7) | usize::from(b & 0x7f));
+        let footer = if header[5] & 0x10 != 0 { 10 } else { 0 };
+        let mut left = 10 + size + footer;
+        while left > 0 {
+            if !self.fill(1)? {
+                return Err(DecodeError::Truncated("ID3 tag"));
+            }
+            let n = left.min(self.input.len() - self.pos);
+            self.pos += n;
+            left -= n;
+        }
+        Ok(())
+    }
+
+    /// Makes at least `n` unconsumed bytes available. Returns false if the
+    /// stream ends first.
+    fn fill(&mut self, n: usize) -> Result<bool, DecodeError> {
+        if self.input.len() - self.pos >= n {
+            return Ok(true);
+        }
+        self.input.drain(..self.pos);
+        self.pos = 0;
+        let mut chunk = [0u8; 4096];
+        while self.input.len() < n {
+            match self.reader.read(&mut chunk) {
+                Ok(0) => return Ok(false),
+                Ok(read) => self.input.extend_from_slice(&chunk[..read]),
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok(true)
+    }
+
+    fn header_at(&self, offset: usize) -> Option<Header> {
+        let at = self.pos + offset;
+        Header::parse(self.input.get(at..at + 4)?.try_into().ok()?)
+    }
+
+    /// Finds the next frame, skipping anything that is not a header
+    /// matching the stream. Until the first frame is found, a header only
+    /// counts if another follows it or the stream ends with its frame.
+    fn next(&mut self) -> Result<Option<(Header, Vec<u8>)>, DecodeError> {
+        loop {
+            if !self.fill(4)? {
+                return Ok(None);
+            }
+            let header = match self.header_at(0) {
+                Some(h) if self.first.is_none_or(|first| first.matches(&h)) => h,
+                _ => {
+                    self.pos += 1;
+                    continue;
+                }
+            };
+            let size = header.frame_size();
+            if self.first.is_none() {
+                let complete = self.fill(size)?;
+                let followed = !self.fill(size + 4)?
+                    || self
+                        .header_at(size)
+                        .is_some_and(|next| next.matches(&header));
+                if !complete || !followed {
+                    self.pos += 1;
+                    continue;
+                }
+                self.first = Some(header);
+            } else if !self.fill(size)? {
+                return Err(DecodeError::Truncated("MP3 frame"));
+            }
+            let frame = self.input[self.pos..self.pos + size].to_vec();
+            self.pos += size;
+            return Ok(Some((header, frame)));
+        }
+    }
+}
+
+/// What a Xing, Info or VBRI frame says about the stream.
+struct InfoFrame {
+    /// Audio frames, not counting the info frame.
+    frames: Option<u64>,
+    /// Encoder delay and padding from a LAME tag.
+    gapless: Option<(usize, usize)>,
+}
+
+/// Recognises a frame that holds stream information instead of audio.
+fn info_frame(header: &Header, frame: &[u8]) -> Option<InfoFrame> {
+    if frame.get(36..40) == Some(b"VBRI") {
+        return Some(InfoFrame {
+            frames: None,
+            gapless: None,
+        });
+    }
+    let tag = frame.get(header.side_info_start() + header.side_info_size()..)?;
+    if !(tag.starts_with(b"Xing") || tag.starts_with(b"Info")) {
+        return None;
+    }
+    let be32 = |at: usize| -> Option<u32> {
+        Some(u32::from_be_bytes(tag.get(at..at + 4)?.try_into().ok()?))
+    };
+    let flags = be32(4)?;
+    let mut at = 8;
+    let mut frames = None;
+    if flags & 1 != 0 {
+        frames = Some(u64::from(be32(at)?));
+        at += 4;
+    }
+    // Byte count, seek table and quality.
+    for (flag, size) in [(2, 4), (4, 100), (8, 4)] {
+        if flags & flag != 0 {
+            at += size;
+        }
+    }
+    let lame = tag.get(at..at + 24);
+    let gapless = lame
+        .filter(|l| [&b"LAME"[..], b"Lavf", b"Lavc"].contains(&&l[..4]))
+        .map(|l| {
+            let (a, b, c) = (usize::from(l[21]), usize::from(l[22]), usize::from(l[23]));
+            ((a << 4) | (b >> 4), ((b & 15) << 8) | c)
+        });
+    Some(InfoFrame { frames, gapless })
+}
+
+impl<R: Read> StreamDecoder for Decoder<R> {
+    fn channels(&self) -> usize {
+        self.header.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.header.sample_rate
+    }
+
+    fn frames(&self) -> Option<u64> {
+        self.total
+    }
+
+    fn read(&mut self, out: &mut [f32]) -> Result<usize, DecodeError> {
+        let channels = self.channels();
+        let wanted = out.len() / channels;
+        let mut written = 0;
+        while written < wanted {
+            if self.output_read == self.output.len() {
+                if self.finished {
+                    break;
+                }
+                self.fill()?;
+                continue;
+            }
+            let available = (self.output.len() - self.output_read) / channels;
+            let n = available.min(wanted - written);
+            let src = &self.output[self.output_read..self.output_read + n * channels];
+            out[written * channels..(written + n) * channels].copy_from_slice(src);
+            self.output_read += n * channels;
+            written += n;
+        }
+        Ok(written)
+    }
+}
@@ -0,0 +1,144 @@
+//! Polyphase synthesis filterbank (ISO/IEC 11172-3 clause 2.4.3.2.2).
+
+use std::f64::consts::PI;
+use std::sync::OnceLock;
+
+/// Turns 32 subband samples per time slot back into 32 PCM samples.
+pub(super) struct Synth {
+    /// The standard's V vector as a ring buffer, newest slot at `offset`.
+    v: [f32; 1024],
+    offset: usize,
+}
+
+impl Synth {
+    pub fn new() -> Self {
+        Synth {
+            v: [0.0; 1024],
+            offset: 0,
+        }
+    }
+
+    pub fn process(&mut self, subbands: &[f32; 32], out: &mut [f32]) {
+        let tables = tables();
+        self.offset = (self.offset + 1024 - 64) % 1024;
+        for (i, row) in tables.matrix.iter().enumerate() {
+            self.v[self.offset + i] = row.iter().zip(subbands).map(|(n, s)| n * s).sum();
+        }
+        let v = |n: usize| self.v[(self.offset + n) % 1024];
+        for (j, sample) in out.iter_mut().take(32).enumerate() {
+            let mut sum = 0.0;
+            for i in 0..8 {
+                sum += tables.window[64 * i + j] * v(128 * i + j);
+                sum += tables.window[64 * i + 32 + j] * v(128 * i + 96 + j);
+            }
+            *sample = sum;
+        }
+    }
+}
+
+struct Tables {
+    /// `cos((16 + i)(2k + 1)π/64)` for the 64 outputs of each slot.
+    matrix: [[f32; 32]; 64],
+    /// The synthesis window D.
+    window: [f32; 512],
+}
+
+fn tables() -> &'static Tables {
+    static TABLES: OnceLock<Tables> = OnceLock::new();
+    TABLES.get_or_init(|| {
+        let matrix = std::array::from_fn(|i| {
+            std::array::from_fn(|k| ((16 + i) as f64 * (2 * k + 1) as f64 * PI / 64.0).cos() as f32)
+        });
+        // The window is symmetric about 256 once the sign flip every 64
+        // taps is taken out.
+        let window = std::array::from_fn(|n| {
+            let value = if n <= 256 {
+                WINDOW_HALF[n]
+            } else {
+                let m = 512 - n;
+                let flip = (m / 64 + n / 64) % 2 == 1;
+                if flip {
+                    -WINDOW_HALF[m]
+                } else {
+                    WINDOW_HALF[m]
+                }
+            };
+            value as f32 / 65536.0
+        });
+        Tables { matrix, window }
+    })
+}
+
+/// The first 257 synthesis window coefficients (table B.3), in units of
+/// 2^-16.
+const WINDOW_HALF: [i32; 257] = [
+    0, -1, -1, -1, -1, -1, -1, -2, -2, -2, -2, -3, -3, -4, -4, -5, -5, -6, -7, -7, -8, -9, -10,
+    -11, -13, -14, -16, -17, -19, -21, -24, -26, -29, -31, -35, -38, -41, -45, -49, -53, -58, -63,
+    -68, -73, -79, -85, -91, -97, -104, -111, -117, -125, -132, -139, -147, -154, -161, -169, -176,
+    -183, -190, -196, -202, -208, 213, 218, 222, 225, 227, 228, 228, 227, 224, 221, 215, 208, 200,
+    189, 177, 163, 146, 127, 106, 83, 57, 29, -2, -36, -72, -111, -153, -197, -244, -294, -347,
+    -401, -459, -519, -581, -645, -711, -779, -848, -919, -991, -1064, -1137, -1210, -1283, -1356,
+    -1428, -1498, -1567, -1634, -1698, -1759, -1817, -1870, -1919, -1962, -2001, -2032, -2057,
+    -2075, -2085, -2087, -2080, -2063, 2037, 2000, 1952, 1893, 1822, 1739, 1644, 1535, 1414, 1280,
+    1131, 970, 794, 605, 402, 185, -45, -288, -545, -814, -1095, -1388, -1692, -2006, -2330, -2663,
+    -3004, -3351, -3705, -4063, -4425, -4788, -5153, -5517, -5879, -6237, -6589, -6935, -7271,
+    -7597, -7910, -8209, -8491, -8755, -8998, -9219, -9416, -9585, -9727, -9838, -9916, -9959,
+    -9966, -9935, -9863, -9750, -9592, -9389, -9139, -8840, -8492, -8092, -7640, -7134, 6574, 5959,
+    5288, 4561, 3776, 2935, 2037, 1082, 70, -998, -2122, -3300, -4533, -5818, -7154, -8540, -9975,
+    -11455, -12980, -14548, -16155, -17799, -19478, -21189, -22929, -24694, -26482, -28289, -30112,
+    -31947, -33791, -35640, -37489, -39336, -41176, -43006, -44821, -46617, -48390, -50137, -51853,
+    -53534, -55178, -56778, -58333, -59838, -61289, -62684, -64019, -65290, -66494, -67629, -68692,
+    -69679, -70590, -71420, -72169, -72835, -73415, -73908, -74313, -74630, -74856, -74992, 75038,
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The matching analysis filterbank, with window `C = D / 32`.
+    fn analyse(signal: &[f32]) -> Vec<[f32; 32]> {
+        let window = &tables().window;
+        let mut x = [0.0f32; 512];
+        signal
+            .chunks(32)
+            .map(|block| {
+                x.copy_within(0..480, 32);
+                for (i, &s) in block.iter().enumerate() {
+                    x[31 - i] = s;
+                }
+                let y: Vec<f32> = (0..64)
+                    .map(|i| {
+                        (0..8)
+                            .map(|j| window[i + 64 * j] / 32.0 * x[i + 64 * j])
+                            .sum()
+                    })
+                    .collect();
+                std::array::from_fn(|i| {
+                    y.iter()
+                        .enumerate()
+                        .map(|(k, y)| {
+                            y * ((2 * i + 1) as f64 * (k as f64 - 16.0) * PI / 64.0).cos() as f32
+                        })
+                        .sum()
+                })
+            })
+            .collect()
+    }
+
+    #[test]
+    fn reconstructs_the_analysis_input() {
+        let signal: Vec<f32> = (0..32 * 80)
+            .map(|n| 0.5 * (0.05 * n as f32).sin() + 0.3 * (0.31 * n as f32).sin())
+            .collect();
+        let mut synth = Synth::new();
+        let mut out = vec![0.0; signal.len()];
+        for (slot, subbands) in analyse(&signal).iter().enumerate() {
+            synth.process(subbands, &mut out[slot * 32..]);
+        }
+        // Analysis and synthesis together delay the signal by 481 samples.
+        for (n, &expected) in signal.iter().enumerate().take(signal.len() - 481).skip(600) {
+            let got = out[n + 481];
+            assert!((got - expected).abs() < 2e-4, "{n}: {got} != {expected}");
+        }
+    }
+}
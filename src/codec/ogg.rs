@@ -0,0 +1,185 @@
+//! Ogg container: pages are read, checked and reassembled into packets.
+
+use std::collections::VecDeque;
+use std::io::{self, Read};
+
+use super::DecodeError;
+
+/// One packet of the logical stream.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Packet {
+    pub data: Vec<u8>,
+    /// Granule position of the page, on the last packet that completes on it.
+    pub granule: Option<u64>,
+    /// This is the final packet of the stream.
+    pub last: bool,
+}
+
+/// Reads the packets of the first logical stream in a physical Ogg stream.
+/// Pages of other multiplexed streams are skipped.
+pub(crate) struct PacketReader<R> {
+    reader: R,
+    serial: Option<u32>,
+    /// A packet continuing onto the next page.
+    partial: Vec<u8>,
+    done: bool,
+}
+
+impl<R: Read> PacketReader<R> {
+    pub(crate) fn new(reader: R) -> Self {
+        PacketReader {
+            reader,
+            serial: None,
+            partial: Vec::new(),
+            done: false,
+        }
+    }
+
+    /// Reads pages until at least one packet completes, and appends every
+    /// packet completed on that page to `out`. Returns false at the end of
+    /// the stream.
+    pub(crate) fn read_page(&mut self, out: &mut VecDeque<Packet>) -> Result<bool, DecodeError> {
+        while !self.done {
+            let Some(page) = self.next_page()? else {
+                if self.serial.is_some() && !self.partial.is_empty() {
+                    return Err(DecodeError::Truncated("Ogg packet"));
+                }
+                self.done = true;
+                break;
+            };
+            if *self.serial.get_or_insert(page.serial) != page.serial {
+                continue;
+            }
+            if !page.continued && !self.partial.is_empty() {
+                return Err(DecodeError::Malformed("Ogg page: missing continuation"));
+            }
+            // A stream cut mid-packet starts with the tail of a packet we
+            // never saw; drop it.
+            let mut skipping = page.continued && self.partial.is_empty();
+
+            let mut pos = 0;
+            let mut completed = 0;
+            for &lace in &page.lacing {
+                let end = pos + usize::from(lace);
+                if skipping {
+                    skipping = lace == 255;
+                    pos = end;
+                    continue;
+                }
+                self.partial.extend_from_slice(&page.body[pos..end]);
+                pos = end;
+                if lace < 255 {
+                    out.push_back(Packet {
+                        data: std::mem::take(&mut self.partial),
+                        granule: None,
+                        last: false,
+                    });
+                    completed += 1;
+                }
+            }
+            if page.last {
+                self.done = true;
+            }
+            if completed > 0 {
+                let packet = out.back_mut().expect("a packet completed");
+                packet.granule = page.granule;
+                packet.last = page.last;
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    fn next_page(&mut self) -> Result<Option<Page>, DecodeError> {
+        let mut header = [0u8; 27];
+        match read_exact_or_eof(&mut self.reader, &mut header)? {
+            0 => return Ok(None),
+            27 => {}
+            _ => return Err(DecodeError::Truncated("Ogg page header")),
+        }
+        if &header[..4] != b"OggS" {
+            return Err(DecodeError::Malformed("Ogg page: missing capture pattern"));
+        }
+        if header[4] != 0 {
+            return Err(DecodeError::Unsupported(format!(
+                "Ogg version {}",
+                header[4]
+            )));
+        }
+        let flags = header[5];
+        let granule = i64::from_le_bytes(header[6..14].try_into().expect("8 bytes"));
+        let serial = u32::from_le_bytes(header[14..18].try_into().expect("4 bytes"));
+        let crc = u32::from_le_bytes(header[22..26].try_into().expect("4 bytes"));
+
+        let mut lacing = vec![0u8; usize::from(header[26])];
+        self.reader
+            .read_exact(&mut lacing)
+            .map_err(|e| truncated(e, "Ogg page header"))?;
+        let len = lacing.iter().map(|&l| usize::from(l)).sum();
+        let mut body = vec![0u8; len];
+        self.reader
+            .read_exact(&mut body)
+            .map_err(|e| truncated(e, "Ogg page"))?;
+
+        header[22..26].fill(0);
+        let actual = [&header[..], &lacing, &body]
+            .iter()
+            .fold(0, |crc, part| crc32(crc, part));
+        if actual != crc {
+            return Err(DecodeError::Malformed("Ogg page: CRC mismatch"));
+        }
+        Ok(Some(Page {
+            continued: flags & 1 != 0,
+            last: flags & 4 != 0,
+            granule: (granule >= 0).then_some(granule as u64),
+            serial,
+            lacing,
+            body,
+        }))
+    }
+}
+
+struct Page {
+    continued: bool,
+    last: bool,
+    granule: Option<u64>,
+    serial: u32,
+    lacing: Vec<u8>,
+    body: Vec<u8>,
+}
+
+/// Fills `buf`, returning how many bytes were read before the end of input.
+fn read_exact_or_eof(reader: &mut impl Read, buf: &mut [u8]) -> Result<usize, DecodeError> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(filled)
+}
+
+fn truncated(e: io::Error, what: &'static str) -> DecodeError {
+    if e.kind() == io::ErrorKind::UnexpectedEof {
+        DecodeError::Truncated(what)
+    } else {
+        DecodeError::Io(e)
+    }
+}
+
+fn crc32(mut crc: u32, data: &[u8]) -> u32 {
+    for &b in data {
+        crc ^= u32::from(b) << 24;
+        for _ in 0..8 {
+            crc = if crc & 0x8000_0000 != 0 {
+                (crc << 1) ^ 0x04c1_1db7
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
@@ -0,0 +1,61 @@
+//! LSB-first bit reader over one Vorbis packet.
+
+/// Reading past the end of the packet returns zeros and sets
+/// [`eop`](BitReader::eop), which the decoder checks where the specification
+/// gives the end-of-packet condition a meaning.
+pub(super) struct BitReader<'a> {
+    data: &'a [u8],
+    /// Position in bits.
+    pos: usize,
+    eop: bool,
+}
+
+impl<'a> BitReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        BitReader {
+            data,
+            pos: 0,
+            eop: false,
+        }
+    }
+
+    /// True once a read ran past the end of the packet.
+    pub fn eop(&self) -> bool {
+        self.eop
+    }
+
+    /// The next `n` bits (up to 32) without consuming them, zero-padded
+    /// past the end.
+    pub fn peek(&self, n: u32) -> u32 {
+        if n == 0 {
+            return 0;
+        }
+        let byte = self.pos / 8;
+        let mut word = 0u64;
+        for i in 0..5 {
+            if let Some(&b) = self.data.get(byte + i) {
+                word |= u64::from(b) << (8 * i);
+            }
+        }
+        ((word >> (self.pos % 8)) & ((1u64 << n) - 1)) as u32
+    }
+
+    pub fn skip(&mut self, n: u32) {
+        self.pos += n as usize;
+        if self.pos > self.data.len() * 8 {
+            self.pos = self.data.len() * 8;
+            self.eop = true;
+        }
+    }
+
+    /// Reads `n` bits (up to 32) as an unsigned value.
+    pub fn read(&mut self, n: u32) -> u32 {
+        if self.pos + n as usize > self.data.len() * 8 {
+            self.skip(n);
+            return 0;
+        }
+        let v = self.peek(n);
+        self.pos += n as usize;
+        v
+    }
+}
@@ -0,0 +1,238 @@
+//! Floor decoding and curve synthesis.
+
+use std::f32::consts::PI;
+use std::sync::OnceLock;
+
+use super::bits::BitReader;
+use super::setup::{ilog, Codebook, Floor, Floor0, Floor1};
+
+/// One channel's floor as read from the current packet.
+#[derive(Default)]
+pub(super) struct FloorData {
+    /// False when the channel is silent in this packet.
+    pub used: bool,
+    /// Floor 1 Y values in coded order.
+    ys: Vec<i32>,
+    /// Floor 0 amplitude and LSP coefficients.
+    amplitude: u32,
+    coefficients: Vec<f32>,
+}
+
+impl Floor {
+    /// Reads the floor for one channel into `data`.
+    pub fn decode(&self, r: &mut BitReader<'_>, books: &[Codebook], data: &mut FloorData) {
+        data.used = match self {
+            Floor::Zero(f) => f.decode(r, books, data),
+            Floor::One(f) => f.decode(r, books, data),
+        } && !r.eop();
+    }
+
+    /// Multiplies `spectrum` (half a block) by the floor curve.
+    pub fn apply(&self, data: &FloorData, spectrum: &mut [f32]) {
+        match self {
+            Floor::Zero(f) => f.apply(data, spectrum),
+            Floor::One(f) => f.apply(data, spectrum),
+        }
+    }
+}
+
+impl Floor0 {
+    fn decode(&self, r: &mut BitReader<'_>, books: &[Codebook], data: &mut FloorData) -> bool {
+        data.amplitude = r.read(self.amplitude_bits);
+        if data.amplitude == 0 {
+            return false;
+        }
+        let Some(&book) = self
+            .books
+            .get(r.read(ilog(self.books.len() as u32)) as usize)
+        else {
+            return false;
+        };
+        data.coefficients.clear();
+        let mut last = 0.0;
+        while data.coefficients.len() < self.order {
+            let Some(v) = books[book].decode_vector(r) else {
+                return false;
+            };
+            data.coefficients.extend(v.iter().map(|x| x + last));
+            last = *data.coefficients.last().expect("vectors are not empty");
+        }
+        data.coefficients.truncate(self.order);
+        true
+    }
+
+    fn apply(&self, data: &FloorData, spectrum: &mut [f32]) {
+        let n = spectrum.len();
+        let bark =
+            |x: f32| 13.1 * (0.00074 * x).atan() + 2.24 * (1.85e-8 * x * x).atan() + 1e-4 * x;
+        let size = self.bark_map_size as f32;
+        let nyquist = bark(0.5 * self.rate as f32);
+        let map = |i: usize| {
+            let b = bark(self.rate as f32 * i as f32 / (2.0 * n as f32));
+            ((b * size / nyquist).floor() as u32).min(self.bark_map_size - 1)
+        };
+        let cos: Vec<f32> = data.coefficients.iter().map(|c| c.cos()).collect();
+        let max_amplitude = ((1u64 << self.amplitude_bits) - 1) as f32;
+
+        let mut i = 0;
+        while i < n {
+            let bin = map(i);
+            let w = (PI * bin as f32 / size).cos();
+            let product = |start: usize| {
+                cos.iter()
+                    .skip(start)
+                    .step_by(2)
+                    .map(|c| 4.0 * (c - w) * (c - w))
+                    .product::<f32>()
+            };
+            let (p, q) = if self.order % 2 == 1 {
+                ((1.0 - w * w) * product(1), 0.25 * product(0))
+            } else {
+                ((1.0 - w) / 2.0 * product(1), (1.0 + w) / 2.0 * product(0))
+            };
+            let offset = self.amplitude_offset as f32;
+            let value = (0.115_129_25
+                * (data.amplitude as f32 * offset / (max_amplitude * (p + q).sqrt()) - offset))
+                .exp();
+            while i < n && map(i) == bin {
+                spectrum[i] *= value;
+                i += 1;
+            }
+        }
+    }
+}
+
+impl Floor1 {
+    fn range(&self) -> i32 {
+        [256, 128, 86, 64][self.multiplier as usize - 1]
+    }
+
+    fn decode(&self, r: &mut BitReader<'_>, books: &[Codebook], data: &mut FloorData) -> bool {
+        if r.read(1) == 0 {
+            return false;
+        }
+        let bits = ilog(self.range() as u32 - 1);
+        data.ys.clear();
+        data.ys.push(r.read(bits) as i32);
+        data.ys.push(r.read(bits) as i32);
+        for &class in &self.partition_classes {
+            let class = &self.classes[class];
+            let mask = (1 << class.subclass_bits) - 1;
+            let mut value = match class.masterbook {
+                Some(book) => match books[book].decode(r) {
+                    Some(v) => v,
+                    None => return false,
+                },
+                None => 0,
+            };
+            for _ in 0..class.dimensions {
+                let y = match class.subclass_books[value & mask] {
+                    Some(book) => match books[book].decode(r) {
+                        Some(y) => y as i32,
+                        None => return false,
+                    },
+                    None => 0,
+                };
+                data.ys.push(y);
+                value >>= class.subclass_bits;
+            }
+        }
+        true
+    }
+
+    fn apply(&self, data: &FloorData, spectrum: &mut [f32]) {
+        let range = self.range();
+        let count = self.xs.len();
+        let x = |i: usize| self.xs[i] as i32;
+        let mut ys = [0i32; 65];
+        let mut step2 = [false; 65];
+        ys[0] = data.ys[0];
+        ys[1] = data.ys[1];
+        step2[0] = true;
+        step2[1] = true;
+        for i in 2..count {
+            let (low, high) = self.neighbors[i - 2];
+            let predicted = render_point(x(low), ys[low], x(high), ys[high], x(i));
+            let value = data.ys[i];
+            let high_room = range - predicted;
+            let low_room = predicted;
+            let room = 2 * high_room.min(low_room);
+            if value == 0 {
+                ys[i] = predicted;
+                continue;
+            }
+            step2[low] = true;
+            step2[high] = true;
+            step2[i] = true;
+            ys[i] = if value >= room {
+                if high_room > low_room {
+                    value - low_room + predicted
+                } else {
+                    predicted - value + high_room - 1
+                }
+            } else if value % 2 == 1 {
+                predicted - (value + 1) / 2
+            } else {
+                predicted + value / 2
+            };
+        }
+
+        let multiplier = self.multiplier as i32;
+        let n = spectrum.len() as i32;
+        let (mut lx, mut ly) = (0, ys[self.sorted[0]] * multiplier);
+        for &i in &self.sorted[1..] {
+            if step2[i] {
+                let (hx, hy) = (x(i), ys[i] * multiplier);
+                render_line(lx, ly, hx, hy, spectrum);
+                (lx, ly) = (hx, hy);
+            }
+        }
+        if lx < n {
+            render_line(lx, ly, n, ly, spectrum);
+        }
+    }
+}
+
+fn render_point(x0: i32, y0: i32, x1: i32, y1: i32, x: i32) -> i32 {
+    let dy = y1 - y0;
+    let offset = dy.abs() * (x - x0) / (x1 - x0);
+    if dy < 0 {
+        y0 - offset
+    } else {
+        y0 + offset
+    }
+}
+
+/// Multiplies `spectrum[x0..x1]` by the dB values along the line, clipped
+/// to the spectrum.
+fn render_line(x0: i32, y0: i32, x1: i32, y1: i32, spectrum: &mut [f32]) {
+    let table = inverse_db();
+    let dy = y1 - y0;
+    let adx = x1 - x0;
+    let base = dy / adx;
+    let step = if dy < 0 { base - 1 } else { base + 1 };
+    let ady = dy.abs() - base.abs() * adx;
+    let mut y = y0;
+    let mut err = 0;
+    for x in x0..x1.min(spectrum.len() as i32) {
+        if x > x0 {
+            err += ady;
+            if err >= adx {
+                err -= adx;
+                y += step;
+            } else {
+                y += base;
+            }
+        }
+        spectrum[x as usize] *= table[y.clamp(0, 255) as usize];
+    }
+}
+
+/// `floor1_inverse_dB_table`: 256 steps from about -140 dB to 0 dB.
+fn inverse_db() -> &'static [f32; 256] {
+    static TABLE: OnceLock<[f32; 256]> = OnceLock::new();
+    TABLE.get_or_init(|| {
+        let min = 1.064_986_3e-7f64;
+        std::array::from_fn(|i| (min * (-min.ln() * i as f64 / 255.0).exp()) as f32)
+    })
+}
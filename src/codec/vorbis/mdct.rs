@@ -0,0 +1,150 @@
+//! Inverse MDCT, computed through a DCT-IV and a quarter-length complex FFT.
+
+use std::f64::consts::PI;
+
+/// Inverse MDCT for one block size.
+pub(super) struct Imdct {
+    /// Block size: outputs per transform.
+    n: usize,
+    /// Pre-rotation `exp(-iπk / (n/2))`.
+    pre: Vec<(f32, f32)>,
+    /// Post-rotation `exp(-iπ(k + 1/4) / (n/2))`.
+    post: Vec<(f32, f32)>,
+    /// FFT twiddles `exp(-2πik / (n/4))` for the first half of the FFT.
+    twiddles: Vec<(f32, f32)>,
+    bit_reverse: Vec<usize>,
+    fft: Vec<(f32, f32)>,
+    dct: Vec<f32>,
+}
+
+impl Imdct {
+    /// `n` must be a power of two of at least 16.
+    pub fn new(n: usize) -> Self {
+        let m = n / 2;
+        let quarter = n / 4;
+        let rotation = |offset: f64| {
+            (0..quarter)
+                .map(|k| {
+                    let a = -PI * (k as f64 + offset) / m as f64;
+                    (a.cos() as f32, a.sin() as f32)
+                })
+                .collect()
+        };
+        let twiddles = (0..quarter / 2)
+            .map(|k| {
+                let a = -2.0 * PI * k as f64 / quarter as f64;
+                (a.cos() as f32, a.sin() as f32)
+            })
+            .collect();
+        let bits = quarter.trailing_zeros();
+        let bit_reverse = (0..quarter)
+            .map(|i| {
+                if bits == 0 {
+                    0
+                } else {
+                    i.reverse_bits() >> (usize::BITS - bits)
+                }
+            })
+            .collect();
+        Imdct {
+            n,
+            pre: rotation(0.0),
+            post: rotation(0.25),
+            twiddles,
+            bit_reverse,
+            fft: vec![(0.0, 0.0); quarter],
+            dct: vec![0.0; m],
+        }
+    }
+
+    /// Transforms `n/2` coefficients into `n` samples:
+    /// `y[i] = Σ x[k] cos(2π/n (i + 1/2 + n/4)(k + 1/2))`, the unscaled
+    /// form Vorbis encoders expect.
+    pub fn inverse(&mut self, x: &[f32], y: &mut [f32]) {
+        let m = self.n / 2;
+        let quarter = self.n / 4;
+        debug_assert_eq!(x.len(), m);
+        debug_assert_eq!(y.len(), self.n);
+
+        // DCT-IV of size m: u[j] = Σ x[k] cos(π/m (j + 1/2)(k + 1/2)).
+        for k in 0..quarter {
+            let (re, im) = (x[2 * k], x[m - 1 - 2 * k]);
+            let (c, s) = self.pre[k];
+            self.fft[self.bit_reverse[k]] = (re * c - im * s, re * s + im * c);
+        }
+        self.transform();
+        for k in 0..quarter {
+            let (re, im) = self.fft[k];
+            let (c, s) = self.post[k];
+            self.dct[2 * k] = re * c - im * s;
+            self.dct[m - 1 - 2 * k] = -(re * s + im * c);
+        }
+
+        // y[i] = u[i + m/2], extending u by its symmetries.
+        let half = m / 2;
+        for (i, out) in y.iter_mut().enumerate() {
+            let j = i + half;
+            *out = if j < m {
+                self.dct[j]
+            } else if j < 2 * m {
+                -self.dct[2 * m - 1 - j]
+            } else {
+                -self.dct[j - 2 * m]
+            };
+        }
+    }
+
+    /// In-place radix-2 FFT of `self.fft`, whose input is already in
+    /// bit-reversed order.
+    fn transform(&mut self) {
+        let len = self.fft.len();
+        let mut size = 2;
+        while size <= len {
+            let step = len / size;
+            for start in (0..len).step_by(size) {
+                for k in 0..size / 2 {
+                    let (c, s) = self.twiddles[k * step];
+                    let (br, bi) = self.fft[start + k + size / 2];
+                    let t = (br * c - bi * s, br * s + bi * c);
+                    let a = self.fft[start + k];
+                    self.fft[start + k] = (a.0 + t.0, a.1 + t.1);
+                    self.fft[start + k + size / 2] = (a.0 - t.0, a.1 - t.1);
+                }
+            }
+            size *= 2;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_the_direct_formula() {
+        for n in [16, 64, 256, 2048] {
+            let x: Vec<f32> = (0..n / 2)
+                .map(|k| ((k * 7919) % 23) as f32 / 23.0 - 0.5)
+                .collect();
+            let mut y = vec![0.0; n];
+            Imdct::new(n).inverse(&x, &mut y);
+            for (i, &got) in y.iter().enumerate() {
+                let expected: f64 = x
+                    .iter()
+                    .enumerate()
+                    .map(|(k, &v)| {
+                        f64::from(v)
+                            * (2.0 * PI / n as f64
+                                * (i as f64 + 0.5 + n as f64 / 4.0)
+                                * (k as f64 + 0.5))
+                                .cos()
+                    })
+                    .sum();
+                assert!(
+                    (f64::from(got) - expected).abs() < 1e-3,
+                    "n={n} i={i}: {got} != {expected}"
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,419 @@
+//! Ogg Vorbis decoder.
+//!
+//! A complete Vorbis I decoder: both floor types, all three residue types,
+//! channel coupling and block-size switching. Output is trimmed to the
+//! stream's granule positions, so leading priming samples and trailing
+//! padding never reach the decoded audio and loops stay gapless.
+
+use std::collections::VecDeque;
+use std::f32::consts::FRAC_PI_2;
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::Path;
+
+use super::ogg::{Packet, PacketReader};
+use super::{DecodeError, StreamDecoder};
+use crate::sample::Sample;
+
+mod bits;
+mod floor;
+mod mdct;
+mod residue;
+mod setup;
+
+use bits::BitReader;
+use floor::FloorData;
+use mdct::Imdct;
+use residue::Scratch;
+use setup::{ilog, Setup};
+
+/// Reads and decodes an Ogg Vorbis file from disk.
+pub fn load(path: impl AsRef<Path>) -> Result<Sample, DecodeError> {
+    let mut decoder = Decoder::new(BufReader::new(File::open(path)?))?;
+    super::read_to_end(&mut decoder)
+}
+
+/// Decodes an in-memory Ogg Vorbis file into an interleaved [`Sample`].
+pub fn decode(bytes: &[u8]) -> Result<Sample, DecodeError> {
+    super::read_to_end(&mut Decoder::new(bytes)?)
+}
+
+/// Incremental Ogg Vorbis decoder.
+pub struct Decoder<R> {
+    packets: PacketReader<R>,
+    queue: VecDeque<Packet>,
+    channels: usize,
+    sample_rate: u32,
+    block_sizes: [usize; 2],
+    setup: Setup,
+    imdct: [Imdct; 2],
+    /// Rising window halves for the short and long block sizes.
+    slopes: [Vec<f32>; 2],
+
+    floors: Vec<FloorData>,
+    skip: Vec<bool>,
+    spectrum: Vec<f32>,
+    residue: Vec<f32>,
+    scratch: Scratch,
+    /// Frames completed by the last packet, planar.
+    block: Vec<f32>,
+    /// The previous block's windowed samples, planar, and its size.
+    previous: Vec<f32>,
+    previous_size: Option<usize>,
+    /// The buffer the next block is transformed into.
+    spare: Vec<f32>,
+
+    /// Granule position of the next decoded frame. Frames before 0 are
+    /// encoder priming and are dropped. `None` until the first audio page.
+    position: Option<i64>,
+    /// Decoded, trimmed, interleaved frames not yet read.
+    output: Vec<f32>,
+    output_read: usize,
+    finished: bool,
+}
+
+impl<R: Read> Decoder<R> {
+    /// Reads the three Vorbis headers from the start of the stream.
+    pub fn new(reader: R) -> Result<Self, DecodeError> {
+        let mut packets = PacketReader::new(reader);
+        let mut queue = VecDeque::new();
+        while queue.len() < 3 {
+            if !packets.read_page(&mut queue)? {
+                return Err(DecodeError::Truncated("Vorbis headers"));
+            }
+        }
+        let headers: Vec<Packet> = queue.drain(..3).collect();
+
+        let id = &headers[0].data;
+        if id.len() < 30 || id[0] != 1 || &id[1..7] != b"vorbis" {
+            return Err(DecodeError::Malformed("Vorbis identification header"));
+        }
+        let version = u32::from_le_bytes(id[7..11].try_into().expect("4 bytes"));
+        if version != 0 {
+            return Err(DecodeError::Unsupported(format!(
+                "Vorbis version {version}"
+            )));
+        }
+        let channels = usize::from(id[11]);
+        let sample_rate = u32::from_le_bytes(id[12..16].try_into().expect("4 bytes"));
+        let block_sizes = [1usize << (id[28] & 15), 1usize << (id[28] >> 4)];
+        if channels == 0
+            || sample_rate == 0
+            || block_sizes[0] < 64
+            || block_sizes[1] > 8192
+            || block_sizes[0] > block_sizes[1]
+            || id[29] & 1 == 0
+        {
+            return Err(DecodeError::Malformed("Vorbis identification header"));
+        }
+        if headers[1].data.first() != Some(&3) || !headers[1].data[1..].starts_with(b"vorbis") {
+            return Err(DecodeError::Malformed("Vorbis comment header"));
+        }
+        let setup = Setup::read(&headers[2].data, channels)?;
+
+        let slope = |n: usize| {
+            let half = n / 2;
+            (0..half)
+                .map(|i| {
+                    let s = ((i as f32 + 0.5) / half as f32 * FRAC_PI_2).sin();
+                    (FRAC_PI_2 * s * s).sin()
+                })
+                .collect()
+        };
+        Ok(Decoder {
+            packets,
+            queue,
+            channels,
+            sample_rate,
+            block_sizes,
+            imdct: [Imdct::new(block_sizes[0]), Imdct::new(block_sizes[1])],
+            slopes: [slope(block_sizes[0]), slope(block_sizes[1])],
+            setup,
+            floors: (0..channels).map(|_| FloorData::default()).collect(),
+            skip: vec![false; channels],
+            spectrum: Vec::new(),
+            residue: Vec::new(),
+            scratch: Scratch::default(),
+            block: Vec::new(),
+            previous: Vec::new(),
+            previous_size: None,
+            spare: Vec::new(),
+            position: None,
+            output: Vec::new(),
+            output_read: 0,
+            finished: false,
+        })
+    }
+
+    /// Decodes packets until there is output or the stream ends.
+    fn fill(&mut self) -> Result<(), DecodeError> {
+        self.output.clear();
+        self.output_read = 0;
+        while self.output.is_empty() && !self.finished {
+            if self.queue.is_empty() && !self.packets.read_page(&mut self.queue)? {
+                self.finished = true;
+                break;
+            }
+            if self.position.is_none() {
+                self.position = Some(self.start_position());
+            }
+            let packet = self.queue.pop_front().expect("queue is not empty");
+            let Some(frames) = self.decode_packet(&packet.data) else {
+                continue;
+            };
+
+            // Drop priming frames before position 0 and, on the final
+            // packet, padding past the last granule position.
+            let position = self.position.expect("set above");
+            let mut keep = 0..frames;
+            if position < 0 {
+                keep.start = (-position).min(frames as i64) as usize;
+            }
+            if let (true, Some(granule)) = (packet.last, packet.granule) {
+                let end = (granule as i64 - position).clamp(0, frames as i64) as usize;
+                keep.end = end.max(keep.start);
+            }
+            self.position = Some(position + frames as i64);
+            for frame in keep {
+                for ch in 0..self.channels {
+                    self.output.push(self.block[ch * frames + frame]);
+                }
+            }
+            if packet.last {
+                self.finished = true;
+            }
+        }
+        Ok(())
+    }
+
+    /// Works out where the first audio page starts from its granule
+    /// position: when the page's packets decode to more frames than the
+    /// granule position says, the excess at the start is priming.
+    fn start_position(&self) -> i64 {
+        let Some(end) = self.queue.iter().position(|p| p.granule.is_some()) else {
+            return 0;
+        };
+        let packet = &self.queue[end];
+        if packet.last {
+            // A single-page stream: its granule position marks the end.
+            return 0;
+        }
+        let mut previous = self.previous_size;
+        let mut frames = 0;
+        for p in self.queue.iter().take(end + 1) {
+            if let Some(size) = self.block_size(&p.data) {
+                if let Some(prev) = previous {
+                    frames += (prev / 4 + size / 4) as i64;
+                }
+                previous = Some(size);
+            }
+        }
+        packet.granule.expect("found above") as i64 - frames
+    }
+
+    /// Block size of an audio packet, or `None` if it is not one.
+    fn block_size(&self, packet: &[u8]) -> Option<usize> {
+        let mut r = BitReader::new(packet);
+        if r.read(1) != 0 {
+            return None;
+        }
+        let mode = self
+            .setup
+            .modes
+            .get(r.read(ilog(self.setup.modes.len() as u32 - 1)) as usize)?;
+        (!r.eop()).then_some(self.block_sizes[usize::from(mode.long)])
+    }
+
+    /// Decodes one audio packet. On success the frames it completes are
+    /// left planar in `self.block` and their count is returned. Packets
+    /// that are not audio, or are too damaged to use, return `None`.
+    fn decode_packet(&mut self, packet: &[u8]) -> Option<usize> {
+        let setup = &self.setup;
+        let mut r = BitReader::new(packet);
+        if r.read(1) != 0 {
+            return None;
+        }
+        let mode = setup
+            .modes
+            .get(r.read(ilog(setup.modes.len() as u32 - 1)) as usize)?;
+        let long = mode.long;
+        let (previous_long, next_long) = if long {
+            (r.read(1) == 1, r.read(1) == 1)
+        } else {
+            (false, false)
+        };
+        if r.eop() {
+            return None;
+        }
+        let n = self.block_sizes[usize::from(long)];
+        let half = n / 2;
+        let mapping = &setup.mappings[mode.mapping];
+
+        for ch in 0..self.channels {
+            let (floor, _) = mapping.submaps[mapping.mux[ch]];
+            setup.floors[floor].decode(&mut r, &setup.codebooks, &mut self.floors[ch]);
+            self.skip[ch] = !self.floors[ch].used;
+        }
+        for &(magnitude, angle) in &mapping.coupling {
+            if !self.skip[magnitude] || !self.skip[angle] {
+                self.skip[magnitude] = false;
+                self.skip[angle] = false;
+            }
+        }
+
+        self.spectrum.clear();
+        self.spectrum.resize(self.channels * half, 0.0);
+        let mut skip = Vec::with_capacity(self.channels);
+        for (submap, &(_, residue)) in mapping.submaps.iter().enumerate() {
+            let channels: Vec<usize> = (0..self.channels)
+                .filter(|&ch| mapping.mux[ch] == submap)
+                .collect();
+            skip.clear();
+            skip.extend(channels.iter().map(|&ch| self.skip[ch]));
+            self.residue.clear();
+            self.residue.resize(channels.len() * half, 0.0);
+            setup.residues[residue].decode(
+                &mut r,
+                &setup.codebooks,
+                half,
+                &skip,
+                &mut self.residue,
+                &mut self.scratch,
+            );
+            for (i, &ch) in channels.iter().enumerate() {
+                self.spectrum[ch * half..(ch + 1) * half]
+                    .copy_from_slice(&self.residue[i * half..(i + 1) * half]);
+            }
+        }
+
+        for &(magnitude, angle) in mapping.coupling.iter().rev() {
+            for i in 0..half {
+                let m = self.spectrum[magnitude * half + i];
+                let a = self.spectrum[angle * half + i];
+                let (m, a) = match (m > 0.0, a > 0.0) {
+                    (true, true) => (m, m - a),
+                    (true, false) => (m + a, m),
+                    (false, true) => (m, m + a),
+                    (false, false) => (m - a, m),
+                };
+                self.spectrum[magnitude * half + i] = m;
+                self.spectrum[angle * half + i] = a;
+            }
+        }
+
+        // Floors, inverse MDCT and windowing.
+        let left = if long && !previous_long {
+            self.block_sizes[0]
+        } else {
+            n
+        };
+        let right = if long && !next_long {
+            self.block_sizes[0]
+        } else {
+            n
+        };
+        let mut current = std::mem::take(&mut self.spare);
+        current.clear();
+        current.resize(self.channels * n, 0.0);
+        for ch in 0..self.channels {
+            let spectrum = &mut self.spectrum[ch * half..(ch + 1) * half];
+            let out = &mut current[ch * n..(ch + 1) * n];
+            if !self.floors[ch].used {
+                continue;
+            }
+            let (floor, _) = mapping.submaps[mapping.mux[ch]];
+            setup.floors[floor].apply(&self.floors[ch], spectrum);
+            self.imdct[usize::from(long)].inverse(spectrum, out);
+            window(
+                out,
+                &self.slopes[usize::from(left == self.block_sizes[1])],
+                &self.slopes[usize::from(right == self.block_sizes[1])],
+            );
+        }
+
+        // Overlap-add with the previous block: the output runs from its
+        // centre to this block's centre.
+        let frames = match self.previous_size {
+            Some(prev) => {
+                let frames = prev / 4 + n / 4;
+                self.block.clear();
+                self.block.resize(self.channels * frames, 0.0);
+                for ch in 0..self.channels {
+                    for i in 0..frames {
+                        let p = prev / 2 + i;
+                        let c = (i + n / 4) as isize - (prev / 4) as isize;
+                        let mut v = 0.0;
+                        if p < prev {
+                            v += self.previous[ch * prev + p];
+                        }
+                        if (0..n as isize).contains(&c) {
+                            v += current[ch * n + c as usize];
+                        }
+                        self.block[ch * frames + i] = v;
+                    }
+                }
+                frames
+            }
+            None => 0,
+        };
+        self.spare = std::mem::replace(&mut self.previous, current);
+        self.previous_size = Some(n);
+        Some(frames)
+    }
+}
+
+/// Applies the Vorbis window to a block, with the given rising and falling
+/// slopes (each half the length of the block size they belong to).
+fn window(block: &mut [f32], left: &[f32], right: &[f32]) {
+    let n = block.len();
+    let left_start = n / 4 - left.len() / 2;
+    let right_start = 3 * n / 4 - right.len() / 2;
+    for (i, v) in block.iter_mut().enumerate() {
+        *v *= if i < left_start {
+            0.0
+        } else if i < left_start + left.len() {
+            left[i - left_start]
+        } else if i < right_start {
+            1.0
+        } else if i < right_start + right.len() {
+            right[right.len() - 1 - (i - right_start)]
+        } else {
+            0.0
+        };
+    }
+}
+
+impl<R: Read> StreamDecoder for Decoder<R> {
+    fn channels(&self) -> usize {
+        self.channels
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn frames(&self) -> Option<u64> {
+        None
+    }
+
+    fn read(&mut self, out: &mut [f32]) -> Result<usize, DecodeError> {
+        let wanted = out.len() / self.channels;
+        let mut written = 0;
+        while written < wanted {
+            if self.output_read == self.output.len() {
+                if self.finished {
+                    break;
+                }
+                self.fill()?;
+                continue;
+            }
+            let available = (self.output.len() - self.output_read) / self.channels;
+            let n = available.min(wanted - written);
+            let src = &self.output[self.output_read..self.output_read + n * self.channels];
+            out[written * self.channels..(written + n) * self.channels].copy_from_slice(src);
+            self.output_read += n * self.channels;
+            written += n;
+        }
+        Ok(written)
+    }
+}
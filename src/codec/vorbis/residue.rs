@@ -0,0 +1,140 @@
+//! Residue vector decoding.
+
+use super::bits::BitReader;
+use super::setup::{Codebook, Residue};
+
+impl Residue {
+    /// Decodes the residue of the channels in one submap into `out`, which
+    /// holds `skip.len()` planar vectors of `size` values and is added to.
+    /// Channels flagged in `skip` are left alone; for type 2 they are still
+    /// decoded unless every channel is skipped.
+    pub fn decode(
+        &self,
+        r: &mut BitReader<'_>,
+        books: &[Codebook],
+        size: usize,
+        skip: &[bool],
+        out: &mut [f32],
+        scratch: &mut Scratch,
+    ) {
+        if self.kind != 2 {
+            self.decode_vectors(r, books, size, skip, out, &mut scratch.classes);
+            return;
+        }
+        if skip.iter().all(|&s| s) {
+            return;
+        }
+        // Type 2 codes all channels as one interleaved vector.
+        let channels = skip.len();
+        let interleaved = &mut scratch.interleaved;
+        interleaved.clear();
+        interleaved.resize(size * channels, 0.0);
+        self.decode_vectors(
+            r,
+            books,
+            size * channels,
+            &[false],
+            interleaved,
+            &mut scratch.classes,
+        );
+        for (i, frame) in interleaved.chunks_exact(channels).enumerate() {
+            for (ch, &v) in frame.iter().enumerate() {
+                out[ch * size + i] += v;
+            }
+        }
+    }
+
+    fn decode_vectors(
+        &self,
+        r: &mut BitReader<'_>,
+        books: &[Codebook],
+        size: usize,
+        skip: &[bool],
+        out: &mut [f32],
+        classes: &mut Vec<usize>,
+    ) {
+        let begin = self.begin.min(size);
+        let end = self.end.min(size);
+        let partitions = end.saturating_sub(begin) / self.partition_size;
+        if partitions == 0 {
+            return;
+        }
+        let classbook = &books[self.classbook];
+        let per_word = classbook.dimensions;
+        let stride = partitions + per_word;
+        classes.clear();
+        classes.resize(skip.len() * stride, 0);
+
+        for pass in 0..8 {
+            let mut partition = 0;
+            while partition < partitions {
+                if pass == 0 {
+                    for (ch, _) in skip.iter().enumerate().filter(|(_, &s)| !s) {
+                        let Some(mut word) = classbook.decode(r) else {
+                            return;
+                        };
+                        for i in (0..per_word).rev() {
+                            classes[ch * stride + partition + i] = word % self.classifications;
+                            word /= self.classifications;
+                        }
+                    }
+                }
+                for _ in 0..per_word {
+                    if partition >= partitions {
+                        break;
+                    }
+                    for (ch, _) in skip.iter().enumerate().filter(|(_, &s)| !s) {
+                        let class = classes[ch * stride + partition];
+                        let Some(book) = self.books[class][pass] else {
+                            continue;
+                        };
+                        let start = ch * size + begin + partition * self.partition_size;
+                        let target = &mut out[start..start + self.partition_size];
+                        if !self.decode_partition(r, &books[book], target) {
+                            return;
+                        }
+                    }
+                    partition += 1;
+                }
+            }
+        }
+    }
+
+    /// Adds one partition's VQ vectors to `target`. Returns false at the end
+    /// of the packet.
+    fn decode_partition(&self, r: &mut BitReader<'_>, book: &Codebook, target: &mut [f32]) -> bool {
+        let dims = book.dimensions;
+        if self.kind == 0 {
+            let step = target.len() / dims;
+            for j in 0..step {
+                let Some(v) = book.decode_vector(r) else {
+                    return false;
+                };
+                for (k, x) in v.iter().enumerate() {
+                    target[j + k * step] += x;
+                }
+            }
+        } else {
+            let mut i = 0;
+            while i < target.len() {
+                let Some(v) = book.decode_vector(r) else {
+                    return false;
+                };
+                for x in v {
+                    if let Some(t) = target.get_mut(i) {
+                        *t += x;
+                    }
+                    i += 1;
+                }
+            }
+        }
+        true
+    }
+}
+
+/// Buffers reused across packets.
+#[derive(Default)]
+pub(super) struct Scratch {
+    classes: Vec<usize>,
+    interleaved: Vec<f32>,
+}
@@ -0,0 +1,581 @@
+//! Vorbis header parsing: codebooks, floors, residues, mappings and modes.
+
+use super::bits::BitReader;
+use crate::codec::DecodeError;
+
+/// Codewords up to this long are decoded with a single table lookup.
+const FAST_BITS: u32 = 10;
+
+/// Upper bound on unpacked VQ values per codebook, far above what encoders
+/// produce, so a corrupt header cannot ask for gigabytes.
+const MAX_VECTOR_VALUES: usize = 1 << 24;
+
+/// A Huffman codebook, with its VQ vectors unpacked if it has any.
+pub(super) struct Codebook {
+    pub dimensions: usize,
+    /// `(entry << 5) | length` indexed by the next `FAST_BITS` bits, or 0.
+    fast: Vec<u32>,
+    /// Decoding tree for longer codewords. Children with the top bit set are
+    /// leaves holding an entry number; 0 is an absent child.
+    tree: Vec<[u32; 2]>,
+    /// `entries * dimensions` values, if the book has a lookup table.
+    vectors: Option<Vec<f32>>,
+}
+
+const LEAF: u32 = 1 << 31;
+
+impl Codebook {
+    fn read(r: &mut BitReader<'_>) -> Result<Self, DecodeError> {
+        if r.read(24) != 0x564342 {
+            return Err(DecodeError::Malformed("Vorbis codebook: bad sync pattern"));
+        }
+        let dimensions = r.read(16) as usize;
+        let entries = r.read(24) as usize;
+        let mut lengths = vec![0u8; entries];
+        if r.read(1) == 1 {
+            // Ordered: runs of entries with increasing lengths.
+            let mut length = r.read(5) + 1;
+            let mut entry = 0;
+            while entry < entries {
+                let count = r.read(ilog((entries - entry) as u32)) as usize;
+                if entry + count > entries || length > 32 {
+                    return Err(DecodeError::Malformed("Vorbis codebook: bad lengths"));
+                }
+                lengths[entry..entry + count].fill(length as u8);
+                entry += count;
+                length += 1;
+            }
+        } else {
+            let sparse = r.read(1) == 1;
+            for len in &mut lengths {
+                if !sparse || r.read(1) == 1 {
+                    *len = r.read(5) as u8 + 1;
+                }
+            }
+        }
+
+        let lookup = r.read(4);
+        let vectors = match lookup {
+            0 => None,
+            1 | 2 => {
+                let min = float32(r.read(32));
+                let delta = float32(r.read(32));
+                let value_bits = r.read(4) + 1;
+                let sequence = r.read(1) == 1;
+                let values = if lookup == 1 {
+                    lookup1_values(entries, dimensions)
+                } else {
+                    entries * dimensions
+                };
+                if entries.saturating_mul(dimensions.max(1)) > MAX_VECTOR_VALUES {
+                    return Err(DecodeError::Unsupported("Vorbis codebook size".into()));
+                }
+                let multiplicands: Vec<u32> = (0..values).map(|_| r.read(value_bits)).collect();
+                if r.eop() {
+                    return Err(DecodeError::Truncated("Vorbis codebook"));
+                }
+                let mut vectors = Vec::with_capacity(entries * dimensions);
+                for entry in 0..entries {
+                    let mut last = 0.0;
+                    let mut divisor = 1;
+                    for i in 0..dimensions {
+                        let offset = if lookup == 1 {
+                            (entry / divisor) % values
+                        } else {
+                            entry * dimensions + i
+                        };
+                        let v = multiplicands[offset] as f32 * delta + min + last;
+                        if sequence {
+                            last = v;
+                        }
+                        vectors.push(v);
+                        divisor *= values;
+                    }
+                }
+                Some(vectors)
+            }
+            _ => return Err(DecodeError::Malformed("Vorbis codebook: bad lookup type")),
+        };
+        if r.eop() {
+            return Err(DecodeError::Truncated("Vorbis codebook"));
+        }
+
+        let (fast, tree) = build_decoder(&lengths)?;
+        Ok(Codebook {
+            dimensions,
+            fast,
+            tree,
+            vectors,
+        })
+    }
+
+    /// Decodes one entry number, or `None` at the end of the packet.
+    pub fn decode(&self, r: &mut BitReader<'_>) -> Option<usize> {
+        let hit = self.fast[r.peek(FAST_BITS) as usize];
+        if hit != 0 {
+            r.skip(hit & 31);
+            return (!r.eop()).then_some((hit >> 5) as usize);
+        }
+        let mut node = 0;
+        loop {
+            let child = self.tree.get(node)?[r.read(1) as usize];
+            if r.eop() || child == 0 {
+                return None;
+            }
+            if child & LEAF != 0 {
+                return Some((child & !LEAF) as usize);
+            }
+            node = child as usize;
+        }
+    }
+
+    /// Decodes one VQ vector.
+    pub fn decode_vector(&self, r: &mut BitReader<'_>) -> Option<&[f32]> {
+        let entry = self.decode(r)?;
+        let vectors = self.vectors.as_ref()?;
+        Some(&vectors[entry * self.dimensions..(entry + 1) * self.dimensions])
+    }
+}
+
+/// Assigns codewords to the lengths as the specification does and builds
+/// the lookup table and tree that decode them.
+fn build_decoder(lengths: &[u8]) -> Result<(Vec<u32>, Vec<[u32; 2]>), DecodeError> {
+    let mut fast = vec![0u32; 1 << FAST_BITS];
+    let mut tree = vec![[0u32; 2]];
+    let mut marker = [0u32; 33];
+    for (entry, &len) in lengths.iter().enumerate() {
+        if len == 0 {
+            continue;
+        }
+        let len = u32::from(len);
+        let code = marker[len as usize];
+        if len < 32 && code >> len != 0 {
+            return Err(DecodeError::Malformed(
+                "Vorbis codebook: overspecified lengths",
+            ));
+        }
+        for j in (1..=len as usize).rev() {
+            if marker[j] & 1 != 0 {
+                marker[j] = if j == 1 {
+                    marker[1] + 1
+                } else {
+                    marker[j - 1] << 1
+                };
+                break;
+            }
+            marker[j] += 1;
+        }
+        let mut prefix = code;
+        for j in len as usize + 1..33 {
+            if marker[j] >> 1 != prefix {
+                break;
+            }
+            prefix = marker[j];
+            marker[j] = marker[j - 1] << 1;
+        }
+
+        // Codewords are sent most significant bit first, so the bits as they
+        // appear in the LSB-first stream are reversed.
+        let reversed = code.reverse_bits() >> (32 - len);
+        if len <= FAST_BITS {
+            for high in 0..1u32 << (FAST_BITS - len) {
+                fast[(reversed | (high << len)) as usize] = ((entry as u32) << 5) | len;
+            }
+        } else {
+            let mut node = 0;
+            for i in 0..len {
+                let bit = ((reversed >> i) & 1) as usize;
+                if i == len - 1 {
+                    tree[node][bit] = LEAF | entry as u32;
+                } else {
+                    if tree[node][bit] == 0 {
+                        tree.push([0; 2]);
+                        tree[node][bit] = (tree.len() - 1) as u32;
+                    }
+                    node = tree[node][bit] as usize;
+                }
+            }
+        }
+    }
+    Ok((fast, tree))
+}
+
+/// Unpacks the specification's 32-bit float format.
+fn float32(x: u32) -> f32 {
+    let mantissa = (x & 0x1f_ffff) as f64;
+    let exponent = ((x & 0x7fe0_0000) >> 21) as i32;
+    let v = mantissa * 2f64.powi(exponent - 788);
+    (if x & 0x8000_0000 != 0 { -v } else { v }) as f32
+}
+
+/// The largest `r` with `r^dimensions <= entries`.
+fn lookup1_values(entries: usize, dimensions: usize) -> usize {
+    if dimensions == 0 {
+        return 0;
+    }
+    let mut r = (entries as f64).powf(1.0 / dimensions as f64).floor() as usize;
+    while (r + 1)
+        .checked_pow(dimensions as u32)
+        .is_some_and(|p| p <= entries)
+    {
+        r += 1;
+    }
+    while r > 0 && r.checked_pow(dimensions as u32).is_none_or(|p| p > entries) {
+        r -= 1;
+    }
+    r
+}
+
+/// Number of bits needed to represent `x`.
+pub(super) fn ilog(x: u32) -> u32 {
+    32 - x.leading_zeros()
+}
+
+pub(super) enum Floor {
+    Zero(Floor0),
+    One(Floor1),
+}
+
+/// Floor type 0: an LSP filter curve on a Bark scale.
+pub(super) struct Floor0 {
+    pub order: usize,
+    pub rate: u32,
+    pub bark_map_size: u32,
+    pub amplitude_bits: u32,
+    pub amplitude_offset: u32,
+    pub books: Vec<usize>,
+}
+
+/// Floor type 1: a piecewise linear curve in the dB domain.
+pub(super) struct Floor1 {
+    pub partition_classes: Vec<usize>,
+    pub classes: Vec<FloorClass>,
+    pub multiplier: u32,
+    /// X coordinates of the posts, in the order their Y values are coded.
+    pub xs: Vec<u32>,
+    /// Post indices sorted by X.
+    pub sorted: Vec<usize>,
+    /// The low and high neighbours of each post from index 2 on.
+    pub neighbors: Vec<(usize, usize)>,
+}
+
+pub(super) struct FloorClass {
+    pub dimensions: usize,
+    pub subclass_bits: u32,
+    pub masterbook: Option<usize>,
+    pub subclass_books: Vec<Option<usize>>,
+}
+
+pub(super) struct Residue {
+    pub kind: u16,
+    pub begin: usize,
+    pub end: usize,
+    pub partition_size: usize,
+    pub classifications: usize,
+    pub classbook: usize,
+    /// The book for each classification and pass, if that pass codes it.
+    pub books: Vec<[Option<usize>; 8]>,
+}
+
+pub(super) struct Mapping {
+    /// Magnitude and angle channels, applied in reverse order when decoding.
+    pub coupling: Vec<(usize, usize)>,
+    /// Submap of each channel.
+    pub mux: Vec<usize>,
+    /// Floor and residue of each submap.
+    pub submaps: Vec<(usize, usize)>,
+}
+
+pub(super) struct Mode {
+    pub long: bool,
+    pub mapping: usize,
+}
+
+/// Everything the setup header configures.
+pub(super) struct Setup {
+    pub codebooks: Vec<Codebook>,
+    pub floors: Vec<Floor>,
+    pub residues: Vec<Residue>,
+    pub mappings: Vec<Mapping>,
+    pub modes: Vec<Mode>,
+}
+
+impl Setup {
+    /// Parses the setup header packet for a stream with `channels` channels.
+    pub fn read(packet: &[u8], channels: usize) -> Result<Self, DecodeError> {
+        let mut r = BitReader::new(packet);
+        if r.read(8) != 5 || !packet[1..].starts_with(b"vorbis") {
+            return Err(DecodeError::Malformed("Vorbis setup header"));
+        }
+        r.skip(48);
+
+        let codebooks = (0..r.read(8) + 1)
+            .map(|_| Codebook::read(&mut r))
+            .collect::<Result<Vec<_>, _>>()?;
+        let book = |index: u32| -> Result<usize, DecodeError> {
+            let index = index as usize;
+            if index < codebooks.len() {
+                Ok(index)
+            } else {
+                Err(DecodeError::Malformed(
+                    "Vorbis setup header: bad codebook number",
+                ))
+            }
+        };
+
+        for _ in 0..r.read(6) + 1 {
+            if r.read(16) != 0 {
+                return Err(DecodeError::Malformed(
+                    "Vorbis setup header: bad time domain type",
+                ));
+            }
+        }
+
+        let floors = (0..r.read(6) + 1)
+            .map(|_| match r.read(16) {
+                0 => read_floor0(&mut r, &book).map(Floor::Zero),
+                1 => read_floor1(&mut r, &book).map(Floor::One),
+                _ => Err(DecodeError::Malformed(
+                    "Vorbis setup header: bad floor type",
+                )),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let residues = (0..r.read(6) + 1)
+            .map(|_| read_residue(&mut r, &book, &codebooks))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mappings = (0..r.read(6) + 1)
+            .map(|_| read_mapping(&mut r, channels, floors.len(), residues.len()))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let modes = (0..r.read(6) + 1)
+            .map(|_| {
+                let long = r.read(1) == 1;
+                let window = r.read(16);
+                let transform = r.read(16);
+                let mapping = r.read(8) as usize;
+                if window != 0 || transform != 0 || mapping >= mappings.len() {
+                    return Err(DecodeError::Malformed("Vorbis setup header: bad mode"));
+                }
+                Ok(Mode { long, mapping })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        if r.read(1) != 1 || r.eop() {
+            return Err(DecodeError::Malformed(
+                "Vorbis setup header: missing framing bit",
+            ));
+        }
+        Ok(Setup {
+            codebooks,
+            floors,
+            residues,
+            mappings,
+            modes,
+        })
+    }
+}
+
+fn read_floor0(
+    r: &mut BitReader<'_>,
+    book: &impl Fn(u32) -> Result<usize, DecodeError>,
+) -> Result<Floor0, DecodeError> {
+    let order = r.read(8) as usize;
+    let rate = r.read(16);
+    let bark_map_size = r.read(16);
+    let amplitude_bits = r.read(6);
+    let amplitude_offset = r.read(8);
+    let books = (0..r.read(4) + 1)
+        .map(|_| book(r.read(8)))
+        .collect::<Result<Vec<_>, _>>()?;
+    if order == 0 || rate == 0 || bark_map_size == 0 {
+        return Err(DecodeError::Malformed("Vorbis floor 0"));
+    }
+    Ok(Floor0 {
+        order,
+        rate,
+        bark_map_size,
+        amplitude_bits,
+        amplitude_offset,
+        books,
+    })
+}
+
+fn read_floor1(
+    r: &mut BitReader<'_>,
+    book: &impl Fn(u32) -> Result<usize, DecodeError>,
+) -> Result<Floor1, DecodeError> {
+    let partitions = r.read(5) as usize;
+    let partition_classes: Vec<usize> = (0..partitions).map(|_| r.read(4) as usize).collect();
+    let class_count = partition_classes.iter().max().map_or(0, |&c| c + 1);
+    let mut classes = Vec::with_capacity(class_count);
+    for _ in 0..class_count {
+        let dimensions = r.read(3) as usize + 1;
+        let subclass_bits = r.read(2);
+        let masterbook = if subclass_bits > 0 {
+            Some(book(r.read(8))?)
+        } else {
+            None
+        };
+        let subclass_books = (0..1 << subclass_bits)
+            .map(|_| match r.read(8) {
+                0 => Ok(None),
+                n => book(n - 1).map(Some),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        classes.push(FloorClass {
+            dimensions,
+            subclass_bits,
+            masterbook,
+            subclass_books,
+        });
+    }
+
+    let multiplier = r.read(2) + 1;
+    let range_bits = r.read(4);
+    let mut xs = vec![0, 1 << range_bits];
+    for &class in &partition_classes {
+        for _ in 0..classes[class].dimensions {
+            xs.push(r.read(range_bits));
+        }
+    }
+    if xs.len() > 65 {
+        return Err(DecodeError::Malformed("Vorbis floor 1: too many posts"));
+    }
+    let mut sorted: Vec<usize> = (0..xs.len()).collect();
+    sorted.sort_by_key(|&i| xs[i]);
+    if sorted.windows(2).any(|w| xs[w[0]] == xs[w[1]]) {
+        return Err(DecodeError::Malformed("Vorbis floor 1: repeated X value"));
+    }
+    let neighbors = (2..xs.len())
+        .map(|i| {
+            let below = (0..i).filter(|&j| xs[j] < xs[i]);
+            let above = (0..i).filter(|&j| xs[j] > xs[i]);
+            (
+                below.max_by_key(|&j| xs[j]).expect("post 0 is below"),
+                above.min_by_key(|&j| xs[j]).expect("post 1 is above"),
+            )
+        })
+        .collect();
+    Ok(Floor1 {
+        partition_classes,
+        classes,
+        multiplier,
+        xs,
+        sorted,
+        neighbors,
+    })
+}
+
+fn read_residue(
+    r: &mut BitReader<'_>,
+    book: &impl Fn(u32) -> Result<usize, DecodeError>,
+    codebooks: &[Codebook],
+) -> Result<Residue, DecodeError> {
+    let kind = r.read(16) as u16;
+    if kind > 2 {
+        return Err(DecodeError::Malformed(
+            "Vorbis setup header: bad residue type",
+        ));
+    }
+    let begin = r.read(24) as usize;
+    let end = r.read(24) as usize;
+    let partition_size = r.read(24) as usize + 1;
+    let classifications = r.read(6) as usize + 1;
+    let classbook = book(r.read(8))?;
+    let cascade: Vec<u32> = (0..classifications)
+        .map(|_| {
+            let low = r.read(3);
+            let high = if r.read(1) == 1 { r.read(5) } else { 0 };
+            high << 3 | low
+        })
+        .collect();
+    let mut books = Vec::with_capacity(classifications);
+    for bits in cascade {
+        let mut passes = [None; 8];
+        for (pass, slot) in passes.iter_mut().enumerate() {
+            if bits & (1 << pass) != 0 {
+                let b = book(r.read(8))?;
+                if codebooks[b].dimensions == 0 || codebooks[b].vectors.is_none() {
+                    return Err(DecodeError::Malformed(
+                        "Vorbis residue: book without vectors",
+                    ));
+                }
+                *slot = Some(b);
+            }
+        }
+        books.push(passes);
+    }
+    if codebooks[classbook].dimensions == 0 {
+        return Err(DecodeError::Malformed("Vorbis residue: bad classbook"));
+    }
+    Ok(Residue {
+        kind,
+        begin,
+        end,
+        partition_size,
+        classifications,
+        classbook,
+        books,
+    })
+}
+
+fn read_mapping(
+    r: &mut BitReader<'_>,
+    channels: usize,
+    floors: usize,
+    residues: usize,
+) -> Result<Mapping, DecodeError> {
+    if r.read(16) != 0 {
+        return Err(DecodeError::Malformed(
+            "Vorbis setup header: bad mapping type",
+        ));
+    }
+    let submap_count = if r.read(1) == 1 {
+        r.read(4) as usize + 1
+    } else {
+        1
+    };
+    let mut coupling = Vec::new();
+    if r.read(1) == 1 {
+        let bits = ilog(channels as u32 - 1);
+        for _ in 0..r.read(8) + 1 {
+            let magnitude = r.read(bits) as usize;
+            let angle = r.read(bits) as usize;
+            if magnitude == angle || magnitude >= channels || angle >= channels {
+                return Err(DecodeError::Malformed("Vorbis mapping: bad coupling"));
+            }
+            coupling.push((magnitude, angle));
+        }
+    }
+    if r.read(2) != 0 {
+        return Err(DecodeError::Malformed("Vorbis mapping: reserved bits set"));
+    }
+    let mux = if submap_count > 1 {
+        (0..channels).map(|_| r.read(4) as usize).collect()
+    } else {
+        vec![0; channels]
+    };
+    if mux.iter().any(|&m| m >= submap_count) {
+        return Err(DecodeError::Malformed("Vorbis mapping: bad submap"));
+    }
+    let submaps = (0..submap_count)
+        .map(|_| {
+            r.skip(8);
+            let floor = r.read(8) as usize;
+            let residue = r.read(8) as usize;
+            if floor >= floors || residue >= residues {
+                return Err(DecodeError::Malformed(
+                    "Vorbis mapping: bad floor or residue",
+                ));
+            }
+            Ok((floor, residue))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(Mapping {
+        coupling,
+        mux,
+        submaps,
+    })
+}
@@ -0,0 +1,527 @@
+//! RIFF/WAVE decoder and encoder.
+//!
+//! Handles plain `WAVE_FORMAT_PCM` and `WAVE_FORMAT_IEEE_FLOAT` files as well
+//! as `WAVE_FORMAT_EXTENSIBLE` wrappers around either. Integer PCM may be
+//! 8 (unsigned), 16, 24 or 32 bits; float data may be 32 or 64 bits. Every
+//! format is converted to `f32` in the range `[-1.0, 1.0)`.
+//!
+//! The first loop of a `smpl` chunk, if present, becomes the sample's
+//! [`LoopRegion`].
+//!
+//! [`Reader`] reads frames straight from the file instead, for
+//! [streaming](crate::stream).
+//!
+//! [`write()`] and [`save`] go the other way, to 16, 24 or 32-bit integer PCM
+//! or 32-bit float, with optional dither for the integer formats.
+
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use super::{Cursor, DecodeError};
+use crate::sample::{LoopMode, LoopRegion, Sample};
+use crate::stream::StreamSource;
+
+const FORMAT_PCM: u16 = 0x0001;
+const FORMAT_IEEE_FLOAT: u16 = 0x0003;
+const FORMAT_EXTENSIBLE: u16 = 0xFFFE;
+
+/// Bytes 2..16 of every `KSDATAFORMAT_SUBTYPE_*` GUID used by WAVE files.
+const SUBFORMAT_GUID_TAIL: [u8; 14] = [
+    0x00, 0x00, 0x00, 0x00, 0x10, 0x00, 0x80, 0x00, 0x00, 0xAA, 0x00, 0x38, 0x9B, 0x71,
+];
+
+/// Sample encoding of a WAVE `data` chunk.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Encoding {
+    /// Integer PCM with the given container size in bits.
+    Pcm(u16),
+    /// IEEE float with the given size in bits.
+    Float(u16),
+}
+
+impl Encoding {
+    fn bytes(self) -> usize {
+        match self {
+            Encoding::Pcm(bits) | Encoding::Float(bits) => usize::from(bits / 8),
+        }
+    }
+}
+
+/// Contents of the `fmt ` chunk that matter for decoding.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Format {
+    encoding: Encoding,
+    channels: u16,
+    sample_rate: u32,
+}
+
+/// Reads and decodes a WAV file from disk.
+pub fn load(path: impl AsRef<Path>) -> Result<Sample, DecodeError> {
+    decode(&fs::read(path)?)
+}
+
+/// Decodes an in-memory WAV file into an interleaved [`Sample`].
+pub fn decode(bytes: &[u8]) -> Result<Sample, DecodeError> {
+    let mut cur = Cursor::new(bytes);
+    if &cur.array::<4>("RIFF header")? != b"RIFF" {
+        return Err(DecodeError::Malformed("RIFF header: missing RIFF tag"));
+    }
+    cur.skip(4, "RIFF header")?;
+    if &cur.array::<4>("RIFF header")? != b"WAVE" {
+        return Err(DecodeError::Malformed("RIFF header: not a WAVE file"));
+    }
+
+    let mut format = None;
+    let mut data = None;
+    let mut loop_region = None;
+    while cur.remaining() > 0 {
+        // Some writers pad the file with a few stray bytes; ignore them.
+        if cur.remaining() < 8 {
+            break;
+        }
+        let id = cur.array::<4>("chunk header")?;
+        let size = cur.u32_le("chunk header")? as usize;
+        match &id {
+            b"fmt " => format = Some(parse_format(cur.take(size, "fmt chunk")?)?),
+            b"data" => {
+                data = Some(cur.take(size, "data chunk")?);
+            }
+            b"smpl" => loop_region = parse_sampler(cur.take(size, "smpl chunk")?)?,
+            _ => cur.skip(size, "chunk body")?,
+        }
+        if size % 2 == 1 && cur.remaining() > 0 {
+            cur.skip(1, "chunk padding")?;
+        }
+    }
+
+    let format = format.ok_or(DecodeError::MissingChunk("fmt chunk"))?;
+    let data = data.ok_or(DecodeError::MissingChunk("data chunk"))?;
+    let mut sample = decode_data(&format, data)?;
+    if let Some(mut region) = loop_region {
+        region.end = region.end.min(sample.frames());
+        if region.start < region.end {
+            sample.set_loop_region(Some(region));
+        }
+    }
+    Ok(sample)
+}
+
+/// Reads the first loop of a `smpl` chunk. Loop types other than forward,
+/// alternating and backward are ignored.
+fn parse_sampler(chunk: &[u8]) -> Result<Option<LoopRegion>, DecodeError> {
+    let mut cur = Cursor::new(chunk);
+    // Manufacturer, product, period, unity note, pitch fraction, SMPTE
+    // format and SMPTE offset.
+    cur.skip(28, "smpl chunk")?;
+    let loops = cur.u32_le("smpl chunk")?;
+    let _sampler_data = cur.u32_le("smpl chunk")?;
+    if loops == 0 {
+        return Ok(None);
+    }
+    let _cue_id = cur.u32_le("smpl loop")?;
+    let kind = cur.u32_le("smpl loop")?;
+    let start = cur.u32_le("smpl loop")? as usize;
+    let end = cur.u32_le("smpl loop")? as usize;
+    let mode = match kind {
+        0 => LoopMode::Forward,
+        1 => LoopMode::PingPong,
+        2 => LoopMode::Reverse,
+        _ => return Ok(None),
+    };
+    if end < start {
+        return Err(DecodeError::Malformed("smpl loop: end before start"));
+    }
+    // `smpl` loop ends are inclusive.
+    Ok(Some(LoopRegion {
+        start,
+        end: end + 1,
+        mode,
+        crossfade: 0,
+    }))
+}
+
+fn parse_format(chunk: &[u8]) -> Result<Format, DecodeError> {
+    let mut cur = Cursor::new(chunk);
+    let mut tag = cur.u16_le("fmt chunk")?;
+    let channels = cur.u16_le("fmt chunk")?;
+    let sample_rate = cur.u32_le("fmt chunk")?;
+    let _byte_rate = cur.u32_le("fmt chunk")?;
+    let block_align = cur.u16_le("fmt chunk")?;
+    let bits = cur.u16_le("fmt chunk")?;
+
+    if tag == FORMAT_EXTENSIBLE {
+        let cb_size = cur.u16_le("fmt extension")?;
+        if cb_size < 22 {
+            return Err(DecodeError::Malformed("fmt extension: too short"));
+        }
+        let valid_bits = cur.u16_le("fmt extension")?;
+        let _channel_mask = cur.u32_le("fmt extension")?;
+        let guid = cur.array::<16>("fmt extension")?;
+        if guid[2..] != SUBFORMAT_GUID_TAIL {
+            return Err(DecodeError::Unsupported("WAVE sub-format GUID".into()));
+        }
+        tag = u16::from_le_bytes([guid[0], guid[1]]);
+        // Samples are left-justified in their container, so scaling by the
+        // container size is correct whatever the valid bit count is.
+        if valid_bits > bits {
+            return Err(DecodeError::Malformed(
+                "fmt extension: valid bits exceed container size",
+            ));
+        }
+    }
+
+    let encoding = match (tag, bits) {
+        (FORMAT_PCM, 8 | 16 | 24 | 32) => Encoding::Pcm(bits),
+        (FORMAT_IEEE_FLOAT, 32 | 64) => Encoding::Float(bits),
+        (FORMAT_PCM | FORMAT_IEEE_FLOAT, _) => {
+            return Err(DecodeError::Unsupported(format!(
+                "WAVE bit depth {bits} for format tag {tag:#06x}"
+            )))
+        }
+        _ => {
+            return Err(DecodeError::Unsupported(format!(
+                "WAVE format tag {tag:#06x}"
+            )))
+        }
+    };
+    if channels == 0 {
+        return Err(DecodeError::Malformed("fmt chunk: zero channels"));
+    }
+    if sample_rate == 0 {
+        return Err(DecodeError::Malformed("fmt chunk: zero sample rate"));
+    }
+    if usize::from(block_align) != usize::from(channels) * encoding.bytes() {
+        return Err(DecodeError::Malformed(
+            "fmt chunk: block align does not match channels and bit depth",
+        ));
+    }
+    Ok(Format {
+        encoding,
+        channels,
+        sample_rate,
+    })
+}
+
+fn decode_data(format: &Format, data: &[u8]) -> Result<Sample, DecodeError> {
+    let width = format.encoding.bytes();
+    let block = width * usize::from(format.channels);
+    // A partial trailing frame is treated as truncation rather than padding.
+    if !data.len().is_multiple_of(block) {
+        return Err(DecodeError::Truncated("data chunk: partial frame"));
+    }
+
+    let mut out = vec![0.0; data.len() / width];
+    convert(format.encoding, data, &mut out);
+    Ok(Sample::from_interleaved(
+        out,
+        usize::from(format.channels),
+        format.sample_rate,
+    )?)
+}
+
+/// Converts little-endian values in `encoding` to `f32`s, one per `out`
+/// slot.
+fn convert(encoding: Encoding, bytes: &[u8], out: &mut [f32]) {
+    let values = bytes.chunks_exact(encoding.bytes()).zip(out);
+    match encoding {
+        Encoding::Pcm(8) => values.for_each(|(b, v)| *v = (f32::from(b[0]) - 128.0) / 128.0),
+        Encoding::Pcm(16) => {
+            values.for_each(|(b, v)| *v = f32::from(i16::from_le_bytes([b[0], b[1]])) / 32_768.0)
+        }
+        Encoding::Pcm(24) => values.for_each(|(b, v)| {
+            *v = (i32::from_le_bytes([0, b[0], b[1], b[2]]) >> 8) as f32 / 8_388_608.0
+        }),
+        Encoding::Pcm(32) => values.for_each(|(b, v)| {
+            *v = (f64::from(i32::from_le_bytes([b[0], b[1], b[2], b[3]])) / 2_147_483_648.0) as f32
+        }),
+        Encoding::Float(32) => {
+            values.for_each(|(b, v)| *v = f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        }
+        Encoding::Float(64) => values.for_each(|(b, v)| {
+            *v = f64::from_le_bytes([b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7]]) as f32
+        }),
+        _ => unreachable!("rejected by parse_format"),
+    }
+}
+
+/// Sample formats the encoder writes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum BitDepth {
+    Pcm16,
+    Pcm24,
+    Pcm32,
+    Float32,
+}
+
+impl BitDepth {
+    fn encoding(self) -> Encoding {
+        match self {
+            BitDepth::Pcm16 => Encoding::Pcm(16),
+            BitDepth::Pcm24 => Encoding::Pcm(24),
+            BitDepth::Pcm32 => Encoding::Pcm(32),
+            BitDepth::Float32 => Encoding::Float(32),
+        }
+    }
+}
+
+/// How the encoder quantizes to an integer [`BitDepth`]. Float output is
+/// written as it is either way.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Dither {
+    /// Rounds to the nearest step.
+    None,
+    /// Adds triangular (TPDF) noise spanning one step either way before
+    /// rounding, which turns quantization distortion into a steady noise
+    /// floor. The noise comes from a generator seeded with `seed`, so the
+    /// same input always encodes to the same bytes.
+    Tpdf { seed: u64 },
+}
+
+/// Writes `sample` to disk as a WAV file.
+pub fn save(
+    path: impl AsRef<Path>,
+    sample: &Sample,
+    depth: BitDepth,
+    dither: Dither,
+) -> io::Result<()> {
+    let mut out = BufWriter::new(File::create(path)?);
+    write(&mut out, sample, depth, dither)?;
+    out.flush()
+}
+
+/// Encodes `sample` as a WAV file. Values outside `[-1.0, 1.0]` are clipped
+/// in the integer formats.
+///
+/// Fails with [`io::ErrorKind::InvalidInput`] if the audio does not fit in a
+/// RIFF file's 4 GiB, or its format does not fit the header's fields.
+pub fn write(
+    mut out: impl Write,
+    sample: &Sample,
+    depth: BitDepth,
+    dither: Dither,
+) -> io::Result<()> {
+    let encoding = depth.encoding();
+    let channels = u16::try_from(sample.channels())
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "too many channels"))?;
+    let block = encoding.bytes() * sample.channels();
+    let block_align = u16::try_from(block)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "too many channels"))?;
+    let byte_rate = sample
+        .sample_rate()
+        .checked_mul(u32::from(block_align))
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "sample rate too high"))?;
+    // Leaves room for the header and the pad byte after an odd-sized chunk.
+    let data_size = sample
+        .frames()
+        .checked_mul(block)
+        .and_then(|size| u32::try_from(size).ok())
+        .filter(|&size| size <= u32::MAX - 37)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "too long for a WAV file"))?;
+    let padding = data_size & 1;
+    let (tag, bits) = match encoding {
+        Encoding::Pcm(bits) => (FORMAT_PCM, bits),
+        Encoding::Float(bits) => (FORMAT_IEEE_FLOAT, bits),
+    };
+
+    let mut header = Vec::with_capacity(44);
+    header.extend_from_slice(b"RIFF");
+    header.extend_from_slice(&(36 + data_size + padding).to_le_bytes());
+    header.extend_from_slice(b"WAVEfmt ");
+    header.extend_from_slice(&16u32.to_le_bytes());
+    header.extend_from_slice(&tag.to_le_bytes());
+    header.extend_from_slice(&channels.to_le_bytes());
+    header.extend_from_slice(&sample.sample_rate().to_le_bytes());
+    header.extend_from_slice(&byte_rate.to_le_bytes());
+    header.extend_from_slice(&block_align.to_le_bytes());
+    header.extend_from_slice(&bits.to_le_bytes());
+    header.extend_from_slice(b"data");
+    header.extend_from_slice(&data_size.to_le_bytes());
+    out.write_all(&header)?;
+
+    let mut interleaved = vec![0.0; sample.frames() * sample.channels()];
+    sample.copy_interleaved_into(&mut interleaved);
+    let mut noise = match dither {
+        Dither::None => None,
+        Dither::Tpdf { seed } => Some(SplitMix(seed)),
+    };
+    let mut bytes = Vec::with_capacity(4096 * block);
+    for frames in interleaved.chunks(4096 * sample.channels()) {
+        bytes.clear();
+        for &v in frames {
+            let mut quantize = |scale: f64| {
+                let dither = noise.as_mut().map_or(0.0, |n| n.unit() - n.unit());
+                (f64::from(v) * scale + dither)
+                    .round()
+                    .clamp(-scale, scale - 1.0) as i32
+            };
+            match encoding {
+                Encoding::Pcm(16) => {
+                    bytes.extend_from_slice(&(quantize(32_768.0) as i16).to_le_bytes())
+                }
+                Encoding::Pcm(24) => {
+                    bytes.extend_from_slice(&quantize(8_388_608.0).to_le_bytes()[..3])
+                }
+                Encoding::Pcm(_) => {
+                    bytes.extend_from_slice(&quantize(2_147_483_648.0).to_le_bytes())
+                }
+                Encoding::Float(_) => bytes.extend_from_slice(&v.to_le_bytes()),
+            }
+        }
+        out.write_all(&bytes)?;
+    }
+    if padding == 1 {
+        out.write_all(&[0])?;
+    }
+    Ok(())
+}
+
+/// SplitMix64, a small generator that is fine with any seed.
+struct SplitMix(u64);
+
+impl SplitMix {
+    /// Uniform in `[0, 1)`.
+    fn unit(&mut self) -> f64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^= z >> 31;
+        (z >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+/// Reads frames from a WAV file on demand, leaving the audio on disk until
+/// it is asked for. Used to stream long files.
+pub struct Reader<R> {
+    reader: R,
+    format: Format,
+    /// Byte offset of the audio data.
+    data: u64,
+    frames: usize,
+    loop_region: Option<LoopRegion>,
+    bytes: Vec<u8>,
+}
+
+impl Reader<BufReader<File>> {
+    /// Opens a WAV file on disk.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, DecodeError> {
+        Reader::new(BufReader::new(File::open(path)?))
+    }
+}
+
+impl<R: Read + Seek> Reader<R> {
+    /// Reads the chunk headers, along with the `fmt ` and `smpl` chunks.
+    pub fn new(mut reader: R) -> Result<Self, DecodeError> {
+        let length = reader.seek(SeekFrom::End(0))?;
+        reader.seek(SeekFrom::Start(0))?;
+        let mut header = [0; 12];
+        read_exact(&mut reader, &mut header, "RIFF header")?;
+        if &header[..4] != b"RIFF" {
+            return Err(DecodeError::Malformed("RIFF header: missing RIFF tag"));
+        }
+        if &header[8..] != b"WAVE" {
+            return Err(DecodeError::Malformed("RIFF header: not a WAVE file"));
+        }
+
+        let mut format = None;
+        let mut data = None;
+        let mut loop_region = None;
+        let mut position = 12;
+        // As in `decode`, a few stray bytes at the end are ignored.
+        while length - position >= 8 {
+            let mut chunk = [0; 8];
+            read_exact(&mut reader, &mut chunk, "chunk header")?;
+            let size = u64::from(u32::from_le_bytes([chunk[4], chunk[5], chunk[6], chunk[7]]));
+            let body = position + 8;
+            if length - body < size {
+                return Err(DecodeError::Truncated(match &chunk[..4] {
+                    b"fmt " => "fmt chunk",
+                    b"data" => "data chunk",
+                    b"smpl" => "smpl chunk",
+                    _ => "chunk body",
+                }));
+            }
+            match &chunk[..4] {
+                b"fmt " => format = Some(parse_format(&read_chunk(&mut reader, size)?)?),
+                b"smpl" => loop_region = parse_sampler(&read_chunk(&mut reader, size)?)?,
+                b"data" => data = Some((body, size)),
+                _ => {}
+            }
+            position = (body + size + size % 2).min(length);
+            reader.seek(SeekFrom::Start(position))?;
+        }
+
+        let format = format.ok_or(DecodeError::MissingChunk("fmt chunk"))?;
+        let (data, size) = data.ok_or(DecodeError::MissingChunk("data chunk"))?;
+        let block = (format.encoding.bytes() * usize::from(format.channels)) as u64;
+        if !size.is_multiple_of(block) {
+            return Err(DecodeError::Truncated("data chunk: partial frame"));
+        }
+        let frames = (size / block) as usize;
+        let loop_region = loop_region
+            .map(|region: LoopRegion| LoopRegion {
+                end: region.end.min(frames),
+                ..region
+            })
+            .filter(|region| region.start < region.end);
+        Ok(Reader {
+            reader,
+            format,
+            data,
+            frames,
+            loop_region,
+            bytes: Vec::new(),
+        })
+    }
+}
+
+fn read_exact(
+    reader: &mut impl Read,
+    buf: &mut [u8],
+    what: &'static str,
+) -> Result<(), DecodeError> {
+    reader.read_exact(buf).map_err(|e| match e.kind() {
+        io::ErrorKind::UnexpectedEof => DecodeError::Truncated(what),
+        _ => e.into(),
+    })
+}
+
+fn read_chunk(reader: &mut impl Read, size: u64) -> Result<Vec<u8>, DecodeError> {
+    let mut chunk = vec![0; size as usize];
+    read_exact(reader, &mut chunk, "chunk body")?;
+    Ok(chunk)
+}
+
+impl<R: Read + Seek + Send> StreamSource for Reader<R> {
+    fn channels(&self) -> usize {
+        usize::from(self.format.channels)
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.format.sample_rate
+    }
+
+    fn frames(&self) -> usize {
+        self.frames
+    }
+
+    fn loop_region(&self) -> Option<LoopRegion> {
+        self.loop_region
+    }
+
+    fn read(&mut self, frame: usize, out: &mut [f32]) -> Result<usize, DecodeError> {
+        let channels = usize::from(self.format.channels);
+        let width = self.format.encoding.bytes();
+        let n = (out.len() / channels).min(self.frames.saturating_sub(frame));
+        if n == 0 {
+            return Ok(0);
+        }
+        let offset = self.data + (frame * channels * width) as u64;
+        self.reader.seek(SeekFrom::Start(offset))?;
+        self.bytes.resize(n * channels * width, 0);
+        read_exact(&mut self.reader, &mut self.bytes, "data chunk")?;
+        convert(self.format.encoding, &self.bytes, &mut out[..n * channels]);
+        Ok(n)
+    }
+}
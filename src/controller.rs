@@ -0,0 +1,456 @@
+//! The control-thread half of the engine.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use crate::effect::{Effect, EffectSlot, SENDS};
+use crate::engine::{
+    Command, EngineConfig, EngineError, Event, Garbage, Message, SampleId, EFFECTS, MATRICES,
+};
+use crate::modulation::ModMatrix;
+use crate::pool::Inserts;
+use crate::resample::{self, ResampleQuality};
+use crate::sample::Sample;
+use crate::spsc::{Consumer, Producer};
+use crate::stream::{self, StreamedSample, Streamer};
+use crate::voice::{TriggerParams, VoiceId};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Slot {
+    Free,
+    Loaded,
+    /// Removal was requested; the slot is reusable once the engine returns
+    /// the sample.
+    Retiring,
+}
+
+/// Sends commands to an [`Engine`](crate::Engine) from a non-realtime thread.
+///
+/// Created together with the engine by [`Engine::new`](crate::Engine::new).
+/// Call [`collect_garbage`](Controller::collect_garbage) periodically so memory
+/// released by the audio thread is freed here.
+///
+/// Streamed samples also need the engine's [`Streamer`], taken once with
+/// [`streamer`](Controller::streamer), to be serviced on an I/O thread.
+pub struct Controller {
+    messages: Producer<Message>,
+    garbage: Consumer<Garbage>,
+    slots: Vec<Slot>,
+    next_voice: u64,
+    clock: Arc<AtomicU64>,
+    streaming: Arc<stream::Shared>,
+    streamer: Option<Streamer>,
+    /// Modulation matrices handed to the engine and not yet returned.
+    matrices: usize,
+    /// Effects, or sets of voice inserts, handed to the engine and not yet
+    /// returned.
+    effects: usize,
+    voices: usize,
+    /// Output buses, each of which can hold an effect.
+    buses: usize,
+    sample_rate: u32,
+    max_block: usize,
+    resample: Option<ResampleQuality>,
+}
+
+impl Controller {
+    pub(crate) fn new(
+        messages: Producer<Message>,
+        garbage: Consumer<Garbage>,
+        clock: Arc<AtomicU64>,
+        streaming: Arc<stream::Shared>,
+        config: &EngineConfig,
+    ) -> Self {
+        Controller {
+            messages,
+            garbage,
+            slots: vec![Slot::Free; config.max_samples],
+            next_voice: 1,
+            clock,
+            streamer: Some(Streamer::new(Arc::clone(&streaming))),
+            streaming,
+            matrices: 0,
+            effects: 0,
+            voices: config.voices,
+            buses: config.channels.div_ceil(2),
+            sample_rate: config.sample_rate,
+            max_block: config.max_block,
+            resample: config.resample,
+        }
+    }
+
+    /// Number of frames the engine had rendered at the end of its last block.
+    /// Schedule events relative to this, plus enough latency to reach the
+    /// engine before they fall due.
+    pub fn now(&self) -> u64 {
+        self.clock.load(Ordering::Acquire)
+    }
+
+    /// Number of times a voice ran out of streamed data and went silent
+    /// waiting for the disk.
+    pub fn underruns(&self) -> u64 {
+        self.streaming.underruns.load(Ordering::Relaxed)
+    }
+
+    /// Takes the engine's [`Streamer`]. Returns `None` after the first call.
+    pub fn streamer(&mut self) -> Option<Streamer> {
+        self.streamer.take()
+    }
+
+    /// Loads a sample into a free slot, first converting it to the engine's
+    /// rate if [`EngineConfig::resample`] asks for that.
+    pub fn add_sample(&mut self, sample: impl Into<Arc<Sample>>) -> Result<SampleId, EngineError> {
+        let id = self.free_slot()?;
+        let mut sample = sample.into();
+        let mut scale = 1.0;
+        if let Some(quality) = self.resample {
+            if sample.sample_rate() != self.sample_rate {
+                scale = f64::from(self.sample_rate) / f64::from(sample.sample_rate());
+                sample = Arc::new(resample::resample(&sample, self.sample_rate, quality));
+            }
+        }
+        self.post(Message::InsertSample(id, sample, scale))?;
+        self.slots[id.index()] = Slot::Loaded;
+        Ok(id)
+    }
+
+    /// Loads a streamed sample into a free slot. Its preloaded head goes to
+    /// the engine and its source to the [`Streamer`].
+    pub fn add_streamed_sample(&mut self, sample: StreamedSample) -> Result<SampleId, EngineError> {
+        if self.streaming.buffers.is_empty() {
+            return Err(EngineError::StreamingDisabled);
+        }
+        let id = self.free_slot()?;
+        let (head, frames, source) = sample.into_parts();
+        self.streaming.set_source(id, Some(source));
+        if let Err(e) = self.post(Message::InsertStreamed(id, Arc::new(head), frames)) {
+            self.streaming.set_source(id, None);
+            return Err(e);
+        }
+        self.slots[id.index()] = Slot::Loaded;
+        Ok(id)
+    }
+
+    fn free_slot(&mut self) -> Result<SampleId, EngineError> {
+        self.collect_garbage();
+        let index = self
+            .slots
+            .iter()
+            .position(|s| *s == Slot::Free)
+            .ok_or(EngineError::NoFreeSlot)?;
+        Ok(SampleId(index as u32))
+    }
+
+    /// Unloads a sample, stopping any voice that plays it.
+    pub fn remove_sample(&mut self, id: SampleId) -> Result<(), EngineError> {
+        self.check_sample(id)?;
+        self.post(Message::RemoveSample(id))?;
+        self.slots[id.index()] = Slot::Retiring;
+        Ok(())
+    }
+
+    /// Starts playing `sample` and returns the id of the new voice.
+    pub fn trigger(
+        &mut self,
+        sample: SampleId,
+        params: TriggerParams,
+    ) -> Result<VoiceId, EngineError> {
+        self.check_sample(sample)?;
+        let voice = self.next_voice_id();
+        self.send(Command::Trigger {
+            voice,
+            sample,
+            params,
+        })?;
+        Ok(voice)
+    }
+
+    /// Starts playing `sample` on output frame `frame` and returns the id
+    /// the voice will have.
+    pub fn trigger_at(
+        &mut self,
+        frame: u64,
+        sample: SampleId,
+        params: TriggerParams,
+    ) -> Result<VoiceId, EngineError> {
+        self.check_sample(sample)?;
+        let voice = self.next_voice_id();
+        self.schedule(
+            frame,
+            Command::Trigger {
+                voice,
+                sample,
+                params,
+            },
+        )?;
+        Ok(voice)
+    }
+
+    /// Signals note-off to a voice.
+    pub fn release(&mut self, voice: VoiceId) -> Result<(), EngineError> {
+        self.send(Command::Release(voice))
+    }
+
+    /// Signals note-off to a voice on output frame `frame`.
+    pub fn release_at(&mut self, frame: u64, voice: VoiceId) -> Result<(), EngineError> {
+        self.schedule(frame, Command::Release(voice))
+    }
+
+    /// Silences a voice.
+    pub fn stop(&mut self, voice: VoiceId) -> Result<(), EngineError> {
+        self.send(Command::Stop(voice))
+    }
+
+    /// Moves a voice's playback position.
+    pub fn seek(&mut self, voice: VoiceId, frame: usize) -> Result<(), EngineError> {
+        self.send(Command::Seek { voice, frame })
+    }
+
+    /// Sets how much longer a [stretched](crate::stretch) voice makes its
+    /// sample last: 2 plays it at half tempo. Its pitch stays put.
+    pub fn set_stretch(&mut self, voice: VoiceId, ratio: f64) -> Result<(), EngineError> {
+        self.send(Command::SetStretch { voice, ratio })
+    }
+
+    /// Sets a [stretched](crate::stretch) voice's pitch shift in semitones.
+    /// Its tempo stays put.
+    pub fn set_pitch_shift(&mut self, voice: VoiceId, semitones: f32) -> Result<(), EngineError> {
+        self.send(Command::SetPitchShift { voice, semitones })
+    }
+
+    /// Silences every voice.
+    pub fn stop_all(&mut self) -> Result<(), EngineError> {
+        self.send(Command::StopAll)
+    }
+
+    /// Replaces the engine's modulation matrix, from the start of the next
+    /// block. Voices already playing carry on under the new routes.
+    ///
+    /// Returns [`EngineError::QueueFull`] if several matrices are still on
+    /// their way to or back from the engine; collect garbage and retry.
+    pub fn set_mod_matrix(&mut self, matrix: ModMatrix) -> Result<(), EngineError> {
+        self.collect_garbage();
+        // One matrix stays with the engine, and it can only retire one for
+        // each it is given.
+        if self.matrices >= MATRICES {
+            return Err(EngineError::QueueFull);
+        }
+        self.post(Message::SetModMatrix(Arc::new(matrix)))?;
+        self.matrices += 1;
+        Ok(())
+    }
+
+    /// Prepares `effect` for the engine's rate and block size and puts it
+    /// on send bus `send`, from the start of the next block. The effect it
+    /// replaces comes back through [`collect_garbage`](Controller::collect_garbage).
+    ///
+    /// Returns [`EngineError::QueueFull`] if several effects are still on
+    /// their way to or back from the engine; collect garbage and retry.
+    ///
+    /// # Panics
+    ///
+    /// If `send` is not below [`SENDS`].
+    pub fn set_send(
+        &mut self,
+        send: usize,
+        mut effect: Box<dyn Effect>,
+    ) -> Result<(), EngineError> {
+        assert!(send < SENDS, "no send bus {send}");
+        self.hand_over(|sample_rate, max_block| {
+            effect.prepare(sample_rate, max_block);
+            Message::SetSend(send, Some(effect))
+        })
+    }
+
+    /// Takes the effect off send bus `send`. Voices still send to the bus,
+    /// but nothing returns from it.
+    ///
+    /// # Panics
+    ///
+    /// If `send` is not below [`SENDS`].
+    pub fn clear_send(&mut self, send: usize) -> Result<(), EngineError> {
+        assert!(send < SENDS, "no send bus {send}");
+        self.post(Message::SetSend(send, None))
+    }
+
+    /// Prepares `effect` and puts it on output bus `bus`, the pair of
+    /// output channels starting at `2 * bus`, from the start of the next
+    /// block. It processes every voice playing on the bus, before the send
+    /// returns are mixed in. The effect it replaces comes back through
+    /// [`collect_garbage`](Controller::collect_garbage).
+    ///
+    /// Returns [`EngineError::QueueFull`] if several effects are still on
+    /// their way to or back from the engine; collect garbage and retry.
+    ///
+    /// # Panics
+    ///
+    /// If the engine has no bus `bus`: there is one for each pair of output
+    /// channels, and one for an odd channel out.
+    pub fn set_bus(&mut self, bus: usize, mut effect: Box<dyn Effect>) -> Result<(), EngineError> {
+        assert!(bus < self.buses, "no output bus {bus}");
+        self.hand_over(|sample_rate, max_block| {
+            effect.prepare(sample_rate, max_block);
+            Message::SetBus(bus, Some(effect))
+        })
+    }
+
+    /// Takes the effect off output bus `bus`.
+    ///
+    /// # Panics
+    ///
+    /// If the engine has no bus `bus`.
+    pub fn clear_bus(&mut self, bus: usize) -> Result<(), EngineError> {
+        assert!(bus < self.buses, "no output bus {bus}");
+        self.post(Message::SetBus(bus, None))
+    }
+
+    /// Prepares `effect` and makes it the master effect, which processes
+    /// the first two output channels after the send returns, from the start
+    /// of the next block. The effect it replaces comes back through
+    /// [`collect_garbage`](Controller::collect_garbage).
+    ///
+    /// Returns [`EngineError::QueueFull`] if several effects are still on
+    /// their way to or back from the engine; collect garbage and retry.
+    pub fn set_master(&mut self, mut effect: Box<dyn Effect>) -> Result<(), EngineError> {
+        self.hand_over(|sample_rate, max_block| {
+            effect.prepare(sample_rate, max_block);
+            Message::SetMaster(Some(effect))
+        })
+    }
+
+    /// Takes the master effect off.
+    pub fn clear_master(&mut self) -> Result<(), EngineError> {
+        self.post(Message::SetMaster(None))
+    }
+
+    /// Gives every voice an insert effect of its own, built by `make` and
+    /// prepared here, from the start of the next block. Voices already
+    /// playing carry on through theirs; each is reset when its voice next
+    /// starts. The inserts they replace come back through
+    /// [`collect_garbage`](Controller::collect_garbage).
+    ///
+    /// `make` is called twice for each voice, as stolen voices fade out
+    /// through their inserts while the new note starts on a fresh one.
+    ///
+    /// Returns [`EngineError::QueueFull`] if several effects are still on
+    /// their way to or back from the engine; collect garbage and retry.
+    pub fn set_voice_inserts(
+        &mut self,
+        mut make: impl FnMut() -> Box<dyn Effect>,
+    ) -> Result<(), EngineError> {
+        let voices = self.voices;
+        if voices == 0 {
+            return Ok(());
+        }
+        self.hand_over(|sample_rate, max_block| {
+            let inserts = (0..2 * voices)
+                .map(|_| {
+                    let mut effect = make();
+                    effect.prepare(sample_rate, max_block);
+                    effect
+                })
+                .collect();
+            Message::SetInserts(Inserts::new(inserts))
+        })
+    }
+
+    /// Takes every voice's insert effect off.
+    pub fn clear_voice_inserts(&mut self) -> Result<(), EngineError> {
+        self.post(Message::SetInserts(Inserts::default()))
+    }
+
+    /// Sets parameter `param` of the effect in `slot` to `value`, from the
+    /// start of the next block. See [`Effect::params`] for what each
+    /// effect's parameters are.
+    pub fn set_param(
+        &mut self,
+        slot: EffectSlot,
+        param: usize,
+        value: f32,
+    ) -> Result<(), EngineError> {
+        self.send(Command::SetParam { slot, param, value })
+    }
+
+    /// Posts the message `build` makes from the engine's rate and block
+    /// size, which hands the engine an effect, unless too many are still
+    /// out. `build` is only called if not.
+    fn hand_over(&mut self, build: impl FnOnce(u32, usize) -> Message) -> Result<(), EngineError> {
+        self.collect_garbage();
+        if self.effects >= EFFECTS + self.buses {
+            return Err(EngineError::QueueFull);
+        }
+        self.post(build(self.sample_rate, self.max_block))?;
+        self.effects += 1;
+        Ok(())
+    }
+
+    /// Sets the tempo that tempo-synced LFOs and effects follow, in beats
+    /// per minute. Defaults to 120.
+    pub fn set_tempo(&mut self, bpm: f64) -> Result<(), EngineError> {
+        self.send(Command::SetTempo(bpm))
+    }
+
+    /// Sends a raw command, to take effect at the start of the next block.
+    pub fn send(&mut self, command: Command) -> Result<(), EngineError> {
+        self.post(Message::Command(command))
+    }
+
+    /// Sends a raw command to take effect on output frame `frame`.
+    pub fn schedule(&mut self, frame: u64, command: Command) -> Result<(), EngineError> {
+        self.post(Message::Event(Event { frame, command }))
+    }
+
+    /// Allocates a fresh voice id without sending anything, for building
+    /// [`Command::Trigger`] by hand.
+    pub fn next_voice_id(&mut self) -> VoiceId {
+        let id = VoiceId(self.next_voice);
+        self.next_voice += 1;
+        id
+    }
+
+    /// Frees memory the audio thread has finished with. Returns the number of
+    /// items reclaimed.
+    pub fn collect_garbage(&mut self) -> usize {
+        let mut count = 0;
+        while let Some(garbage) = self.garbage.pop() {
+            match garbage {
+                Garbage::Sample(id, sample) => {
+                    if self.slots[id.index()] == Slot::Retiring {
+                        self.slots[id.index()] = Slot::Free;
+                        // No voice plays the sample any more, so a streamed
+                        // one's source can close.
+                        self.streaming.set_source(id, None);
+                    }
+                    drop(sample);
+                }
+                Garbage::ModMatrix(matrix) => {
+                    self.matrices -= 1;
+                    drop(matrix);
+                }
+                Garbage::Effect(effect) => {
+                    self.effects -= 1;
+                    drop(effect);
+                }
+                Garbage::Inserts(inserts) => {
+                    self.effects -= 1;
+                    drop(inserts);
+                }
+            }
+            count += 1;
+        }
+        count
+    }
+
+    fn check_sample(&self, id: SampleId) -> Result<(), EngineError> {
+        match self.slots.get(id.index()) {
+            Some(Slot::Loaded) => Ok(()),
+            _ => Err(EngineError::UnknownSample(id)),
+        }
+    }
+
+    fn post(&mut self, message: Message) -> Result<(), EngineError> {
+        self.messages
+            .push(message)
+            .map_err(|_| EngineError::QueueFull)
+    }
+}
@@ -0,0 +1,212 @@
+//! A stereo delay.
+//!
+//! Each channel has its own delay line and time. Echoes are fed back
+//! through a high-pass and a low-pass filter, so every repeat comes back
+//! thinner and darker than the one before, the way tape and analog delays
+//! lose detail.
+
+use std::f32::consts::FRAC_1_SQRT_2;
+
+use crate::filter::{FilterMode, Svf, SvfCoefficients};
+
+use super::{Effect, ParamInfo};
+
+const PARAMS: [ParamInfo; 4] = [
+    ParamInfo {
+        name: "feedback",
+        unit: "",
+        min: 0.0,
+        max: 1.0,
+        default: 0.4,
+    },
+    ParamInfo {
+        name: "low_cut",
+        unit: "Hz",
+        min: 20.0,
+        max: 20_000.0,
+        default: 20.0,
+    },
+    ParamInfo {
+        name: "high_cut",
+        unit: "Hz",
+        min: 20.0,
+        max: 20_000.0,
+        default: 20_000.0,
+    },
+    ParamInfo {
+        name: "mix",
+        unit: "",
+        min: 0.0,
+        max: 1.0,
+        default: 1.0,
+    },
+];
+
+/// How long a delay is.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DelayTime {
+    Seconds(f32),
+    /// Beats at the engine's tempo, set with
+    /// [`Command::SetTempo`](crate::Command::SetTempo).
+    Beats(f32),
+}
+
+/// A tempo-syncable stereo delay with filtered feedback.
+///
+/// Its parameters are `feedback`, `low_cut`, `high_cut` and `mix`, in that
+/// order.
+#[derive(Clone, Debug)]
+pub struct Delay {
+    /// Delay of the left and right channels.
+    pub time: [DelayTime; 2],
+    /// Level of each repeat relative to the one before, before filtering.
+    /// Below 1 for the echoes to die away.
+    pub feedback: f32,
+    /// Feeds each channel's echoes back into the other, so repeats bounce
+    /// from side to side.
+    pub ping_pong: bool,
+    /// Cutoff of the feedback high-pass, in Hz.
+    pub low_cut: f32,
+    /// Cutoff of the feedback low-pass, in Hz.
+    pub high_cut: f32,
+    /// Share of echoes in the output: 0 passes the input through, 1 (the
+    /// default, for a send) plays only the echoes.
+    pub mix: f32,
+    /// Longest delay the lines hold, in seconds. Longer times are
+    /// shortened to it.
+    pub max_time: f32,
+    lines: [Vec<f32>; 2],
+    write: usize,
+    sample_rate: f32,
+    tempo: f64,
+    filters: [[Svf; 2]; 2],
+    low_cut_coefficients: SvfCoefficients,
+    high_cut_coefficients: SvfCoefficients,
+}
+
+impl Delay {
+    /// A delay of `time` on both channels.
+    pub fn new(time: DelayTime) -> Self {
+        Delay {
+            time: [time; 2],
+            feedback: 0.4,
+            ping_pong: false,
+            low_cut: 20.0,
+            high_cut: 20_000.0,
+            mix: 1.0,
+            max_time: 4.0,
+            lines: [Vec::new(), Vec::new()],
+            write: 0,
+            sample_rate: 48_000.0,
+            tempo: 120.0,
+            filters: [[Svf::default(); 2]; 2],
+            low_cut_coefficients: SvfCoefficients::new(20.0, FRAC_1_SQRT_2, 48_000.0),
+            high_cut_coefficients: SvfCoefficients::new(20_000.0, FRAC_1_SQRT_2, 48_000.0),
+        }
+    }
+
+    /// Delay of `time` in frames, at least one and within the lines.
+    fn frames(&self, time: DelayTime) -> f32 {
+        let seconds = match time {
+            DelayTime::Seconds(s) => s,
+            DelayTime::Beats(beats) => (f64::from(beats) * 60.0 / self.tempo) as f32,
+        };
+        let longest = self.lines[0].len().saturating_sub(2) as f32;
+        (seconds * self.sample_rate).clamp(1.0, longest.max(1.0))
+    }
+}
+
+impl Effect for Delay {
+    fn prepare(&mut self, sample_rate: u32, _max_block: usize) {
+        self.sample_rate = sample_rate as f32;
+        let len = (self.max_time.max(0.0) * self.sample_rate).ceil() as usize + 2;
+        self.lines = [vec![0.0; len], vec![0.0; len]];
+        self.low_cut_coefficients =
+            SvfCoefficients::new(self.low_cut, FRAC_1_SQRT_2, self.sample_rate);
+        self.high_cut_coefficients =
+            SvfCoefficients::new(self.high_cut, FRAC_1_SQRT_2, self.sample_rate);
+        self.reset();
+    }
+
+    fn process(&mut self, buffer: &mut [f32]) {
+        let len = self.lines[0].len();
+        if len == 0 {
+            return;
+        }
+        let delays = [self.frames(self.time[0]), self.frames(self.time[1])];
+        for frame in buffer.chunks_exact_mut(2) {
+            let mut echo = [0.0; 2];
+            for (c, e) in echo.iter_mut().enumerate() {
+                let read = (self.write as f32 - delays[c]).rem_euclid(len as f32);
+                let i = read as usize % len;
+                let t = read - read.floor();
+                let line = &self.lines[c];
+                *e = line[i] + (line[(i + 1) % len] - line[i]) * t;
+            }
+            let returned = if self.ping_pong {
+                [echo[1], echo[0]]
+            } else {
+                echo
+            };
+            for c in 0..2 {
+                let [low_cut, high_cut] = &mut self.filters[c];
+                let fed = low_cut.process(
+                    returned[c],
+                    &self.low_cut_coefficients,
+                    FilterMode::HighPass,
+                );
+                let fed = high_cut.process(fed, &self.high_cut_coefficients, FilterMode::LowPass);
+                self.lines[c][self.write] = frame[c] + fed * self.feedback;
+                frame[c] = frame[c] * (1.0 - self.mix) + echo[c] * self.mix;
+            }
+            self.write = (self.write + 1) % len;
+        }
+    }
+
+    fn reset(&mut self) {
+        for line in &mut self.lines {
+            line.fill(0.0);
+        }
+        self.filters = [[Svf::default(); 2]; 2];
+        self.write = 0;
+    }
+
+    fn set_tempo(&mut self, bpm: f64) {
+        self.tempo = bpm;
+    }
+
+    fn params(&self) -> &[ParamInfo] {
+        &PARAMS
+    }
+
+    fn param(&self, index: usize) -> f32 {
+        match index {
+            0 => self.feedback,
+            1 => self.low_cut,
+            2 => self.high_cut,
+            3 => self.mix,
+            _ => 0.0,
+        }
+    }
+
+    fn set_param(&mut self, index: usize, value: f32) {
+        let Some(info) = PARAMS.get(index) else {
+            return;
+        };
+        let value = info.clamp(value);
+        match index {
+            0 => self.feedback = value,
+            1 => {
+                self.low_cut = value;
+                self.low_cut_coefficients =
+                    SvfCoefficients::new(value, FRAC_1_SQRT_2, self.sample_rate);
+            }
+            2 => {
+                self.high_cut = value;
+                self.high_cut_coefficients =
+                    SvfCoefficients::new(value, FRAC_1_SQRT_2, self.sample_rate);
+            }
+            _ => self.mix = value,
+        }
+    }
+}
@@ -0,0 +1,204 @@
+//! Audio effects: per-voice inserts, send buses and the master.
+//!
+//! Effects implement [`Effect`], and an [`EffectChain`] runs several in
+//! series as one. Two come built in: a [`Reverb`] and a tempo-syncable
+//! stereo [`Delay`]. The engine runs effects in four places, each an
+//! [`EffectSlot`]:
+//!
+//! - On each voice, as an insert. Every voice gets its own instance, reset
+//!   when the voice starts, and plays through it on a single channel pair.
+//! - On each [output bus](crate::TriggerParams::bus), as an insert on the
+//!   bus's pair of channels once every voice has played onto it.
+//! - On the [`SENDS`] send buses. Each voice feeds every bus its stereo
+//!   output, after its insert, scaled by its
+//!   [send level](crate::TriggerParams::sends); the bus's effect processes
+//!   the sum and its return is mixed into the first two output channels.
+//! - On the master: the first two output channels, which are also the first
+//!   output bus, once the returns are in.
+//!
+//! An output bus or master that is a single channel is processed as both
+//! sides of a stereo pair and folded back.
+//!
+//! Effects are prepared and handed over whole by the
+//! [`Controller`](crate::Controller), so their buffers are allocated on
+//! the control thread; an effect or set of voice inserts that is replaced
+//! goes back to the controller and is dropped by
+//! [`collect_garbage`](crate::Controller::collect_garbage), never on the
+//! audio thread. Parameters can be changed in place, from the next block,
+//! with [`Command::SetParam`](crate::Command::SetParam).
+
+pub mod delay;
+pub mod reverb;
+
+pub use delay::{Delay, DelayTime};
+pub use reverb::Reverb;
+
+/// Number of send buses.
+pub const SENDS: usize = 2;
+
+/// Where the engine runs an effect.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum EffectSlot {
+    /// Every voice's insert.
+    Voice,
+    /// A send bus, below [`SENDS`].
+    Send(usize),
+    /// An output bus: the pair of output channels starting at twice its
+    /// number.
+    Bus(usize),
+    Master,
+}
+
+/// Describes one of an effect's parameters.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ParamInfo {
+    pub name: &'static str,
+    /// Unit of the value, such as `"Hz"`, or `""` for none.
+    pub unit: &'static str,
+    pub min: f32,
+    pub max: f32,
+    pub default: f32,
+}
+
+impl ParamInfo {
+    /// `value` kept within the parameter's range.
+    pub fn clamp(&self, value: f32) -> f32 {
+        value.clamp(self.min, self.max)
+    }
+}
+
+/// An audio effect run on the audio thread.
+///
+/// [`process`](Effect::process), [`reset`](Effect::reset),
+/// [`set_tempo`](Effect::set_tempo) and [`set_param`](Effect::set_param)
+/// are called from [`Engine::render`](crate::Engine::render), so they must
+/// not allocate, lock or block.
+pub trait Effect: Send {
+    /// Sizes the effect for `sample_rate` and for blocks of up to
+    /// `max_block` frames. Called on the control thread before the effect
+    /// reaches the engine, so this is the place to allocate.
+    fn prepare(&mut self, sample_rate: u32, max_block: usize);
+
+    /// Processes interleaved stereo frames in place.
+    fn process(&mut self, buffer: &mut [f32]);
+
+    /// Clears any state carried between blocks, such as a tail.
+    fn reset(&mut self);
+
+    /// Follows the engine's tempo, in beats per minute. Called when the
+    /// effect is installed and whenever the tempo changes.
+    fn set_tempo(&mut self, _bpm: f64) {}
+
+    /// The parameters [`param`](Effect::param) and
+    /// [`set_param`](Effect::set_param) take indices into.
+    fn params(&self) -> &[ParamInfo] {
+        &[]
+    }
+
+    /// Current value of parameter `index`, or 0 past the last.
+    fn param(&self, _index: usize) -> f32 {
+        0.0
+    }
+
+    /// Sets parameter `index`, kept within its range. Does nothing past the
+    /// last.
+    fn set_param(&mut self, _index: usize, _value: f32) {}
+}
+
+/// Effects run one after another.
+///
+/// A chain's parameters are those of its effects in order, so parameter
+/// `n` of the second effect is parameter `n` plus the first effect's count.
+#[derive(Default)]
+pub struct EffectChain {
+    effects: Vec<Box<dyn Effect>>,
+    params: Vec<ParamInfo>,
+}
+
+impl EffectChain {
+    /// An empty chain, which passes audio through.
+    pub fn new() -> Self {
+        EffectChain::default()
+    }
+
+    /// Adds `effect` to the end of the chain.
+    pub fn with(mut self, effect: impl Effect + 'static) -> Self {
+        self.push(Box::new(effect));
+        self
+    }
+
+    /// Adds `effect` to the end of the chain.
+    pub fn push(&mut self, effect: Box<dyn Effect>) {
+        self.params.extend_from_slice(effect.params());
+        self.effects.push(effect);
+    }
+
+    /// Number of effects in the chain.
+    pub fn len(&self) -> usize {
+        self.effects.len()
+    }
+
+    /// Returns `true` if the chain has no effects.
+    pub fn is_empty(&self) -> bool {
+        self.effects.is_empty()
+    }
+
+    /// The effect owning chain parameter `index`, and the index within it.
+    fn locate(&mut self, mut index: usize) -> Option<(&mut dyn Effect, usize)> {
+        for effect in &mut self.effects {
+            let count = effect.params().len();
+            if index < count {
+                return Some((effect.as_mut(), index));
+            }
+            index -= count;
+        }
+        None
+    }
+}
+
+impl Effect for EffectChain {
+    fn prepare(&mut self, sample_rate: u32, max_block: usize) {
+        for effect in &mut self.effects {
+            effect.prepare(sample_rate, max_block);
+        }
+    }
+
+    fn process(&mut self, buffer: &mut [f32]) {
+        for effect in &mut self.effects {
+            effect.process(buffer);
+        }
+    }
+
+    fn reset(&mut self) {
+        for effect in &mut self.effects {
+            effect.reset();
+        }
+    }
+
+    fn set_tempo(&mut self, bpm: f64) {
+        for effect in &mut self.effects {
+            effect.set_tempo(bpm);
+        }
+    }
+
+    fn params(&self) -> &[ParamInfo] {
+        &self.params
+    }
+
+    fn param(&self, mut index: usize) -> f32 {
+        for effect in &self.effects {
+            let count = effect.params().len();
+            if index < count {
+                return effect.param(index);
+            }
+            index -= count;
+        }
+        0.0
+    }
+
+    fn set_param(&mut self, index: usize, value: f32) {
+        if let Some((effect, index)) = self.locate(index) {
+            effect.set_param(index, value);
+        }
+    }
+}
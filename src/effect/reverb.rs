@@ -0,0 +1,202 @@
+//! An algorithmic reverb after Jezar's Freeverb.
+//!
+//! Each channel runs eight parallel lowpass-feedback comb filters into four
+//! series allpass filters. The right channel's delays are a few frames
+//! longer than the left's, which decorrelates the two into a wide stereo
+//! tail. Delay lengths are Freeverb's, scaled from 44.1 kHz to the engine's
+//! rate.
+
+use super::{Effect, ParamInfo};
+
+const COMBS: [usize; 8] = [1116, 1188, 1277, 1356, 1422, 1491, 1557, 1617];
+const ALLPASSES: [usize; 4] = [556, 441, 341, 225];
+/// Extra delay of the right channel, in frames at 44.1 kHz.
+const SPREAD: usize = 23;
+const INPUT_GAIN: f32 = 0.015;
+
+const PARAMS: [ParamInfo; 4] = [
+    ParamInfo {
+        name: "room_size",
+        unit: "",
+        min: 0.0,
+        max: 1.0,
+        default: 0.5,
+    },
+    ParamInfo {
+        name: "damping",
+        unit: "",
+        min: 0.0,
+        max: 1.0,
+        default: 0.5,
+    },
+    ParamInfo {
+        name: "width",
+        unit: "",
+        min: 0.0,
+        max: 1.0,
+        default: 1.0,
+    },
+    ParamInfo {
+        name: "mix",
+        unit: "",
+        min: 0.0,
+        max: 1.0,
+        default: 1.0,
+    },
+];
+
+#[derive(Clone, Debug, Default)]
+struct Comb {
+    buffer: Vec<f32>,
+    index: usize,
+    /// The lowpass in the feedback path.
+    store: f32,
+}
+
+impl Comb {
+    #[inline]
+    fn process(&mut self, x: f32, feedback: f32, damp: f32) -> f32 {
+        let out = self.buffer[self.index];
+        self.store = out + (self.store - out) * damp;
+        self.buffer[self.index] = x + self.store * feedback;
+        self.index = (self.index + 1) % self.buffer.len();
+        out
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+struct Allpass {
+    buffer: Vec<f32>,
+    index: usize,
+}
+
+impl Allpass {
+    #[inline]
+    fn process(&mut self, x: f32) -> f32 {
+        let delayed = self.buffer[self.index];
+        self.buffer[self.index] = x + delayed * 0.5;
+        self.index = (self.index + 1) % self.buffer.len();
+        delayed - x
+    }
+}
+
+/// A stereo Freeverb-style reverb.
+///
+/// Its parameters are `room_size`, `damping`, `width` and `mix`, in that
+/// order.
+#[derive(Clone, Debug)]
+pub struct Reverb {
+    /// Size of the room, from 0 to 1. Larger rooms ring longer.
+    pub room_size: f32,
+    /// How quickly high frequencies die away, from 0 to 1.
+    pub damping: f32,
+    /// Stereo width of the tail, from 0 (mono) to 1.
+    pub width: f32,
+    /// Share of reverb in the output: 0 passes the input through, 1 (the
+    /// default, for a send) plays only the reverb.
+    pub mix: f32,
+    combs: [[Comb; 8]; 2],
+    allpasses: [[Allpass; 4]; 2],
+}
+
+impl Default for Reverb {
+    fn default() -> Self {
+        Reverb {
+            room_size: 0.5,
+            damping: 0.5,
+            width: 1.0,
+            mix: 1.0,
+            combs: Default::default(),
+            allpasses: Default::default(),
+        }
+    }
+}
+
+impl Reverb {
+    /// A medium room with moderate damping.
+    pub fn new() -> Self {
+        Reverb::default()
+    }
+}
+
+impl Effect for Reverb {
+    fn prepare(&mut self, sample_rate: u32, _max_block: usize) {
+        let scale = |frames: usize| {
+            ((frames as f64 * f64::from(sample_rate) / 44_100.0).round() as usize).max(1)
+        };
+        for (c, spread) in [0, SPREAD].into_iter().enumerate() {
+            for (comb, frames) in self.combs[c].iter_mut().zip(COMBS) {
+                comb.buffer = vec![0.0; scale(frames + spread)];
+            }
+            for (allpass, frames) in self.allpasses[c].iter_mut().zip(ALLPASSES) {
+                allpass.buffer = vec![0.0; scale(frames + spread)];
+            }
+        }
+        self.reset();
+    }
+
+    fn process(&mut self, buffer: &mut [f32]) {
+        if self.combs[0][0].buffer.is_empty() {
+            return;
+        }
+        let feedback = 0.7 + 0.28 * self.room_size.clamp(0.0, 1.0);
+        let damp = 0.4 * self.damping.clamp(0.0, 1.0);
+        let width = self.width.clamp(0.0, 1.0);
+        let (near, far) = ((1.0 + width) / 2.0, (1.0 - width) / 2.0);
+        for frame in buffer.chunks_exact_mut(2) {
+            let input = (frame[0] + frame[1]) * INPUT_GAIN;
+            let mut wet = [0.0; 2];
+            for (c, w) in wet.iter_mut().enumerate() {
+                for comb in &mut self.combs[c] {
+                    *w += comb.process(input, feedback, damp);
+                }
+                for allpass in &mut self.allpasses[c] {
+                    *w = allpass.process(*w);
+                }
+            }
+            let left = wet[0] * near + wet[1] * far;
+            let right = wet[1] * near + wet[0] * far;
+            frame[0] = frame[0] * (1.0 - self.mix) + left * self.mix;
+            frame[1] = frame[1] * (1.0 - self.mix) + right * self.mix;
+        }
+    }
+
+    fn reset(&mut self) {
+        for comb in self.combs.iter_mut().flatten() {
+            comb.buffer.fill(0.0);
+            comb.index = 0;
+            comb.store = 0.0;
+        }
+        for allpass in self.allpasses.iter_mut().flatten() {
+            allpass.buffer.fill(0.0);
+            allpass.index = 0;
+        }
+    }
+
+    fn params(&self) -> &[ParamInfo] {
+        &PARAMS
+    }
+
+    fn param(&self, index: usize) -> f32 {
+        match index {
+            0 => self.room_size,
+            1 => self.damping,
+            2 => self.width,
+            3 => self.mix,
+            _ => 0.0,
+        }
+    }
+
+    fn set_param(&mut self, index: usize, value: f32) {
+        let Some(info) = PARAMS.get(index) else {
+            return;
+        };
+        let value = info.clamp(value);
+        match index {
+            0 => self.room_size = value,
+            1 => self.damping = value,
+            2 => self.width = value,
+            _ => self.mix = value,
+        }
+    }
+}
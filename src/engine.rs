@@ -0,0 +1,676 @@
+//! The real-time playback engine.
+//!
+//! [`Engine::new`] returns two halves. The [`Engine`] lives on the audio
+//! thread and is driven by [`Engine::render`]; the [`Controller`] lives on a
+//! control thread and sends it commands through a lock-free SPSC queue.
+//!
+//! `render` never allocates, locks or blocks. Everything it needs (voices,
+//! sample slots, queues) is allocated up front by [`Engine::new`], and memory
+//! it releases, such as a removed sample, is handed back to the controller
+//! instead of being freed on the audio thread.
+//!
+//! Commands sent with [`Controller::send`] take effect at the start of the
+//! next block. An [`Event`] instead carries the output frame it is due on;
+//! the engine splits its blocks at those frames so scheduled commands land
+//! exactly, whatever the buffer size.
+//!
+//! A [modulation matrix](crate::modulation) is handed over the same way as
+//! a sample: whole, behind an `Arc`, with the one it replaces returned to
+//! the controller. So are [effects](crate::effect), in a `Box`.
+//!
+//! Samples too long to hold in memory can be [streamed](crate::stream) from
+//! disk. Each voice gets a stream buffer, allocated here as well, that a
+//! [`Streamer`](crate::stream::Streamer) fills on an I/O thread.
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use crate::controller::Controller;
+use crate::effect::{Effect, EffectSlot, SENDS};
+use crate::interp::SincTable;
+use crate::modulation::{ChannelControls, ModMatrix};
+use crate::pan::PanLaw;
+use crate::pool::{Inserts, StealPolicy, VoicePool};
+use crate::resample::{self, ResampleQuality};
+use crate::sample::Sample;
+use crate::spsc::{self, Consumer, Producer};
+use crate::stream::{self, Streams};
+use crate::voice::{RenderContext, TriggerParams, VoiceId};
+
+/// Modulation matrices the controller can have handed over and not yet
+/// had back: the engine's current one plus those queued or retired.
+pub(crate) const MATRICES: usize = 4;
+
+/// Effects the controller can have handed over and not yet had back: one on
+/// each send, the master and the voices' set of inserts, plus a few queued
+/// or retired. Each output bus can hold one more.
+pub(crate) const EFFECTS: usize = SENDS + 2 + 4;
+
+/// Identifies a sample loaded into an engine slot.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct SampleId(pub(crate) u32);
+
+impl SampleId {
+    /// The slot index backing this id.
+    pub fn index(self) -> usize {
+        self.0 as usize
+    }
+}
+
+/// Fixed resources allocated when the engine is created.
+#[derive(Clone, Debug, PartialEq)]
+pub struct EngineConfig {
+    /// Output sample rate in Hz.
+    pub sample_rate: u32,
+    /// Number of interleaved output channels.
+    pub channels: usize,
+    /// Number of voices that can play at once.
+    pub voices: usize,
+    /// What to do when a trigger arrives and every voice is busy.
+    pub steal_policy: StealPolicy,
+    /// Fade-out time for stolen voices, in seconds.
+    pub steal_fade: f32,
+    /// Number of sample slots.
+    pub max_samples: usize,
+    /// Capacity of the command queue, and the number of scheduled events the
+    /// engine holds before they fall due.
+    pub queue_capacity: usize,
+    /// Size of each voice's disk-streaming buffer, in stereo frames. Every
+    /// voice and every fade-out slot gets one; 0 disables streaming.
+    pub stream_buffer: usize,
+    /// Converts samples added with [`Controller::add_sample`] to
+    /// `sample_rate` at this quality. With `None`, samples keep their own
+    /// rate and voices fold the ratio into their pitch instead.
+    ///
+    /// Frame positions in commands, such as a trigger's start frame or a
+    /// seek, stay in the frames of the sample as it was added either way.
+    /// Streamed samples are never converted.
+    pub resample: Option<ResampleQuality>,
+    /// How panned voices share their level between the two channels of
+    /// their bus.
+    pub pan_law: PanLaw,
+    /// Most frames rendered in one go; longer blocks are split. Sizes the
+    /// buffers effects work in.
+    pub max_block: usize,
+    /// Length of the grains [time-stretched](crate::stretch) voices are
+    /// built from, in frames: a power of two of at least 64. Every voice
+    /// and every fade-out slot gets buffers for it; 0 disables stretching.
+    pub stretch_window: usize,
+}
+
+impl Default for EngineConfig {
+    fn default() -> Self {
+        EngineConfig {
+            sample_rate: 48_000,
+            channels: 2,
+            voices: 32,
+            steal_policy: StealPolicy::Oldest,
+            steal_fade: 0.005,
+            max_samples: 256,
+            queue_capacity: 1024,
+            stream_buffer: 16_384,
+            resample: None,
+            pan_law: PanLaw::default(),
+            max_block: 1024,
+            stretch_window: 2048,
+        }
+    }
+}
+
+/// Errors reported by the [`Controller`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EngineError {
+    /// The command queue is full; the audio thread has not caught up yet.
+    QueueFull,
+    /// Every sample slot is in use.
+    NoFreeSlot,
+    /// The sample id does not refer to a loaded sample.
+    UnknownSample(SampleId),
+    /// A streamed sample was added to an engine created with
+    /// [`EngineConfig::stream_buffer`] set to 0.
+    StreamingDisabled,
+}
+
+impl fmt::Display for EngineError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EngineError::QueueFull => write!(f, "engine command queue is full"),
+            EngineError::NoFreeSlot => write!(f, "no free sample slot"),
+            EngineError::UnknownSample(id) => write!(f, "sample {} is not loaded", id.0),
+            EngineError::StreamingDisabled => write!(f, "engine has no stream buffers"),
+        }
+    }
+}
+
+impl std::error::Error for EngineError {}
+
+/// A playback command addressed to the audio thread.
+// Commands travel by value through preallocated queues; boxing the trigger
+// would leave the audio thread to free it.
+#[allow(clippy::large_enum_variant)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Command {
+    /// Starts `sample` on a free voice, identified from now on by `voice`.
+    Trigger {
+        voice: VoiceId,
+        sample: SampleId,
+        params: TriggerParams,
+    },
+    /// Signals note-off to a voice.
+    Release(VoiceId),
+    /// Silences a voice.
+    Stop(VoiceId),
+    /// Moves a voice's playback position.
+    Seek { voice: VoiceId, frame: usize },
+    /// Sets how much longer a [stretched](crate::stretch) voice makes its
+    /// sample last, leaving its pitch alone.
+    SetStretch { voice: VoiceId, ratio: f64 },
+    /// Sets a [stretched](crate::stretch) voice's pitch shift in semitones,
+    /// leaving its tempo alone.
+    SetPitchShift { voice: VoiceId, semitones: f32 },
+    /// Silences every voice.
+    StopAll,
+    /// Changes the voice-stealing policy.
+    SetStealPolicy(StealPolicy),
+    /// Scales the playback rate of every voice on a MIDI channel, including
+    /// voices triggered later. `1.0` is no bend.
+    PitchBend { channel: u8, ratio: f64 },
+    /// Sets a MIDI channel's modulation wheel, in `0..=1`.
+    ModWheel { channel: u8, value: f32 },
+    /// Sets a MIDI channel's aftertouch, in `0..=1`.
+    Aftertouch { channel: u8, value: f32 },
+    /// Sets the tempo, in beats per minute, that synced LFOs and effects
+    /// follow.
+    SetTempo(f64),
+    /// Sets parameter `param` of the effect in `slot`, or of every voice's
+    /// insert for [`EffectSlot::Voice`]. Does nothing if the slot is empty
+    /// or does not exist.
+    SetParam {
+        slot: EffectSlot,
+        param: usize,
+        value: f32,
+    },
+}
+
+/// A command due at an absolute output frame.
+///
+/// Frames count from the engine's creation; [`Controller::now`] reports how
+/// far rendering has got. An event whose frame has already passed takes
+/// effect at the start of the next block.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Event {
+    pub frame: u64,
+    pub command: Command,
+}
+
+/// Everything that travels from the controller to the engine.
+pub(crate) enum Message {
+    Command(Command),
+    Event(Event),
+    /// A sample and the factor its rate was converted by.
+    InsertSample(SampleId, Arc<Sample>, f64),
+    /// A streamed sample's preloaded head and its total length.
+    InsertStreamed(SampleId, Arc<Sample>, usize),
+    RemoveSample(SampleId),
+    SetModMatrix(Arc<ModMatrix>),
+    /// An effect for a send bus, or `None` to clear it.
+    SetSend(usize, Option<Box<dyn Effect>>),
+    /// An effect for an output bus, or `None` to clear it.
+    SetBus(usize, Option<Box<dyn Effect>>),
+    SetMaster(Option<Box<dyn Effect>>),
+    /// Every voice's and tail's insert, or none to clear them.
+    SetInserts(Inserts),
+}
+
+/// Memory retired by the audio thread, to be dropped by the controller.
+pub(crate) enum Garbage {
+    Sample(SampleId, Arc<Sample>),
+    ModMatrix(Arc<ModMatrix>),
+    Effect(Box<dyn Effect>),
+    Inserts(Inserts),
+}
+
+/// The audio-thread half of the engine.
+pub struct Engine {
+    config: EngineConfig,
+    messages: Consumer<Message>,
+    garbage: Producer<Garbage>,
+    samples: Vec<Option<Arc<Sample>>>,
+    /// How much each sample's rate was converted by when it was added.
+    frame_scales: Vec<f64>,
+    /// Total frames of each streamed sample.
+    stream_lengths: Vec<Option<usize>>,
+    streaming: Arc<stream::Shared>,
+    pool: VoicePool,
+    sinc: SincTable,
+    bend: [f64; 16],
+    controls: [ChannelControls; 16],
+    tempo: f64,
+    modulation: Option<Arc<ModMatrix>>,
+    effects: [Option<Box<dyn Effect>>; SENDS],
+    /// The insert on each output bus.
+    buses: Vec<Option<Box<dyn Effect>>>,
+    master: Option<Box<dyn Effect>>,
+    /// Interleaved stereo input of each send bus.
+    sends: [Vec<f32>; SENDS],
+    /// Where voices that send or have an insert render before being mixed,
+    /// and where bus and master effects work.
+    scratch: Vec<f32>,
+    /// Scheduled events in frame order, oldest first.
+    pending: VecDeque<Event>,
+    /// Frames rendered so far.
+    now: u64,
+    clock: Arc<AtomicU64>,
+}
+
+impl Engine {
+    /// Allocates an engine and the controller that drives it.
+    pub fn new(config: EngineConfig) -> (Engine, Controller) {
+        assert!(config.channels > 0, "engine needs at least one channel");
+        assert!(config.max_block > 0, "engine needs a block size");
+        let (tx, messages) = spsc::channel(config.queue_capacity);
+        // Each slot can retire at most one sample before the controller
+        // reclaims it, and the controller keeps at most `MATRICES` matrices
+        // and `EFFECTS` effects plus one for each output bus out, so this
+        // queue can never overflow.
+        let buses = config.channels.div_ceil(2);
+        let (garbage, garbage_rx) = spsc::channel(config.max_samples + MATRICES + EFFECTS + buses);
+        let fade_frames = (config.steal_fade * config.sample_rate as f32).round() as usize;
+        let clock = Arc::new(AtomicU64::new(0));
+        let buffers = if config.stream_buffer > 0 {
+            2 * config.voices
+        } else {
+            0
+        };
+        let streaming = Arc::new(stream::Shared::new(
+            buffers,
+            2 * config.stream_buffer,
+            config.max_samples,
+        ));
+        let engine = Engine {
+            samples: vec![None; config.max_samples],
+            frame_scales: vec![1.0; config.max_samples],
+            stream_lengths: vec![None; config.max_samples],
+            streaming: Arc::clone(&streaming),
+            pool: VoicePool::new(config.voices, config.steal_policy, fade_frames)
+                .with_stretch_window(config.stretch_window),
+            sinc: SincTable::new(),
+            bend: [1.0; 16],
+            controls: [ChannelControls::default(); 16],
+            tempo: 120.0,
+            modulation: None,
+            effects: Default::default(),
+            buses: (0..buses).map(|_| None).collect(),
+            master: None,
+            sends: std::array::from_fn(|_| vec![0.0; 2 * config.max_block]),
+            scratch: vec![0.0; config.channels.max(2) * config.max_block],
+            pending: VecDeque::with_capacity(config.queue_capacity),
+            now: 0,
+            clock: Arc::clone(&clock),
+            messages,
+            garbage,
+            config,
+        };
+        let controller = Controller::new(tx, garbage_rx, clock, streaming, &engine.config);
+        (engine, controller)
+    }
+
+    /// The configuration the engine was created with.
+    pub fn config(&self) -> &EngineConfig {
+        &self.config
+    }
+
+    /// Number of voices currently producing sound, not counting stolen
+    /// voices that are fading out.
+    pub fn active_voices(&self) -> usize {
+        self.pool.active()
+    }
+
+    /// The engine's voices.
+    pub fn pool(&self) -> &VoicePool {
+        &self.pool
+    }
+
+    /// Number of output frames rendered so far.
+    pub fn frame(&self) -> u64 {
+        self.now
+    }
+
+    /// Number of scheduled events that have not fallen due yet.
+    pub fn pending_events(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Number of times a voice ran out of streamed data and went silent
+    /// waiting for the disk.
+    pub fn underruns(&self) -> u64 {
+        self.streaming.underruns.load(Ordering::Relaxed)
+    }
+
+    /// Renders the next block into `out`, interleaved with
+    /// [`EngineConfig::channels`] channels. Any trailing partial frame is
+    /// zeroed.
+    ///
+    /// Scheduled events due inside the block are applied on their exact
+    /// frame, with the block rendered in pieces between them. Pieces are at
+    /// most [`EngineConfig::max_block`] frames long.
+    ///
+    /// Voices play through their inserts, then each output bus through its
+    /// own. Voices that send feed the send effects, whose returns are mixed
+    /// into the first two channels, which the master effect then processes.
+    ///
+    /// Safe to call from an audio callback: it never allocates, locks or
+    /// blocks.
+    pub fn render(&mut self, out: &mut [f32]) {
+        out.fill(0.0);
+        self.drain_messages();
+
+        let channels = self.config.channels;
+        let frames = out.len() / channels;
+        let mut done = 0;
+        loop {
+            while let Some(event) = self.pending.front() {
+                if event.frame > self.now {
+                    break;
+                }
+                let command = event.command;
+                self.pending.pop_front();
+                self.apply(command);
+            }
+            if done == frames {
+                break;
+            }
+            let remaining = (frames - done) as u64;
+            let run = match self.pending.front() {
+                Some(event) => (event.frame - self.now).min(remaining),
+                None => remaining,
+            } as usize;
+            let run = run.min(self.config.max_block);
+            let ctx = RenderContext {
+                sample_rate: self.config.sample_rate,
+                sinc: &self.sinc,
+                bend: &self.bend,
+                controls: &self.controls,
+                tempo: self.tempo,
+                modulation: self.modulation.as_deref(),
+                pan_law: self.config.pan_law,
+                streams: Streams {
+                    buffers: &self.streaming.buffers,
+                    lengths: &self.stream_lengths,
+                    underruns: &self.streaming.underruns,
+                },
+            };
+            let out = &mut out[done * channels..(done + run) * channels];
+            let sending = self.effects.iter().any(Option::is_some);
+            if sending {
+                for send in &mut self.sends {
+                    send[..2 * run].fill(0.0);
+                }
+                self.pool.render_sends(
+                    &self.samples,
+                    &ctx,
+                    out,
+                    channels,
+                    &mut self.scratch,
+                    Some(&mut self.sends),
+                );
+            } else {
+                self.pool
+                    .render_sends(&self.samples, &ctx, out, channels, &mut self.scratch, None);
+            }
+            for (bus, effect) in self.buses.iter_mut().enumerate() {
+                if let Some(effect) = effect {
+                    Self::process_pair(effect.as_mut(), &mut self.scratch, out, channels, 2 * bus);
+                }
+            }
+            if sending {
+                Self::mix_returns(&mut self.effects, &mut self.sends, out, channels);
+            }
+            if let Some(master) = &mut self.master {
+                Self::process_pair(master.as_mut(), &mut self.scratch, out, channels, 0);
+            }
+            done += run;
+            self.now += run as u64;
+        }
+        self.clock.store(self.now, Ordering::Release);
+    }
+
+    /// Runs each send's effect over its input and adds the return to the
+    /// first two channels of `out`, or to the only one.
+    fn mix_returns(
+        effects: &mut [Option<Box<dyn Effect>>; SENDS],
+        sends: &mut [Vec<f32>; SENDS],
+        out: &mut [f32],
+        channels: usize,
+    ) {
+        let frames = out.len() / channels;
+        for (effect, send) in effects.iter_mut().zip(sends) {
+            let Some(effect) = effect else {
+                continue;
+            };
+            let send = &mut send[..2 * frames];
+            effect.process(send);
+            for (frame, ret) in out.chunks_exact_mut(channels).zip(send.chunks_exact(2)) {
+                if channels == 1 {
+                    frame[0] += (ret[0] + ret[1]) / 2.0;
+                } else {
+                    frame[0] += ret[0];
+                    frame[1] += ret[1];
+                }
+            }
+        }
+    }
+
+    /// Runs `effect` over the pair of channels of `out` starting at
+    /// `first`, or over a lone last channel as both sides of a stereo pair.
+    fn process_pair(
+        effect: &mut dyn Effect,
+        scratch: &mut [f32],
+        out: &mut [f32],
+        channels: usize,
+        first: usize,
+    ) {
+        let frames = out.len() / channels;
+        let width = (channels - first).min(2);
+        let stereo = &mut scratch[..2 * frames];
+        for (frame, s) in out.chunks_exact(channels).zip(stereo.chunks_exact_mut(2)) {
+            s[0] = frame[first];
+            s[1] = frame[first + width - 1];
+        }
+        effect.process(stereo);
+        for (frame, s) in out.chunks_exact_mut(channels).zip(stereo.chunks_exact(2)) {
+            if width == 1 {
+                frame[first] = (s[0] + s[1]) / 2.0;
+            } else {
+                frame[first] = s[0];
+                frame[first + 1] = s[1];
+            }
+        }
+    }
+
+    fn drain_messages(&mut self) {
+        // Scheduled events wait in `pending`; once it is full, leave the rest
+        // in the queue until some fall due.
+        while self.pending.len() < self.pending.capacity() {
+            let Some(message) = self.messages.pop() else {
+                break;
+            };
+            match message {
+                Message::Command(command) => self.apply(command),
+                Message::Event(event) => {
+                    // Equal frames keep the order they were sent in.
+                    let at = self.pending.partition_point(|e| e.frame <= event.frame);
+                    self.pending.insert(at, event);
+                }
+                Message::InsertSample(id, sample, scale) => self.insert(id, sample, scale, None),
+                Message::InsertStreamed(id, head, frames) => {
+                    self.insert(id, head, 1.0, Some(frames))
+                }
+                Message::SetModMatrix(matrix) => {
+                    if let Some(old) = self.modulation.replace(matrix) {
+                        self.retire(Garbage::ModMatrix(old));
+                    }
+                }
+                Message::SetSend(index, mut effect) => {
+                    if let Some(effect) = &mut effect {
+                        effect.set_tempo(self.tempo);
+                    }
+                    let old = std::mem::replace(&mut self.effects[index], effect);
+                    if let Some(old) = old {
+                        self.retire(Garbage::Effect(old));
+                    }
+                }
+                Message::SetBus(bus, mut effect) => {
+                    if let Some(effect) = &mut effect {
+                        effect.set_tempo(self.tempo);
+                    }
+                    let old = std::mem::replace(&mut self.buses[bus], effect);
+                    if let Some(old) = old {
+                        self.retire(Garbage::Effect(old));
+                    }
+                }
+                Message::SetMaster(mut effect) => {
+                    if let Some(effect) = &mut effect {
+                        effect.set_tempo(self.tempo);
+                    }
+                    if let Some(old) = std::mem::replace(&mut self.master, effect) {
+                        self.retire(Garbage::Effect(old));
+                    }
+                }
+                Message::SetInserts(mut inserts) => {
+                    for insert in inserts.iter_mut() {
+                        insert.set_tempo(self.tempo);
+                    }
+                    let old = self.pool.replace_inserts(inserts);
+                    if !old.is_empty() {
+                        self.retire(Garbage::Inserts(old));
+                    }
+                }
+                Message::RemoveSample(id) => {
+                    self.pool.stop_sample(id);
+                    self.stream_lengths[id.index()] = None;
+                    if let Some(sample) = self.samples[id.index()].take() {
+                        self.retire(Garbage::Sample(id, sample));
+                    }
+                }
+            }
+        }
+    }
+
+    fn insert(&mut self, id: SampleId, sample: Arc<Sample>, scale: f64, streamed: Option<usize>) {
+        let old = self.samples[id.index()].replace(sample);
+        debug_assert!(old.is_none(), "controller reused an occupied slot");
+        if let Some(old) = old {
+            self.retire(Garbage::Sample(id, old));
+        }
+        self.frame_scales[id.index()] = scale;
+        self.stream_lengths[id.index()] = streamed;
+    }
+
+    fn apply(&mut self, command: Command) {
+        match command {
+            Command::Trigger {
+                voice,
+                sample,
+                mut params,
+            } => {
+                if self.samples[sample.index()].is_none() {
+                    return;
+                }
+                let scale = self.frame_scales[sample.index()];
+                if scale != 1.0 {
+                    params.start = resample::scale_frame(params.start, scale);
+                    params.loop_region = params
+                        .loop_region
+                        .map(|region| resample::scale_region(region, scale));
+                }
+                if let Some(group) = params.choke_group {
+                    self.pool.choke(group, self.config.sample_rate);
+                }
+                self.pool.trigger(voice, sample, &params);
+                if let (Some(matrix), Some(v)) = (&self.modulation, self.pool.get_mut(voice)) {
+                    let controls = self.controls[usize::from(params.channel & 0x0f)];
+                    v.start_modulation(matrix, controls, scale);
+                }
+            }
+            Command::Release(id) => {
+                if let Some(v) = self.pool.get_mut(id) {
+                    v.release();
+                }
+            }
+            Command::Stop(id) => {
+                if let Some(v) = self.pool.get_mut(id) {
+                    v.stop();
+                }
+            }
+            Command::Seek { voice, frame } => {
+                if let Some(v) = self.pool.get_mut(voice) {
+                    let scale = self.frame_scales[v.sample().index()];
+                    v.seek(resample::scale_frame(frame, scale));
+                }
+            }
+            Command::SetStretch { voice, ratio } => {
+                if let Some(v) = self.pool.get_mut(voice) {
+                    v.set_stretch(ratio);
+                }
+            }
+            Command::SetPitchShift { voice, semitones } => {
+                if let Some(v) = self.pool.get_mut(voice) {
+                    v.set_pitch_shift(semitones);
+                }
+            }
+            Command::StopAll => self.pool.stop_all(),
+            Command::SetStealPolicy(policy) => self.pool.set_policy(policy),
+            Command::PitchBend { channel, ratio } => {
+                self.bend[usize::from(channel & 0x0f)] = ratio;
+            }
+            Command::ModWheel { channel, value } => {
+                self.controls[usize::from(channel & 0x0f)].mod_wheel = value;
+            }
+            Command::Aftertouch { channel, value } => {
+                self.controls[usize::from(channel & 0x0f)].aftertouch = value;
+            }
+            Command::SetTempo(bpm) => {
+                self.tempo = bpm;
+                let sends = self.effects.iter_mut().flatten();
+                let buses = self.buses.iter_mut().flatten();
+                let inserts = self.pool.inserts_mut().iter_mut();
+                for effect in sends.chain(buses).chain(&mut self.master).chain(inserts) {
+                    effect.set_tempo(bpm);
+                }
+            }
+            Command::SetParam { slot, param, value } => match slot {
+                EffectSlot::Voice => {
+                    for insert in self.pool.inserts_mut().iter_mut() {
+                        insert.set_param(param, value);
+                    }
+                }
+                EffectSlot::Send(send) => {
+                    if let Some(Some(effect)) = self.effects.get_mut(send) {
+                        effect.set_param(param, value);
+                    }
+                }
+                EffectSlot::Bus(bus) => {
+                    if let Some(Some(effect)) = self.buses.get_mut(bus) {
+                        effect.set_param(param, value);
+                    }
+                }
+                EffectSlot::Master => {
+                    if let Some(master) = &mut self.master {
+                        master.set_param(param, value);
+                    }
+                }
+            },
+        }
+    }
+
+    fn retire(&mut self, garbage: Garbage) {
+        if let Err(garbage) = self.garbage.push(garbage) {
+            // Unreachable by construction (see `new`), but leaking is the only
+            // option that keeps deallocation off the audio thread.
+            std::mem::forget(garbage);
+        }
+    }
+}
@@ -0,0 +1,217 @@
+//! Attack-hold-decay-sustain-release envelopes.
+//!
+//! An [`Adsr`] describes the shape in seconds; an [`Envelope`] is the running
+//! state that steps through it one frame at a time. Stepping is
+//! allocation-free and sample-accurate: stage changes happen on the exact
+//! frame they fall due, and a release starts from whatever level the
+//! envelope has reached, so it never jumps.
+
+/// Shape of one envelope stage.
+///
+/// Each curve maps normalised stage time `t` in `0..=1` to progress in
+/// `0..=1`; the stage then moves from its start level towards its target by
+/// that fraction.
+#[derive(Clone, Copy, Debug, Default)]
+pub enum Curve {
+    /// Constant rate of change.
+    #[default]
+    Linear,
+    /// `(1 - e^(-k t)) / (1 - e^(-k))`. Positive `k` moves quickly at first
+    /// and then settles, like an analog RC stage; negative `k` starts slowly.
+    Exponential(f32),
+    /// Any function with `f(0) = 0` and `f(1) = 1`.
+    Custom(fn(f32) -> f32),
+}
+
+impl PartialEq for Curve {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Curve::Linear, Curve::Linear) => true,
+            (Curve::Exponential(a), Curve::Exponential(b)) => a == b,
+            (Curve::Custom(a), Curve::Custom(b)) => std::ptr::fn_addr_eq(*a, *b),
+            _ => false,
+        }
+    }
+}
+
+impl Curve {
+    /// Progress at normalised time `t`.
+    #[inline]
+    pub fn apply(self, t: f32) -> f32 {
+        match self {
+            Curve::Linear => t,
+            Curve::Exponential(k) if k.abs() < 1e-4 => t,
+            Curve::Exponential(k) => (1.0 - (-k * t).exp()) / (1.0 - (-k).exp()),
+            Curve::Custom(f) => f(t),
+        }
+    }
+}
+
+/// Envelope shape. Times are in seconds; `sustain` is a level in `0..=1`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Adsr {
+    pub attack: f32,
+    /// Time spent at full level between attack and decay.
+    pub hold: f32,
+    pub decay: f32,
+    pub sustain: f32,
+    pub release: f32,
+    pub attack_curve: Curve,
+    pub decay_curve: Curve,
+    pub release_curve: Curve,
+}
+
+impl Default for Adsr {
+    /// An organ-style gate with a 5 ms release to avoid clicks on note-off.
+    fn default() -> Self {
+        Adsr {
+            attack: 0.0,
+            hold: 0.0,
+            decay: 0.0,
+            sustain: 1.0,
+            release: 0.005,
+            attack_curve: Curve::Linear,
+            decay_curve: Curve::Linear,
+            release_curve: Curve::Linear,
+        }
+    }
+}
+
+impl Adsr {
+    /// A linear envelope with the given times and sustain level.
+    pub fn new(attack: f32, decay: f32, sustain: f32, release: f32) -> Self {
+        Adsr {
+            attack,
+            decay,
+            sustain,
+            release,
+            ..Adsr::default()
+        }
+    }
+}
+
+/// The stage an [`Envelope`] is in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum Stage {
+    /// Not triggered, or finished.
+    #[default]
+    Idle,
+    Attack,
+    Hold,
+    Decay,
+    Sustain,
+    Release,
+}
+
+/// Running state of an [`Adsr`].
+#[derive(Clone, Debug, Default)]
+pub struct Envelope {
+    shape: Adsr,
+    stage: Stage,
+    level: f32,
+    /// Level at the start of the current stage.
+    from: f32,
+    /// Frames into the current stage.
+    pos: u32,
+    /// Length of the current stage in frames, worked out on its first frame.
+    len: Option<u32>,
+}
+
+impl Envelope {
+    /// Starts the attack stage from silence.
+    pub fn trigger(&mut self, shape: Adsr) {
+        self.shape = shape;
+        self.level = 0.0;
+        self.enter(Stage::Attack);
+    }
+
+    /// Moves to the release stage from the current level.
+    pub fn release(&mut self) {
+        if !matches!(self.stage, Stage::Idle | Stage::Release) {
+            self.enter(Stage::Release);
+        }
+    }
+
+    /// Jumps straight to idle.
+    pub fn reset(&mut self) {
+        self.stage = Stage::Idle;
+        self.level = 0.0;
+    }
+
+    /// The current stage.
+    pub fn stage(&self) -> Stage {
+        self.stage
+    }
+
+    /// The most recent output level.
+    pub fn level(&self) -> f32 {
+        self.level
+    }
+
+    /// Returns `true` while the envelope produces output.
+    pub fn is_active(&self) -> bool {
+        self.stage != Stage::Idle
+    }
+
+    fn enter(&mut self, stage: Stage) {
+        self.stage = stage;
+        self.from = self.level;
+        self.pos = 0;
+        self.len = None;
+    }
+
+    /// Advances one frame at `sample_rate` Hz and returns the new level.
+    #[inline]
+    pub fn next(&mut self, sample_rate: f32) -> f32 {
+        loop {
+            let (seconds, target, curve) = match self.stage {
+                Stage::Idle => return 0.0,
+                Stage::Sustain => {
+                    self.level = self.shape.sustain;
+                    return self.level;
+                }
+                Stage::Attack => (self.shape.attack, 1.0, self.shape.attack_curve),
+                Stage::Hold => (self.shape.hold, 1.0, Curve::Linear),
+                Stage::Decay => (self.shape.decay, self.shape.sustain, self.shape.decay_curve),
+                Stage::Release => (self.shape.release, 0.0, self.shape.release_curve),
+            };
+            let len = *self
+                .len
+                .get_or_insert_with(|| (seconds.max(0.0) * sample_rate).round() as u32);
+            if self.pos >= len {
+                self.level = target;
+                self.advance();
+                continue;
+            }
+            self.pos += 1;
+            let t = self.pos as f32 / len as f32;
+            self.level = self.from + (target - self.from) * curve.apply(t);
+            if self.pos == len {
+                self.advance();
+            }
+            return self.level;
+        }
+    }
+
+    /// Fills `out` with successive levels.
+    pub fn process(&mut self, sample_rate: f32, out: &mut [f32]) {
+        for v in out {
+            *v = self.next(sample_rate);
+        }
+    }
+
+    fn advance(&mut self) {
+        let next = match self.stage {
+            Stage::Attack => Stage::Hold,
+            Stage::Hold => Stage::Decay,
+            Stage::Decay if self.shape.sustain <= 0.0 => Stage::Idle,
+            Stage::Decay => Stage::Sustain,
+            Stage::Release => Stage::Idle,
+            Stage::Idle | Stage::Sustain => return,
+        };
+        self.enter(next);
+        if next == Stage::Idle {
+            self.level = 0.0;
+        }
+    }
+}
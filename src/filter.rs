@@ -0,0 +1,147 @@
+//! Resonant per-voice filters.
+//!
+//! [`Svf`] is a state-variable filter in topology-preserving transform
+//! form (the trapezoidal "TPT" or "ZDF" design): an analog two-pole
+//! prototype discretised so that its state carries the meaning it has in
+//! the circuit. Coefficients can change on every frame without the clicks
+//! or blow-ups of a direct-form biquad, so cutoff and resonance can be
+//! swept as fast as a modulator likes.
+//!
+//! A voice whose [`TriggerParams`](crate::TriggerParams) carry
+//! [`FilterParams`] runs its sample through one, with a cutoff moved by its
+//! own envelope, the note's velocity and the key.
+
+use std::f32::consts::PI;
+
+use crate::envelope::Adsr;
+
+/// Which response a filter has.
+///
+/// Gains at the cutoff frequency are in terms of the resonance `Q`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Default)]
+pub enum FilterMode {
+    /// 12 dB per octave above the cutoff; `Q` at it.
+    #[default]
+    LowPass,
+    /// 12 dB per octave below the cutoff; `Q` at it.
+    HighPass,
+    /// Unity gain at the cutoff, 6 dB per octave either side; higher `Q`
+    /// narrows it.
+    BandPass,
+    /// Silence at the cutoff, unity gain away from it; higher `Q` narrows
+    /// the notch.
+    Notch,
+    /// Low-pass minus high-pass: unity gain far either side and a resonant
+    /// peak of `2Q` at the cutoff.
+    Peak,
+}
+
+/// A voice's filter and what moves its cutoff.
+///
+/// The cutoff played on each frame is `cutoff` shifted by the sum of the
+/// modulations, all in cents.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FilterParams {
+    pub mode: FilterMode,
+    /// Cutoff, or centre, frequency in Hz before modulation.
+    pub cutoff: f32,
+    /// Resonance as `Q`. 0.707 gives a flat (Butterworth) low or high pass;
+    /// higher values ring.
+    pub resonance: f32,
+    /// The filter envelope, triggered and released with the voice.
+    pub envelope: Adsr,
+    /// Cutoff shift at full envelope level, in cents.
+    pub envelope_depth: f32,
+    /// Cutoff shift at velocity 127, in cents, scaled down linearly to none
+    /// at velocity 0.
+    pub velocity_track: f32,
+    /// Cutoff shift per key above `key_center`, in cents. 100 makes the
+    /// cutoff follow the pitch of the keyboard.
+    pub key_track: f32,
+    pub key_center: u8,
+}
+
+impl Default for FilterParams {
+    fn default() -> Self {
+        FilterParams {
+            mode: FilterMode::LowPass,
+            cutoff: 20_000.0,
+            resonance: std::f32::consts::FRAC_1_SQRT_2,
+            envelope: Adsr::default(),
+            envelope_depth: 0.0,
+            velocity_track: 0.0,
+            key_track: 0.0,
+            key_center: 60,
+        }
+    }
+}
+
+impl FilterParams {
+    /// The cutoff in Hz for a note played at `velocity` with the filter
+    /// envelope at `envelope`.
+    pub fn cutoff_at(&self, envelope: f32, note: u8, velocity: u8) -> f32 {
+        let cents = self.envelope_depth * envelope
+            + self.velocity_track * f32::from(velocity) / 127.0
+            + self.key_track * (f32::from(note) - f32::from(self.key_center));
+        self.cutoff * (cents / 1200.0).exp2()
+    }
+}
+
+/// Coefficients of an [`Svf`] for one cutoff and resonance.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SvfCoefficients {
+    k: f32,
+    a1: f32,
+    a2: f32,
+    a3: f32,
+}
+
+impl SvfCoefficients {
+    /// Designs for `cutoff` Hz and resonance `q` at `sample_rate`. The
+    /// cutoff is kept between 1 Hz and just under Nyquist, and `q` above
+    /// 0.01.
+    pub fn new(cutoff: f32, q: f32, sample_rate: f32) -> Self {
+        let cutoff = cutoff.clamp(1.0, 0.49 * sample_rate);
+        let g = (PI * cutoff / sample_rate).tan();
+        let k = 1.0 / q.max(0.01);
+        let a1 = 1.0 / (1.0 + g * (g + k));
+        let a2 = g * a1;
+        SvfCoefficients {
+            k,
+            a1,
+            a2,
+            a3: g * a2,
+        }
+    }
+}
+
+/// State of a two-pole state-variable filter.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Svf {
+    ic1eq: f32,
+    ic2eq: f32,
+}
+
+impl Svf {
+    /// Filters one value.
+    #[inline]
+    pub fn process(&mut self, x: f32, c: &SvfCoefficients, mode: FilterMode) -> f32 {
+        let v3 = x - self.ic2eq;
+        let v1 = c.a1 * self.ic1eq + c.a2 * v3;
+        let v2 = self.ic2eq + c.a2 * self.ic1eq + c.a3 * v3;
+        self.ic1eq = 2.0 * v1 - self.ic1eq;
+        self.ic2eq = 2.0 * v2 - self.ic2eq;
+        match mode {
+            FilterMode::LowPass => v2,
+            FilterMode::HighPass => x - c.k * v1 - v2,
+            FilterMode::BandPass => c.k * v1,
+            FilterMode::Notch => x - c.k * v1,
+            FilterMode::Peak => 2.0 * v2 - x + c.k * v1,
+        }
+    }
+
+    /// Clears the filter's memory.
+    pub fn reset(&mut self) {
+        *self = Svf::default();
+    }
+}
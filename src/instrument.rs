@@ -0,0 +1,215 @@
+//! Multisampled instruments.
+//!
+//! An [`Instrument`] maps notes and velocities to samples through a list of
+//! [`Zone`]s. Each zone covers a key range and a velocity range and holds one
+//! or more [`Variant`]s: alternative recordings of the same note, played in
+//! turn or at random. Every zone that matches a note plays, so overlapping
+//! zones layer while adjacent velocity ranges switch between layers.
+
+use std::ops::RangeInclusive;
+
+use crate::engine::SampleId;
+use crate::voice::TriggerParams;
+
+/// How a zone picks among its variants.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum Alternation {
+    /// Each note plays the next variant, wrapping around.
+    #[default]
+    RoundRobin,
+    /// Each note plays a random variant, never the same one twice in a row.
+    Random,
+}
+
+/// One sample as played by a zone.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Variant {
+    pub sample: SampleId,
+    /// Note at which the sample plays at its recorded pitch.
+    pub root: u8,
+    /// Fine tuning in cents.
+    pub tune: f64,
+    /// Template for triggered voices. The note-dependent rate multiplies
+    /// `params.rate`.
+    pub params: TriggerParams,
+}
+
+impl Variant {
+    /// `sample` at its recorded pitch on note `root`, with default params.
+    pub fn new(sample: SampleId, root: u8) -> Self {
+        Variant {
+            sample,
+            root,
+            tune: 0.0,
+            params: TriggerParams::default(),
+        }
+    }
+
+    /// Replaces the trigger template.
+    pub fn with_params(mut self, params: TriggerParams) -> Self {
+        self.params = params;
+        self
+    }
+
+    /// Playback rate for `note`.
+    pub fn rate(&self, note: u8) -> f64 {
+        let semitones = f64::from(note) - f64::from(self.root) + self.tune / 100.0;
+        self.params.rate * (semitones / 12.0).exp2()
+    }
+}
+
+/// A key and velocity range mapped to one or more variants.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Zone {
+    pub keys: RangeInclusive<u8>,
+    pub velocities: RangeInclusive<u8>,
+    pub alternation: Alternation,
+    pub variants: Vec<Variant>,
+}
+
+impl Zone {
+    /// A zone playing `variant` across `keys` at every velocity.
+    pub fn new(keys: RangeInclusive<u8>, variant: Variant) -> Self {
+        Zone {
+            keys,
+            velocities: 0..=127,
+            alternation: Alternation::RoundRobin,
+            variants: vec![variant],
+        }
+    }
+
+    /// Restricts the zone to `velocities`.
+    pub fn with_velocities(mut self, velocities: RangeInclusive<u8>) -> Self {
+        self.velocities = velocities;
+        self
+    }
+
+    /// Adds alternative variants, picked between by `alternation`.
+    pub fn with_alternates(
+        mut self,
+        alternation: Alternation,
+        variants: impl IntoIterator<Item = Variant>,
+    ) -> Self {
+        self.alternation = alternation;
+        self.variants.extend(variants);
+        self
+    }
+
+    /// Returns `true` if the zone plays `note` at `velocity`.
+    pub fn matches(&self, note: u8, velocity: u8) -> bool {
+        self.keys.contains(&note) && self.velocities.contains(&velocity)
+    }
+}
+
+/// Per-zone alternation state.
+#[derive(Clone, Copy, Debug, Default)]
+struct Cycle {
+    next: usize,
+    last: Option<usize>,
+}
+
+/// A set of zones plus the state that alternates between their variants.
+#[derive(Clone, Debug)]
+pub struct Instrument {
+    zones: Vec<Zone>,
+    cycles: Vec<Cycle>,
+    rng: u64,
+}
+
+impl Default for Instrument {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Instrument {
+    /// An instrument with no zones.
+    pub fn new() -> Self {
+        Instrument {
+            zones: Vec::new(),
+            cycles: Vec::new(),
+            rng: 0x9e37_79b9_7f4a_7c15,
+        }
+    }
+
+    /// Plays one sample chromatically across the whole keyboard.
+    pub fn single(sample: SampleId, root: u8) -> Self {
+        let mut instrument = Instrument::new();
+        instrument.add_zone(Zone::new(0..=127, Variant::new(sample, root)));
+        instrument
+    }
+
+    /// Adds a zone and returns its index.
+    pub fn add_zone(&mut self, zone: Zone) -> usize {
+        self.zones.push(zone);
+        self.cycles.push(Cycle::default());
+        self.zones.len() - 1
+    }
+
+    /// The zones, in the order they were added.
+    pub fn zones(&self) -> &[Zone] {
+        &self.zones
+    }
+
+    /// Reseeds the generator behind [`Alternation::Random`], for repeatable
+    /// output.
+    pub fn set_seed(&mut self, seed: u64) {
+        // Xorshift gets stuck at zero.
+        self.rng = seed.max(1);
+    }
+
+    /// Restarts every round-robin cycle from its first variant.
+    pub fn reset_alternation(&mut self) {
+        self.cycles.fill(Cycle::default());
+    }
+
+    /// Picks a variant from every zone matching `note` at `velocity` and
+    /// returns the samples to play, with their trigger parameters pitched
+    /// for `note`. Advances round-robin and random state.
+    pub fn select(
+        &mut self,
+        note: u8,
+        velocity: u8,
+    ) -> impl Iterator<Item = (SampleId, TriggerParams)> + '_ {
+        let rng = &mut self.rng;
+        self.zones
+            .iter()
+            .zip(&mut self.cycles)
+            .filter(move |(zone, _)| zone.matches(note, velocity) && !zone.variants.is_empty())
+            .map(move |(zone, cycle)| {
+                let n = zone.variants.len();
+                let index = match zone.alternation {
+                    Alternation::RoundRobin => {
+                        let i = cycle.next % n;
+                        cycle.next = (i + 1) % n;
+                        i
+                    }
+                    Alternation::Random => {
+                        let mut i = (xorshift(rng) % n as u64) as usize;
+                        if n > 1 && cycle.last == Some(i) {
+                            i = (i + 1 + (xorshift(rng) % (n as u64 - 1)) as usize) % n;
+                        }
+                        i
+                    }
+                };
+                cycle.last = Some(index);
+                let variant = &zone.variants[index];
+                let params = TriggerParams {
+                    note,
+                    velocity,
+                    rate: variant.rate(note),
+                    ..variant.params
+                };
+                (variant.sample, params)
+            })
+    }
+}
+
+fn xorshift(state: &mut u64) -> u64 {
+    let mut x = *state;
+    x ^= x << 13;
+    x ^= x >> 7;
+    x ^= x << 17;
+    *state = x;
+    x
+}
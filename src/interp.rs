@@ -0,0 +1,170 @@
+//! Fractional-position sample readers.
+//!
+//! Each [`Interpolation`] mode reconstructs a value between stored frames.
+//! The windowed-sinc mode is band-limited: when a sample is played faster
+//! than its native rate it lowers its cutoff by the same factor, so content
+//! that would fold back above the output Nyquist frequency is filtered out
+//! instead of aliasing.
+
+use std::f64::consts::PI;
+
+use crate::sample::ChannelView;
+
+/// How a voice reads between sample frames.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Default)]
+pub enum Interpolation {
+    /// Drop-sample: take the frame at or before the position.
+    None,
+    /// Straight line between the two neighbouring frames.
+    Linear,
+    /// 4-point, 3rd-order Hermite spline.
+    #[default]
+    Cubic,
+    /// Kaiser-windowed sinc with an anti-aliasing cutoff.
+    Sinc,
+}
+
+impl Interpolation {
+    /// Reads `view` at fractional frame `pos` while advancing `step` frames
+    /// per output frame. Positions outside the view read as silence.
+    #[inline]
+    pub(crate) fn read(self, view: &ChannelView<'_>, pos: f64, step: f64, sinc: &SincTable) -> f32 {
+        let index = pos.floor();
+        let frac = (pos - index) as f32;
+        let i = index as isize;
+        match self {
+            Interpolation::None => view.at(i),
+            Interpolation::Linear => {
+                let a = view.at(i);
+                let b = view.at(i + 1);
+                a + (b - a) * frac
+            }
+            Interpolation::Cubic => hermite(
+                view.at(i - 1),
+                view.at(i),
+                view.at(i + 1),
+                view.at(i + 2),
+                frac,
+            ),
+            Interpolation::Sinc => sinc.read(view, pos, step),
+        }
+    }
+
+    /// How many frames either side of a position [`read`](Self::read) may
+    /// touch when advancing `step` frames per output frame.
+    pub(crate) fn reach(self, step: f64) -> usize {
+        match self {
+            Interpolation::None => 0,
+            Interpolation::Linear => 1,
+            Interpolation::Cubic => 2,
+            Interpolation::Sinc => {
+                let cutoff = CUTOFF / step.clamp(1.0, MAX_STEP);
+                (ZERO_CROSSINGS as f64 / cutoff).ceil() as usize + 1
+            }
+        }
+    }
+}
+
+#[inline]
+fn hermite(xm1: f32, x0: f32, x1: f32, x2: f32, t: f32) -> f32 {
+    let c = (x1 - xm1) * 0.5;
+    let v = x0 - x1;
+    let w = c + v;
+    let a = w + v + (x2 - x0) * 0.5;
+    let b = w + a;
+    ((a * t - b) * t + c) * t + x0
+}
+
+/// Zero crossings of the sinc kernel on each side of the centre.
+const ZERO_CROSSINGS: usize = 16;
+/// Table entries per unit distance.
+const OVERSAMPLING: usize = 512;
+/// Kaiser window shape; about 90 dB of stopband attenuation.
+const KAISER_BETA: f64 = 9.0;
+/// Highest playback step the cutoff keeps up with. Faster playback still
+/// works but is only filtered as if it were at this step, bounding the cost
+/// per output frame to `2 * ZERO_CROSSINGS * MAX_STEP` taps.
+const MAX_STEP: f64 = 4.0;
+/// Cutoff relative to Nyquist, leaving room for the window's transition band.
+const CUTOFF: f64 = 0.92;
+
+/// Precomputed half of a Kaiser-windowed sinc kernel.
+///
+/// Built once, outside the audio thread, and shared by every voice.
+#[derive(Clone, Debug)]
+pub struct SincTable {
+    table: Vec<f32>,
+}
+
+impl Default for SincTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SincTable {
+    /// Builds the kernel table.
+    pub fn new() -> Self {
+        let len = ZERO_CROSSINGS * OVERSAMPLING;
+        let norm = bessel_i0(KAISER_BETA);
+        let table = (0..=len + 1)
+            .map(|n| {
+                let x = n as f64 / OVERSAMPLING as f64;
+                let r = x / ZERO_CROSSINGS as f64;
+                if r >= 1.0 {
+                    return 0.0;
+                }
+                let sinc = if x == 0.0 {
+                    1.0
+                } else {
+                    (PI * x).sin() / (PI * x)
+                };
+                (sinc * bessel_i0(KAISER_BETA * (1.0 - r * r).sqrt()) / norm) as f32
+            })
+            .collect();
+        SincTable { table }
+    }
+
+    /// Kernel value at distance `x` (in zero crossings), linearly interpolated.
+    #[inline]
+    fn kernel(&self, x: f64) -> f32 {
+        let p = x.abs() * OVERSAMPLING as f64;
+        let i = p as usize;
+        if i >= ZERO_CROSSINGS * OVERSAMPLING {
+            return 0.0;
+        }
+        let frac = (p - i as f64) as f32;
+        let a = self.table[i];
+        a + (self.table[i + 1] - a) * frac
+    }
+
+    fn read(&self, view: &ChannelView<'_>, pos: f64, step: f64) -> f32 {
+        // Playing faster than native squeezes the spectrum upward, so the
+        // cutoff must drop below the source Nyquist by the same factor.
+        let cutoff = CUTOFF / step.clamp(1.0, MAX_STEP);
+        let half_width = ZERO_CROSSINGS as f64 / cutoff;
+        let first = (pos - half_width).ceil() as isize;
+        let last = (pos + half_width).floor() as isize;
+
+        let mut sum = 0.0;
+        for i in first.max(0)..=last.min(view.len() as isize - 1) {
+            sum += view.at(i) * self.kernel((pos - i as f64) * cutoff);
+        }
+        sum * cutoff as f32
+    }
+}
+
+/// Zeroth-order modified Bessel function of the first kind.
+pub(crate) fn bessel_i0(x: f64) -> f64 {
+    let mut sum = 1.0;
+    let mut term = 1.0;
+    let half = x / 2.0;
+    for k in 1..100 {
+        term *= half / k as f64;
+        sum += term * term;
+        if term * term < sum * 1e-17 {
+            break;
+        }
+    }
+    sum
+}
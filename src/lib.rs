@@ -0,0 +1,74 @@
+//! samplerust: a basic audio sample playback engine.
+//!
+//! The central data type is [`Sample`], a block of `f32` audio that knows its
+//! sample rate, channel count and memory [`Layout`]. Files are decoded into
+//! samples by the [`codec`] module, and [`resample()`] converts them to
+//! another rate.
+//!
+//! Playback happens in an [`Engine`], which renders [`Voice`]s on the audio
+//! thread and takes its orders from a [`Controller`] on another thread.
+//!
+//! Multisampled [`Instrument`]s map notes to samples; they can be built by
+//! hand or loaded from SFZ files by the [`sfz`] module, and played from MIDI
+//! input with a [`MidiMapper`] or from MIDI files with an [`Smf`]. Each voice
+//! can run through its own resonant [`filter`], and a [`ModMatrix`] routes
+//! LFOs, envelopes and controllers to voice parameters. Voices are
+//! [panned](pan) within, and can be routed to, separate channel pairs of
+//! the output, and can feed send [`effect`]s such as a [`Reverb`] and a
+//! [`Delay`]. Chains of effects, built-in or implementing [`Effect`], can
+//! also be inserted on every voice, on each output bus and on the master
+//! output.
+//!
+//! Samples too long to load whole can be [streamed](stream) from disk, with
+//! only their first frames in memory. Samples in memory can be
+//! [time-stretched](stretch) and pitch-shifted independently.
+//!
+//! An engine plays on an audio device through a [`backend`], or into memory
+//! through a [`NullBackend`] for tests. A [`Bounce`] renders MIDI offline,
+//! as fast as it can, into a sample or a WAV file.
+
+pub mod backend;
+pub mod bounce;
+pub mod codec;
+pub mod controller;
+pub mod effect;
+pub mod engine;
+pub mod envelope;
+pub mod filter;
+pub mod instrument;
+pub mod interp;
+pub mod midi;
+pub mod modulation;
+pub mod pan;
+pub mod pool;
+pub mod resample;
+pub mod sample;
+pub mod sfz;
+pub mod spsc;
+pub mod stream;
+pub mod stretch;
+pub mod voice;
+
+pub use backend::{Backend, BackendError, Callback, DeviceConfig, NullBackend, NullStream};
+#[cfg(feature = "cpal")]
+pub use backend::{CpalBackend, CpalStream};
+pub use bounce::{Bounce, BounceError};
+pub use codec::DecodeError;
+pub use controller::Controller;
+pub use effect::{Delay, DelayTime, Effect, EffectChain, EffectSlot, ParamInfo, Reverb};
+pub use engine::{Command, Engine, EngineConfig, EngineError, Event, SampleId};
+pub use envelope::{Adsr, Curve, Envelope, Stage};
+pub use filter::{FilterMode, FilterParams, Svf, SvfCoefficients};
+pub use instrument::{Alternation, Instrument, Variant, Zone};
+pub use interp::Interpolation;
+pub use midi::{MidiMapper, MidiMessage, Sequence, Smf, SmfError};
+pub use modulation::{
+    ChannelControls, Lfo, LfoRate, LfoShape, ModDestination, ModMatrix, ModSource, Route,
+};
+pub use pan::{PanLaw, PanMode};
+pub use pool::{StealPolicy, VoicePool};
+pub use resample::{resample, ResampleQuality};
+pub use sample::{Layout, LoopMode, LoopRegion, Sample, SampleError};
+pub use stream::{StreamSource, StreamedSample, Streamer};
+pub use stretch::{StretchMode, TimeStretch};
+pub use voice::{RenderContext, TriggerParams, Voice, VoiceId};
@@ -0,0 +1,265 @@
+//! Mapping MIDI messages to engine commands.
+
+use super::{MidiMessage, Parser};
+use crate::controller::Controller;
+use crate::engine::{Command, EngineError};
+use crate::envelope::Curve;
+use crate::instrument::Instrument;
+use crate::voice::{TriggerParams, VoiceId};
+
+/// Controller number of the modulation wheel.
+const MOD_WHEEL: u8 = 1;
+
+/// Controller number of the sustain pedal.
+const SUSTAIN: u8 = 64;
+
+/// A voice started by a note-on that has not been released yet.
+#[derive(Clone, Copy, Debug)]
+struct Held {
+    channel: u8,
+    note: u8,
+    voice: VoiceId,
+    /// Note-off arrived while the sustain pedal was down.
+    sustained: bool,
+}
+
+/// Plays an [`Instrument`] from MIDI input.
+///
+/// Note-on triggers a voice for every zone the instrument selects, pitched
+/// relative to the zone's root key, with gain scaled by velocity. Note-off
+/// releases them, unless the channel's sustain
+/// pedal (CC64) is down, in which case the release waits for the pedal to
+/// lift. Pitch bend changes the playback rate of every voice on its channel.
+/// The modulation wheel (CC1) and channel pressure set the channel's
+/// [`ModSource::ModWheel`](crate::ModSource) and
+/// [`ModSource::Aftertouch`](crate::ModSource).
+///
+/// All commands are scheduled at the frame passed in with the bytes, so
+/// events keep their relative timing inside a block.
+#[derive(Clone, Debug)]
+pub struct MidiMapper {
+    parser: Parser,
+    instrument: Instrument,
+    channel: Option<u8>,
+    bend_range: f64,
+    velocity_curve: Curve,
+    held: Vec<Held>,
+    pedal: [bool; 16],
+}
+
+impl MidiMapper {
+    /// Plays `instrument`.
+    pub fn new(instrument: Instrument) -> Self {
+        MidiMapper {
+            parser: Parser::new(),
+            instrument,
+            channel: None,
+            bend_range: 2.0,
+            velocity_curve: Curve::Linear,
+            held: Vec::new(),
+            pedal: [false; 16],
+        }
+    }
+
+    /// The instrument being played.
+    pub fn instrument(&self) -> &Instrument {
+        &self.instrument
+    }
+
+    /// The instrument being played, for editing zones between notes.
+    pub fn instrument_mut(&mut self) -> &mut Instrument {
+        &mut self.instrument
+    }
+
+    /// Listens on one channel only, or on all channels with `None`.
+    pub fn set_channel(&mut self, channel: Option<u8>) {
+        self.channel = channel;
+    }
+
+    /// Sets the pitch-bend range in semitones either way. Defaults to 2.
+    pub fn set_bend_range(&mut self, semitones: f64) {
+        self.bend_range = semitones;
+    }
+
+    /// Sets the curve from normalised velocity to gain. Defaults to linear.
+    pub fn set_velocity_curve(&mut self, curve: Curve) {
+        self.velocity_curve = curve;
+    }
+
+    /// Number of voices started and not yet released, including those held
+    /// by the sustain pedal.
+    pub fn held_voices(&self) -> usize {
+        self.held.len()
+    }
+
+    /// Parses `bytes` and maps the resulting messages, scheduling commands
+    /// at output frame `frame`. Messages the mapper does not use, such as
+    /// SysEx, are passed to `unhandled`.
+    ///
+    /// Keeps going if a command cannot be sent and returns the first error.
+    pub fn process(
+        &mut self,
+        ctl: &mut Controller,
+        frame: u64,
+        bytes: &[u8],
+        mut unhandled: impl FnMut(MidiMessage),
+    ) -> Result<(), EngineError> {
+        let mut result = Ok(());
+        let mut parser = std::mem::take(&mut self.parser);
+        for message in parser.parse(bytes) {
+            match self.handle(ctl, frame, &message) {
+                Ok(true) => {}
+                Ok(false) => unhandled(message),
+                Err(e) => {
+                    if result.is_ok() {
+                        result = Err(e);
+                    }
+                }
+            }
+        }
+        self.parser = parser;
+        result
+    }
+
+    /// Maps one message at output frame `frame`. Returns `Ok(false)` for
+    /// messages the mapper ignores.
+    pub fn handle(
+        &mut self,
+        ctl: &mut Controller,
+        frame: u64,
+        message: &MidiMessage,
+    ) -> Result<bool, EngineError> {
+        if let Some(channel) = message.channel() {
+            if self.channel.is_some_and(|c| c != channel) {
+                return Ok(false);
+            }
+        }
+        match *message {
+            MidiMessage::NoteOn {
+                channel,
+                note,
+                velocity,
+            } => self.note_on(ctl, frame, channel, note, velocity)?,
+            MidiMessage::NoteOff { channel, note, .. } => {
+                self.note_off(ctl, frame, channel, note)?
+            }
+            MidiMessage::ControlChange {
+                channel,
+                controller: SUSTAIN,
+                value,
+            } => self.pedal(ctl, frame, channel, value >= 64)?,
+            MidiMessage::ControlChange {
+                channel,
+                controller: MOD_WHEEL,
+                value,
+            } => ctl.schedule(
+                frame,
+                Command::ModWheel {
+                    channel,
+                    value: f32::from(value) / 127.0,
+                },
+            )?,
+            MidiMessage::ChannelPressure { channel, pressure } => ctl.schedule(
+                frame,
+                Command::Aftertouch {
+                    channel,
+                    value: f32::from(pressure) / 127.0,
+                },
+            )?,
+            MidiMessage::PitchBend { channel, value } => {
+                let semitones = f64::from(value) / 8192.0 * self.bend_range;
+                ctl.schedule(
+                    frame,
+                    Command::PitchBend {
+                        channel,
+                        ratio: (semitones / 12.0).exp2(),
+                    },
+                )?;
+            }
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    fn note_on(
+        &mut self,
+        ctl: &mut Controller,
+        frame: u64,
+        channel: u8,
+        note: u8,
+        velocity: u8,
+    ) -> Result<(), EngineError> {
+        let gain = self.velocity_curve.apply(f32::from(velocity) / 127.0);
+        for (sample, params) in self.instrument.select(note, velocity) {
+            let params = TriggerParams {
+                channel,
+                gain: params.gain * gain,
+                ..params
+            };
+            let voice = ctl.trigger_at(frame, sample, params)?;
+            self.held.push(Held {
+                channel,
+                note,
+                voice,
+                sustained: false,
+            });
+        }
+        Ok(())
+    }
+
+    fn note_off(
+        &mut self,
+        ctl: &mut Controller,
+        frame: u64,
+        channel: u8,
+        note: u8,
+    ) -> Result<(), EngineError> {
+        if self.pedal[usize::from(channel)] {
+            for h in &mut self.held {
+                if h.channel == channel && h.note == note {
+                    h.sustained = true;
+                }
+            }
+            return Ok(());
+        }
+        self.release_where(ctl, frame, |h| h.channel == channel && h.note == note)
+    }
+
+    fn pedal(
+        &mut self,
+        ctl: &mut Controller,
+        frame: u64,
+        channel: u8,
+        down: bool,
+    ) -> Result<(), EngineError> {
+        self.pedal[usize::from(channel)] = down;
+        if down {
+            return Ok(());
+        }
+        self.release_where(ctl, frame, |h| h.channel == channel && h.sustained)
+    }
+
+    /// Releases and forgets every held voice matching `filter`. Voices whose
+    /// release could not be sent stay held.
+    fn release_where(
+        &mut self,
+        ctl: &mut Controller,
+        frame: u64,
+        filter: impl Fn(&Held) -> bool,
+    ) -> Result<(), EngineError> {
+        let mut result = Ok(());
+        self.held.retain(|h| {
+            if !filter(h) || result.is_err() {
+                return true;
+            }
+            match ctl.release_at(frame, h.voice) {
+                Ok(()) => false,
+                Err(e) => {
+                    result = Err(e);
+                    true
+                }
+            }
+        });
+        result
+    }
+}
@@ -0,0 +1,258 @@
+//! MIDI 1.0 input.
+//!
+//! [`Parser`] turns a raw byte stream into [`MidiMessage`]s. It keeps state
+//! between calls, so a transport can hand it bytes in whatever chunks they
+//! arrive in: running status, messages split across chunks and real-time
+//! bytes interleaved with other messages are all handled. [`MidiMapper`]
+//! then turns those messages into engine commands.
+//!
+//! [`Smf`] reads Standard MIDI Files and times their messages to output
+//! frames, to be rendered offline by a [`Bounce`](crate::Bounce) or played
+//! in real time by a [`Sequence`].
+
+mod map;
+mod smf;
+
+pub use map::MidiMapper;
+pub use smf::{Division, Sequence, Smf, SmfError, SmfEvent, TempoMap, TimedEvent};
+
+/// A parsed MIDI message. Channels are `0..16`; data values are `0..128`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum MidiMessage {
+    /// Note-off. A note-on with velocity 0 is reported as a note-off with
+    /// velocity 0.
+    NoteOff {
+        channel: u8,
+        note: u8,
+        velocity: u8,
+    },
+    NoteOn {
+        channel: u8,
+        note: u8,
+        velocity: u8,
+    },
+    PolyPressure {
+        channel: u8,
+        note: u8,
+        pressure: u8,
+    },
+    ControlChange {
+        channel: u8,
+        controller: u8,
+        value: u8,
+    },
+    ProgramChange {
+        channel: u8,
+        program: u8,
+    },
+    ChannelPressure {
+        channel: u8,
+        pressure: u8,
+    },
+    /// Pitch bend in `-8192..8192`; 0 is centred.
+    PitchBend {
+        channel: u8,
+        value: i16,
+    },
+    /// A system-exclusive message: the bytes between `0xF0` and `0xF7`.
+    SysEx(Vec<u8>),
+    MtcQuarterFrame(u8),
+    SongPosition(u16),
+    SongSelect(u8),
+    TuneRequest,
+    TimingClock,
+    Start,
+    Continue,
+    Stop,
+    ActiveSensing,
+    Reset,
+}
+
+impl MidiMessage {
+    /// The channel of a channel message.
+    pub fn channel(&self) -> Option<u8> {
+        match *self {
+            MidiMessage::NoteOff { channel, .. }
+            | MidiMessage::NoteOn { channel, .. }
+            | MidiMessage::PolyPressure { channel, .. }
+            | MidiMessage::ControlChange { channel, .. }
+            | MidiMessage::ProgramChange { channel, .. }
+            | MidiMessage::ChannelPressure { channel, .. }
+            | MidiMessage::PitchBend { channel, .. } => Some(channel),
+            _ => None,
+        }
+    }
+}
+
+/// Incremental MIDI 1.0 byte-stream parser.
+///
+/// Malformed input never fails: stray data bytes with no status to apply to
+/// are dropped, as is a message cut short by a new status byte. A SysEx
+/// message interrupted by anything but a real-time byte or its `0xF7`
+/// terminator is dropped too.
+#[derive(Clone, Debug, Default)]
+pub struct Parser {
+    /// Status byte of the message being assembled, kept after a channel
+    /// message completes for running status.
+    status: Option<u8>,
+    data: [u8; 2],
+    len: usize,
+    sysex: Option<Vec<u8>>,
+}
+
+impl Parser {
+    /// Creates a parser with no running status.
+    pub fn new() -> Self {
+        Parser::default()
+    }
+
+    /// Parses `bytes`, returning the messages they complete.
+    pub fn parse<'a>(&'a mut self, bytes: &'a [u8]) -> Messages<'a> {
+        Messages {
+            parser: self,
+            bytes: bytes.iter(),
+        }
+    }
+
+    /// Feeds one byte, returning a message if it completes one.
+    pub fn push(&mut self, byte: u8) -> Option<MidiMessage> {
+        if byte >= 0xf8 {
+            // Real-time bytes may appear anywhere and leave all other state
+            // untouched.
+            return realtime(byte);
+        }
+        if byte & 0x80 == 0 {
+            if let Some(sysex) = &mut self.sysex {
+                sysex.push(byte);
+                return None;
+            }
+            return self.data(byte);
+        }
+
+        let sysex = self.sysex.take();
+        self.len = 0;
+        match byte {
+            0xf7 => {
+                self.status = None;
+                sysex.map(MidiMessage::SysEx)
+            }
+            0xf0 => {
+                self.status = None;
+                let mut buf = sysex.unwrap_or_default();
+                buf.clear();
+                self.sysex = Some(buf);
+                None
+            }
+            0xf6 => {
+                self.status = None;
+                Some(MidiMessage::TuneRequest)
+            }
+            _ => {
+                // Channel messages and the remaining system common messages
+                // wait for their data bytes. Undefined 0xF4/0xF5 are kept as
+                // a status that no data byte will ever complete.
+                self.status = Some(byte);
+                None
+            }
+        }
+    }
+
+    fn data(&mut self, byte: u8) -> Option<MidiMessage> {
+        let status = self.status?;
+        self.data[self.len] = byte;
+        self.len += 1;
+        let needed = match status {
+            0xc0..=0xdf | 0xf1 | 0xf3 => 1,
+            0x80..=0xef | 0xf2 => 2,
+            _ => {
+                self.len = 0;
+                return None;
+            }
+        };
+        if self.len < needed {
+            return None;
+        }
+        self.len = 0;
+        if status >= 0xf0 {
+            // System common messages do not set running status.
+            self.status = None;
+        }
+
+        let channel = status & 0x0f;
+        let [a, b] = self.data;
+        Some(match status {
+            0x80..=0x8f => MidiMessage::NoteOff {
+                channel,
+                note: a,
+                velocity: b,
+            },
+            0x90..=0x9f if b == 0 => MidiMessage::NoteOff {
+                channel,
+                note: a,
+                velocity: 0,
+            },
+            0x90..=0x9f => MidiMessage::NoteOn {
+                channel,
+                note: a,
+                velocity: b,
+            },
+            0xa0..=0xaf => MidiMessage::PolyPressure {
+                channel,
+                note: a,
+                pressure: b,
+            },
+            0xb0..=0xbf => MidiMessage::ControlChange {
+                channel,
+                controller: a,
+                value: b,
+            },
+            0xc0..=0xcf => MidiMessage::ProgramChange {
+                channel,
+                program: a,
+            },
+            0xd0..=0xdf => MidiMessage::ChannelPressure {
+                channel,
+                pressure: a,
+            },
+            0xe0..=0xef => MidiMessage::PitchBend {
+                channel,
+                value: ((u16::from(b) << 7 | u16::from(a)) as i16) - 8192,
+            },
+            0xf1 => MidiMessage::MtcQuarterFrame(a),
+            0xf2 => MidiMessage::SongPosition(u16::from(b) << 7 | u16::from(a)),
+            _ => MidiMessage::SongSelect(a),
+        })
+    }
+}
+
+fn realtime(byte: u8) -> Option<MidiMessage> {
+    match byte {
+        0xf8 => Some(MidiMessage::TimingClock),
+        0xfa => Some(MidiMessage::Start),
+        0xfb => Some(MidiMessage::Continue),
+        0xfc => Some(MidiMessage::Stop),
+        0xfe => Some(MidiMessage::ActiveSensing),
+        0xff => Some(MidiMessage::Reset),
+        _ => None,
+    }
+}
+
+/// Iterator returned by [`Parser::parse`].
+#[derive(Debug)]
+pub struct Messages<'a> {
+    parser: &'a mut Parser,
+    bytes: std::slice::Iter<'a, u8>,
+}
+
+impl Iterator for Messages<'_> {
+    type Item = MidiMessage;
+
+    fn next(&mut self) -> Option<MidiMessage> {
+        for &byte in self.bytes.by_ref() {
+            if let Some(message) = self.parser.push(byte) {
+                return Some(message);
+            }
+        }
+        None
+    }
+}
@@ -0,0 +1,495 @@
+//! Standard MIDI Files.
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use super::{MidiMapper, MidiMessage, Parser};
+use crate::controller::Controller;
+use crate::engine::EngineError;
+
+/// Tempo until a file sets one: 120 beats per minute.
+const DEFAULT_TEMPO: u32 = 500_000;
+
+/// Errors that stop a MIDI file from loading.
+#[derive(Debug)]
+pub enum SmfError {
+    /// The file could not be read.
+    Io(io::Error),
+    /// The file ended in the middle of the named structure.
+    Truncated(&'static str),
+    /// A structure was present but its contents are invalid.
+    Malformed(&'static str),
+    /// The file is valid but uses something this reader does not handle.
+    Unsupported(String),
+}
+
+impl fmt::Display for SmfError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SmfError::Io(e) => write!(f, "i/o error: {e}"),
+            SmfError::Truncated(what) => write!(f, "truncated {what}"),
+            SmfError::Malformed(what) => write!(f, "malformed {what}"),
+            SmfError::Unsupported(what) => write!(f, "unsupported {what}"),
+        }
+    }
+}
+
+impl std::error::Error for SmfError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SmfError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for SmfError {
+    fn from(e: io::Error) -> Self {
+        SmfError::Io(e)
+    }
+}
+
+/// How a file counts time.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Division {
+    /// Ticks per quarter note, with the tempo set by tempo events.
+    Ppq(u16),
+    /// Ticks per SMPTE frame at `fps` frames per second, whatever the
+    /// tempo. An `fps` of 29 stands for 29.97 drop-frame.
+    Smpte { fps: u8, ticks_per_frame: u8 },
+}
+
+/// Something that happens in a track.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SmfEvent {
+    /// A channel message, or a complete SysEx message.
+    Midi(MidiMessage),
+    /// A tempo change, in microseconds per quarter note.
+    Tempo(u32),
+    /// Any other meta event: its type byte and data. End-of-track markers
+    /// are not kept.
+    Meta { kind: u8, data: Vec<u8> },
+    /// An `F7` escape: bytes to send as they are, such as a SysEx message
+    /// split into packets.
+    Escape(Vec<u8>),
+}
+
+/// An event and the tick it happens on, counted from the start of the file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TimedEvent {
+    pub tick: u64,
+    pub event: SmfEvent,
+}
+
+/// A parsed Standard MIDI File of type 0 or 1.
+///
+/// [`events`](Smf::events) flattens the tracks into the output frames an
+/// engine schedules on, for a [`Bounce`](crate::Bounce) or a [`Sequence`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Smf {
+    format: u16,
+    division: Division,
+    tracks: Vec<Vec<TimedEvent>>,
+}
+
+impl Smf {
+    /// Reads and parses a MIDI file from disk.
+    pub fn load(path: impl AsRef<Path>) -> Result<Smf, SmfError> {
+        Smf::parse(&fs::read(path)?)
+    }
+
+    /// Parses an in-memory MIDI file.
+    ///
+    /// Running status is honoured across meta and SysEx events as well,
+    /// which the standard does not allow but some writers rely on. A track
+    /// missing its end-of-track marker ends with its chunk, and chunks of
+    /// unknown type are skipped.
+    pub fn parse(bytes: &[u8]) -> Result<Smf, SmfError> {
+        let mut cur = Reader::new(bytes);
+        if cur.take(4, "header chunk")? != b"MThd" {
+            return Err(SmfError::Malformed("header chunk: missing MThd tag"));
+        }
+        let size = cur.u32_be("header chunk")? as usize;
+        if size < 6 {
+            return Err(SmfError::Malformed("header chunk: too short"));
+        }
+        let mut header = Reader::new(cur.take(size, "header chunk")?);
+        let format = header.u16_be("header chunk")?;
+        let count = header.u16_be("header chunk")?;
+        let division = header.u16_be("header chunk")?;
+        match format {
+            0 | 1 => {}
+            2 => return Err(SmfError::Unsupported("SMF type 2".into())),
+            _ => return Err(SmfError::Malformed("header chunk: unknown format")),
+        }
+        if format == 0 && count != 1 {
+            return Err(SmfError::Malformed(
+                "header chunk: type 0 with several tracks",
+            ));
+        }
+        let division = if division & 0x8000 == 0 {
+            if division == 0 {
+                return Err(SmfError::Malformed("header chunk: zero ticks per quarter"));
+            }
+            Division::Ppq(division)
+        } else {
+            let fps = (division >> 8) as u8 as i8;
+            let ticks_per_frame = division as u8;
+            match fps {
+                -24 | -25 | -29 | -30 if ticks_per_frame > 0 => Division::Smpte {
+                    fps: fps.unsigned_abs(),
+                    ticks_per_frame,
+                },
+                _ => return Err(SmfError::Malformed("header chunk: bad SMPTE division")),
+            }
+        };
+
+        let mut tracks = Vec::with_capacity(usize::from(count));
+        while tracks.len() < usize::from(count) {
+            let id = cur.take(4, "track chunk")?;
+            let size = cur.u32_be("track chunk")? as usize;
+            let body = cur.take(size, "track chunk")?;
+            if id == b"MTrk" {
+                tracks.push(parse_track(body)?);
+            }
+        }
+        Ok(Smf {
+            format,
+            division,
+            tracks,
+        })
+    }
+
+    /// 0 for a single multi-channel track, 1 for several tracks played
+    /// together.
+    pub fn format(&self) -> u16 {
+        self.format
+    }
+
+    pub fn division(&self) -> Division {
+        self.division
+    }
+
+    /// Each track's events, in order.
+    pub fn tracks(&self) -> &[Vec<TimedEvent>] {
+        &self.tracks
+    }
+
+    /// The file's tempo changes, from every track.
+    pub fn tempo_map(&self) -> TempoMap {
+        let mut changes: Vec<(u64, u32)> = self
+            .tracks
+            .iter()
+            .flatten()
+            .filter_map(|e| match e.event {
+                SmfEvent::Tempo(tempo) => Some((e.tick, tempo)),
+                _ => None,
+            })
+            .collect();
+        changes.sort_by_key(|&(tick, _)| tick);
+        TempoMap::new(self.division, &changes)
+    }
+
+    /// Every MIDI message in the file with the output frame it falls on at
+    /// `sample_rate`, in time order. Messages on the same tick keep their
+    /// order within a track, and lower-numbered tracks go first.
+    pub fn events(&self, sample_rate: u32) -> Vec<(u64, MidiMessage)> {
+        let map = self.tempo_map();
+        let mut events: Vec<(u64, MidiMessage)> = self
+            .tracks
+            .iter()
+            .flatten()
+            .filter_map(|e| match &e.event {
+                SmfEvent::Midi(message) => Some((e.tick, message.clone())),
+                _ => None,
+            })
+            .collect();
+        events.sort_by_key(|&(tick, _)| tick);
+        for (time, _) in &mut events {
+            *time = map.frame(*time, sample_rate);
+        }
+        events
+    }
+
+    /// Tick of the last event in any track.
+    pub fn length(&self) -> u64 {
+        self.tracks
+            .iter()
+            .filter_map(|track| track.last())
+            .map(|e| e.tick)
+            .max()
+            .unwrap_or(0)
+    }
+}
+
+/// A file's tempo changes, for converting ticks to time.
+///
+/// Conversion is exact: frames are rounded once, from the rational time of
+/// the tick, so long files do not drift.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TempoMap {
+    /// Time is counted in units of `1 / unit` seconds.
+    unit: u128,
+    /// Tick, time at that tick, and time units per tick from there on,
+    /// starting at tick 0.
+    segments: Vec<(u64, u128, u128)>,
+}
+
+impl TempoMap {
+    /// Builds a map from tempo changes in microseconds per quarter note,
+    /// sorted by tick. SMPTE files ignore them.
+    pub fn new(division: Division, changes: &[(u64, u32)]) -> Self {
+        match division {
+            Division::Ppq(ppq) => {
+                // Microseconds per quarter divided by ticks per quarter.
+                let unit = 1_000_000 * u128::from(ppq);
+                let mut segments = vec![(0, 0, u128::from(DEFAULT_TEMPO))];
+                for &(tick, tempo) in changes {
+                    let &(start, time, rate) = segments.last().expect("never empty");
+                    let time = time + u128::from(tick - start) * rate;
+                    if start == tick {
+                        segments.pop();
+                    }
+                    segments.push((tick, time, u128::from(tempo)));
+                }
+                TempoMap { unit, segments }
+            }
+            Division::Smpte {
+                fps,
+                ticks_per_frame,
+            } => {
+                let (frames, seconds) = match fps {
+                    29 => (30_000, 1_001),
+                    fps => (u128::from(fps), 1),
+                };
+                TempoMap {
+                    unit: frames * u128::from(ticks_per_frame),
+                    segments: vec![(0, 0, seconds)],
+                }
+            }
+        }
+    }
+
+    /// Time of `tick` in seconds.
+    pub fn seconds(&self, tick: u64) -> f64 {
+        self.time(tick) as f64 / self.unit as f64
+    }
+
+    /// The output frame `tick` falls on at `sample_rate`, rounded to the
+    /// nearest.
+    pub fn frame(&self, tick: u64, sample_rate: u32) -> u64 {
+        let scaled = self.time(tick) * u128::from(sample_rate);
+        ((2 * scaled + self.unit) / (2 * self.unit)) as u64
+    }
+
+    fn time(&self, tick: u64) -> u128 {
+        let i = self
+            .segments
+            .partition_point(|&(start, _, _)| start <= tick)
+            - 1;
+        let (start, time, rate) = self.segments[i];
+        time + u128::from(tick - start) * rate
+    }
+}
+
+/// Timed MIDI messages being played through [`MidiMapper`]s in real time.
+///
+/// Call [`play`](Sequence::play) from the control thread every so often with
+/// a frame a little ahead of [`Controller::now`]; it schedules whatever falls
+/// before that frame, and the engine places each message on its exact frame.
+/// Keep the lookahead short enough that the scheduled messages fit in the
+/// engine's queue.
+#[derive(Clone, Debug)]
+pub struct Sequence {
+    events: Vec<(u64, MidiMessage)>,
+    next: usize,
+    start: u64,
+}
+
+impl Sequence {
+    /// Wraps messages and their frames, as returned by [`Smf::events`],
+    /// to start on output frame 0.
+    pub fn new(mut events: Vec<(u64, MidiMessage)>) -> Self {
+        events.sort_by_key(|&(frame, _)| frame);
+        Sequence {
+            events,
+            next: 0,
+            start: 0,
+        }
+    }
+
+    /// Moves the start of the sequence to output frame `frame` and plays it
+    /// again from the beginning.
+    pub fn start_at(&mut self, frame: u64) {
+        self.start = frame;
+        self.next = 0;
+    }
+
+    /// Schedules, through every mapper, each message due before output
+    /// frame `until` that has not been played yet. Returns the number of
+    /// messages played.
+    ///
+    /// Keeps going if a command cannot be sent and returns the first error.
+    pub fn play(
+        &mut self,
+        mappers: &mut [MidiMapper],
+        ctl: &mut Controller,
+        until: u64,
+    ) -> Result<usize, EngineError> {
+        let mut result = Ok(());
+        let first = self.next;
+        while let Some((frame, message)) = self.events.get(self.next) {
+            let frame = self.start + frame;
+            if frame >= until {
+                break;
+            }
+            for mapper in mappers.iter_mut() {
+                if let Err(e) = mapper.handle(ctl, frame, message) {
+                    if result.is_ok() {
+                        result = Err(e);
+                    }
+                }
+            }
+            self.next += 1;
+        }
+        result.map(|()| self.next - first)
+    }
+
+    /// Every message has been played.
+    pub fn is_finished(&self) -> bool {
+        self.next == self.events.len()
+    }
+
+    /// Output frame of the last message.
+    pub fn end(&self) -> u64 {
+        self.start + self.events.last().map_or(0, |&(frame, _)| frame)
+    }
+}
+
+fn parse_track(body: &[u8]) -> Result<Vec<TimedEvent>, SmfError> {
+    let mut cur = Reader::new(body);
+    let mut events = Vec::new();
+    let mut tick = 0u64;
+    let mut running = None;
+    while cur.remaining() > 0 {
+        tick += u64::from(cur.vlq("track event")?);
+        let mut status = cur.u8("track event")?;
+        let event = match status {
+            0xff => {
+                let kind = cur.u8("meta event")?;
+                let len = cur.vlq("meta event")? as usize;
+                let data = cur.take(len, "meta event")?;
+                match kind {
+                    0x2f => break,
+                    0x51 => {
+                        let [a, b, c] = data else {
+                            return Err(SmfError::Malformed("tempo event: wrong length"));
+                        };
+                        let tempo = u32::from_be_bytes([0, *a, *b, *c]);
+                        if tempo == 0 {
+                            return Err(SmfError::Malformed("tempo event: zero tempo"));
+                        }
+                        SmfEvent::Tempo(tempo)
+                    }
+                    _ => SmfEvent::Meta {
+                        kind,
+                        data: data.to_vec(),
+                    },
+                }
+            }
+            0xf0 => {
+                let len = cur.vlq("sysex event")? as usize;
+                let data = cur.take(len, "sysex event")?;
+                let data = data.strip_suffix(&[0xf7]).unwrap_or(data);
+                SmfEvent::Midi(MidiMessage::SysEx(data.to_vec()))
+            }
+            0xf7 => {
+                let len = cur.vlq("escape event")? as usize;
+                SmfEvent::Escape(cur.take(len, "escape event")?.to_vec())
+            }
+            0xf1..=0xfe => return Err(SmfError::Malformed("track event: system message")),
+            _ => {
+                let mut first = None;
+                if status < 0x80 {
+                    first = Some(status);
+                    status = running.ok_or(SmfError::Malformed(
+                        "track event: running status without a status",
+                    ))?;
+                }
+                running = Some(status);
+                let needed = if matches!(status, 0xc0..=0xdf) { 1 } else { 2 };
+                let mut parser = Parser::new();
+                parser.push(status);
+                let mut message = None;
+                for i in 0..needed {
+                    let byte = match (i, first) {
+                        (0, Some(byte)) => byte,
+                        _ => cur.u8("channel message")?,
+                    };
+                    if byte >= 0x80 {
+                        return Err(SmfError::Malformed("channel message: status in data"));
+                    }
+                    message = parser.push(byte);
+                }
+                SmfEvent::Midi(message.expect("a complete channel message"))
+            }
+        };
+        events.push(TimedEvent { tick, event });
+    }
+    Ok(events)
+}
+
+/// Big-endian cursor over a byte slice that reports truncation by name.
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Reader { data, pos: 0 }
+    }
+
+    fn remaining(&self) -> usize {
+        self.data.len() - self.pos
+    }
+
+    fn take(&mut self, n: usize, what: &'static str) -> Result<&'a [u8], SmfError> {
+        if self.remaining() < n {
+            return Err(SmfError::Truncated(what));
+        }
+        let bytes = &self.data[self.pos..self.pos + n];
+        self.pos += n;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self, what: &'static str) -> Result<u8, SmfError> {
+        Ok(self.take(1, what)?[0])
+    }
+
+    fn u16_be(&mut self, what: &'static str) -> Result<u16, SmfError> {
+        let b = self.take(2, what)?;
+        Ok(u16::from_be_bytes([b[0], b[1]]))
+    }
+
+    fn u32_be(&mut self, what: &'static str) -> Result<u32, SmfError> {
+        let b = self.take(4, what)?;
+        Ok(u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    /// A variable-length quantity: up to four bytes of seven bits each,
+    /// most significant first.
+    fn vlq(&mut self, what: &'static str) -> Result<u32, SmfError> {
+        let mut value = 0;
+        for _ in 0..4 {
+            let byte = self.u8(what)?;
+            value = value << 7 | u32::from(byte & 0x7f);
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(SmfError::Malformed("variable-length quantity: too long"))
+    }
+}
@@ -0,0 +1,342 @@
+//! The modulation matrix.
+//!
+//! A [`ModMatrix`] routes modulation sources, such as LFOs, envelopes and
+//! MIDI controllers, to voice parameters. Each [`Route`] adds its source's
+//! value times its depth to one destination, and routes to the same
+//! destination sum.
+//!
+//! The engine holds one matrix, applied to every voice. It is built on the
+//! control thread and handed over whole with
+//! [`Controller::set_mod_matrix`](crate::Controller::set_mod_matrix): the
+//! audio thread swaps it in between blocks and returns the old one to be
+//! freed by the controller, so editing routes never locks or allocates on
+//! the audio thread.
+//!
+//! Voices evaluate the matrix every [`MOD_BLOCK`] frames. Gain, pan and
+//! cutoff ramp between evaluations; pitch and loop position step. The start
+//! offset is evaluated once, when the voice starts.
+
+use std::f64::consts::TAU;
+
+use crate::envelope::{Adsr, Envelope};
+
+/// Number of LFOs in a matrix.
+pub const LFOS: usize = 4;
+
+/// Frames between evaluations of the matrix.
+pub const MOD_BLOCK: usize = 32;
+
+/// An LFO's waveform. Every shape runs from -1 to 1.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Default)]
+pub enum LfoShape {
+    /// Starts at 0, rising.
+    #[default]
+    Sine,
+    /// Starts at 0, rising.
+    Triangle,
+    /// Rises from -1 to 1 over each cycle.
+    Saw,
+    /// 1 for the first half of each cycle, -1 for the second.
+    Square,
+    /// A new random level at the start of each cycle.
+    SampleAndHold,
+}
+
+/// How fast an LFO runs.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LfoRate {
+    /// Cycles per second.
+    Hz(f32),
+    /// Beats per cycle at the engine's tempo, set with
+    /// [`Command::SetTempo`](crate::Command::SetTempo).
+    Beats(f32),
+}
+
+/// A low-frequency oscillator. Each voice runs its own copy, starting at
+/// `phase` when the voice starts.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Lfo {
+    pub shape: LfoShape,
+    pub rate: LfoRate,
+    /// Starting point in the cycle, in `0..1`.
+    pub phase: f32,
+}
+
+impl Default for Lfo {
+    fn default() -> Self {
+        Lfo {
+            shape: LfoShape::Sine,
+            rate: LfoRate::Hz(1.0),
+            phase: 0.0,
+        }
+    }
+}
+
+impl Lfo {
+    /// An LFO of `shape` at `rate`, starting at the beginning of its cycle.
+    pub fn new(shape: LfoShape, rate: LfoRate) -> Self {
+        Lfo {
+            shape,
+            rate,
+            phase: 0.0,
+        }
+    }
+
+    /// Cycles per second at `tempo` beats per minute.
+    pub fn frequency(&self, tempo: f64) -> f64 {
+        match self.rate {
+            LfoRate::Hz(hz) => f64::from(hz),
+            LfoRate::Beats(beats) => tempo / 60.0 / f64::from(beats),
+        }
+    }
+
+    /// The level at `phase`, for the shapes with no random element.
+    /// Sample-and-hold reads as 0.
+    pub fn value(&self, phase: f64) -> f32 {
+        let p = phase.rem_euclid(1.0);
+        (match self.shape {
+            LfoShape::Sine => (TAU * p).sin(),
+            LfoShape::Triangle => 4.0 * ((p + 0.75).fract() - 0.5).abs() - 1.0,
+            LfoShape::Saw => 2.0 * p - 1.0,
+            LfoShape::Square => {
+                if p < 0.5 {
+                    1.0
+                } else {
+                    -1.0
+                }
+            }
+            LfoShape::SampleAndHold => 0.0,
+        }) as f32
+    }
+}
+
+/// Where a route's value comes from.
+///
+/// Velocity, the controllers and the envelopes run from 0 to 1, and LFOs
+/// from -1 to 1.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ModSource {
+    /// One of the matrix's [`LFOS`] LFOs. An index past the last reads as 0.
+    Lfo(usize),
+    /// The matrix's own envelope, triggered and released with each voice.
+    Envelope,
+    /// The voice's amplitude envelope.
+    AmpEnvelope,
+    /// The voice's filter envelope, or 0 if it plays unfiltered.
+    FilterEnvelope,
+    Velocity,
+    /// Octaves from middle C (note 60): -1 at note 48, 1 at note 72.
+    Key,
+    /// The channel's modulation wheel, CC1.
+    ModWheel,
+    /// The channel's aftertouch (channel pressure).
+    Aftertouch,
+}
+
+/// What a route changes, and the unit of its depth.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ModDestination {
+    /// Playback pitch, in semitones.
+    Pitch,
+    /// Voice gain, in dB.
+    Gain,
+    /// Stereo position, from -1 (left) to 1 (right), added to the voice's
+    /// own [pan](crate::TriggerParams::pan).
+    Pan,
+    /// Filter cutoff, in cents. Does nothing for an unfiltered voice.
+    Cutoff,
+    /// Start position, in sample frames. Evaluated when the voice starts.
+    Start,
+    /// Moves the loop, both ends together, in sample frames.
+    LoopPosition,
+}
+
+/// One source feeding one destination.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Route {
+    pub source: ModSource,
+    pub destination: ModDestination,
+    /// Destination units per unit of source.
+    pub depth: f32,
+}
+
+/// A set of routes and the LFOs and envelope they can draw on.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ModMatrix {
+    pub lfos: [Lfo; LFOS],
+    /// Shape of [`ModSource::Envelope`].
+    pub envelope: Adsr,
+    pub routes: Vec<Route>,
+}
+
+impl Default for ModMatrix {
+    fn default() -> Self {
+        ModMatrix {
+            lfos: [Lfo::default(); LFOS],
+            envelope: Adsr::default(),
+            routes: Vec::new(),
+        }
+    }
+}
+
+impl ModMatrix {
+    /// A matrix with no routes.
+    pub fn new() -> Self {
+        ModMatrix::default()
+    }
+
+    /// Replaces LFO `index`.
+    ///
+    /// # Panics
+    ///
+    /// If `index` is not below [`LFOS`].
+    pub fn with_lfo(mut self, index: usize, lfo: Lfo) -> Self {
+        self.lfos[index] = lfo;
+        self
+    }
+
+    /// Replaces the modulation envelope.
+    pub fn with_envelope(mut self, envelope: Adsr) -> Self {
+        self.envelope = envelope;
+        self
+    }
+
+    /// Adds a route from `source` to `destination`.
+    pub fn route(mut self, source: ModSource, destination: ModDestination, depth: f32) -> Self {
+        self.routes.push(Route {
+            source,
+            destination,
+            depth,
+        });
+        self
+    }
+
+    /// Returns `true` if any route feeds `destination`.
+    pub(crate) fn routes_to(&self, destination: ModDestination) -> bool {
+        self.routes.iter().any(|r| r.destination == destination)
+    }
+
+    /// Sums the routes for the given source values.
+    pub(crate) fn evaluate(&self, sources: &Sources) -> Modulation {
+        let mut out = Modulation::default();
+        for route in &self.routes {
+            let amount = sources.get(route.source) * route.depth;
+            match route.destination {
+                ModDestination::Pitch => out.pitch += amount,
+                ModDestination::Gain => out.gain += amount,
+                ModDestination::Pan => out.pan += amount,
+                ModDestination::Cutoff => out.cutoff += amount,
+                ModDestination::Start => out.start += amount,
+                ModDestination::LoopPosition => out.loop_shift += amount,
+            }
+        }
+        out
+    }
+}
+
+/// Controller values of one MIDI channel, each in `0..=1`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ChannelControls {
+    pub mod_wheel: f32,
+    pub aftertouch: f32,
+}
+
+/// Source values seen by one voice at one moment.
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct Sources {
+    pub lfo: [f32; LFOS],
+    pub envelope: f32,
+    pub amp_envelope: f32,
+    pub filter_envelope: f32,
+    pub velocity: f32,
+    pub key: f32,
+    pub controls: ChannelControls,
+}
+
+impl Sources {
+    fn get(&self, source: ModSource) -> f32 {
+        match source {
+            ModSource::Lfo(i) => self.lfo.get(i).copied().unwrap_or(0.0),
+            ModSource::Envelope => self.envelope,
+            ModSource::AmpEnvelope => self.amp_envelope,
+            ModSource::FilterEnvelope => self.filter_envelope,
+            ModSource::Velocity => self.velocity,
+            ModSource::Key => self.key,
+            ModSource::ModWheel => self.controls.mod_wheel,
+            ModSource::Aftertouch => self.controls.aftertouch,
+        }
+    }
+}
+
+/// Summed offsets for each destination, in the destination's units.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub(crate) struct Modulation {
+    pub pitch: f32,
+    pub gain: f32,
+    pub pan: f32,
+    pub cutoff: f32,
+    pub start: f32,
+    pub loop_shift: f32,
+}
+
+/// A voice's LFOs and modulation envelope.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct ModState {
+    phases: [f64; LFOS],
+    /// Current sample-and-hold levels.
+    held: [f32; LFOS],
+    rng: u64,
+    pub envelope: Envelope,
+}
+
+impl ModState {
+    /// Starts the LFOs and envelope of `matrix` afresh. `seed` varies the
+    /// sample-and-hold levels between voices.
+    pub fn trigger(&mut self, matrix: &ModMatrix, seed: u64) {
+        // SplitMix64 of the seed, so neighbouring seeds diverge at once.
+        let mut z = seed.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        self.rng = (z ^ (z >> 31)) | 1;
+        for (i, lfo) in matrix.lfos.iter().enumerate() {
+            self.phases[i] = f64::from(lfo.phase).rem_euclid(1.0);
+            self.held[i] = self.random();
+        }
+        self.envelope.trigger(matrix.envelope);
+    }
+
+    /// Current level of each LFO.
+    pub fn lfos(&self, matrix: &ModMatrix) -> [f32; LFOS] {
+        let mut out = [0.0; LFOS];
+        for (i, lfo) in matrix.lfos.iter().enumerate() {
+            out[i] = match lfo.shape {
+                LfoShape::SampleAndHold => self.held[i],
+                _ => lfo.value(self.phases[i]),
+            };
+        }
+        out
+    }
+
+    /// Moves the LFOs and envelope on by `frames`.
+    pub fn advance(&mut self, matrix: &ModMatrix, frames: usize, sample_rate: u32, tempo: f64) {
+        for (i, lfo) in matrix.lfos.iter().enumerate() {
+            let phase =
+                self.phases[i] + lfo.frequency(tempo) * frames as f64 / f64::from(sample_rate);
+            if !(0.0..1.0).contains(&phase) {
+                self.held[i] = self.random();
+            }
+            self.phases[i] = phase.rem_euclid(1.0);
+        }
+        for _ in 0..frames {
+            self.envelope.next(sample_rate as f32);
+        }
+    }
+
+    /// A uniform value in `-1..1`.
+    fn random(&mut self) -> f32 {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        (self.rng >> 40) as f32 / (1u64 << 23) as f32 - 1.0
+    }
+}
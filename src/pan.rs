@@ -0,0 +1,87 @@
+//! Stereo panning.
+//!
+//! A voice's pan places it between the two channels of its output bus. How
+//! the level is shared between them is set engine-wide by a [`PanLaw`];
+//! whether a stereo sample is balanced or truly panned is set per voice by
+//! its [`PanMode`].
+//!
+//! A panned voice follows its law's curve as it stands: the near side of a
+//! hard-panned source plays at unity, and a centred one sits the law's
+//! attenuation below that on both sides. A mono sample is one source at the
+//! voice's pan, a balanced stereo sample gives each channel its own side's
+//! gain, and a truly panned one places its two channels as sources either
+//! side of it. An unpanned voice plays at unity on both sides.
+
+use std::f32::consts::FRAC_PI_2;
+
+/// How a panned source's level is split between left and right.
+///
+/// Named after how far below a hard-panned source a centred one sits.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Default)]
+pub enum PanLaw {
+    /// -3 dB at the centre: the summed power stays constant across the
+    /// field.
+    #[default]
+    ConstantPower,
+    /// -4.5 dB at the centre, between constant power and linear.
+    Compromise,
+    /// -6 dB at the centre: the summed amplitude stays constant, so a
+    /// panned source folds down to mono at a steady level.
+    Linear,
+}
+
+impl PanLaw {
+    /// Left and right gains at `position`, from -1 (left) to 1 (right).
+    /// The near side of a hard-panned source gets 1.
+    pub fn gains(self, position: f32) -> (f32, f32) {
+        let x = (position.clamp(-1.0, 1.0) + 1.0) / 2.0;
+        match self {
+            PanLaw::ConstantPower => (((1.0 - x) * FRAC_PI_2).sin(), (x * FRAC_PI_2).sin()),
+            PanLaw::Compromise => {
+                let (l, r) = PanLaw::ConstantPower.gains(position);
+                ((l * (1.0 - x)).sqrt(), (r * x).sqrt())
+            }
+            PanLaw::Linear => (1.0 - x, x),
+        }
+    }
+}
+
+/// How a stereo sample is panned. Samples of more channels are always
+/// balanced, on the first two channels of their bus.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Default)]
+pub enum PanMode {
+    /// Each sample channel stays on its own side, and panning turns the
+    /// other one down, like a balance control.
+    #[default]
+    Balance,
+    /// Both sample channels move across the field: at a hard pan both play
+    /// on the one side.
+    TruePan,
+}
+
+/// Gains from each of the first two sample channels to the left and right
+/// channels of a bus, for a sample of `channels` channels at `position`, or
+/// unpanned.
+pub(crate) fn matrix(
+    law: PanLaw,
+    mode: PanMode,
+    channels: usize,
+    position: Option<f32>,
+) -> [[f32; 2]; 2] {
+    let Some(position) = position else {
+        return match channels {
+            1 => [[1.0, 1.0], [0.0, 0.0]],
+            _ => [[1.0, 0.0], [0.0, 1.0]],
+        };
+    };
+    let (l, r) = law.gains(position);
+    match (channels, mode) {
+        (1, _) => [[l, r], [0.0, 0.0]],
+        (_, PanMode::Balance) => [[l, 0.0], [0.0, r]],
+        (_, PanMode::TruePan) => {
+            let (ll, lr) = law.gains(2.0 * position - 1.0);
+            let (rl, rr) = law.gains(2.0 * position + 1.0);
+            [[ll, lr], [rl, rr]]
+        }
+    }
+}
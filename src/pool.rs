@@ -0,0 +1,377 @@
+//! Polyphonic voice allocation and voice stealing.
+
+use std::fmt;
+use std::sync::Arc;
+
+use crate::effect::{Effect, SENDS};
+use crate::engine::SampleId;
+use crate::sample::Sample;
+use crate::stretch::Stretcher;
+use crate::voice::{RenderContext, TriggerParams, Voice, VoiceId};
+
+/// What to do when a note is triggered and every voice is busy.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum StealPolicy {
+    /// Steal the voice that was started first.
+    #[default]
+    Oldest,
+    /// Steal the voice with the lowest output level.
+    Quietest,
+    /// A new note always replaces a voice already playing the same note, even
+    /// if free voices remain. Falls back to [`Oldest`](StealPolicy::Oldest)
+    /// when the pool is full and no voice shares the note.
+    SameNote,
+    /// Drop the new note.
+    Refuse,
+}
+
+/// A fixed set of voices plus room for stolen voices to fade out.
+///
+/// Stealing never makes the new note wait: the victim's state is copied into
+/// a separate tail slot where it fades over a few milliseconds while its
+/// original slot starts the new note straight away. Tails have as many slots
+/// as there are voices; if they are all busy, the tail closest to silence is
+/// cut.
+///
+/// Each voice can also have its own insert effect and time-stretch buffers.
+/// Both go with the voice into its tail slot, so a stolen voice fades out
+/// through them.
+#[derive(Debug)]
+pub struct VoicePool {
+    voices: Vec<Voice>,
+    tails: Vec<Voice>,
+    policy: StealPolicy,
+    fade_frames: usize,
+    serial: u64,
+    inserts: Inserts,
+    /// Time-stretch buffers for each voice, then for each tail, or none.
+    stretchers: Vec<Stretcher>,
+}
+
+/// An insert effect for each voice, then one for each tail, or none at all.
+#[derive(Default)]
+pub(crate) struct Inserts(Vec<Box<dyn Effect>>);
+
+impl Inserts {
+    pub(crate) fn new(effects: Vec<Box<dyn Effect>>) -> Self {
+        Inserts(effects)
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub(crate) fn iter_mut(&mut self) -> impl Iterator<Item = &mut Box<dyn Effect>> {
+        self.0.iter_mut()
+    }
+}
+
+impl fmt::Debug for Inserts {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Inserts({})", self.0.len())
+    }
+}
+
+impl VoicePool {
+    /// Creates a pool of `voices` voices. Stolen voices fade out over
+    /// `fade_frames` frames.
+    pub fn new(voices: usize, policy: StealPolicy, fade_frames: usize) -> Self {
+        // Voice `i` reads streamed samples through stream buffer `i`, and
+        // tail `i` through buffer `voices + i`.
+        let numbered = |first: usize| {
+            (first..first + voices)
+                .map(|ring| {
+                    let mut voice = Voice::default();
+                    voice.set_ring(ring);
+                    voice
+                })
+                .collect()
+        };
+        VoicePool {
+            voices: numbered(0),
+            tails: numbered(voices),
+            policy,
+            fade_frames,
+            serial: 0,
+            inserts: Inserts::default(),
+            stretchers: Vec::new(),
+        }
+    }
+
+    /// Gives every voice and tail time-stretch buffers for grains of
+    /// `window` frames, or none for 0.
+    pub(crate) fn with_stretch_window(mut self, window: usize) -> Self {
+        self.stretchers = match window {
+            0 => Vec::new(),
+            _ => (0..2 * self.voices.len())
+                .map(|_| Stretcher::new(window))
+                .collect(),
+        };
+        self
+    }
+
+    /// Gives every voice and tail its insert effect, returning the ones they
+    /// had. `inserts` must be empty, for none, or hold one for each voice
+    /// followed by one for each tail.
+    pub(crate) fn replace_inserts(&mut self, inserts: Inserts) -> Inserts {
+        debug_assert!(inserts.is_empty() || inserts.0.len() == 2 * self.voices.len());
+        std::mem::replace(&mut self.inserts, inserts)
+    }
+
+    /// The voices' insert effects, including those of the tails.
+    pub(crate) fn inserts_mut(&mut self) -> &mut Inserts {
+        &mut self.inserts
+    }
+
+    /// The current stealing policy.
+    pub fn policy(&self) -> StealPolicy {
+        self.policy
+    }
+
+    /// Changes the stealing policy.
+    pub fn set_policy(&mut self, policy: StealPolicy) {
+        self.policy = policy;
+    }
+
+    /// Total number of voices.
+    pub fn capacity(&self) -> usize {
+        self.voices.len()
+    }
+
+    /// Number of voices playing, not counting stolen voices fading out.
+    pub fn active(&self) -> usize {
+        self.voices.iter().filter(|v| v.is_active()).count()
+    }
+
+    /// Number of stolen voices still fading out.
+    pub fn fading(&self) -> usize {
+        self.tails.iter().filter(|v| v.is_active()).count()
+    }
+
+    /// Starts a voice, stealing one according to the policy if needed.
+    /// Returns `false` if the note was refused.
+    pub fn trigger(&mut self, id: VoiceId, sample: SampleId, params: &TriggerParams) -> bool {
+        let Some(index) = self.allocate(params.note) else {
+            return false;
+        };
+        if self.voices[index].is_active() {
+            self.retire(index, self.fade_frames);
+        }
+        if let Some(insert) = self.inserts.0.get_mut(index) {
+            insert.reset();
+        }
+        if let Some(stretcher) = self.stretchers.get_mut(index) {
+            stretcher.reset();
+        }
+        self.serial += 1;
+        let voice = &mut self.voices[index];
+        voice.start(id, sample, params);
+        voice.set_serial(self.serial);
+        true
+    }
+
+    fn allocate(&self, note: u8) -> Option<usize> {
+        if self.policy == StealPolicy::SameNote {
+            if let Some(i) = self.position_min(|v| v.note() == note, Voice::serial) {
+                return Some(i);
+            }
+        }
+        if let Some(i) = self.voices.iter().position(|v| !v.is_active()) {
+            return Some(i);
+        }
+        match self.policy {
+            StealPolicy::Refuse => None,
+            StealPolicy::Oldest | StealPolicy::SameNote => {
+                self.position_min(|_| true, Voice::serial)
+            }
+            StealPolicy::Quietest => self.position_min(|_| true, |v| v.level()),
+        }
+    }
+
+    /// Index of the active voice matching `filter` with the smallest `key`.
+    fn position_min<K: PartialOrd>(
+        &self,
+        filter: impl Fn(&Voice) -> bool,
+        key: impl Fn(&Voice) -> K,
+    ) -> Option<usize> {
+        let mut best: Option<(usize, K)> = None;
+        for (i, v) in self.voices.iter().enumerate() {
+            if !v.is_active() || !filter(v) {
+                continue;
+            }
+            let k = key(v);
+            if best.as_ref().is_none_or(|(_, b)| k < *b) {
+                best = Some((i, k));
+            }
+        }
+        best.map(|(i, _)| i)
+    }
+
+    /// Fades out every voice choked by group `group`, each over its
+    /// own [`choke_fade`](TriggerParams::choke_fade) at `sample_rate`. The
+    /// fades play from tail slots, so the voices are free again at once.
+    pub fn choke(&mut self, group: u32, sample_rate: u32) {
+        for index in 0..self.voices.len() {
+            let voice = &self.voices[index];
+            if voice.is_active() && voice.choked_by() == Some(group) {
+                let frames = (voice.choke_fade() * sample_rate as f32).round() as usize;
+                self.retire(index, frames);
+            }
+        }
+    }
+
+    /// Moves the voice at `index` into a tail slot and fades it out over
+    /// `fade_frames` frames.
+    fn retire(&mut self, index: usize, fade_frames: usize) {
+        let slot = match self.tails.iter().position(|t| !t.is_active()) {
+            Some(slot) => slot,
+            None => (0..self.tails.len())
+                .min_by(|&a, &b| self.tails[a].level().total_cmp(&self.tails[b].level()))
+                .expect("pool has at least one tail slot"),
+        };
+        // The tail takes the victim's stream buffer along with its state.
+        let ring = self.tails[slot].ring();
+        self.tails[slot].clone_from(&self.voices[index]);
+        self.tails[slot].fade_out(fade_frames);
+        self.voices[index].stop();
+        self.voices[index].set_ring(ring);
+        // So does the victim's insert, tail and all.
+        if !self.inserts.is_empty() {
+            self.inserts.0.swap(index, self.voices.len() + slot);
+        }
+        if !self.stretchers.is_empty() {
+            self.stretchers.swap(index, self.voices.len() + slot);
+        }
+    }
+
+    /// The playing voice with the given id, if any.
+    pub fn get_mut(&mut self, id: VoiceId) -> Option<&mut Voice> {
+        self.voices
+            .iter_mut()
+            .find(|v| v.is_active() && v.id() == id)
+    }
+
+    /// Stops every voice and tail immediately.
+    pub fn stop_all(&mut self) {
+        self.voices.iter_mut().for_each(Voice::stop);
+        self.tails.iter_mut().for_each(Voice::stop);
+    }
+
+    /// Stops every voice and tail that plays `sample`.
+    pub fn stop_sample(&mut self, sample: SampleId) {
+        for v in self.voices.iter_mut().chain(&mut self.tails) {
+            if v.sample() == sample {
+                v.stop();
+            }
+        }
+    }
+
+    /// Mixes every active voice and tail into `out`, then lets go of the
+    /// stream buffers of voices no longer playing a streamed sample.
+    ///
+    /// Voices play without their insert effects; the engine renders those.
+    pub fn render(
+        &mut self,
+        samples: &[Option<Arc<Sample>>],
+        ctx: &RenderContext<'_>,
+        out: &mut [f32],
+        channels: usize,
+    ) {
+        let inserts = std::mem::take(&mut self.inserts);
+        self.render_sends(samples, ctx, out, channels, &mut [], None);
+        self.inserts = inserts;
+    }
+
+    /// Like [`render`](VoicePool::render), but runs voices through their
+    /// insert effects, and with `sends` also adds each voice's stereo
+    /// output, scaled by its send levels, to the matching interleaved
+    /// stereo send buffer.
+    ///
+    /// Voices that send or have an insert render into `scratch` first,
+    /// which must hold `out.len()` samples and at least two channels' worth
+    /// of frames.
+    pub(crate) fn render_sends(
+        &mut self,
+        samples: &[Option<Arc<Sample>>],
+        ctx: &RenderContext<'_>,
+        out: &mut [f32],
+        channels: usize,
+        scratch: &mut [f32],
+        mut sends: Option<&mut [Vec<f32>; SENDS]>,
+    ) {
+        let frames = out.len() / channels;
+        let tails = self.voices.len();
+        let voices = self.voices.iter_mut().enumerate();
+        let all = voices.chain(
+            self.tails
+                .iter_mut()
+                .enumerate()
+                .map(|(i, v)| (tails + i, v)),
+        );
+        for (index, voice) in all.filter(|(_, v)| v.is_active()) {
+            let Some(sample) = &samples[voice.sample().index()] else {
+                voice.stop();
+                continue;
+            };
+            let sending = sends.is_some() && voice.sends().iter().any(|&s| s != 0.0);
+            let insert = self.inserts.0.get_mut(index);
+            let stretcher = self.stretchers.get_mut(index);
+            if insert.is_none() && !sending {
+                voice.render_with(sample, ctx, out, channels, stretcher);
+                continue;
+            }
+            let (first, width) = voice.outputs(channels);
+            // With an insert the voice plays in stereo, through the effect,
+            // onto its outputs; without one, straight onto them.
+            let (rendered, stride, left, right) = match insert {
+                Some(insert) => {
+                    let stereo = &mut scratch[..2 * frames];
+                    stereo.fill(0.0);
+                    voice.render_with(sample, ctx, stereo, 2, stretcher);
+                    insert.process(stereo);
+                    for (frame, v) in out.chunks_exact_mut(channels).zip(stereo.chunks_exact(2)) {
+                        let frame = &mut frame[first..first + width];
+                        if width == 1 {
+                            frame[0] += (v[0] + v[1]) / 2.0;
+                        } else {
+                            for (c, o) in frame.iter_mut().enumerate() {
+                                *o += v[c % 2];
+                            }
+                        }
+                    }
+                    (&scratch[..2 * frames], 2, 0, 1)
+                }
+                None => {
+                    let scratch = &mut scratch[..out.len()];
+                    scratch.fill(0.0);
+                    voice.render_with(sample, ctx, scratch, channels, stretcher);
+                    for (o, v) in out.iter_mut().zip(scratch.iter()) {
+                        *o += v;
+                    }
+                    let right = first + usize::from(width > 1);
+                    (&scratch[..], channels, first, right)
+                }
+            };
+            let Some(buses) = sends.as_deref_mut().filter(|_| sending) else {
+                continue;
+            };
+            for (bus, &level) in buses.iter_mut().zip(voice.sends()) {
+                if level == 0.0 {
+                    continue;
+                }
+                for (frame, send) in rendered.chunks_exact(stride).zip(bus.chunks_exact_mut(2)) {
+                    send[0] += frame[left] * level;
+                    send[1] += frame[right] * level;
+                }
+            }
+        }
+        for voice in self.voices.iter().chain(&self.tails) {
+            let streaming = voice.is_active() && ctx.streams.length(voice.sample()).is_some();
+            if !streaming {
+                if let Some(buffer) = ctx.streams.buffers.get(voice.ring()) {
+                    buffer.cancel();
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,496 @@
+//! Multi-channel `f32` sample buffers.
+//!
+//! A [`Sample`] owns a single contiguous `Vec<f32>` and records how the
+//! channels are arranged inside it. Interleaved storage (`L R L R ...`) is what
+//! files and audio devices use; planar storage (`L L ... R R ...`) is what most
+//! DSP code wants. Switching between the two happens in place, so a loaded
+//! sample never needs a second buffer of the same size.
+
+use std::fmt;
+
+use crate::stream::RingChannel;
+
+/// How the channels of a [`Sample`] are arranged in memory.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Default)]
+pub enum Layout {
+    /// Frames are stored one after another, channels adjacent within a frame.
+    #[default]
+    Interleaved,
+    /// Each channel is stored as one contiguous run of `frames` values.
+    Planar,
+}
+
+/// How a voice behaves when it reaches a [`LoopRegion`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Default)]
+pub enum LoopMode {
+    /// Play straight through to the end of the sample.
+    #[default]
+    Off,
+    /// Jump from the loop end back to the loop start, forever.
+    Forward,
+    /// Bounce between the loop end and the loop start.
+    PingPong,
+    /// On reaching the loop end, play the loop backwards, jumping from the
+    /// loop start back to the loop end.
+    Reverse,
+    /// Loop forwards until the note is released, then play on to the end of
+    /// the sample.
+    UntilRelease,
+}
+
+/// Loop points of a sample.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Default)]
+pub struct LoopRegion {
+    /// First frame of the loop.
+    pub start: usize,
+    /// Frame just past the end of the loop.
+    pub end: usize,
+    pub mode: LoopMode,
+    /// Length in frames of the crossfade that smooths the loop seam. Applies
+    /// to forward, reverse and until-release loops; ping-pong loops have no
+    /// seam. Clamped to the data available on the far side of the seam.
+    pub crossfade: usize,
+}
+
+impl LoopRegion {
+    /// A forward loop over `start..end` without a crossfade.
+    pub fn forward(start: usize, end: usize) -> Self {
+        LoopRegion {
+            start,
+            end,
+            mode: LoopMode::Forward,
+            crossfade: 0,
+        }
+    }
+
+    /// Number of frames in the loop.
+    pub fn len(&self) -> usize {
+        self.end.saturating_sub(self.start)
+    }
+
+    /// Returns `true` if the loop covers no frames.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Errors produced when constructing a [`Sample`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SampleError {
+    /// A sample must have at least one channel.
+    NoChannels,
+    /// A sample rate of zero was given.
+    ZeroSampleRate,
+    /// The data length is not a multiple of the channel count.
+    LengthMismatch { len: usize, channels: usize },
+    /// Per-channel buffers passed to [`Sample::from_channels`] differ in length.
+    RaggedChannels {
+        channel: usize,
+        expected: usize,
+        found: usize,
+    },
+}
+
+impl fmt::Display for SampleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SampleError::NoChannels => write!(f, "sample has no channels"),
+            SampleError::ZeroSampleRate => write!(f, "sample rate must be non-zero"),
+            SampleError::LengthMismatch { len, channels } => write!(
+                f,
+                "data length {len} is not a multiple of the channel count {channels}"
+            ),
+            SampleError::RaggedChannels {
+                channel,
+                expected,
+                found,
+            } => write!(
+                f,
+                "channel {channel} has {found} frames, expected {expected}"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for SampleError {}
+
+/// A block of audio: `frames` frames of `channels` channels at `sample_rate`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Sample {
+    data: Vec<f32>,
+    channels: usize,
+    frames: usize,
+    sample_rate: u32,
+    layout: Layout,
+    loop_region: Option<LoopRegion>,
+}
+
+impl Sample {
+    /// Wraps interleaved data (`frame0ch0, frame0ch1, ..., frame1ch0, ...`).
+    pub fn from_interleaved(
+        data: Vec<f32>,
+        channels: usize,
+        sample_rate: u32,
+    ) -> Result<Self, SampleError> {
+        Self::new(data, channels, sample_rate, Layout::Interleaved)
+    }
+
+    /// Wraps planar data (`ch0frame0, ch0frame1, ..., ch1frame0, ...`).
+    pub fn from_planar(
+        data: Vec<f32>,
+        channels: usize,
+        sample_rate: u32,
+    ) -> Result<Self, SampleError> {
+        Self::new(data, channels, sample_rate, Layout::Planar)
+    }
+
+    /// Builds a planar sample from one buffer per channel.
+    ///
+    /// All channels must have the same length.
+    pub fn from_channels(channels: &[Vec<f32>], sample_rate: u32) -> Result<Self, SampleError> {
+        let expected = channels.first().map_or(0, Vec::len);
+        if let Some((channel, ch)) = channels
+            .iter()
+            .enumerate()
+            .find(|(_, ch)| ch.len() != expected)
+        {
+            return Err(SampleError::RaggedChannels {
+                channel,
+                expected,
+                found: ch.len(),
+            });
+        }
+        let data = channels.concat();
+        Self::new(data, channels.len(), sample_rate, Layout::Planar)
+    }
+
+    /// Creates a zero-filled sample.
+    pub fn silent(
+        channels: usize,
+        frames: usize,
+        sample_rate: u32,
+        layout: Layout,
+    ) -> Result<Self, SampleError> {
+        Self::new(vec![0.0; channels * frames], channels, sample_rate, layout)
+    }
+
+    fn new(
+        data: Vec<f32>,
+        channels: usize,
+        sample_rate: u32,
+        layout: Layout,
+    ) -> Result<Self, SampleError> {
+        if channels == 0 {
+            return Err(SampleError::NoChannels);
+        }
+        if sample_rate == 0 {
+            return Err(SampleError::ZeroSampleRate);
+        }
+        if !data.len().is_multiple_of(channels) {
+            return Err(SampleError::LengthMismatch {
+                len: data.len(),
+                channels,
+            });
+        }
+        Ok(Sample {
+            frames: data.len() / channels,
+            data,
+            channels,
+            sample_rate,
+            layout,
+            loop_region: None,
+        })
+    }
+
+    /// Number of channels.
+    pub fn channels(&self) -> usize {
+        self.channels
+    }
+
+    /// Number of frames (samples per channel).
+    pub fn frames(&self) -> usize {
+        self.frames
+    }
+
+    /// Sample rate in Hz.
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Current memory layout.
+    pub fn layout(&self) -> Layout {
+        self.layout
+    }
+
+    /// Length in seconds.
+    pub fn duration(&self) -> f64 {
+        self.frames as f64 / f64::from(self.sample_rate)
+    }
+
+    /// The sample's loop points, if it has any.
+    pub fn loop_region(&self) -> Option<LoopRegion> {
+        self.loop_region
+    }
+
+    /// Sets or clears the loop points.
+    pub fn set_loop_region(&mut self, region: Option<LoopRegion>) {
+        self.loop_region = region;
+    }
+
+    /// Builder form of [`set_loop_region`](Self::set_loop_region).
+    pub fn with_loop_region(mut self, region: LoopRegion) -> Self {
+        self.loop_region = Some(region);
+        self
+    }
+
+    /// Returns `true` if the sample holds no frames.
+    pub fn is_empty(&self) -> bool {
+        self.frames == 0
+    }
+
+    /// The raw storage, arranged according to [`layout`](Self::layout).
+    pub fn as_slice(&self) -> &[f32] {
+        &self.data
+    }
+
+    /// Mutable access to the raw storage.
+    pub fn as_mut_slice(&mut self) -> &mut [f32] {
+        &mut self.data
+    }
+
+    /// Consumes the sample and returns its storage.
+    pub fn into_vec(self) -> Vec<f32> {
+        self.data
+    }
+
+    #[inline]
+    fn index(&self, channel: usize, frame: usize) -> usize {
+        debug_assert!(channel < self.channels && frame < self.frames);
+        match self.layout {
+            Layout::Interleaved => frame * self.channels + channel,
+            Layout::Planar => channel * self.frames + frame,
+        }
+    }
+
+    /// Reads one value. Panics if `channel` or `frame` is out of range.
+    #[inline]
+    pub fn get(&self, channel: usize, frame: usize) -> f32 {
+        assert!(channel < self.channels && frame < self.frames);
+        self.data[self.index(channel, frame)]
+    }
+
+    /// Writes one value. Panics if `channel` or `frame` is out of range.
+    #[inline]
+    pub fn set(&mut self, channel: usize, frame: usize, value: f32) {
+        assert!(channel < self.channels && frame < self.frames);
+        let i = self.index(channel, frame);
+        self.data[i] = value;
+    }
+
+    /// One channel as a contiguous slice. Only available for planar samples.
+    pub fn channel(&self, channel: usize) -> Option<&[f32]> {
+        if self.layout != Layout::Planar || channel >= self.channels {
+            return None;
+        }
+        let start = channel * self.frames;
+        Some(&self.data[start..start + self.frames])
+    }
+
+    /// One channel as a mutable slice. Only available for planar samples.
+    pub fn channel_mut(&mut self, channel: usize) -> Option<&mut [f32]> {
+        if self.layout != Layout::Planar || channel >= self.channels {
+            return None;
+        }
+        let start = channel * self.frames;
+        Some(&mut self.data[start..start + self.frames])
+    }
+
+    /// One frame as a contiguous slice. Only available for interleaved samples.
+    pub fn frame(&self, frame: usize) -> Option<&[f32]> {
+        if self.layout != Layout::Interleaved || frame >= self.frames {
+            return None;
+        }
+        let start = frame * self.channels;
+        Some(&self.data[start..start + self.channels])
+    }
+
+    /// A strided view of one channel, used by the interpolators.
+    #[inline]
+    pub(crate) fn view(&self, channel: usize) -> ChannelView<'_> {
+        debug_assert!(channel < self.channels);
+        let (offset, stride) = match self.layout {
+            Layout::Interleaved => (channel, self.channels),
+            Layout::Planar => (channel * self.frames, 1),
+        };
+        ChannelView {
+            data: &self.data,
+            offset,
+            stride,
+            held: self.frames,
+            len: self.frames,
+            tail: None,
+        }
+    }
+
+    /// Iterates over one channel regardless of layout.
+    pub fn channel_iter(&self, channel: usize) -> impl Iterator<Item = f32> + '_ {
+        assert!(channel < self.channels);
+        let (start, step) = match self.layout {
+            Layout::Interleaved => (channel, self.channels),
+            Layout::Planar => (channel * self.frames, 1),
+        };
+        self.data[start..]
+            .iter()
+            .step_by(step)
+            .take(self.frames)
+            .copied()
+    }
+
+    /// Rearranges the storage into `layout` in place, without allocating.
+    pub fn set_layout(&mut self, layout: Layout) {
+        if layout == self.layout {
+            return;
+        }
+        if self.channels > 1 && self.frames > 1 {
+            match layout {
+                Layout::Planar => deinterleave_in_place(&mut self.data, self.channels),
+                Layout::Interleaved => interleave_in_place(&mut self.data, self.channels),
+            }
+        }
+        self.layout = layout;
+    }
+
+    /// Consuming form of [`set_layout`](Self::set_layout).
+    pub fn into_layout(mut self, layout: Layout) -> Self {
+        self.set_layout(layout);
+        self
+    }
+
+    /// Copies the sample into `out` in interleaved order.
+    ///
+    /// Panics if `out` is not exactly `frames * channels` long.
+    pub fn copy_interleaved_into(&self, out: &mut [f32]) {
+        assert_eq!(out.len(), self.data.len());
+        match self.layout {
+            Layout::Interleaved => out.copy_from_slice(&self.data),
+            Layout::Planar => {
+                for (ch, plane) in self.data.chunks_exact(self.frames).enumerate() {
+                    for (frame, &v) in plane.iter().enumerate() {
+                        out[frame * self.channels + ch] = v;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Copies the sample into `out` in planar order.
+    ///
+    /// Panics if `out` is not exactly `frames * channels` long.
+    pub fn copy_planar_into(&self, out: &mut [f32]) {
+        assert_eq!(out.len(), self.data.len());
+        match self.layout {
+            Layout::Planar => out.copy_from_slice(&self.data),
+            Layout::Interleaved => {
+                for (frame, values) in self.data.chunks_exact(self.channels).enumerate() {
+                    for (ch, &v) in values.iter().enumerate() {
+                        out[ch * self.frames + frame] = v;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// One channel of a [`Sample`], whatever its layout.
+///
+/// For a streamed sample the `Sample` holds only the preloaded frames, and
+/// the rest are read from the voice's stream buffer.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct ChannelView<'a> {
+    data: &'a [f32],
+    offset: usize,
+    stride: usize,
+    /// Frames held in `data`.
+    held: usize,
+    /// Frames in the whole channel.
+    len: usize,
+    tail: Option<RingChannel<'a>>,
+}
+
+impl<'a> ChannelView<'a> {
+    /// Extends the view to `len` frames, reading those past the sample's
+    /// own from `tail`.
+    #[inline]
+    pub(crate) fn with_tail(self, len: usize, tail: Option<RingChannel<'a>>) -> Self {
+        ChannelView { len, tail, ..self }
+    }
+
+    /// Number of frames.
+    #[inline]
+    pub(crate) fn len(&self) -> usize {
+        self.len
+    }
+
+    /// The value at `frame`, or silence outside the channel or outside what
+    /// the stream buffer holds.
+    #[inline]
+    pub(crate) fn at(&self, frame: isize) -> f32 {
+        if frame < 0 || frame as usize >= self.len {
+            return 0.0;
+        }
+        let frame = frame as usize;
+        if frame < self.held {
+            self.data[self.offset + frame * self.stride]
+        } else {
+            self.tail.map_or(0.0, |tail| tail.at(frame))
+        }
+    }
+}
+
+// In-place layout conversion.
+//
+// A general in-place matrix transpose needs either scratch memory or a
+// quadratic cycle search. Instead we split the frames in half, convert each
+// half recursively and then stitch the per-channel blocks together with slice
+// rotations. That costs O(channels * n * log(frames)) moves, no heap memory and
+// a recursion depth of log2(frames).
+
+/// Converts `channels`-way interleaved `data` to planar.
+fn deinterleave_in_place(data: &mut [f32], channels: usize) {
+    let frames = data.len() / channels;
+    if frames <= 1 {
+        return;
+    }
+    let head = frames / 2;
+    let tail = frames - head;
+    let (a, b) = data.split_at_mut(head * channels);
+    deinterleave_in_place(a, channels);
+    deinterleave_in_place(b, channels);
+
+    // Layout is now A0 A1 .. A(c-1) B0 B1 .. B(c-1); pull each Bk next to Ak.
+    for k in 0..channels {
+        let start = k * (head + tail) + head;
+        let end = channels * head + (k + 1) * tail;
+        data[start..end].rotate_right(tail);
+    }
+}
+
+/// Converts `channels`-way planar `data` to interleaved.
+fn interleave_in_place(data: &mut [f32], channels: usize) {
+    let frames = data.len() / channels;
+    if frames <= 1 {
+        return;
+    }
+    let head = frames / 2;
+    let tail = frames - head;
+
+    // Layout is A0 B0 A1 B1 ..; gather all the A blocks to the front first.
+    for k in 1..channels {
+        let start = k * head;
+        let end = k * (head + tail) + head;
+        data[start..end].rotate_right(head);
+    }
+    let (a, b) = data.split_at_mut(head * channels);
+    interleave_in_place(a, channels);
+    interleave_in_place(b, channels);
+}
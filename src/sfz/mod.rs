@@ -0,0 +1,361 @@
+//! SFZ instrument definitions.
+//!
+//! [`Sfz::parse_file`] reads an `.sfz` file, resolving `#define` and
+//! `#include`, and flattens the `<global>`, `<master>`, `<group>` and
+//! `<region>` hierarchy into one fully inherited [`Region`] per region
+//! header. [`Sfz::load`] then decodes the referenced samples into an engine
+//! and builds an [`Instrument`] from the regions.
+//!
+//! Opcodes this module does not implement are not silently dropped: each
+//! one is reported once in [`Sfz::warnings`], along with anything else in
+//! the file that had to be skipped.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::codec::{self, DecodeError};
+use crate::controller::Controller;
+use crate::engine::{EngineError, SampleId};
+use crate::envelope::Adsr;
+use crate::instrument::{Alternation, Instrument, Variant, Zone};
+use crate::sample::{LoopMode, LoopRegion};
+use crate::voice::TriggerParams;
+
+mod parse;
+
+/// Errors that stop an SFZ file from loading.
+#[derive(Debug)]
+pub enum SfzError {
+    /// The file, an included file or a sample could not be read.
+    Io { path: PathBuf, source: io::Error },
+    /// A sample file could not be decoded.
+    Decode { path: PathBuf, source: DecodeError },
+    /// `#include` nested too deeply, most likely a cycle.
+    IncludeDepth { path: PathBuf },
+    /// The engine refused a sample.
+    Engine(EngineError),
+}
+
+impl fmt::Display for SfzError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SfzError::Io { path, source } => write!(f, "{}: {source}", path.display()),
+            SfzError::Decode { path, source } => write!(f, "{}: {source}", path.display()),
+            SfzError::IncludeDepth { path } => {
+                write!(f, "{}: #include nested too deeply", path.display())
+            }
+            SfzError::Engine(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl std::error::Error for SfzError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SfzError::Io { source, .. } => Some(source),
+            SfzError::Decode { source, .. } => Some(source),
+            SfzError::IncludeDepth { .. } => None,
+            SfzError::Engine(e) => Some(e),
+        }
+    }
+}
+
+impl From<EngineError> for SfzError {
+    fn from(e: EngineError) -> Self {
+        SfzError::Engine(e)
+    }
+}
+
+/// Something in an SFZ file that was skipped or only partly honoured.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Warning {
+    pub file: PathBuf,
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.file.display(), self.line, self.message)
+    }
+}
+
+/// The `loop_mode` opcode.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SfzLoopMode {
+    NoLoop,
+    /// Plays to the end regardless of note-off.
+    OneShot,
+    Continuous,
+    /// Loops until note-off, then plays on to the end.
+    Sustain,
+}
+
+/// One region with every inherited opcode applied.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Region {
+    /// Sample path, resolved against the file's directory and `default_path`.
+    pub sample: PathBuf,
+    pub lokey: u8,
+    pub hikey: u8,
+    pub pitch_keycenter: u8,
+    pub lovel: u8,
+    pub hivel: u8,
+    /// Fine tuning in cents.
+    pub tune: f64,
+    /// Coarse tuning in semitones.
+    pub transpose: i32,
+    /// Gain in dB.
+    pub volume: f32,
+    /// Gain in percent.
+    pub amplitude: f32,
+    /// Stereo position, from -100 (left) to 100 (right).
+    pub pan: f32,
+    /// First frame to play.
+    pub offset: usize,
+    /// `None` when the file leaves it to the sample's own loop.
+    pub loop_mode: Option<SfzLoopMode>,
+    pub loop_start: Option<usize>,
+    /// Last frame of the loop, inclusive as in SFZ.
+    pub loop_end: Option<usize>,
+    /// The `ampeg_*` opcodes.
+    pub ampeg: Adsr,
+    pub seq_length: u32,
+    /// 1-based position in the round-robin sequence.
+    pub seq_position: u32,
+    pub lorand: f32,
+    pub hirand: f32,
+    /// Exclusive group, 0 for none.
+    pub group: u32,
+    /// Group whose notes cut this region off, 0 for none.
+    pub off_by: u32,
+    /// Seconds a region takes to fade out when cut off.
+    pub off_time: f32,
+}
+
+impl Default for Region {
+    fn default() -> Self {
+        Region {
+            sample: PathBuf::new(),
+            lokey: 0,
+            hikey: 127,
+            pitch_keycenter: 60,
+            lovel: 0,
+            hivel: 127,
+            tune: 0.0,
+            transpose: 0,
+            volume: 0.0,
+            amplitude: 100.0,
+            pan: 0.0,
+            offset: 0,
+            loop_mode: None,
+            loop_start: None,
+            loop_end: None,
+            ampeg: Adsr {
+                release: 0.001,
+                ..Adsr::default()
+            },
+            seq_length: 1,
+            seq_position: 1,
+            lorand: 0.0,
+            hirand: 1.0,
+            group: 0,
+            off_by: 0,
+            off_time: TriggerParams::default().choke_fade,
+        }
+    }
+}
+
+impl Region {
+    /// Trigger parameters for this region, given the loop stored in the
+    /// sample file.
+    pub fn params(&self, sample_loop: Option<LoopRegion>) -> TriggerParams {
+        let gain = 10f32.powf(self.volume / 20.0) * self.amplitude / 100.0;
+        TriggerParams {
+            gain,
+            start: self.offset,
+            loop_region: self.loop_region(sample_loop),
+            envelope: self.ampeg,
+            pan: (self.pan != 0.0).then_some(self.pan / 100.0),
+            choke_group: (self.group != 0).then_some(self.group),
+            choked_by: (self.off_by != 0).then_some(self.off_by),
+            choke_fade: self.off_time,
+            one_shot: self.loop_mode == Some(SfzLoopMode::OneShot),
+            ..TriggerParams::default()
+        }
+    }
+
+    fn loop_region(&self, sample_loop: Option<LoopRegion>) -> Option<LoopRegion> {
+        let off = LoopRegion {
+            start: 0,
+            end: 0,
+            mode: LoopMode::Off,
+            crossfade: 0,
+        };
+        let mode = match self.loop_mode {
+            Some(SfzLoopMode::NoLoop | SfzLoopMode::OneShot) => return Some(off),
+            Some(SfzLoopMode::Continuous) => LoopMode::Forward,
+            Some(SfzLoopMode::Sustain) => LoopMode::UntilRelease,
+            None => match sample_loop {
+                Some(region) => region.mode,
+                None => return None,
+            },
+        };
+        let start = self.loop_start.or(sample_loop.map(|r| r.start));
+        let end = self.loop_end.map(|e| e + 1).or(sample_loop.map(|r| r.end));
+        match (start, end) {
+            (Some(start), Some(end)) if start < end => Some(LoopRegion {
+                start,
+                end,
+                mode,
+                crossfade: sample_loop.map_or(0, |r| r.crossfade),
+            }),
+            _ => Some(off),
+        }
+    }
+
+    fn variant(&self, sample: SampleId, sample_loop: Option<LoopRegion>) -> Variant {
+        Variant {
+            tune: self.tune + 100.0 * f64::from(self.transpose),
+            params: self.params(sample_loop),
+            ..Variant::new(sample, self.pitch_keycenter)
+        }
+    }
+}
+
+/// A parsed SFZ file.
+#[derive(Clone, Debug, Default)]
+pub struct Sfz {
+    regions: Vec<Region>,
+    warnings: Vec<Warning>,
+}
+
+impl Sfz {
+    /// Reads and parses an SFZ file. Includes and sample paths are resolved
+    /// relative to its directory.
+    pub fn parse_file(path: impl AsRef<Path>) -> Result<Sfz, SfzError> {
+        let path = path.as_ref();
+        let source = std::fs::read_to_string(path).map_err(|source| SfzError::Io {
+            path: path.to_owned(),
+            source,
+        })?;
+        let dir = path.parent().unwrap_or(Path::new(""));
+        parse::parse(&source, path, dir)
+    }
+
+    /// Parses SFZ source text. Includes and sample paths are resolved
+    /// relative to `dir`.
+    pub fn parse_str(source: &str, dir: impl AsRef<Path>) -> Result<Sfz, SfzError> {
+        parse::parse(source, Path::new("<string>"), dir.as_ref())
+    }
+
+    /// The regions, in file order.
+    pub fn regions(&self) -> &[Region] {
+        &self.regions
+    }
+
+    /// Everything that was skipped or approximated while parsing.
+    pub fn warnings(&self) -> &[Warning] {
+        &self.warnings
+    }
+
+    /// Decodes every sample the regions use, adds them to the engine and
+    /// builds the instrument. Samples shared between regions are loaded
+    /// once.
+    ///
+    /// Regions with the same key and velocity ranges and a `seq_length`
+    /// above 1 become one round-robin zone; regions with the same ranges
+    /// and a narrowed `lorand`/`hirand` become one random zone.
+    pub fn load(&self, ctl: &mut Controller) -> Result<Instrument, SfzError> {
+        let mut loaded: HashMap<&Path, (SampleId, Option<LoopRegion>)> = HashMap::new();
+        let mut variants = Vec::with_capacity(self.regions.len());
+        for region in &self.regions {
+            let (id, sample_loop) = match loaded.get(region.sample.as_path()) {
+                Some(&entry) => entry,
+                None => {
+                    let sample = codec::load(&region.sample).map_err(|e| match e {
+                        DecodeError::Io(source) => SfzError::Io {
+                            path: region.sample.clone(),
+                            source,
+                        },
+                        source => SfzError::Decode {
+                            path: region.sample.clone(),
+                            source,
+                        },
+                    })?;
+                    let sample_loop = sample.loop_region();
+                    let entry = (ctl.add_sample(sample)?, sample_loop);
+                    loaded.insert(&region.sample, entry);
+                    entry
+                }
+            };
+            variants.push(region.variant(id, sample_loop));
+        }
+        Ok(self.build(variants))
+    }
+
+    /// Groups regions into zones; `variants[i]` plays region `i`.
+    fn build(&self, variants: Vec<Variant>) -> Instrument {
+        let mut zones: Vec<(ZoneKey, Vec<(u32, Variant)>)> = Vec::new();
+        for (region, variant) in self.regions.iter().zip(variants) {
+            let key = ZoneKey::of(region);
+            let order = region.seq_position;
+            match zones
+                .iter_mut()
+                .find(|(k, _)| key.alternates() && *k == key)
+            {
+                Some((_, members)) => members.push((order, variant)),
+                None => zones.push((key, vec![(order, variant)])),
+            }
+        }
+
+        let mut instrument = Instrument::new();
+        for (key, mut members) in zones {
+            members.sort_by_key(|(order, _)| *order);
+            let mut variants = members.into_iter().map(|(_, v)| v);
+            let first = variants.next().expect("zones have at least one member");
+            let zone = Zone::new(key.lokey..=key.hikey, first)
+                .with_velocities(key.lovel..=key.hivel)
+                .with_alternates(key.alternation, variants);
+            instrument.add_zone(zone);
+        }
+        instrument
+    }
+}
+
+/// What regions must share to become alternatives within one zone.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct ZoneKey {
+    lokey: u8,
+    hikey: u8,
+    lovel: u8,
+    hivel: u8,
+    alternation: Alternation,
+    seq_length: u32,
+    random: bool,
+}
+
+impl ZoneKey {
+    fn of(region: &Region) -> Self {
+        let random = region.lorand > 0.0 || region.hirand < 1.0;
+        ZoneKey {
+            lokey: region.lokey,
+            hikey: region.hikey,
+            lovel: region.lovel,
+            hivel: region.hivel,
+            alternation: if random {
+                Alternation::Random
+            } else {
+                Alternation::RoundRobin
+            },
+            seq_length: region.seq_length,
+            random,
+        }
+    }
+
+    fn alternates(&self) -> bool {
+        self.random || self.seq_length > 1
+    }
+}
@@ -0,0 +1,464 @@
+//! SFZ text processing: comments, `#define`, `#include`, headers and
+//! opcode inheritance.
+
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+use super::{Region, Sfz, SfzError, SfzLoopMode, Warning};
+
+/// Deepest `#include` nesting accepted before assuming a cycle.
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Header {
+    /// Before the first header.
+    None,
+    Control,
+    Global,
+    Master,
+    Group,
+    Region,
+    /// A header this parser does not handle; its opcodes are skipped.
+    Other,
+}
+
+/// Where a token came from, for warnings.
+#[derive(Clone, Copy)]
+struct Loc<'a> {
+    file: &'a Path,
+    line: usize,
+}
+
+struct Parser<'a> {
+    dir: &'a Path,
+    defines: Vec<(String, String)>,
+    header: Header,
+    default_path: PathBuf,
+    global: Region,
+    /// Valid while `master_open`.
+    master: Region,
+    /// Valid while `group_open`.
+    group: Region,
+    master_open: bool,
+    group_open: bool,
+    region: Option<Region>,
+    /// Line of the open region's header.
+    region_line: usize,
+    regions: Vec<Region>,
+    warnings: Vec<Warning>,
+    /// Unsupported opcodes already warned about.
+    reported: HashSet<String>,
+    /// Inside a `/* */` comment.
+    in_comment: bool,
+}
+
+pub(super) fn parse(source: &str, file: &Path, dir: &Path) -> Result<Sfz, SfzError> {
+    let mut parser = Parser {
+        dir,
+        defines: Vec::new(),
+        header: Header::None,
+        default_path: PathBuf::new(),
+        global: Region::default(),
+        master: Region::default(),
+        group: Region::default(),
+        master_open: false,
+        group_open: false,
+        region: None,
+        region_line: 0,
+        regions: Vec::new(),
+        warnings: Vec::new(),
+        reported: HashSet::new(),
+        in_comment: false,
+    };
+    parser.source(source, file, 0)?;
+    parser.finish_region(Loc { file, line: 0 });
+    Ok(Sfz {
+        regions: parser.regions,
+        warnings: parser.warnings,
+    })
+}
+
+impl Parser<'_> {
+    fn warn(&mut self, loc: Loc<'_>, message: impl Into<String>) {
+        self.warnings.push(Warning {
+            file: loc.file.to_owned(),
+            line: loc.line,
+            message: message.into(),
+        });
+    }
+
+    fn source(&mut self, source: &str, file: &Path, depth: usize) -> Result<(), SfzError> {
+        let in_comment = std::mem::replace(&mut self.in_comment, false);
+        for (i, line) in source.lines().enumerate() {
+            let loc = Loc { file, line: i + 1 };
+            let line = self.strip_comments(line);
+            let trimmed = line.trim();
+            if let Some(rest) = trimmed.strip_prefix("#define") {
+                self.define(rest, loc);
+            } else if let Some(rest) = trimmed.strip_prefix("#include") {
+                self.include(rest, loc, depth)?;
+            } else {
+                let line = self.substitute(&line);
+                self.tokens(&line, loc);
+            }
+        }
+        self.in_comment = in_comment;
+        Ok(())
+    }
+
+    fn strip_comments(&mut self, line: &str) -> String {
+        let mut out = String::with_capacity(line.len());
+        let mut rest = line;
+        loop {
+            if self.in_comment {
+                match rest.find("*/") {
+                    Some(end) => {
+                        self.in_comment = false;
+                        rest = &rest[end + 2..];
+                    }
+                    None => return out,
+                }
+            }
+            let line_comment = rest.find("//");
+            let block = rest.find("/*");
+            match (line_comment, block) {
+                (Some(l), b) if b.is_none_or(|b| l < b) => {
+                    out.push_str(&rest[..l]);
+                    return out;
+                }
+                (_, Some(b)) => {
+                    out.push_str(&rest[..b]);
+                    out.push(' ');
+                    self.in_comment = true;
+                    rest = &rest[b + 2..];
+                }
+                _ => {
+                    out.push_str(rest);
+                    return out;
+                }
+            }
+        }
+    }
+
+    fn define(&mut self, rest: &str, loc: Loc<'_>) {
+        let mut parts = rest.split_whitespace();
+        match (parts.next(), parts.next()) {
+            (Some(name), Some(value)) if name.starts_with('$') && name.len() > 1 => {
+                let value = value.to_owned();
+                match self.defines.iter_mut().find(|(n, _)| n == name) {
+                    Some(entry) => entry.1 = value,
+                    None => self.defines.push((name.to_owned(), value)),
+                }
+                // Longest first, so `$AB` is not replaced as `$A` + "B".
+                self.defines
+                    .sort_by_key(|(n, _)| std::cmp::Reverse(n.len()));
+            }
+            _ => self.warn(loc, "malformed #define"),
+        }
+    }
+
+    fn substitute(&self, line: &str) -> String {
+        let mut line = line.to_owned();
+        if line.contains('$') {
+            for (name, value) in &self.defines {
+                line = line.replace(name.as_str(), value);
+            }
+        }
+        line
+    }
+
+    fn include(&mut self, rest: &str, loc: Loc<'_>, depth: usize) -> Result<(), SfzError> {
+        let rest = self.substitute(rest);
+        let Some(name) = rest
+            .trim()
+            .strip_prefix('"')
+            .and_then(|r| r.split('"').next())
+        else {
+            self.warn(loc, "malformed #include");
+            return Ok(());
+        };
+        let path = self.dir.join(name.replace('\\', "/"));
+        if depth >= MAX_INCLUDE_DEPTH {
+            return Err(SfzError::IncludeDepth { path });
+        }
+        let source = std::fs::read_to_string(&path).map_err(|source| SfzError::Io {
+            path: path.clone(),
+            source,
+        })?;
+        self.source(&source, &path, depth + 1)
+    }
+
+    /// Splits a line into headers and `name=value` opcodes. A value runs up
+    /// to the next opcode or header, so sample paths may contain spaces.
+    fn tokens(&mut self, line: &str, loc: Loc<'_>) {
+        let mut rest = line.trim_start();
+        while !rest.is_empty() {
+            if let Some(after) = rest.strip_prefix('<') {
+                let Some(end) = after.find('>') else {
+                    self.warn(loc, "unterminated header");
+                    return;
+                };
+                self.header(&after[..end], loc);
+                rest = after[end + 1..].trim_start();
+                continue;
+            }
+            let Some(eq) = rest.find('=') else {
+                self.warn(loc, format!("unexpected text `{}`", rest.trim()));
+                return;
+            };
+            let name = rest[..eq].trim();
+            let tail = &rest[eq + 1..];
+            let end = value_end(tail);
+            let value = tail[..end].trim();
+            if name.is_empty() || name.contains(char::is_whitespace) {
+                self.warn(loc, format!("unexpected text `{name}`"));
+            } else {
+                self.opcode(name, value, loc);
+            }
+            rest = tail[end..].trim_start();
+        }
+    }
+
+    fn header(&mut self, name: &str, loc: Loc<'_>) {
+        self.finish_region(loc);
+        self.header = match name {
+            "control" => Header::Control,
+            "global" => {
+                self.global = Region::default();
+                self.master_open = false;
+                self.group_open = false;
+                Header::Global
+            }
+            "master" => {
+                self.master = self.global.clone();
+                self.master_open = true;
+                self.group_open = false;
+                Header::Master
+            }
+            "group" => {
+                self.group = self.parent(Header::Group).clone();
+                self.group_open = true;
+                Header::Group
+            }
+            "region" => {
+                self.region = Some(self.parent(Header::Region).clone());
+                self.region_line = loc.line;
+                Header::Region
+            }
+            _ => {
+                self.warn(loc, format!("unsupported header <{name}>"));
+                Header::Other
+            }
+        };
+    }
+
+    /// The nearest open level above `header`, which it inherits from.
+    fn parent(&self, header: Header) -> &Region {
+        if header == Header::Region && self.group_open {
+            &self.group
+        } else if self.master_open {
+            &self.master
+        } else {
+            &self.global
+        }
+    }
+
+    fn finish_region(&mut self, loc: Loc<'_>) {
+        if let Some(region) = self.region.take() {
+            let loc = Loc {
+                line: self.region_line,
+                ..loc
+            };
+            if region.sample.as_os_str().is_empty() {
+                self.warn(loc, "region without a sample skipped");
+                return;
+            }
+            self.regions.push(region);
+        }
+    }
+
+    fn opcode(&mut self, name: &str, value: &str, loc: Loc<'_>) {
+        let target = match self.header {
+            Header::None => {
+                self.warn(loc, format!("opcode `{name}` outside any header"));
+                return;
+            }
+            Header::Other => return,
+            Header::Control => {
+                match name {
+                    "default_path" => self.default_path = PathBuf::from(value.replace('\\', "/")),
+                    _ => self.unsupported(name, loc),
+                }
+                return;
+            }
+            Header::Global => &mut self.global,
+            Header::Master => &mut self.master,
+            Header::Group => &mut self.group,
+            Header::Region => match &mut self.region {
+                Some(region) => region,
+                None => return,
+            },
+        };
+        if name == "sample" {
+            let path = self
+                .dir
+                .join(&self.default_path)
+                .join(value.replace('\\', "/"));
+            target.sample = path;
+            return;
+        }
+        match apply(target, name, value) {
+            Applied::Ok => {}
+            Applied::Invalid => self.warn(loc, format!("invalid value `{value}` for `{name}`")),
+            Applied::Unsupported => self.unsupported(name, loc),
+        }
+    }
+
+    fn unsupported(&mut self, name: &str, loc: Loc<'_>) {
+        if self.reported.insert(name.to_owned()) {
+            self.warn(loc, format!("unsupported opcode `{name}`"));
+        }
+    }
+}
+
+/// Byte offset where an opcode value ends within `tail`.
+fn value_end(tail: &str) -> usize {
+    let header = tail.find('<').unwrap_or(tail.len());
+    match tail[..header].find('=') {
+        // The next opcode's name is the last word before its `=`.
+        Some(eq) => tail[..eq]
+            .trim_end()
+            .rfind(char::is_whitespace)
+            .unwrap_or(0),
+        None => header,
+    }
+}
+
+enum Applied {
+    Ok,
+    Invalid,
+    Unsupported,
+}
+
+fn apply(region: &mut Region, name: &str, value: &str) -> Applied {
+    let ok = |valid: bool| if valid { Applied::Ok } else { Applied::Invalid };
+    let env = &mut region.ampeg;
+    match name {
+        "lokey" => ok(set(&mut region.lokey, key(value))),
+        "hikey" => ok(set(&mut region.hikey, key(value))),
+        "pitch_keycenter" => ok(set(&mut region.pitch_keycenter, key(value))),
+        "key" => match key(value) {
+            Some(k) => {
+                region.lokey = k;
+                region.hikey = k;
+                region.pitch_keycenter = k;
+                Applied::Ok
+            }
+            None => Applied::Invalid,
+        },
+        "lovel" => ok(set(&mut region.lovel, midi_value(value))),
+        "hivel" => ok(set(&mut region.hivel, midi_value(value))),
+        "tune" => ok(set(&mut region.tune, value.parse().ok())),
+        "transpose" => ok(set(&mut region.transpose, value.parse().ok())),
+        "volume" => ok(set(&mut region.volume, value.parse().ok())),
+        "amplitude" => ok(set(&mut region.amplitude, value.parse().ok())),
+        "pan" => ok(set(&mut region.pan, value.parse().ok())),
+        "offset" => ok(set(&mut region.offset, value.parse().ok())),
+        "loop_start" | "loopstart" => ok(set_some(&mut region.loop_start, value.parse().ok())),
+        "loop_end" | "loopend" => ok(set_some(&mut region.loop_end, value.parse().ok())),
+        "loop_mode" | "loopmode" => {
+            let mode = match value {
+                "no_loop" => SfzLoopMode::NoLoop,
+                "one_shot" => SfzLoopMode::OneShot,
+                "loop_continuous" => SfzLoopMode::Continuous,
+                "loop_sustain" => SfzLoopMode::Sustain,
+                _ => return Applied::Invalid,
+            };
+            region.loop_mode = Some(mode);
+            Applied::Ok
+        }
+        "group" => ok(set(&mut region.group, value.parse().ok())),
+        "off_by" => ok(set(&mut region.off_by, value.parse().ok())),
+        "off_time" => ok(set(&mut region.off_time, seconds(value))),
+        "ampeg_attack" => ok(set(&mut env.attack, seconds(value))),
+        "ampeg_hold" => ok(set(&mut env.hold, seconds(value))),
+        "ampeg_decay" => ok(set(&mut env.decay, seconds(value))),
+        "ampeg_release" => ok(set(&mut env.release, seconds(value))),
+        "ampeg_sustain" => ok(set(
+            &mut env.sustain,
+            value
+                .parse::<f32>()
+                .ok()
+                .map(|p| (p / 100.0).clamp(0.0, 1.0)),
+        )),
+        "seq_length" => ok(set(
+            &mut region.seq_length,
+            value.parse().ok().filter(|&n| n >= 1),
+        )),
+        "seq_position" => ok(set(
+            &mut region.seq_position,
+            value.parse().ok().filter(|&n| n >= 1),
+        )),
+        "lorand" => ok(set(&mut region.lorand, value.parse().ok())),
+        "hirand" => ok(set(&mut region.hirand, value.parse().ok())),
+        _ => Applied::Unsupported,
+    }
+}
+
+fn set<T>(field: &mut T, value: Option<T>) -> bool {
+    match value {
+        Some(v) => {
+            *field = v;
+            true
+        }
+        None => false,
+    }
+}
+
+fn set_some<T>(field: &mut Option<T>, value: Option<T>) -> bool {
+    let valid = value.is_some();
+    if valid {
+        *field = value;
+    }
+    valid
+}
+
+fn seconds(value: &str) -> Option<f32> {
+    value.parse::<f32>().ok().filter(|s| *s >= 0.0)
+}
+
+fn midi_value(value: &str) -> Option<u8> {
+    value.parse::<u8>().ok().filter(|&v| v <= 127)
+}
+
+/// A MIDI note given as a number or a name such as `c4`, `F#2` or `eb-1`,
+/// with middle C as `c4` = 60.
+fn key(value: &str) -> Option<u8> {
+    if let Some(n) = midi_value(value) {
+        return Some(n);
+    }
+    let lower = value.to_ascii_lowercase();
+    let mut chars = lower.chars();
+    let base: i32 = match chars.next()? {
+        'c' => 0,
+        'd' => 2,
+        'e' => 4,
+        'f' => 5,
+        'g' => 7,
+        'a' => 9,
+        'b' => 11,
+        _ => return None,
+    };
+    let rest = chars.as_str();
+    let (accidental, octave) = if let Some(o) = rest.strip_prefix('#') {
+        (1, o)
+    } else if let Some(o) = rest.strip_prefix('b') {
+        (-1, o)
+    } else {
+        (0, rest)
+    };
+    let octave: i32 = octave.parse().ok()?;
+    u8::try_from((octave + 1) * 12 + base + accidental)
+        .ok()
+        .filter(|&n| n <= 127)
+}
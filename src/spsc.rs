@@ -0,0 +1,172 @@
+//! Bounded lock-free single-producer single-consumer queue.
+//!
+//! This is the only channel between the control thread and the audio thread.
+//! Both ends are wait-free: `push` and `pop` are a couple of atomic loads and
+//! one release store, and neither ever allocates. The storage is allocated
+//! once, by [`channel`].
+
+use std::cell::UnsafeCell;
+use std::fmt;
+use std::mem::MaybeUninit;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+/// Keeps the producer and consumer indices on separate cache lines.
+#[repr(align(64))]
+struct CachePadded<T>(T);
+
+struct Shared<T> {
+    buffer: Box<[UnsafeCell<MaybeUninit<T>>]>,
+    mask: usize,
+    /// Next slot to read. Written only by the consumer.
+    head: CachePadded<AtomicUsize>,
+    /// Next slot to write. Written only by the producer.
+    tail: CachePadded<AtomicUsize>,
+}
+
+// SAFETY: slots are handed from producer to consumer through the head/tail
+// release/acquire pairs, so a `T` is only ever accessed by one thread at once.
+unsafe impl<T: Send> Send for Shared<T> {}
+unsafe impl<T: Send> Sync for Shared<T> {}
+
+impl<T> Drop for Shared<T> {
+    fn drop(&mut self) {
+        let head = *self.head.0.get_mut();
+        let tail = *self.tail.0.get_mut();
+        for i in head..tail {
+            // SAFETY: slots in head..tail were written and never read.
+            unsafe { self.buffer[i & self.mask].get_mut().assume_init_drop() };
+        }
+    }
+}
+
+/// Creates a queue that holds at least `capacity` items.
+///
+/// The capacity is rounded up to the next power of two.
+pub fn channel<T>(capacity: usize) -> (Producer<T>, Consumer<T>) {
+    let capacity = capacity.max(1).next_power_of_two();
+    let buffer = (0..capacity)
+        .map(|_| UnsafeCell::new(MaybeUninit::uninit()))
+        .collect();
+    let shared = Arc::new(Shared {
+        buffer,
+        mask: capacity - 1,
+        head: CachePadded(AtomicUsize::new(0)),
+        tail: CachePadded(AtomicUsize::new(0)),
+    });
+    (
+        Producer {
+            shared: shared.clone(),
+        },
+        Consumer { shared },
+    )
+}
+
+/// The sending half of a queue created by [`channel`].
+pub struct Producer<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> Producer<T> {
+    /// Appends `value`, or hands it back if the queue is full.
+    pub fn push(&mut self, value: T) -> Result<(), T> {
+        let tail = self.shared.tail.0.load(Ordering::Relaxed);
+        let head = self.shared.head.0.load(Ordering::Acquire);
+        if tail.wrapping_sub(head) > self.shared.mask {
+            return Err(value);
+        }
+        // SAFETY: the slot is outside head..tail, so the consumer will not
+        // touch it until the store below publishes it.
+        unsafe { (*self.shared.buffer[tail & self.shared.mask].get()).write(value) };
+        self.shared
+            .tail
+            .0
+            .store(tail.wrapping_add(1), Ordering::Release);
+        Ok(())
+    }
+
+    /// Number of free slots. Only a lower bound while the consumer is active.
+    pub fn free(&self) -> usize {
+        self.capacity() - self.len()
+    }
+
+    /// Number of queued items.
+    pub fn len(&self) -> usize {
+        len(&self.shared)
+    }
+
+    /// Returns `true` if nothing is queued.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Total number of slots.
+    pub fn capacity(&self) -> usize {
+        self.shared.mask + 1
+    }
+}
+
+/// The receiving half of a queue created by [`channel`].
+pub struct Consumer<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> Consumer<T> {
+    /// Removes the oldest item, if any.
+    pub fn pop(&mut self) -> Option<T> {
+        let head = self.shared.head.0.load(Ordering::Relaxed);
+        let tail = self.shared.tail.0.load(Ordering::Acquire);
+        if head == tail {
+            return None;
+        }
+        // SAFETY: the slot is inside head..tail, published by the producer's
+        // release store, and the producer will not reuse it until we advance.
+        let value =
+            unsafe { (*self.shared.buffer[head & self.shared.mask].get()).assume_init_read() };
+        self.shared
+            .head
+            .0
+            .store(head.wrapping_add(1), Ordering::Release);
+        Some(value)
+    }
+
+    /// Number of queued items. Only a lower bound while the producer is active.
+    pub fn len(&self) -> usize {
+        len(&self.shared)
+    }
+
+    /// Returns `true` if nothing is queued.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Total number of slots.
+    pub fn capacity(&self) -> usize {
+        self.shared.mask + 1
+    }
+}
+
+fn len<T>(shared: &Shared<T>) -> usize {
+    // Head first: the tail can only move away from it, never behind it.
+    let head = shared.head.0.load(Ordering::Acquire);
+    let tail = shared.tail.0.load(Ordering::Acquire);
+    tail.wrapping_sub(head)
+}
+
+impl<T> fmt::Debug for Producer<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Producer")
+            .field("len", &self.len())
+            .field("capacity", &self.capacity())
+            .finish()
+    }
+}
+
+impl<T> fmt::Debug for Consumer<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Consumer")
+            .field("len", &self.len())
+            .field("capacity", &self.capacity())
+            .finish()
+    }
+}
@@ -0,0 +1,651 @@
+//! Disk streaming for samples too large to hold in memory.
+//!
+//! A [`StreamedSample`] keeps only its first frames in memory; the rest stay
+//! in a [`StreamSource`], usually a file. A voice playing one starts on the
+//! preloaded head straight away and asks for the frames that follow through
+//! its stream buffer, a ring that the [`Streamer`] fills on an I/O thread.
+//!
+//! The audio thread never waits for the disk. A voice that catches up with
+//! its buffer holds its position and stays silent until the data arrives,
+//! and the engine counts an underrun
+//! ([`Controller::underruns`](crate::Controller::underruns)).
+//!
+//! The buffers are allocated by [`Engine::new`](crate::Engine::new), one per
+//! voice, and shared with the streamer as plain atomics, so streaming adds
+//! no allocation or locking to the render path.
+
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crate::codec::{self, DecodeError, FileFormat, StreamDecoder};
+use crate::engine::SampleId;
+use crate::sample::{LoopRegion, Sample};
+
+/// Where a streamed sample's frames come from.
+///
+/// Once the sample is added to an engine, only the [`Streamer`] reads from
+/// its source, on the streamer's thread.
+pub trait StreamSource: Send {
+    fn channels(&self) -> usize;
+
+    fn sample_rate(&self) -> u32;
+
+    /// Total number of frames.
+    fn frames(&self) -> usize;
+
+    /// Loop points stored with the audio, if any.
+    fn loop_region(&self) -> Option<LoopRegion> {
+        None
+    }
+
+    /// Reads interleaved frames starting at `frame` into `out`, filling it
+    /// unless the source ends first. Returns the number of frames read.
+    fn read(&mut self, frame: usize, out: &mut [f32]) -> Result<usize, DecodeError>;
+}
+
+impl StreamSource for Sample {
+    fn channels(&self) -> usize {
+        Sample::channels(self)
+    }
+
+    fn sample_rate(&self) -> u32 {
+        Sample::sample_rate(self)
+    }
+
+    fn frames(&self) -> usize {
+        Sample::frames(self)
+    }
+
+    fn loop_region(&self) -> Option<LoopRegion> {
+        Sample::loop_region(self)
+    }
+
+    fn read(&mut self, frame: usize, out: &mut [f32]) -> Result<usize, DecodeError> {
+        let channels = Sample::channels(self);
+        let n = (out.len() / channels).min(Sample::frames(self).saturating_sub(frame));
+        for (i, values) in out.chunks_exact_mut(channels).take(n).enumerate() {
+            for (ch, v) in values.iter_mut().enumerate() {
+                *v = self.get(ch, frame + i);
+            }
+        }
+        Ok(n)
+    }
+}
+
+/// Streams from a [`StreamDecoder`], which can only move forwards. Reading
+/// behind the decoder's position starts again from a fresh decoder.
+pub struct DecoderSource<D, F> {
+    open: F,
+    decoder: D,
+    /// The frame the decoder produces next.
+    position: usize,
+    frames: usize,
+    scratch: Vec<f32>,
+}
+
+impl<D, F> DecoderSource<D, F>
+where
+    D: StreamDecoder,
+    F: FnMut() -> Result<D, DecodeError>,
+{
+    /// Creates a source that calls `open` for a decoder positioned at the
+    /// first frame. If the decoder does not state its length up front, the
+    /// whole stream is decoded once here to count the frames.
+    pub fn new(mut open: F) -> Result<Self, DecodeError> {
+        let mut decoder = open()?;
+        let frames = match decoder.frames() {
+            Some(frames) => frames as usize,
+            None => {
+                let mut block = vec![0.0; 4096 * decoder.channels()];
+                let mut frames = 0;
+                loop {
+                    let n = decoder.read(&mut block)?;
+                    if n == 0 {
+                        break;
+                    }
+                    frames += n;
+                }
+                decoder = open()?;
+                frames
+            }
+        };
+        Ok(DecoderSource {
+            open,
+            decoder,
+            position: 0,
+            frames,
+            scratch: Vec::new(),
+        })
+    }
+}
+
+impl<D, F> StreamSource for DecoderSource<D, F>
+where
+    D: StreamDecoder + Send,
+    F: FnMut() -> Result<D, DecodeError> + Send,
+{
+    fn channels(&self) -> usize {
+        self.decoder.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.decoder.sample_rate()
+    }
+
+    fn frames(&self) -> usize {
+        self.frames
+    }
+
+    fn read(&mut self, frame: usize, out: &mut [f32]) -> Result<usize, DecodeError> {
+        let channels = self.decoder.channels();
+        if frame < self.position {
+            self.decoder = (self.open)()?;
+            self.position = 0;
+        }
+        while self.position < frame {
+            let n = (frame - self.position).min(4096);
+            self.scratch.resize(n * channels, 0.0);
+            let skipped = self.decoder.read(&mut self.scratch)?;
+            if skipped == 0 {
+                return Ok(0);
+            }
+            self.position += skipped;
+        }
+        let n = self.decoder.read(out)?;
+        self.position += n;
+        Ok(n)
+    }
+}
+
+/// A sample whose frames past a preloaded head stay in a [`StreamSource`].
+///
+/// Add it to an engine with
+/// [`Controller::add_streamed_sample`](crate::Controller::add_streamed_sample)
+/// and trigger it like any other sample.
+pub struct StreamedSample {
+    head: Sample,
+    frames: usize,
+    source: Box<dyn StreamSource>,
+}
+
+impl StreamedSample {
+    /// Preloads the first `head` frames of `source`. If the source has loop
+    /// points, everything up to the end of the loop is preloaded as well, so
+    /// looping never waits for the disk.
+    pub fn new(mut source: impl StreamSource + 'static, head: usize) -> Result<Self, DecodeError> {
+        let channels = source.channels();
+        let frames = source.frames();
+        let loop_region = source
+            .loop_region()
+            .filter(|region| region.start < region.end.min(frames));
+        let preload = head.max(loop_region.map_or(0, |r| r.end)).min(frames);
+
+        let mut data = vec![0.0; preload * channels];
+        let mut filled = 0;
+        while filled < preload {
+            let n = source.read(filled, &mut data[filled * channels..])?;
+            if n == 0 {
+                return Err(DecodeError::Truncated("streamed sample head"));
+            }
+            filled += n;
+        }
+        let mut head = Sample::from_interleaved(data, channels, source.sample_rate())?;
+        head.set_loop_region(loop_region);
+        Ok(StreamedSample {
+            head,
+            frames,
+            source: Box::new(source),
+        })
+    }
+
+    /// The preloaded frames, with the sample's loop points.
+    pub fn head(&self) -> &Sample {
+        &self.head
+    }
+
+    /// Total number of frames.
+    pub fn frames(&self) -> usize {
+        self.frames
+    }
+
+    pub fn channels(&self) -> usize {
+        self.head.channels()
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.head.sample_rate()
+    }
+
+    pub(crate) fn into_parts(self) -> (Sample, usize, Box<dyn StreamSource>) {
+        (self.head, self.frames, self.source)
+    }
+}
+
+/// Opens a file for streaming and preloads its first `head` frames.
+///
+/// WAV files are read in place. Ogg Vorbis and MP3 files are decoded as they
+/// stream, which costs the I/O thread more time, and jumping backwards in
+/// them decodes again from the start. FLAC files cannot be streamed yet.
+pub fn open(path: impl AsRef<Path>, head: usize) -> Result<StreamedSample, DecodeError> {
+    let path = path.as_ref().to_path_buf();
+    let mut magic = Vec::with_capacity(64);
+    File::open(&path)?.take(64).read_to_end(&mut magic)?;
+    match codec::detect(&magic) {
+        Some(FileFormat::Wav) => StreamedSample::new(codec::wav::Reader::open(&path)?, head),
+        #[cfg(feature = "vorbis")]
+        Some(FileFormat::Vorbis) => {
+            let source = DecoderSource::new(move || {
+                codec::vorbis::Decoder::new(std::io::BufReader::new(File::open(&path)?))
+            })?;
+            StreamedSample::new(source, head)
+        }
+        #[cfg(feature = "mp3")]
+        Some(FileFormat::Mp3) => {
+            let source = DecoderSource::new(move || {
+                codec::mp3::Decoder::new(std::io::BufReader::new(File::open(&path)?))
+            })?;
+            StreamedSample::new(source, head)
+        }
+        #[cfg(not(feature = "vorbis"))]
+        Some(FileFormat::Vorbis) => Err(DecodeError::Unsupported(
+            "Ogg Vorbis file (enable the `vorbis` feature)".into(),
+        )),
+        #[cfg(not(feature = "mp3"))]
+        Some(FileFormat::Mp3) => Err(DecodeError::Unsupported(
+            "MP3 file (enable the `mp3` feature)".into(),
+        )),
+        Some(FileFormat::Flac) => Err(DecodeError::Unsupported("streaming of FLAC files".into())),
+        None => Err(DecodeError::Unsupported("file format".into())),
+    }
+}
+
+/// Marks a buffer that no voice is reading.
+const IDLE: u32 = u32::MAX;
+
+/// One voice's read-ahead ring.
+///
+/// The audio thread owns the request (`generation`, `sample`, `origin`) and
+/// `consumed`; the streamer owns `ready`, `written` and the ring contents.
+/// Frame `origin + i` lives in ring slot `i % capacity`.
+pub(crate) struct StreamBuffer {
+    values: Box<[AtomicU32]>,
+    /// Bumped by the audio thread with every new request.
+    generation: AtomicU32,
+    sample: AtomicU32,
+    /// First frame requested.
+    origin: AtomicUsize,
+    /// Frames from `origin` the voice no longer needs.
+    consumed: AtomicUsize,
+    /// The request the ring contents belong to.
+    ready: AtomicU32,
+    /// Frames from `origin` in the ring, counting consumed ones.
+    written: AtomicUsize,
+}
+
+/// The frames a stream buffer holds, as of the start of a block.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Window {
+    origin: usize,
+    /// First frame still held.
+    pub start: usize,
+    /// Frame just past the last one written.
+    pub end: usize,
+    /// Ring size in frames.
+    pub capacity: usize,
+}
+
+/// One channel of a stream buffer's window.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct RingChannel<'a> {
+    values: &'a [AtomicU32],
+    window: Window,
+    channels: usize,
+    channel: usize,
+}
+
+impl RingChannel<'_> {
+    /// The value at `frame`, or silence outside the window.
+    #[inline]
+    pub(crate) fn at(&self, frame: usize) -> f32 {
+        let w = &self.window;
+        if frame < w.start || frame >= w.end {
+            return 0.0;
+        }
+        let slot = (frame - w.origin) % w.capacity;
+        f32::from_bits(self.values[slot * self.channels + self.channel].load(Ordering::Relaxed))
+    }
+}
+
+impl StreamBuffer {
+    fn new(values: usize) -> Self {
+        StreamBuffer {
+            values: (0..values).map(|_| AtomicU32::new(0)).collect(),
+            generation: AtomicU32::new(0),
+            sample: AtomicU32::new(IDLE),
+            origin: AtomicUsize::new(0),
+            consumed: AtomicUsize::new(0),
+            ready: AtomicU32::new(0),
+            written: AtomicUsize::new(0),
+        }
+    }
+
+    /// Ring size in frames of `channels` channels.
+    pub(crate) fn capacity(&self, channels: usize) -> usize {
+        self.values.len() / channels
+    }
+
+    /// The sample and first frame of the current request. Audio thread only.
+    pub(crate) fn requested(&self) -> Option<(SampleId, usize)> {
+        let sample = self.sample.load(Ordering::Relaxed);
+        (sample != IDLE).then(|| (SampleId(sample), self.origin.load(Ordering::Relaxed)))
+    }
+
+    /// Asks for `sample` from `frame` on, dropping whatever the ring held.
+    /// Audio thread only.
+    pub(crate) fn request(&self, sample: SampleId, frame: usize) {
+        self.consumed.store(0, Ordering::Relaxed);
+        self.sample.store(sample.0, Ordering::Relaxed);
+        self.origin.store(frame, Ordering::Relaxed);
+        self.bump();
+    }
+
+    /// Drops the current request. Audio thread only.
+    pub(crate) fn cancel(&self) {
+        if self.sample.load(Ordering::Relaxed) != IDLE {
+            self.sample.store(IDLE, Ordering::Relaxed);
+            self.bump();
+        }
+    }
+
+    fn bump(&self) {
+        let generation = self.generation.load(Ordering::Relaxed).wrapping_add(1);
+        self.generation.store(generation, Ordering::Release);
+    }
+
+    /// What the ring holds for the current request, once the streamer has
+    /// started on it. Audio thread only.
+    pub(crate) fn window(&self, channels: usize) -> Option<Window> {
+        if self.sample.load(Ordering::Relaxed) == IDLE
+            || self.ready.load(Ordering::Acquire) != self.generation.load(Ordering::Relaxed)
+        {
+            return None;
+        }
+        let origin = self.origin.load(Ordering::Relaxed);
+        Some(Window {
+            origin,
+            start: origin + self.consumed.load(Ordering::Relaxed),
+            end: origin + self.written.load(Ordering::Acquire),
+            capacity: self.capacity(channels),
+        })
+    }
+
+    /// Lets the streamer overwrite frames before `frame`. Audio thread only.
+    pub(crate) fn release(&self, window: &Window, frame: usize) {
+        let consumed = frame.clamp(window.start, window.end) - window.origin;
+        self.consumed.store(consumed, Ordering::Release);
+    }
+
+    pub(crate) fn channel(
+        &self,
+        window: Window,
+        channels: usize,
+        channel: usize,
+    ) -> RingChannel<'_> {
+        RingChannel {
+            values: &self.values,
+            window,
+            channels,
+            channel,
+        }
+    }
+}
+
+type SharedSource = Arc<Mutex<Box<dyn StreamSource>>>;
+
+/// Streaming state shared by the engine, its controller and the streamer.
+pub(crate) struct Shared {
+    /// One per voice and one per voice fade-out slot.
+    pub buffers: Box<[StreamBuffer]>,
+    /// The source of each streamed sample, by slot.
+    pub sources: Mutex<Vec<Option<SharedSource>>>,
+    pub underruns: AtomicU64,
+}
+
+impl Shared {
+    /// `values` is the size of each buffer, in `f32`s.
+    pub(crate) fn new(buffers: usize, values: usize, max_samples: usize) -> Self {
+        Shared {
+            buffers: (0..buffers).map(|_| StreamBuffer::new(values)).collect(),
+            sources: Mutex::new(vec![None; max_samples]),
+            underruns: AtomicU64::new(0),
+        }
+    }
+
+    pub(crate) fn set_source(&self, id: SampleId, source: Option<Box<dyn StreamSource>>) {
+        let mut sources = self.sources.lock().unwrap_or_else(|e| e.into_inner());
+        sources[id.index()] = source.map(|s| Arc::new(Mutex::new(s)));
+    }
+}
+
+/// The audio thread's view of disk streaming, passed to voices in the
+/// [`RenderContext`](crate::RenderContext).
+#[derive(Clone, Copy)]
+pub struct Streams<'a> {
+    pub(crate) buffers: &'a [StreamBuffer],
+    /// Total frames of each streamed sample, by slot.
+    pub(crate) lengths: &'a [Option<usize>],
+    pub(crate) underruns: &'a AtomicU64,
+}
+
+impl Streams<'_> {
+    /// Total frames of `sample` if it is streamed.
+    pub(crate) fn length(&self, sample: SampleId) -> Option<usize> {
+        self.lengths.get(sample.index()).copied().flatten()
+    }
+
+    pub(crate) fn underrun(&self) {
+        self.underruns.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+impl std::fmt::Debug for Streams<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Streams")
+            .field("buffers", &self.buffers.len())
+            .finish_non_exhaustive()
+    }
+}
+
+/// Frames read per buffer per pass, so one busy voice cannot starve the
+/// others.
+const CHUNK: usize = 8192;
+
+/// How long a [`StreamThread`] sleeps when every buffer is full.
+const IDLE_WAIT: Duration = Duration::from_millis(1);
+
+/// Fills voices' stream buffers from their samples' sources.
+///
+/// Taken from the controller with
+/// [`Controller::streamer`](crate::Controller::streamer). Either call
+/// [`service`](Streamer::service) regularly from a thread that may block on
+/// I/O, or hand the streamer its own thread with [`spawn`](Streamer::spawn).
+pub struct Streamer {
+    shared: Arc<Shared>,
+    scratch: Vec<f32>,
+    /// Buffers with work to do, with the frames they hold ahead of the voice.
+    order: Vec<(usize, usize)>,
+    /// The request each buffer last failed on.
+    failed: Vec<Option<u32>>,
+    errors: Vec<(SampleId, DecodeError)>,
+}
+
+impl Streamer {
+    pub(crate) fn new(shared: Arc<Shared>) -> Self {
+        let buffers = shared.buffers.len();
+        Streamer {
+            shared,
+            scratch: Vec::new(),
+            order: Vec::with_capacity(buffers),
+            failed: vec![None; buffers],
+            errors: Vec::new(),
+        }
+    }
+
+    /// Tops up every buffer that has room, emptiest first, reading at most
+    /// one chunk into each. Returns the number of frames read.
+    ///
+    /// A source that fails leaves its voice starved until it is triggered
+    /// or moved again; the error is kept for
+    /// [`take_errors`](Streamer::take_errors).
+    pub fn service(&mut self) -> usize {
+        self.order.clear();
+        for (index, buffer) in self.shared.buffers.iter().enumerate() {
+            let generation = buffer.generation.load(Ordering::Acquire);
+            if buffer.sample.load(Ordering::Relaxed) == IDLE
+                || self.failed[index] == Some(generation)
+            {
+                continue;
+            }
+            if buffer.ready.load(Ordering::Relaxed) != generation {
+                buffer.written.store(0, Ordering::Relaxed);
+                buffer.ready.store(generation, Ordering::Release);
+            }
+            let written = buffer.written.load(Ordering::Relaxed);
+            let consumed = buffer.consumed.load(Ordering::Acquire).min(written);
+            self.order.push((written - consumed, index));
+        }
+        self.order.sort_unstable();
+
+        let mut total = 0;
+        for i in 0..self.order.len() {
+            let index = self.order[i].1;
+            match self.fill(index) {
+                Ok(n) => total += n,
+                Err((sample, error)) => {
+                    let buffer = &self.shared.buffers[index];
+                    self.failed[index] = Some(buffer.ready.load(Ordering::Relaxed));
+                    self.errors.push((sample, error));
+                }
+            }
+        }
+        total
+    }
+
+    /// Reads the next chunk into one buffer.
+    fn fill(&mut self, index: usize) -> Result<usize, (SampleId, DecodeError)> {
+        let buffer = &self.shared.buffers[index];
+        let sample = SampleId(buffer.sample.load(Ordering::Relaxed));
+        if sample.0 == IDLE {
+            return Ok(0);
+        }
+        let origin = buffer.origin.load(Ordering::Relaxed);
+        let source = {
+            let sources = self
+                .shared
+                .sources
+                .lock()
+                .unwrap_or_else(|e| e.into_inner());
+            match sources.get(sample.index()) {
+                Some(Some(source)) => Arc::clone(source),
+                _ => return Ok(0),
+            }
+        };
+        let mut source = source.lock().unwrap_or_else(|e| e.into_inner());
+
+        let channels = source.channels();
+        let capacity = buffer.capacity(channels);
+        let written = buffer.written.load(Ordering::Relaxed);
+        let consumed = buffer.consumed.load(Ordering::Acquire).min(written);
+        let next = origin + written;
+        let n = (capacity - (written - consumed))
+            .min(CHUNK)
+            .min(source.frames().saturating_sub(next));
+        if n == 0 {
+            return Ok(0);
+        }
+        self.scratch.resize(n * channels, 0.0);
+        let n = source
+            .read(next, &mut self.scratch)
+            .map_err(|e| (sample, e))?;
+        if n == 0 {
+            return Err((sample, DecodeError::Truncated("streamed sample")));
+        }
+        for (i, frame) in self.scratch[..n * channels]
+            .chunks_exact(channels)
+            .enumerate()
+        {
+            let slot = (written + i) % capacity;
+            for (value, &v) in buffer.values[slot * channels..][..channels]
+                .iter()
+                .zip(frame)
+            {
+                value.store(v.to_bits(), Ordering::Relaxed);
+            }
+        }
+        buffer.written.store(written + n, Ordering::Release);
+        Ok(n)
+    }
+
+    /// Read errors since the last call, with the sample each came from.
+    pub fn take_errors(&mut self) -> Vec<(SampleId, DecodeError)> {
+        std::mem::take(&mut self.errors)
+    }
+
+    /// Moves the streamer to a thread of its own, which services the
+    /// buffers until the returned handle is stopped or dropped.
+    pub fn spawn(mut self) -> StreamThread {
+        let stop = Arc::new(AtomicBool::new(false));
+        let flag = Arc::clone(&stop);
+        let handle = thread::Builder::new()
+            .name("samplerust-stream".into())
+            .spawn(move || {
+                while !flag.load(Ordering::Relaxed) {
+                    if self.service() == 0 {
+                        thread::sleep(IDLE_WAIT);
+                    }
+                }
+                self
+            })
+            .expect("failed to spawn the streaming thread");
+        StreamThread {
+            stop,
+            handle: Some(handle),
+        }
+    }
+}
+
+/// A [`Streamer`] running on its own thread.
+pub struct StreamThread {
+    stop: Arc<AtomicBool>,
+    handle: Option<JoinHandle<Streamer>>,
+}
+
+impl StreamThread {
+    /// Stops the thread and returns the streamer, for example to read its
+    /// errors.
+    pub fn stop(mut self) -> Streamer {
+        self.join().expect("streaming thread was already joined")
+    }
+
+    fn join(&mut self) -> Option<Streamer> {
+        self.stop.store(true, Ordering::Relaxed);
+        let handle = self.handle.take()?;
+        match handle.join() {
+            Ok(streamer) => Some(streamer),
+            Err(panic) => std::panic::resume_unwind(panic),
+        }
+    }
+}
+
+impl Drop for StreamThread {
+    fn drop(&mut self) {
+        if !thread::panicking() {
+            self.join();
+        }
+    }
+}
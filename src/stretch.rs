@@ -0,0 +1,413 @@
+//! Time-stretching and pitch-shifting.
+//!
+//! A voice triggered with a [`TimeStretch`] plays its sample at a tempo and
+//! a pitch set apart from each other: the stretch ratio scales how long the
+//! sample lasts without touching its pitch, and the semitone shift moves
+//! its pitch without touching its length. Both can be changed while the
+//! voice plays, with [`Command::SetStretch`](crate::Command::SetStretch)
+//! and [`Command::SetPitchShift`](crate::Command::SetPitchShift).
+//!
+//! The voice moves through its sample at the stretched speed and rebuilds
+//! its output from overlapping grains read around that position, each read
+//! at the shifted pitch. Two [`StretchMode`]s do the rebuilding:
+//!
+//! - [WSOLA](StretchMode::Wsola) (waveform-similarity overlap-add) nudges
+//!   each grain to where it best lines up with the one before. Grains keep
+//!   their shape, so drums and other percussive material stay sharp.
+//! - A [phase vocoder](StretchMode::PhaseVocoder) moves every frequency
+//!   component of each grain on at its own rate, so sustained tones stay
+//!   smooth, at the cost of softened attacks.
+//!
+//! Grains are [`EngineConfig::stretch_window`](crate::EngineConfig) frames
+//! long, and the buffers they are built in are allocated with the engine.
+
+use std::f32::consts::TAU;
+use std::fmt;
+
+/// Channels a stretcher builds; a stretched voice plays the first two
+/// channels of its sample.
+pub(crate) const CHANNELS: usize = 2;
+
+/// Shortest stretch ratio a voice plays at.
+pub(crate) const MIN_RATIO: f64 = 0.01;
+
+/// How a stretched voice rebuilds its sample.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Default)]
+pub enum StretchMode {
+    /// Waveform-similarity overlap-add, for percussive material.
+    #[default]
+    Wsola,
+    /// Phase vocoder, for tonal material.
+    PhaseVocoder,
+}
+
+/// Time-stretch and pitch-shift settings for a voice.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TimeStretch {
+    pub mode: StretchMode,
+    /// How much longer the sample lasts: 2 plays it at half tempo, 0.5 at
+    /// double. At least 0.01.
+    pub ratio: f64,
+    /// Pitch shift in semitones.
+    pub semitones: f32,
+}
+
+impl Default for TimeStretch {
+    fn default() -> Self {
+        TimeStretch {
+            mode: StretchMode::default(),
+            ratio: 1.0,
+            semitones: 0.0,
+        }
+    }
+}
+
+/// Where the next grain comes from.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Grain {
+    /// The voice's position, in sample frames.
+    pub position: f64,
+    /// Sample frames the voice moves per output frame.
+    pub speed: f64,
+    /// Sample frames the grain reads per output frame, which sets its pitch.
+    pub rate: f64,
+}
+
+/// Builds a stretched voice's output, a grain at a time.
+///
+/// Output frames are overlap-added into a ring of one window. Each grain
+/// adds a window's worth starting at the ring's read head, after which the
+/// first hop of frames is complete and can be read.
+pub(crate) struct Stretcher {
+    /// Periodic Hann window.
+    window: Vec<f32>,
+    /// `cos` and `-sin` of `2πk/N` for the FFT.
+    twiddles: Vec<(f32, f32)>,
+    output: [Vec<f32>; CHANNELS],
+    head: usize,
+    ready: usize,
+    /// Frames still to throw away at the start, which the first grains
+    /// spread before the voice's start.
+    discard: usize,
+    /// No grain since the last reset.
+    fresh: bool,
+    /// Where the last WSOLA grain would carry on from.
+    natural: f64,
+    /// The samples at `natural` that WSOLA lines the next grain up with.
+    template: Vec<f32>,
+    /// Analysis phase of each bin at the last phase-vocoder grain, and the
+    /// phase it was resynthesised with.
+    phase: [Vec<f32>; CHANNELS],
+    synth: [Vec<f32>; CHANNELS],
+    /// The current grain's magnitude and phase in each bin, and its peaks.
+    magnitude: Vec<f32>,
+    analysis: Vec<f32>,
+    peaks: Vec<usize>,
+    re: Vec<f32>,
+    im: Vec<f32>,
+}
+
+/// A WSOLA search compares grains every `SEARCH_STRIDE` samples.
+const SEARCH_STRIDE: usize = 8;
+
+impl Stretcher {
+    /// A stretcher with grains of `window` frames, a power of two.
+    pub(crate) fn new(window: usize) -> Self {
+        assert!(
+            window.is_power_of_two() && window >= 64,
+            "stretch window must be a power of two of at least 64 frames"
+        );
+        let bins = window / 2 + 1;
+        Stretcher {
+            window: (0..window)
+                .map(|i| 0.5 - 0.5 * (TAU * i as f32 / window as f32).cos())
+                .collect(),
+            twiddles: (0..window / 2)
+                .map(|k| {
+                    let (s, c) = (TAU * k as f32 / window as f32).sin_cos();
+                    (c, -s)
+                })
+                .collect(),
+            output: [vec![0.0; window], vec![0.0; window]],
+            head: 0,
+            ready: 0,
+            discard: window / 2,
+            fresh: true,
+            natural: 0.0,
+            template: vec![0.0; window / 2 / SEARCH_STRIDE],
+            phase: [vec![0.0; bins], vec![0.0; bins]],
+            synth: [vec![0.0; bins], vec![0.0; bins]],
+            magnitude: vec![0.0; bins],
+            analysis: vec![0.0; bins],
+            peaks: Vec::with_capacity(bins),
+            re: vec![0.0; window],
+            im: vec![0.0; window],
+        }
+    }
+
+    /// Drops all output, ready to start a voice.
+    pub(crate) fn reset(&mut self) {
+        for output in &mut self.output {
+            output.fill(0.0);
+        }
+        self.head = 0;
+        self.ready = 0;
+        self.discard = self.window.len() / 2;
+        self.fresh = true;
+    }
+
+    /// Output frames that can be read before another grain is needed.
+    pub(crate) fn ready(&self) -> usize {
+        self.ready
+    }
+
+    /// Ready output frame `frame` of `channel`.
+    #[inline]
+    pub(crate) fn get(&self, channel: usize, frame: usize) -> f32 {
+        match self.output.get(channel) {
+            Some(output) => output[(self.head + frame) & (output.len() - 1)],
+            None => 0.0,
+        }
+    }
+
+    /// Moves past `frames` ready frames.
+    pub(crate) fn consume(&mut self, frames: usize) {
+        debug_assert!(frames <= self.ready);
+        let mask = self.window.len() - 1;
+        for output in &mut self.output {
+            for i in 0..frames {
+                output[(self.head + i) & mask] = 0.0;
+            }
+        }
+        self.head = (self.head + frames) & mask;
+        self.ready -= frames;
+    }
+
+    /// Adds the next grain, making another hop of frames ready. `read`
+    /// reads a channel of the sample at a position, and `peek` cheaply
+    /// reads a mix of its channels for WSOLA to compare.
+    pub(crate) fn synthesize(
+        &mut self,
+        mode: StretchMode,
+        grain: Grain,
+        channels: usize,
+        read: impl Fn(usize, f64) -> f32,
+        peek: impl Fn(f64) -> f32,
+    ) {
+        let n = self.window.len();
+        let hop = match mode {
+            StretchMode::Wsola => n / 2,
+            StretchMode::PhaseVocoder => n / 4,
+        };
+        // The grain is centred as far ahead of the voice as the frames it
+        // will finish, less any that fall before the start.
+        let ahead = (n / 2 - self.discard) as f64;
+        let centre = grain.position + ahead * grain.speed;
+        let start = centre - (n / 2) as f64 * grain.rate;
+        let channels = channels.min(CHANNELS);
+        match mode {
+            StretchMode::Wsola => self.overlap_add(grain, start, hop, channels, read, peek),
+            StretchMode::PhaseVocoder => self.vocode(grain, start, hop, channels, read),
+        }
+        self.fresh = false;
+        self.ready = hop;
+        let skip = self.discard.min(hop);
+        self.consume(skip);
+        self.discard -= skip;
+    }
+
+    /// Adds a grain read from `start`, moved to line up with the last.
+    fn overlap_add(
+        &mut self,
+        grain: Grain,
+        start: f64,
+        hop: usize,
+        channels: usize,
+        read: impl Fn(usize, f64) -> f32,
+        peek: impl Fn(f64) -> f32,
+    ) {
+        let start = if self.fresh {
+            start
+        } else {
+            start + self.align(start, grain.rate, &peek)
+        };
+        let mask = self.window.len() - 1;
+        for c in 0..channels {
+            for (i, w) in self.window.iter().enumerate() {
+                let v = read(c, start + i as f64 * grain.rate);
+                self.output[c][(self.head + i) & mask] += v * w;
+            }
+        }
+        self.natural = start + hop as f64 * grain.rate;
+    }
+
+    /// The offset, within an eighth of a window, that best matches the
+    /// grain at `start` to where the last one would have carried on.
+    fn align(&mut self, start: f64, rate: f64, peek: &impl Fn(f64) -> f32) -> f64 {
+        let step = SEARCH_STRIDE as f64 * rate;
+        let mut energy = 0.0;
+        for (j, t) in self.template.iter_mut().enumerate() {
+            *t = peek(self.natural + j as f64 * step);
+            energy += *t * *t;
+        }
+        if energy < 1e-12 {
+            return 0.0;
+        }
+        let tolerance = (self.window.len() / 8) as isize;
+        let mut best = (0, f32::MIN);
+        for delta in -tolerance..=tolerance {
+            let from = start + delta as f64;
+            let (mut dot, mut power) = (0.0, 1e-12);
+            for (j, t) in self.template.iter().enumerate() {
+                let v = peek(from + j as f64 * step);
+                dot += t * v;
+                power += v * v;
+            }
+            let score = dot / power.sqrt();
+            if score > best.1 {
+                best = (delta, score);
+            }
+        }
+        best.0 as f64
+    }
+
+    /// Adds a grain read from `start`, with the phase of each spectral
+    /// peak carried on from the last grain at the peak's own frequency.
+    /// Bins around a peak keep their phase relative to it (identity phase
+    /// locking), which holds the partial together.
+    fn vocode(
+        &mut self,
+        grain: Grain,
+        start: f64,
+        hop: usize,
+        channels: usize,
+        read: impl Fn(usize, f64) -> f32,
+    ) {
+        let n = self.window.len();
+        let bins = n / 2 + 1;
+        // How far the grain moved through the sample since the last one, in
+        // its own samples.
+        let analysis = (hop as f64 * grain.speed / grain.rate) as f32;
+        let synthesis = hop as f32;
+        // Squared Hann windows overlapping by three quarters sum to 1.5.
+        let scale = 1.0 / (1.5 * n as f32);
+        let mask = n - 1;
+        for c in 0..channels {
+            for (i, w) in self.window.iter().enumerate() {
+                self.re[i] = read(c, start + i as f64 * grain.rate) * w;
+                self.im[i] = 0.0;
+            }
+            fft(&mut self.re, &mut self.im, &self.twiddles);
+            for k in 0..bins {
+                self.magnitude[k] = self.re[k].hypot(self.im[k]);
+                self.analysis[k] = self.im[k].atan2(self.re[k]);
+            }
+            self.peaks.clear();
+            for k in 0..bins {
+                let m = self.magnitude[k];
+                let below = k == 0 || self.magnitude[k - 1] < m;
+                let above = k + 1 == bins || self.magnitude[k + 1] <= m;
+                if below && above && m > 0.0 {
+                    self.peaks.push(k);
+                }
+            }
+            let (phase, synth) = (&mut self.phase[c], &mut self.synth[c]);
+            if self.fresh {
+                synth.copy_from_slice(&self.analysis);
+            } else {
+                for &k in &self.peaks {
+                    let omega = TAU * k as f32 / n as f32;
+                    let frequency = if analysis > 0.0 {
+                        let expected = omega * analysis;
+                        omega + wrap(self.analysis[k] - phase[k] - expected) / analysis
+                    } else {
+                        omega
+                    };
+                    synth[k] = wrap(synth[k] + frequency * synthesis);
+                }
+                // Each bin follows the nearest peak.
+                let mut next = 0;
+                for k in 0..bins {
+                    while next + 1 < self.peaks.len()
+                        && self.peaks[next + 1].abs_diff(k) < self.peaks[next].abs_diff(k)
+                    {
+                        next += 1;
+                    }
+                    if let Some(&p) = self.peaks.get(next) {
+                        if p != k {
+                            synth[k] = wrap(self.analysis[k] + synth[p] - self.analysis[p]);
+                        }
+                    }
+                }
+            }
+            phase.copy_from_slice(&self.analysis);
+            let spectrum = self.re.iter_mut().zip(&mut self.im);
+            for ((re, im), (&m, p)) in spectrum.zip(self.magnitude.iter().zip(synth.iter())) {
+                let (s, c) = p.sin_cos();
+                // Conjugated for the inverse transform below.
+                *re = m * c;
+                *im = -m * s;
+            }
+            for k in 1..n / 2 {
+                self.re[n - k] = self.re[k];
+                self.im[n - k] = -self.im[k];
+            }
+            fft(&mut self.re, &mut self.im, &self.twiddles);
+            for (i, w) in self.window.iter().enumerate() {
+                self.output[c][(self.head + i) & mask] += self.re[i] * w * scale;
+            }
+        }
+    }
+}
+
+impl fmt::Debug for Stretcher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Stretcher")
+            .field("window", &self.window.len())
+            .field("ready", &self.ready)
+            .finish()
+    }
+}
+
+/// `phase` wrapped into `-π..=π`.
+#[inline]
+fn wrap(phase: f32) -> f32 {
+    phase - TAU * (phase / TAU).round()
+}
+
+/// In-place iterative radix-2 FFT, with `twiddles` holding `cos` and
+/// `-sin` of `2πk/N` for the first half of `N`.
+fn fft(re: &mut [f32], im: &mut [f32], twiddles: &[(f32, f32)]) {
+    let n = re.len();
+    let mut j = 0;
+    for i in 1..n {
+        let mut bit = n >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j {
+            re.swap(i, j);
+            im.swap(i, j);
+        }
+    }
+    let mut len = 2;
+    while len <= n {
+        let stride = n / len;
+        for start in (0..n).step_by(len) {
+            for k in 0..len / 2 {
+                let (c, s) = twiddles[k * stride];
+                let a = start + k;
+                let b = a + len / 2;
+                let tr = re[b] * c - im[b] * s;
+                let ti = re[b] * s + im[b] * c;
+                re[b] = re[a] - tr;
+                im[b] = im[a] - ti;
+                re[a] += tr;
+                im[a] += ti;
+            }
+        }
+        len <<= 1;
+    }
+}
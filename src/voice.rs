@@ -0,0 +1,1226 @@
+//! A single playing instance of a sample.
+
+use crate::effect::SENDS;
+use crate::engine::SampleId;
+use crate::envelope::{Adsr, Envelope};
+use crate::filter::{FilterParams, Svf, SvfCoefficients};
+use crate::interp::{Interpolation, SincTable};
+use crate::modulation::{ChannelControls, ModDestination, ModMatrix, ModState, Sources, MOD_BLOCK};
+use crate::pan::{self, PanLaw, PanMode};
+use crate::sample::{ChannelView, LoopMode, LoopRegion, Sample};
+use crate::stream::{StreamBuffer, Streams, Window};
+use crate::stretch::{self, Grain, Stretcher, TimeStretch};
+
+/// Identifies one triggered voice. Handed out by the
+/// [`Controller`](crate::Controller) so later commands can address it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct VoiceId(pub(crate) u64);
+
+/// Per-trigger playback settings.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TriggerParams {
+    /// Linear gain applied to the sample.
+    pub gain: f32,
+    /// Frame to start playing from.
+    pub start: usize,
+    /// MIDI note number, used by [`StealPolicy::SameNote`](crate::StealPolicy).
+    pub note: u8,
+    /// MIDI channel, `0..16`. The voice follows the channel's pitch bend.
+    pub channel: u8,
+    /// Playback speed relative to the sample's native pitch; `2.0` is an
+    /// octave up. The sample and output rates are accounted for separately.
+    pub rate: f64,
+    /// How to read between sample frames.
+    pub interpolation: Interpolation,
+    /// Loop points to use instead of the sample's own. A region with
+    /// [`LoopMode::Off`] disables looping.
+    pub loop_region: Option<LoopRegion>,
+    /// Amplitude envelope.
+    pub envelope: Adsr,
+    /// MIDI velocity, which the filter's cutoff can track.
+    pub velocity: u8,
+    /// Filter to play through, or `None` to play the sample unfiltered.
+    pub filter: Option<FilterParams>,
+    /// Stereo position, from -1 (left) to 1 (right), following the
+    /// engine's [`PanLaw`]. `None` plays the sample unpanned, at unity on
+    /// both sides, unless the modulation matrix moves its pan.
+    pub pan: Option<f32>,
+    /// How a stereo sample is panned.
+    pub pan_mode: PanMode,
+    /// Output bus: the pair of channels starting at `2 * bus`. Past the
+    /// last bus, numbering wraps round. `None` spreads the voice over every
+    /// output channel.
+    pub bus: Option<usize>,
+    /// Choke group. Triggering a voice fades out every voice already
+    /// playing that is [choked by](TriggerParams::choked_by) its group.
+    pub choke_group: Option<u32>,
+    /// Group whose notes cut this voice off, the way a closed hi-hat in one
+    /// group cuts an open one. Give a voice the same group for both to make
+    /// the group choke itself.
+    pub choked_by: Option<u32>,
+    /// Seconds the voice takes to fade out when choked.
+    pub choke_fade: f32,
+    /// Plays on through note-off, to the end of the sample or its envelope.
+    /// Gated voices, the default, release on note-off.
+    pub one_shot: bool,
+    /// Level sent to each of the engine's send buses, as a linear gain on
+    /// the voice's panned output.
+    pub sends: [f32; SENDS],
+    /// Plays the sample time-stretched and pitch-shifted, or `None` to play
+    /// it at its native speed.
+    ///
+    /// `rate`, pitch bend and modulated pitch still change the speed and
+    /// pitch together, on top of the stretch. Streamed samples, and voices
+    /// of an engine without [stretch buffers](crate::EngineConfig::stretch_window),
+    /// play unstretched.
+    pub stretch: Option<TimeStretch>,
+}
+
+impl Default for TriggerParams {
+    fn default() -> Self {
+        TriggerParams {
+            gain: 1.0,
+            start: 0,
+            note: 60,
+            channel: 0,
+            rate: 1.0,
+            interpolation: Interpolation::default(),
+            loop_region: None,
+            envelope: Adsr::default(),
+            velocity: 127,
+            filter: None,
+            pan: None,
+            pan_mode: PanMode::Balance,
+            bus: None,
+            choke_group: None,
+            choked_by: None,
+            choke_fade: 0.005,
+            one_shot: false,
+            sends: [0.0; SENDS],
+            stretch: None,
+        }
+    }
+}
+
+/// Shared, read-only state a voice needs while rendering.
+#[derive(Clone, Copy, Debug)]
+pub struct RenderContext<'a> {
+    /// Output sample rate in Hz.
+    pub sample_rate: u32,
+    /// Kernel used by [`Interpolation::Sinc`].
+    pub sinc: &'a SincTable,
+    /// Pitch-bend playback ratio of each MIDI channel.
+    pub bend: &'a [f64; 16],
+    /// Controller values of each MIDI channel, read by the modulation
+    /// matrix.
+    pub controls: &'a [ChannelControls; 16],
+    /// Tempo in beats per minute, for tempo-synced LFOs.
+    pub tempo: f64,
+    /// The modulation matrix, if one is set.
+    pub modulation: Option<&'a ModMatrix>,
+    /// How panned voices share their level between left and right.
+    pub pan_law: PanLaw,
+    /// Stream buffers and lengths of streamed samples.
+    pub streams: Streams<'a>,
+}
+
+/// Sample channels a voice can filter; any beyond play unfiltered.
+const FILTER_CHANNELS: usize = 8;
+
+/// The frames a voice reads: its sample, extended for a streamed sample by
+/// whatever the voice's stream buffer holds.
+#[derive(Clone, Copy)]
+struct Source<'a> {
+    sample: &'a Sample,
+    /// Total frames, counting those not in memory.
+    frames: usize,
+    /// The voice's stream buffer and what it held at the start of the block.
+    stream: Option<(&'a StreamBuffer, Option<Window>)>,
+}
+
+impl Source<'_> {
+    #[inline]
+    fn view(&self, channel: usize) -> ChannelView<'_> {
+        let view = self.sample.view(channel);
+        match self.stream {
+            Some((buffer, window)) => view.with_tail(
+                self.frames,
+                window.map(|w| buffer.channel(w, self.sample.channels(), channel)),
+            ),
+            None => view,
+        }
+    }
+}
+
+/// What the voice does when the current run of frames ends.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Edge {
+    /// The end of the sample data.
+    SampleEnd,
+    /// The far end of the loop in the direction of travel.
+    LoopEnd,
+    /// The start of a crossfade zone; nothing to do but keep going.
+    Zone,
+}
+
+/// A value moving linearly towards a target, one step a frame.
+#[derive(Clone, Copy, Debug, Default)]
+struct Ramp {
+    value: f32,
+    step: f32,
+    target: f32,
+}
+
+impl Ramp {
+    fn at(value: f32) -> Self {
+        Ramp {
+            value,
+            step: 0.0,
+            target: value,
+        }
+    }
+
+    /// Heads for `target` over `frames` frames from the last target, or
+    /// jumps straight there.
+    fn toward(&mut self, target: f32, frames: usize, jump: bool) {
+        if jump {
+            *self = Ramp::at(target);
+        } else {
+            self.value = self.target;
+            self.step = (target - self.value) / frames as f32;
+            self.target = target;
+        }
+    }
+
+    /// Returns `true` if the ramp is holding still at `value`.
+    fn is_at(&self, value: f32) -> bool {
+        self.step == 0.0 && self.value == value
+    }
+
+    #[inline]
+    fn next(&mut self) -> f32 {
+        let value = self.value;
+        self.value += self.step;
+        value
+    }
+}
+
+/// A crossfade between the current position and the matching position on
+/// the other side of the loop seam.
+#[derive(Clone, Copy, Debug)]
+struct Seam {
+    /// Position where the blend weight is zero.
+    zone_start: f64,
+    /// Blend weight gained per frame of distance travelled past `zone_start`.
+    slope: f64,
+    /// Distance to the matching position across the seam.
+    offset: f64,
+}
+
+/// Reads one sample from a fractional playback position and mixes it into an
+/// output buffer.
+///
+/// A voice never owns its sample data; the engine passes the sample in on
+/// every [`render`](Voice::render) call. All methods are allocation-free.
+#[derive(Clone, Debug)]
+pub struct Voice {
+    id: VoiceId,
+    sample: SampleId,
+    position: f64,
+    rate: f64,
+    interpolation: Interpolation,
+    loop_override: Option<LoopRegion>,
+    /// Playing backwards through a ping-pong or reverse loop.
+    backwards: bool,
+    released: bool,
+    envelope: Envelope,
+    gain: f32,
+    note: u8,
+    velocity: u8,
+    channel: u8,
+    filter: Option<FilterParams>,
+    filter_envelope: Envelope,
+    pan: Option<f32>,
+    pan_mode: PanMode,
+    bus: Option<usize>,
+    choke_group: Option<u32>,
+    choked_by: Option<u32>,
+    choke_fade: f32,
+    one_shot: bool,
+    sends: [f32; SENDS],
+    stretch: Option<TimeStretch>,
+    /// Filter state for each sample channel.
+    svf: [Svf; FILTER_CHANNELS],
+    coefficients: SvfCoefficients,
+    /// Cutoff `coefficients` were designed for, or NaN to force a redesign.
+    cutoff: f32,
+    modulation: ModState,
+    /// Modulated gain as a factor, pan and cutoff offset in cents.
+    mod_gain: Ramp,
+    mod_pan: Ramp,
+    mod_cutoff: Ramp,
+    /// Frames the loop is moved by.
+    loop_shift: isize,
+    /// The ramps have not been set since the voice started.
+    fresh: bool,
+    serial: u64,
+    active: bool,
+    /// Frames left in a fade-out, or `None` if not fading.
+    fade_remaining: Option<usize>,
+    fade_frames: usize,
+    /// Peak absolute output of the last rendered block.
+    level: f32,
+    /// Index of the voice's stream buffer, set by the pool.
+    ring: usize,
+    /// The stream buffer must be pointed at the voice's position afresh.
+    restart: bool,
+}
+
+impl Default for Voice {
+    fn default() -> Self {
+        Voice {
+            id: VoiceId(0),
+            sample: SampleId(0),
+            position: 0.0,
+            rate: 1.0,
+            interpolation: Interpolation::default(),
+            loop_override: None,
+            backwards: false,
+            released: false,
+            envelope: Envelope::default(),
+            gain: 0.0,
+            note: 0,
+            velocity: 0,
+            channel: 0,
+            filter: None,
+            filter_envelope: Envelope::default(),
+            pan: None,
+            pan_mode: PanMode::Balance,
+            bus: None,
+            choke_group: None,
+            choked_by: None,
+            choke_fade: 0.0,
+            one_shot: false,
+            sends: [0.0; SENDS],
+            stretch: None,
+            svf: [Svf::default(); FILTER_CHANNELS],
+            coefficients: SvfCoefficients::new(1_000.0, 1.0, 48_000.0),
+            cutoff: f32::NAN,
+            modulation: ModState::default(),
+            mod_gain: Ramp::at(1.0),
+            mod_pan: Ramp::at(0.0),
+            mod_cutoff: Ramp::at(0.0),
+            loop_shift: 0,
+            fresh: true,
+            serial: 0,
+            active: false,
+            fade_remaining: None,
+            fade_frames: 0,
+            level: 0.0,
+            ring: 0,
+            restart: false,
+        }
+    }
+}
+
+impl Voice {
+    /// Starts playing `sample` from `params.start`.
+    pub fn start(&mut self, id: VoiceId, sample: SampleId, params: &TriggerParams) {
+        self.id = id;
+        self.sample = sample;
+        self.position = params.start as f64;
+        self.rate = params.rate;
+        self.interpolation = params.interpolation;
+        self.loop_override = params.loop_region;
+        self.backwards = false;
+        self.released = false;
+        self.envelope.trigger(params.envelope);
+        self.gain = params.gain;
+        self.note = params.note;
+        self.velocity = params.velocity;
+        self.channel = params.channel & 0x0f;
+        self.filter = params.filter;
+        match &params.filter {
+            Some(filter) => {
+                self.filter_envelope.trigger(filter.envelope);
+                self.svf = [Svf::default(); FILTER_CHANNELS];
+                self.cutoff = f32::NAN;
+            }
+            // Nothing advances the envelope without a filter, so silence it
+            // rather than leave the last note's level for modulation to read.
+            None => self.filter_envelope.reset(),
+        }
+        self.pan = params.pan;
+        self.pan_mode = params.pan_mode;
+        self.bus = params.bus;
+        self.choke_group = params.choke_group;
+        self.choked_by = params.choked_by;
+        self.choke_fade = params.choke_fade;
+        self.one_shot = params.one_shot;
+        self.sends = params.sends;
+        self.stretch = params.stretch;
+        self.modulation = ModState::default();
+        self.mod_gain = Ramp::at(1.0);
+        self.mod_pan = Ramp::at(0.0);
+        self.mod_cutoff = Ramp::at(0.0);
+        self.loop_shift = 0;
+        self.fresh = true;
+        self.active = true;
+        self.fade_remaining = None;
+        self.level = params.gain.abs();
+        self.restart = true;
+    }
+
+    /// Silences the voice immediately.
+    pub fn stop(&mut self) {
+        self.active = false;
+        self.fade_remaining = None;
+    }
+
+    /// Signals note-off. The envelopes move to their release stages from
+    /// their current levels, and an [`UntilRelease`](LoopMode::UntilRelease) loop
+    /// stops looping and plays on to the end of the sample. The voice stops
+    /// itself once the release finishes.
+    ///
+    /// One-shot voices ignore note-off.
+    pub fn release(&mut self) {
+        if self.one_shot {
+            return;
+        }
+        self.released = true;
+        self.envelope.release();
+        self.filter_envelope.release();
+        self.modulation.envelope.release();
+    }
+
+    /// Ramps the voice to silence over `frames` frames, then stops it.
+    pub fn fade_out(&mut self, frames: usize) {
+        if frames == 0 {
+            self.stop();
+        } else if self.fade_remaining.is_none_or(|r| r > frames) {
+            self.fade_remaining = Some(frames);
+            self.fade_frames = frames;
+        }
+    }
+
+    /// Moves the playback position to `frame`, playing forwards.
+    pub fn seek(&mut self, frame: usize) {
+        self.position = frame as f64;
+        self.backwards = false;
+    }
+
+    /// Changes the playback speed relative to the sample's native pitch.
+    pub fn set_rate(&mut self, rate: f64) {
+        self.rate = rate;
+    }
+
+    /// Changes how much longer a stretched voice makes its sample last,
+    /// leaving its pitch alone. Does nothing to a voice not triggered with
+    /// a [`TimeStretch`].
+    pub fn set_stretch(&mut self, ratio: f64) {
+        if let Some(stretch) = &mut self.stretch {
+            stretch.ratio = ratio;
+        }
+    }
+
+    /// Changes a stretched voice's pitch shift, in semitones, leaving its
+    /// tempo alone. Does nothing to a voice not triggered with a
+    /// [`TimeStretch`].
+    pub fn set_pitch_shift(&mut self, semitones: f32) {
+        if let Some(stretch) = &mut self.stretch {
+            stretch.semitones = semitones;
+        }
+    }
+
+    /// Returns `true` while the voice is producing sound.
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+
+    /// Returns `true` while a [`fade_out`](Voice::fade_out) is in progress.
+    pub fn is_fading(&self) -> bool {
+        self.active && self.fade_remaining.is_some()
+    }
+
+    /// Returns `true` once [`release`](Voice::release) has been called.
+    pub fn is_released(&self) -> bool {
+        self.released
+    }
+
+    /// The id passed to the last [`start`](Voice::start).
+    pub fn id(&self) -> VoiceId {
+        self.id
+    }
+
+    /// The sample this voice is playing.
+    pub fn sample(&self) -> SampleId {
+        self.sample
+    }
+
+    /// The note this voice was triggered with.
+    pub fn note(&self) -> u8 {
+        self.note
+    }
+
+    /// The MIDI channel this voice was triggered on.
+    pub fn channel(&self) -> u8 {
+        self.channel
+    }
+
+    /// The choke group this voice was triggered in.
+    pub fn choke_group(&self) -> Option<u32> {
+        self.choke_group
+    }
+
+    /// The group whose notes choke this voice.
+    pub fn choked_by(&self) -> Option<u32> {
+        self.choked_by
+    }
+
+    /// Seconds this voice takes to fade out when choked.
+    pub fn choke_fade(&self) -> f32 {
+        self.choke_fade
+    }
+
+    /// Levels the voice sends to each send bus.
+    pub fn sends(&self) -> &[f32; SENDS] {
+        &self.sends
+    }
+
+    /// Current playback position in (fractional) sample frames.
+    pub fn position(&self) -> f64 {
+        self.position
+    }
+
+    /// Playback speed relative to the sample's native pitch.
+    pub fn rate(&self) -> f64 {
+        self.rate
+    }
+
+    /// The voice's time stretch, if it has one.
+    pub fn stretch(&self) -> Option<TimeStretch> {
+        self.stretch
+    }
+
+    /// The amplitude envelope.
+    pub fn envelope(&self) -> &Envelope {
+        &self.envelope
+    }
+
+    /// The filter envelope, idle unless the voice was triggered with a
+    /// filter.
+    pub fn filter_envelope(&self) -> &Envelope {
+        &self.filter_envelope
+    }
+
+    /// Peak output level of the most recent block, used to find the quietest
+    /// voice when stealing.
+    pub fn level(&self) -> f32 {
+        self.level
+    }
+
+    /// Starts the voice's LFOs and modulation envelope, and moves its start
+    /// by the matrix's start offset. `frame_scale` converts frames of the
+    /// sample as added to frames of the sample as played.
+    pub(crate) fn start_modulation(
+        &mut self,
+        matrix: &ModMatrix,
+        controls: ChannelControls,
+        frame_scale: f64,
+    ) {
+        self.modulation.trigger(matrix, self.id.0);
+        let offset = matrix.evaluate(&self.sources(matrix, controls)).start;
+        if offset != 0.0 {
+            self.position = (self.position + f64::from(offset) * frame_scale).max(0.0);
+        }
+    }
+
+    fn sources(&self, matrix: &ModMatrix, controls: ChannelControls) -> Sources {
+        Sources {
+            lfo: self.modulation.lfos(matrix),
+            envelope: self.modulation.envelope.level(),
+            amp_envelope: self.envelope.level(),
+            filter_envelope: self.filter_envelope.level(),
+            velocity: f32::from(self.velocity) / 127.0,
+            key: (f32::from(self.note) - 60.0) / 12.0,
+            controls,
+        }
+    }
+
+    /// Evaluates the matrix for the next `frames` frames: sets the ramps
+    /// and the loop, advances the LFOs and returns the pitch offset in
+    /// semitones.
+    fn modulate(
+        &mut self,
+        matrix: &ModMatrix,
+        ctx: &RenderContext<'_>,
+        src: &Source<'_>,
+        frames: usize,
+    ) -> f32 {
+        let controls = ctx.controls[usize::from(self.channel)];
+        let m = matrix.evaluate(&self.sources(matrix, controls));
+        self.modulation
+            .advance(matrix, frames, ctx.sample_rate, ctx.tempo);
+        let jump = std::mem::take(&mut self.fresh);
+        self.mod_gain.toward(
+            (m.gain / 20.0 * std::f32::consts::LOG2_10).exp2(),
+            frames,
+            jump,
+        );
+        self.mod_pan.toward(m.pan, frames, jump);
+        self.mod_cutoff.toward(m.cutoff, frames, jump);
+        self.shift_loop(src, m.loop_shift.round() as isize);
+        m.pitch
+    }
+
+    /// Returns `true` if nothing is left of earlier modulation.
+    fn is_unmodulated(&self) -> bool {
+        self.mod_gain.is_at(1.0)
+            && self.mod_pan.is_at(0.0)
+            && self.mod_cutoff.is_at(0.0)
+            && self.loop_shift == 0
+    }
+
+    /// Glides the ramps back to rest and puts the loop back, for a voice
+    /// left modulated when the matrix is taken away.
+    fn unmodulate(&mut self, src: &Source<'_>, frames: usize) {
+        self.mod_gain.toward(1.0, frames, false);
+        self.mod_pan.toward(0.0, frames, false);
+        self.mod_cutoff.toward(0.0, frames, false);
+        self.shift_loop(src, 0);
+    }
+
+    /// Moves the loop by `shift` frames from where it was set. A voice
+    /// inside the old loop but outside the new one wraps into it.
+    fn shift_loop(&mut self, src: &Source<'_>, shift: isize) {
+        if shift == self.loop_shift {
+            return;
+        }
+        let before = self.active_loop(src);
+        self.loop_shift = shift;
+        let (Some(old), Some(new)) = (before, self.active_loop(src)) else {
+            return;
+        };
+        let pos = self.position;
+        let inside = |r: &LoopRegion| r.start as f64 <= pos && pos < r.end as f64;
+        if inside(&old) && !inside(&new) {
+            let start = new.start as f64;
+            self.position = start + (pos - start).rem_euclid(new.len() as f64);
+        }
+    }
+
+    /// Allocation order stamp set by the [`VoicePool`](crate::VoicePool).
+    pub(crate) fn serial(&self) -> u64 {
+        self.serial
+    }
+
+    pub(crate) fn set_serial(&mut self, serial: u64) {
+        self.serial = serial;
+    }
+
+    /// Index of the stream buffer this voice reads streamed samples through.
+    pub(crate) fn ring(&self) -> usize {
+        self.ring
+    }
+
+    pub(crate) fn set_ring(&mut self, ring: usize) {
+        self.ring = ring;
+    }
+
+    /// The loop currently in force, if it is valid and still engaged.
+    fn active_loop(&self, src: &Source<'_>) -> Option<LoopRegion> {
+        let mut region = self.loop_override.or(src.sample.loop_region())?;
+        if self.loop_shift != 0 {
+            let len = region.len();
+            region.start = region
+                .start
+                .saturating_add_signed(self.loop_shift)
+                .min(src.frames.saturating_sub(len));
+            region.end = region.start + len;
+        }
+        region.end = region.end.min(src.frames);
+        let engaged = match region.mode {
+            LoopMode::Off => false,
+            LoopMode::UntilRelease => !self.released,
+            LoopMode::Forward | LoopMode::PingPong | LoopMode::Reverse => true,
+        };
+        (engaged && region.start < region.end).then_some(region)
+    }
+
+    /// Works out how far the voice can travel before something changes: the
+    /// frame count to the next edge, the edge itself and any active seam.
+    fn next_run(&self, src: &Source<'_>, step: f64) -> (usize, Edge, Option<Seam>) {
+        let pos = self.position;
+        let frames = src.frames;
+        let Some(region) = self.active_loop(src) else {
+            return (
+                frames_before(pos, frames as f64, step),
+                Edge::SampleEnd,
+                None,
+            );
+        };
+        let start = region.start as f64;
+        let end = region.end as f64;
+        let len = end - start;
+
+        if self.backwards {
+            if region.mode == LoopMode::Reverse {
+                let fade = region.crossfade.min(region.len()).min(frames - region.end) as f64;
+                if fade > 0.0 {
+                    let zone = start + fade;
+                    if pos >= zone {
+                        return (frames_down_to(pos, zone, step), Edge::Zone, None);
+                    }
+                    let seam = Seam {
+                        zone_start: zone,
+                        slope: -1.0 / fade,
+                        offset: len,
+                    };
+                    return (frames_down_to(pos, start, step), Edge::LoopEnd, Some(seam));
+                }
+            }
+            return (frames_down_to(pos, start, step), Edge::LoopEnd, None);
+        }
+
+        // Starting beyond the loop plays out the rest of the sample.
+        if pos >= end {
+            return (
+                frames_before(pos, frames as f64, step),
+                Edge::SampleEnd,
+                None,
+            );
+        }
+        match region.mode {
+            LoopMode::PingPong | LoopMode::Reverse => {
+                (frames_before(pos, end - 1.0, step), Edge::LoopEnd, None)
+            }
+            _ => {
+                let fade = region.crossfade.min(region.len()).min(region.start) as f64;
+                if fade > 0.0 {
+                    let zone = end - fade;
+                    if pos < zone {
+                        return (frames_before(pos, zone, step), Edge::Zone, None);
+                    }
+                    let seam = Seam {
+                        zone_start: zone,
+                        slope: 1.0 / fade,
+                        offset: -len,
+                    };
+                    return (frames_before(pos, end, step), Edge::LoopEnd, Some(seam));
+                }
+                (frames_before(pos, end, step), Edge::LoopEnd, None)
+            }
+        }
+    }
+
+    /// Applies the loop behaviour at the edge the voice has just reached.
+    /// Returns `false` if the voice has finished.
+    fn cross_edge(&mut self, src: &Source<'_>, edge: Edge) -> bool {
+        let region = match edge {
+            Edge::SampleEnd => return false,
+            Edge::Zone => return true,
+            Edge::LoopEnd => match self.active_loop(src) {
+                Some(region) => region,
+                None => return true,
+            },
+        };
+        let start = region.start as f64;
+        let end = region.end as f64;
+        let len = end - start;
+        let last = end - 1.0;
+
+        self.position = match (region.mode, self.backwards) {
+            (LoopMode::Forward | LoopMode::UntilRelease, _) => {
+                start + (self.position - start).rem_euclid(len)
+            }
+            (LoopMode::PingPong | LoopMode::Reverse, false) => {
+                self.backwards = true;
+                (2.0 * last - self.position).max(start)
+            }
+            (LoopMode::PingPong, true) => {
+                self.backwards = false;
+                (2.0 * start - self.position).min(last)
+            }
+            (LoopMode::Reverse, true) => start + (self.position - start).rem_euclid(len),
+            (LoopMode::Off, _) => unreachable!("inactive loops are filtered out"),
+        };
+        true
+    }
+
+    /// Adds the voice's output to the interleaved `out` buffer, which has
+    /// `channels` channels. The voice deactivates itself at the end of the
+    /// sample, of its envelope or of a fade-out.
+    ///
+    /// Playback proceeds in runs between loop edges, so the per-frame loop
+    /// only advances and reads; wrapping and direction changes happen once per
+    /// run.
+    ///
+    /// A voice on a bus plays on that bus's pair of channels; otherwise
+    /// sample channels are mapped onto all output channels by wrapping: a
+    /// mono sample feeds every output, and surplus sample channels fold back
+    /// onto the available outputs. Pan applies to the first two of them. A
+    /// filter runs on the sample channels, before they are mapped.
+    ///
+    /// A streamed sample plays on past its preloaded frames from the voice's
+    /// stream buffer. If the buffer has not caught up, the voice holds its
+    /// position, leaves the rest of the block silent and counts an underrun.
+    ///
+    /// With a [modulation matrix](crate::modulation) in the context, the
+    /// block is also split every [`MOD_BLOCK`] frames to evaluate it.
+    ///
+    /// A voice triggered with a [`TimeStretch`] needs buffers to build its
+    /// output in, which only the engine has; here it plays unstretched.
+    pub fn render(
+        &mut self,
+        sample: &Sample,
+        ctx: &RenderContext<'_>,
+        out: &mut [f32],
+        channels: usize,
+    ) {
+        self.render_with(sample, ctx, out, channels, None);
+    }
+
+    /// Like [`render`](Voice::render), but a stretched voice playing a
+    /// sample in memory is built in `stretcher`.
+    pub(crate) fn render_with(
+        &mut self,
+        sample: &Sample,
+        ctx: &RenderContext<'_>,
+        out: &mut [f32],
+        channels: usize,
+        stretcher: Option<&mut Stretcher>,
+    ) {
+        if !self.active {
+            return;
+        }
+        if let (Some(stretch), Some(stretcher)) = (self.stretch, stretcher) {
+            if ctx.streams.length(self.sample).is_none() {
+                self.render_stretched(sample, ctx, out, channels, stretch, stretcher);
+                return;
+            }
+        }
+        let rate = self.rate * ctx.bend[usize::from(self.channel)];
+        let base = (rate * f64::from(sample.sample_rate()) / f64::from(ctx.sample_rate)).max(0.0);
+        let mut step = base;
+        let total = out.len() / channels;
+        let matrix = ctx.modulation.filter(|m| !m.routes.is_empty());
+        // Where the current evaluation of the matrix runs out.
+        let mut block_end = if matrix.is_some() || !self.is_unmodulated() {
+            0
+        } else {
+            total
+        };
+        let mut done = 0;
+        let mut stalled = false;
+        self.level = 0.0;
+
+        let mut src = Source {
+            sample,
+            frames: sample.frames(),
+            stream: None,
+        };
+        if let Some(frames) = ctx.streams.length(self.sample) {
+            let buffer = &ctx.streams.buffers[self.ring];
+            src.frames = frames;
+            src.stream = Some((buffer, self.follow_stream(buffer, sample, step)));
+        }
+
+        while done < total && self.active {
+            if done == block_end {
+                (block_end, step) = self.next_mod_block(matrix, ctx, &src, base, done, total);
+            }
+            let (to_edge, edge, seam) = self.next_run(&src, step);
+            let buffered = self.buffered(&src, step);
+            if buffered == 0 && to_edge > 0 {
+                ctx.streams.underrun();
+                break;
+            }
+            let mut n = to_edge.min(block_end - done).min(buffered);
+            if let Some(fade) = self.fade_remaining {
+                n = n.min(fade);
+            }
+            let run = &mut out[done * channels..(done + n) * channels];
+            let (interp, sinc) = (self.interpolation, ctx.sinc);
+            match seam {
+                None => self.render_run(&src, ctx, run, channels, step, |s, _, pos| {
+                    interp.read(&src.view(s), pos, step, sinc)
+                }),
+                Some(seam) => self.render_run(&src, ctx, run, channels, step, |s, _, pos| {
+                    let view = src.view(s);
+                    let t = ((pos - seam.zone_start) * seam.slope).clamp(0.0, 1.0) as f32;
+                    interp.read(&view, pos, step, sinc) * (1.0 - t)
+                        + interp.read(&view, pos + seam.offset, step, sinc) * t
+                }),
+            }
+            done += n;
+
+            if self.fade_remaining == Some(0) || !self.envelope.is_active() {
+                self.stop();
+                break;
+            }
+            if n == to_edge && !self.cross_edge(&src, edge) {
+                self.stop();
+                break;
+            }
+            // Two empty runs in a row means the edges leave no room to move.
+            if n == 0 {
+                if stalled {
+                    break;
+                }
+                stalled = true;
+            } else {
+                stalled = false;
+            }
+        }
+
+        if let Some((buffer, Some(window))) = src.stream {
+            self.release_stream(buffer, &window, &src, step);
+        }
+    }
+
+    /// Evaluates the modulation for the block starting `done` frames into
+    /// `total`, or winds it down without a matrix. Returns where the block
+    /// ends and the step to play it at.
+    fn next_mod_block(
+        &mut self,
+        matrix: Option<&ModMatrix>,
+        ctx: &RenderContext<'_>,
+        src: &Source<'_>,
+        base: f64,
+        done: usize,
+        total: usize,
+    ) -> (usize, f64) {
+        let frames = MOD_BLOCK.min(total - done);
+        match matrix {
+            Some(matrix) => {
+                let pitch = self.modulate(matrix, ctx, src, frames);
+                (done + frames, base * f64::from(pitch / 12.0).exp2())
+            }
+            None => {
+                self.unmodulate(src, frames);
+                let end = if self.is_unmodulated() {
+                    total
+                } else {
+                    done + frames
+                };
+                (end, base)
+            }
+        }
+    }
+
+    /// Renders a stretched voice. The voice moves through the sample at the
+    /// stretched speed, and its output comes from `stretcher`, which builds
+    /// it from grains read around that position at the shifted pitch.
+    ///
+    /// Any loop plays forwards, without a crossfade.
+    fn render_stretched(
+        &mut self,
+        sample: &Sample,
+        ctx: &RenderContext<'_>,
+        out: &mut [f32],
+        channels: usize,
+        stretch: TimeStretch,
+        stretcher: &mut Stretcher,
+    ) {
+        let rate = self.rate * ctx.bend[usize::from(self.channel)];
+        let base = (rate * f64::from(sample.sample_rate()) / f64::from(ctx.sample_rate)).max(0.0);
+        let mut step = base;
+        let total = out.len() / channels;
+        let matrix = ctx.modulation.filter(|m| !m.routes.is_empty());
+        let mut block_end = if matrix.is_some() || !self.is_unmodulated() {
+            0
+        } else {
+            total
+        };
+        let mut done = 0;
+        self.level = 0.0;
+        let src = Source {
+            sample,
+            frames: sample.frames(),
+            stream: None,
+        };
+        let shift = f64::from(stretch.semitones / 12.0).exp2();
+        let ratio = stretch.ratio.max(stretch::MIN_RATIO);
+
+        while done < total && self.active {
+            if done == block_end {
+                (block_end, step) = self.next_mod_block(matrix, ctx, &src, base, done, total);
+            }
+            let speed = step / ratio;
+            let region = self
+                .active_loop(&src)
+                .filter(|r| self.position < r.end as f64);
+            let wrap = |pos: f64| match region {
+                Some(r) if pos >= r.end as f64 => {
+                    r.start as f64 + (pos - r.start as f64).rem_euclid(r.len() as f64)
+                }
+                _ => pos,
+            };
+            let end = region.map_or(src.frames, |r| r.end);
+            let to_edge = frames_before(self.position, end as f64, speed);
+            if stretcher.ready() == 0 {
+                let grain = Grain {
+                    position: self.position,
+                    speed,
+                    rate: step * shift,
+                };
+                let (interp, sinc) = (self.interpolation, ctx.sinc);
+                let mixed = sample.channels().min(stretch::CHANNELS);
+                stretcher.synthesize(
+                    stretch.mode,
+                    grain,
+                    sample.channels(),
+                    |c, pos| interp.read(&src.view(c), wrap(pos), grain.rate, sinc),
+                    |pos| {
+                        let pos = wrap(pos);
+                        (0..mixed)
+                            .map(|c| {
+                                Interpolation::Linear.read(&src.view(c), pos, grain.rate, sinc)
+                            })
+                            .sum()
+                    },
+                );
+            }
+            let mut n = to_edge.min(block_end - done).min(stretcher.ready());
+            if let Some(fade) = self.fade_remaining {
+                n = n.min(fade);
+            }
+            let run = &mut out[done * channels..(done + n) * channels];
+            let built = &*stretcher;
+            self.render_run(&src, ctx, run, channels, speed, |s, i, _| built.get(s, i));
+            stretcher.consume(n);
+            done += n;
+
+            if self.fade_remaining == Some(0) || !self.envelope.is_active() {
+                self.stop();
+                break;
+            }
+            if n == to_edge {
+                if region.is_none() {
+                    self.stop();
+                    break;
+                }
+                self.position = wrap(self.position);
+            }
+        }
+    }
+
+    /// Points the voice's stream buffer at the frames ahead of the voice,
+    /// unless it already holds or is fetching them, and returns what it
+    /// holds.
+    fn follow_stream(
+        &mut self,
+        buffer: &StreamBuffer,
+        sample: &Sample,
+        step: f64,
+    ) -> Option<Window> {
+        let head = sample.frames();
+        let capacity = buffer.capacity(sample.channels());
+        let want = self.lowest_read(step).max(head);
+        let window = buffer.window(sample.channels());
+        let fetching = match buffer.requested() {
+            Some((id, origin)) if !self.restart && id == self.sample && origin <= want => {
+                let (start, end) = window.map_or((origin, origin), |w| (w.start, w.end));
+                start <= want && want <= end + capacity / 2
+            }
+            _ => false,
+        };
+        self.restart = false;
+        if fetching {
+            window
+        } else {
+            buffer.request(self.sample, want);
+            None
+        }
+    }
+
+    /// The lowest frame interpolation reads around the current position.
+    fn lowest_read(&self, step: f64) -> usize {
+        (self.position.max(0.0) as usize).saturating_sub(self.interpolation.reach(step))
+    }
+
+    /// Frames the voice can render moving forwards before it would read
+    /// past what a streamed sample has in memory or in its stream buffer.
+    fn buffered(&self, src: &Source<'_>, step: f64) -> usize {
+        let Some((_, window)) = src.stream else {
+            return usize::MAX;
+        };
+        if self.backwards {
+            return usize::MAX;
+        }
+        let head = src.sample.frames();
+        let end = match window {
+            Some(w) if w.start <= self.lowest_read(step).max(head) => w.end.max(head),
+            _ => head,
+        };
+        if end >= src.frames {
+            return usize::MAX;
+        }
+        let reach = self.interpolation.reach(step);
+        frames_before(self.position, end.saturating_sub(reach) as f64, step)
+    }
+
+    /// Hands back the buffered frames the voice has finished with, keeping a
+    /// loop that fits in the buffer so it can come round again.
+    fn release_stream(&self, buffer: &StreamBuffer, window: &Window, src: &Source<'_>, step: f64) {
+        let reach = self.interpolation.reach(step);
+        let mut keep = self.lowest_read(step);
+        if let Some(region) = self.active_loop(src) {
+            if region.len() + 2 * reach < window.capacity {
+                keep = keep.min(region.start.saturating_sub(reach));
+            }
+        }
+        buffer.release(window, keep);
+    }
+
+    /// The first output channel the voice plays on and how many it spans.
+    pub(crate) fn outputs(&self, channels: usize) -> (usize, usize) {
+        match self.bus {
+            Some(bus) => {
+                let first = 2 * (bus % channels.div_ceil(2));
+                (first, (channels - first).min(2))
+            }
+            None => (0, channels),
+        }
+    }
+
+    /// Renders `out.len() / channels` frames without checking for edges.
+    /// `read` reads one channel for a frame of the run at a position, which
+    /// is where seam crossfades and stretching hook in. The filter's cutoff
+    /// moves every frame, but coefficients are only redesigned when it does.
+    #[inline]
+    fn render_run(
+        &mut self,
+        src: &Source<'_>,
+        ctx: &RenderContext<'_>,
+        out: &mut [f32],
+        channels: usize,
+        step: f64,
+        read: impl Fn(usize, usize, f64) -> f32,
+    ) {
+        let sample_channels = src.sample.channels();
+        let sample_rate = ctx.sample_rate as f32;
+        let velocity = if self.backwards { -step } else { step };
+        let filter = self.filter;
+        let mut peak = self.level;
+        let (first, width) = self.outputs(channels);
+        // True panning places two channels; any more are balanced.
+        let mode = match sample_channels {
+            1 | 2 => self.pan_mode,
+            _ => PanMode::Balance,
+        };
+        // A voice with no pan of its own is panned from the centre once
+        // modulation can move it.
+        let pan_routed = ctx
+            .modulation
+            .is_some_and(|m| m.routes_to(ModDestination::Pan));
+        let pan_from = self.pan.or(pan_routed.then_some(0.0));
+        let mut gains = pan::matrix(ctx.pan_law, mode, sample_channels, None);
+        let mut panned_at = f32::NAN;
+        // Taps of the first sample channels, reused by outputs that repeat
+        // them so each is read and filtered once a frame.
+        let mut taps = [0.0; FILTER_CHANNELS];
+
+        for (i, frame) in out.chunks_exact_mut(channels).enumerate() {
+            let frame = &mut frame[first..first + width];
+            let mut gain = self.gain * self.envelope.next(sample_rate) * self.mod_gain.next();
+            if let Some(fade) = &mut self.fade_remaining {
+                gain *= *fade as f32 / self.fade_frames as f32;
+                *fade -= 1;
+            }
+            if let Some(filter) = &filter {
+                let level = self.filter_envelope.next(sample_rate);
+                let cents = self.mod_cutoff.next();
+                let cutoff =
+                    filter.cutoff_at(level, self.note, self.velocity) * (cents / 1200.0).exp2();
+                if cutoff != self.cutoff {
+                    self.coefficients = SvfCoefficients::new(cutoff, filter.resonance, sample_rate);
+                    self.cutoff = cutoff;
+                }
+            }
+            let offset = self.mod_pan.next();
+            if let Some(from) = pan_from {
+                let pan = (from + offset).clamp(-1.0, 1.0);
+                if pan != panned_at {
+                    gains = pan::matrix(ctx.pan_law, mode, sample_channels, Some(pan));
+                    panned_at = pan;
+                }
+            }
+            let pos = self.position;
+            let (svf, coefficients) = (&mut self.svf, &self.coefficients);
+            let mut tap = |s: usize| {
+                let v = read(s, i, pos);
+                let v = match (&filter, svf.get_mut(s)) {
+                    (Some(filter), Some(svf)) => svf.process(v, coefficients, filter.mode),
+                    _ => v,
+                };
+                v * gain
+            };
+            if width == 1 {
+                for s in 0..sample_channels {
+                    let v = tap(s);
+                    peak = peak.max(v.abs());
+                    frame[0] += v;
+                }
+            } else {
+                let (mut left, mut right) = (0.0, 0.0);
+                for (s, g) in gains.iter().enumerate().take(sample_channels) {
+                    let v = tap(s);
+                    taps[s] = v;
+                    left += v * g[0];
+                    right += v * g[1];
+                }
+                peak = peak.max(left.abs()).max(right.abs());
+                frame[0] += left;
+                frame[1] += right;
+                for s in 2..sample_channels {
+                    let v = tap(s);
+                    if let Some(t) = taps.get_mut(s) {
+                        *t = v;
+                    }
+                    let v = v * match s % width {
+                        0 => gains[0][0],
+                        1 => gains[1][1],
+                        _ => 1.0,
+                    };
+                    peak = peak.max(v.abs());
+                    frame[s % width] += v;
+                }
+                for (c, o) in frame.iter_mut().enumerate().skip(sample_channels.max(2)) {
+                    let v = match taps.get(c % sample_channels) {
+                        Some(&v) => v,
+                        None => tap(c % sample_channels),
+                    };
+                    peak = peak.max(v.abs());
+                    *o += v;
+                }
+            }
+            self.position += velocity;
+        }
+        self.level = peak;
+    }
+}
+
+/// Frames that can be rendered moving forwards from `pos` while staying below
+/// `limit`.
+fn frames_before(pos: f64, limit: f64, step: f64) -> usize {
+    if pos >= limit {
+        0
+    } else if step <= 0.0 {
+        usize::MAX
+    } else {
+        ((limit - pos) / step).ceil() as usize
+    }
+}
+
+/// Frames that can be rendered moving backwards from `pos` while staying at
+/// or above `limit`.
+fn frames_down_to(pos: f64, limit: f64, step: f64) -> usize {
+    if pos < limit {
+        0
+    } else if step <= 0.0 {
+        usize::MAX
+    } else {
+        ((pos - limit) / step).floor() as usize + 1
+    }
+}
@@ -0,0 +1,158 @@
+mod common;
+
+use samplerust::{
+    Backend, Callback, DeviceConfig, Engine, EngineConfig, Interpolation, NullBackend, Sample,
+    TriggerParams,
+};
+
+/// An engine playing a sample whose channels are distinct sines.
+fn playing(channels: usize) -> Engine {
+    let (engine, mut ctl) = Engine::new(EngineConfig {
+        channels,
+        ..EngineConfig::default()
+    });
+    let data: Vec<Vec<f32>> = (0..channels)
+        .map(|ch| common::sine(440.0 * (ch + 1) as f64, 48_000.0, 10_000))
+        .collect();
+    let id = ctl
+        .add_sample(Sample::from_channels(&data, 48_000).unwrap())
+        .unwrap();
+    ctl.trigger(
+        id,
+        TriggerParams {
+            interpolation: Interpolation::None,
+            ..TriggerParams::default()
+        },
+    )
+    .unwrap();
+    engine
+}
+
+fn rendered(channels: usize, frames: usize) -> Vec<f32> {
+    let mut out = vec![0.0; frames * channels];
+    playing(channels).render(&mut out);
+    out
+}
+
+#[test]
+fn null_stream_plays_what_the_engine_renders() {
+    let backend = NullBackend::new(DeviceConfig {
+        buffer_frames: Some(300),
+        ..DeviceConfig::default()
+    });
+    assert_eq!(backend.config().buffer_frames, Some(300));
+    let mut stream = backend.start(playing(2)).unwrap();
+    assert_eq!(stream.buffer_frames(), 300);
+
+    let out = stream.run(1_000);
+    assert_eq!(out.len(), 1_200 * 2);
+    assert_eq!(stream.callbacks(), 4);
+    assert_eq!(out, rendered(2, 1_200));
+    assert_eq!(stream.stop().frame(), 1_200);
+}
+
+#[test]
+fn mono_engine_feeds_every_device_channel() {
+    let mut stream = NullBackend::new(DeviceConfig {
+        channels: 4,
+        ..DeviceConfig::default()
+    })
+    .start(playing(1))
+    .unwrap();
+    let out = stream.tick().to_vec();
+    let expected = rendered(1, 512);
+    for (frame, &v) in out.chunks_exact(4).zip(&expected) {
+        assert_eq!(frame, [v; 4]);
+    }
+}
+
+#[test]
+fn surplus_engine_channels_fold_back() {
+    let expected = rendered(3, 256);
+
+    // Three engine channels onto two: the third joins the first.
+    let mut stream = NullBackend::new(DeviceConfig {
+        buffer_frames: Some(256),
+        ..DeviceConfig::default()
+    })
+    .start(playing(3))
+    .unwrap();
+    for (frame, src) in stream.tick().chunks_exact(2).zip(expected.chunks_exact(3)) {
+        assert_eq!(frame, [src[0] + src[2], src[1]]);
+    }
+
+    // Onto one, they all sum.
+    let mut stream = NullBackend::new(DeviceConfig {
+        channels: 1,
+        buffer_frames: Some(256),
+        ..DeviceConfig::default()
+    })
+    .start(playing(3))
+    .unwrap();
+    for (&v, src) in stream.tick().iter().zip(expected.chunks_exact(3)) {
+        assert_eq!(v, src.iter().sum::<f32>());
+    }
+
+    // Two onto three: the first repeats.
+    let mut stream = NullBackend::new(DeviceConfig {
+        channels: 3,
+        buffer_frames: Some(256),
+        ..DeviceConfig::default()
+    })
+    .start(playing(2))
+    .unwrap();
+    for (frame, src) in stream
+        .tick()
+        .chunks_exact(3)
+        .zip(rendered(2, 256).chunks_exact(2))
+    {
+        assert_eq!(frame, [src[0], src[1], src[0]]);
+    }
+}
+
+#[test]
+fn callbacks_larger_than_the_block_render_in_pieces() {
+    let device = DeviceConfig {
+        buffer_frames: Some(100),
+        ..DeviceConfig::default()
+    };
+    let mut callback = Callback::new(playing(2), &device);
+
+    // Devices can ask for more than they said, and hand over a partial
+    // frame at the end.
+    let mut out = vec![1.0; 1_037 * 2 + 1];
+    callback.process(&mut out);
+    assert_eq!(&out[..1_037 * 2], rendered(2, 1_037).as_slice());
+    assert_eq!(out[1_037 * 2], 0.0);
+    assert_eq!(callback.engine().frame(), 1_037);
+
+    // Other sample types go through the conversion.
+    let mut ints = vec![0i16; 64 * 2];
+    callback.process_with(&mut ints, |v| (v * 32_767.0) as i16);
+    let expected = &rendered(2, 1_101)[1_037 * 2..];
+    for (&i, &v) in ints.iter().zip(expected) {
+        assert_eq!(i, (v * 32_767.0) as i16);
+    }
+    assert_eq!(callback.into_engine().frame(), 1_101);
+}
+
+/// Code written against the trait, not a particular backend.
+fn start<B: Backend>(backend: B, engine: Engine) -> B::Stream {
+    assert_eq!(backend.config().sample_rate, engine.config().sample_rate);
+    backend.start(engine).unwrap()
+}
+
+#[test]
+fn engines_run_through_the_backend_trait() {
+    let mut stream = start(NullBackend::new(DeviceConfig::default()), playing(2));
+    let out = stream.run(48_000);
+    assert!(common::rms(&out[..20_000]) > 0.5);
+    assert!(out[20_000..].iter().all(|&v| v == 0.0));
+    assert_eq!(stream.engine().active_voices(), 0);
+}
+
+#[test]
+fn callbacks_can_move_to_the_audio_thread() {
+    fn send<T: Send>() {}
+    send::<Callback>();
+}
@@ -0,0 +1,170 @@
+mod common;
+
+use samplerust::codec::wav::{self, BitDepth, Dither};
+use samplerust::{Bounce, EngineConfig, Instrument, LoopRegion, MidiMessage, Sample};
+
+fn note_on(channel: u8, note: u8) -> MidiMessage {
+    MidiMessage::NoteOn {
+        channel,
+        note,
+        velocity: 127,
+    }
+}
+
+fn note_off(channel: u8, note: u8) -> MidiMessage {
+    MidiMessage::NoteOff {
+        channel,
+        note,
+        velocity: 0,
+    }
+}
+
+/// A mono bounce with a 100-frame click on channel 0 and a sine on
+/// channel 1.
+fn setup() -> Bounce {
+    let mut bounce = Bounce::new(EngineConfig {
+        channels: 1,
+        ..EngineConfig::default()
+    });
+    let click = Sample::from_interleaved(vec![0.5; 100], 1, 48_000).unwrap();
+    let tone = Sample::from_interleaved(common::sine(440.0, 48_000.0, 48_000), 1, 48_000)
+        .unwrap()
+        .with_loop_region(LoopRegion::forward(0, 48_000));
+    let click = bounce.controller().add_sample(click).unwrap();
+    let tone = bounce.controller().add_sample(tone).unwrap();
+    bounce.add_instrument(Instrument::single(click, 60), Some(0));
+    bounce.add_instrument(Instrument::single(tone, 69), Some(1));
+    bounce
+}
+
+#[test]
+fn events_land_on_their_frames() {
+    let mut bounce = setup();
+    bounce.extend([(1_000, note_on(0, 60)), (777, note_on(0, 60))]);
+    let out = bounce.render().unwrap();
+    let x = out.as_slice();
+    // Both clicks play to their end, then the render stops.
+    assert_eq!(out.frames(), 1_100);
+    assert!(x[..777].iter().all(|&v| v == 0.0));
+    assert_eq!(x[777], 0.5);
+    assert_eq!(x[877..1_000], [0.0; 123]);
+    assert_eq!(x[1_000..], [0.5; 100]);
+}
+
+#[test]
+fn render_waits_for_releases_and_stops_at_the_tail() {
+    let mut bounce = setup();
+    bounce.push(0, note_on(1, 69));
+    bounce.push(10_000, note_off(1, 69));
+    let out = bounce.render().unwrap();
+    // The default envelope releases over 5 ms.
+    assert!((10_200..=10_240).contains(&out.frames()));
+    assert!(common::rms(&out.as_slice()[9_000..10_000]) > 0.5);
+
+    // A looping voice that is never released runs to the tail.
+    let mut bounce = setup();
+    bounce.push(500, note_on(1, 69));
+    bounce.set_tail(0.25);
+    let out = bounce.render().unwrap();
+    assert_eq!(out.frames(), 500 + 12_000);
+    assert!(common::rms(&out.as_slice()[12_000..]) > 0.5);
+}
+
+#[test]
+fn bounces_are_bit_identical() {
+    let dir = common::temp_dir("bounce");
+    let bounce = |seed: u64, name: &str| {
+        let mut bounce = setup();
+        for i in 0..20 {
+            bounce.push(i * 2_000, note_on((i % 2) as u8, 60 + (i % 12) as u8));
+            bounce.push(i * 2_000 + 1_500, note_off(1, 60 + (i % 12) as u8));
+        }
+        let path = dir.join(name);
+        bounce
+            .save_wav(&path, BitDepth::Pcm16, Dither::Tpdf { seed })
+            .unwrap();
+        std::fs::read(path).unwrap()
+    };
+    let first = bounce(1, "a.wav");
+    assert_eq!(first, bounce(1, "b.wav"));
+    assert_ne!(first, bounce(2, "c.wav"));
+    assert!(wav::decode(&first).unwrap().frames() > 39_500);
+}
+
+#[test]
+fn wav_writes_every_depth() {
+    // Kept under full scale, whose top step only the negative side has.
+    let data: Vec<f32> = common::sine(1_000.0, 44_100.0, 1_000)
+        .iter()
+        .map(|v| v * 0.9)
+        .collect();
+    let sample = Sample::from_interleaved(data.clone(), 1, 44_100).unwrap();
+    for (depth, step) in [
+        (BitDepth::Pcm16, 1.0 / 32_768.0),
+        (BitDepth::Pcm24, 1.0 / 8_388_608.0),
+        (BitDepth::Pcm32, 1.0 / 2_147_483_648.0),
+        (BitDepth::Float32, 0.0),
+    ] {
+        let mut bytes = Vec::new();
+        wav::write(&mut bytes, &sample, depth, Dither::None).unwrap();
+        let decoded = wav::decode(&bytes).unwrap();
+        assert_eq!(decoded.sample_rate(), 44_100);
+        assert_eq!(decoded.frames(), 1_000);
+        for (&a, &b) in decoded.as_slice().iter().zip(&data) {
+            let error = f64::from((a - b).abs());
+            assert!(
+                error <= step / 2.0 + f64::from(f32::EPSILON),
+                "{depth:?}: {a} {b} {error}"
+            );
+        }
+    }
+
+    // Integer formats clip instead of wrapping.
+    let loud = Sample::from_interleaved(vec![1.5, -1.5, 1.0], 1, 44_100).unwrap();
+    let mut bytes = Vec::new();
+    wav::write(&mut bytes, &loud, BitDepth::Pcm16, Dither::None).unwrap();
+    assert_eq!(
+        wav::decode(&bytes).unwrap().as_slice(),
+        [32_767.0 / 32_768.0, -1.0, 32_767.0 / 32_768.0]
+    );
+}
+
+#[test]
+fn wav_pads_odd_data_chunks() {
+    // 3 bytes a frame, 101 frames: an odd-sized data chunk.
+    let data = common::sine(1_000.0, 48_000.0, 101);
+    let sample = Sample::from_interleaved(data, 1, 48_000).unwrap();
+    let mut bytes = Vec::new();
+    wav::write(&mut bytes, &sample, BitDepth::Pcm24, Dither::None).unwrap();
+    assert_eq!(bytes.len(), 44 + 303 + 1);
+    assert_eq!(bytes[bytes.len() - 1], 0);
+    let riff_size = u32::from_le_bytes(bytes[4..8].try_into().unwrap());
+    assert_eq!(riff_size as usize, bytes.len() - 8);
+    let data_size = u32::from_le_bytes(bytes[40..44].try_into().unwrap());
+    assert_eq!(data_size, 303);
+    assert_eq!(wav::decode(&bytes).unwrap().frames(), 101);
+}
+
+#[test]
+fn tpdf_dither_decorrelates_quantization() {
+    let lsb = 1.0 / 32_768.0;
+    // A level well under one step rounds away to nothing without dither...
+    let quiet = Sample::from_interleaved(vec![0.3 * lsb as f32; 100_000], 1, 48_000).unwrap();
+    let encode = |dither| {
+        let mut bytes = Vec::new();
+        wav::write(&mut bytes, &quiet, BitDepth::Pcm16, dither).unwrap();
+        wav::decode(&bytes).unwrap()
+    };
+    assert!(encode(Dither::None).as_slice().iter().all(|&v| v == 0.0));
+
+    // ...but survives on average with it, under noise of half a step RMS:
+    // the triangular noise's 1/6 step² plus the rounding's 1/12.
+    let dithered = encode(Dither::Tpdf { seed: 7 });
+    let x = dithered.as_slice();
+    let mean = x.iter().map(|&v| f64::from(v)).sum::<f64>() / x.len() as f64;
+    assert!((mean / lsb - 0.3).abs() < 0.02, "mean {} steps", mean / lsb);
+    let error: Vec<f32> = x.iter().map(|&v| v - 0.3 * lsb as f32).collect();
+    let rms = common::rms(&error) / lsb;
+    assert!((rms - 0.5).abs() < 0.02, "rms {rms} steps");
+    assert!(x.iter().all(|&v| (f64::from(v) / lsb).abs() <= 2.0));
+}
@@ -0,0 +1,142 @@
+//! Signal helpers shared by the integration tests.
+#![allow(dead_code)]
+
+use std::f64::consts::PI;
+
+/// `frames` of a sine at `freq` Hz.
+pub fn sine(freq: f64, sample_rate: f64, frames: usize) -> Vec<f32> {
+    (0..frames)
+        .map(|i| (2.0 * PI * freq * i as f64 / sample_rate).sin() as f32)
+        .collect()
+}
+
+/// Linear sine sweep from `f0` to `f1` Hz.
+pub fn sweep(f0: f64, f1: f64, sample_rate: f64, frames: usize) -> Vec<f32> {
+    let duration = frames as f64 / sample_rate;
+    (0..frames)
+        .map(|i| {
+            let t = i as f64 / sample_rate;
+            let phase = 2.0 * PI * (f0 * t + (f1 - f0) * t * t / (2.0 * duration));
+            phase.sin() as f32
+        })
+        .collect()
+}
+
+pub fn rms(x: &[f32]) -> f64 {
+    (x.iter().map(|&v| f64::from(v) * f64::from(v)).sum::<f64>() / x.len().max(1) as f64).sqrt()
+}
+
+pub fn db(ratio: f64) -> f64 {
+    20.0 * ratio.max(1e-20).log10()
+}
+
+/// Power spectrum of the first power-of-two run of `x`, Hann windowed.
+/// Bin `k` covers `k * sample_rate / len` Hz.
+pub fn power_spectrum(x: &[f32]) -> Vec<f64> {
+    let n = if x.len().is_power_of_two() {
+        x.len()
+    } else {
+        x.len().next_power_of_two() / 2
+    };
+    let mut re: Vec<f64> = x[..n]
+        .iter()
+        .enumerate()
+        .map(|(i, &v)| f64::from(v) * (0.5 - 0.5 * (2.0 * PI * i as f64 / n as f64).cos()))
+        .collect();
+    let mut im = vec![0.0; n];
+    fft(&mut re, &mut im);
+    (0..n / 2).map(|k| re[k] * re[k] + im[k] * im[k]).collect()
+}
+
+/// Fraction of the spectrum's energy that lies in `lo..hi` Hz.
+pub fn band_energy(spectrum: &[f64], sample_rate: f64, lo: f64, hi: f64) -> f64 {
+    let bin = sample_rate / (2.0 * spectrum.len() as f64);
+    let total: f64 = spectrum.iter().sum();
+    let band: f64 = spectrum
+        .iter()
+        .enumerate()
+        .filter(|(k, _)| (lo..hi).contains(&(*k as f64 * bin)))
+        .map(|(_, p)| p)
+        .sum();
+    band / total.max(1e-30)
+}
+
+/// Frequency of the strongest bin, in Hz.
+pub fn peak_frequency(spectrum: &[f64], sample_rate: f64) -> f64 {
+    let k = spectrum
+        .iter()
+        .enumerate()
+        .skip(1)
+        .max_by(|a, b| a.1.total_cmp(b.1))
+        .map_or(0, |(k, _)| k);
+    k as f64 * sample_rate / (2.0 * spectrum.len() as f64)
+}
+
+/// In-place iterative radix-2 FFT.
+pub fn fft(re: &mut [f64], im: &mut [f64]) {
+    let n = re.len();
+    assert!(n.is_power_of_two());
+    let mut j = 0;
+    for i in 1..n {
+        let mut bit = n >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j {
+            re.swap(i, j);
+            im.swap(i, j);
+        }
+    }
+    let mut len = 2;
+    while len <= n {
+        let angle = -2.0 * PI / len as f64;
+        for start in (0..n).step_by(len) {
+            for k in 0..len / 2 {
+                let (s, c) = (angle * k as f64).sin_cos();
+                let a = start + k;
+                let b = a + len / 2;
+                let tr = re[b] * c - im[b] * s;
+                let ti = re[b] * s + im[b] * c;
+                re[b] = re[a] - tr;
+                im[b] = im[a] - ti;
+                re[a] += tr;
+                im[a] += ti;
+            }
+        }
+        len <<= 1;
+    }
+}
+
+/// A mono or interleaved 32-bit float WAV file.
+pub fn wav_bytes(data: &[f32], channels: u16, sample_rate: u32) -> Vec<u8> {
+    let mut fmt = Vec::new();
+    fmt.extend_from_slice(&3u16.to_le_bytes());
+    fmt.extend_from_slice(&channels.to_le_bytes());
+    fmt.extend_from_slice(&sample_rate.to_le_bytes());
+    fmt.extend_from_slice(&(sample_rate * 4 * u32::from(channels)).to_le_bytes());
+    fmt.extend_from_slice(&(4 * channels).to_le_bytes());
+    fmt.extend_from_slice(&32u16.to_le_bytes());
+    let samples: Vec<u8> = data.iter().flat_map(|v| v.to_le_bytes()).collect();
+
+    let mut body = b"WAVE".to_vec();
+    for (id, chunk) in [(b"fmt ", &fmt), (b"data", &samples)] {
+        body.extend_from_slice(id);
+        body.extend_from_slice(&(chunk.len() as u32).to_le_bytes());
+        body.extend_from_slice(chunk);
+    }
+    let mut out = b"RIFF".to_vec();
+    out.extend_from_slice(&(body.len() as u32).to_le_bytes());
+    out.extend_from_slice(&body);
+    out
+}
+
+/// A fresh, empty directory under the system temp dir, unique to this
+/// process and `name`.
+pub fn temp_dir(name: &str) -> std::path::PathBuf {
+    let dir = std::env::temp_dir().join(format!("samplerust-{}-{name}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
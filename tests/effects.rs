@@ -0,0 +1,519 @@
+mod common;
+
+use samplerust::effect::SENDS;
+use samplerust::{
+    Delay, DelayTime, Effect, EffectChain, EffectSlot, Engine, EngineConfig, EngineError,
+    ParamInfo, Reverb, Sample, TriggerParams,
+};
+
+const RATE: u32 = 48_000;
+
+/// Interleaved stereo silence with a unit impulse on the first frame of
+/// each channel in `on`.
+fn impulse(frames: usize, on: &[usize]) -> Vec<f32> {
+    let mut buffer = vec![0.0; 2 * frames];
+    for &c in on {
+        buffer[c] = 1.0;
+    }
+    buffer
+}
+
+fn channel(buffer: &[f32], c: usize) -> Vec<f32> {
+    buffer.iter().skip(c).step_by(2).copied().collect()
+}
+
+fn prepared<E: Effect>(mut effect: E) -> E {
+    effect.prepare(RATE, 1024);
+    effect
+}
+
+#[test]
+fn delay_echoes_on_time_and_follows_the_tempo() {
+    let mut delay = Delay::new(DelayTime::Seconds(0.25));
+    delay.feedback = 0.5;
+    let mut delay = prepared(delay);
+    let mut buffer = impulse(30_000, &[0, 1]);
+    delay.process(&mut buffer);
+    let left = channel(&buffer, 0);
+    assert!(left[..12_000].iter().all(|&v| v == 0.0));
+    assert_eq!(left[12_000], 1.0);
+    assert_eq!(channel(&buffer, 1), left);
+    // The repeat comes round again, a little smeared by the feedback
+    // filters.
+    let second = left[12_001..].iter().map(|v| v.abs()).enumerate();
+    let (at, peak) = second.fold((0, 0.0), |a, (i, v)| if v > a.1 { (i, v) } else { a });
+    assert!((11_998..=12_001).contains(&at), "{at}");
+    assert!((0.3..0.5).contains(&peak), "{peak}");
+
+    // Half a beat is a quarter of a second at 120 bpm and half a second at
+    // 60.
+    let mut delay = prepared(Delay::new(DelayTime::Beats(0.5)));
+    let echo_at = |delay: &mut Delay| {
+        delay.reset();
+        let mut buffer = impulse(30_000, &[0]);
+        delay.process(&mut buffer);
+        buffer.iter().position(|&v| v == 1.0).unwrap() / 2
+    };
+    assert_eq!(echo_at(&mut delay), 12_000);
+    delay.set_tempo(60.0);
+    assert_eq!(echo_at(&mut delay), 24_000);
+}
+
+#[test]
+fn ping_pong_echoes_bounce_between_sides() {
+    let mut delay = Delay::new(DelayTime::Seconds(0.1));
+    delay.ping_pong = true;
+    delay.feedback = 0.5;
+    let mut delay = prepared(delay);
+    let mut buffer = impulse(12_000, &[0]);
+    delay.process(&mut buffer);
+    let (left, right) = (channel(&buffer, 0), channel(&buffer, 1));
+    let energy = |x: &[f32]| x.iter().map(|v| v * v).sum::<f32>();
+    assert_eq!(left[4_800], 1.0);
+    assert_eq!(energy(&right[..9_000]), 0.0);
+    assert!(energy(&right[9_000..]) > 0.1);
+    assert!(energy(&left[9_000..]) < 1e-6);
+}
+
+#[test]
+fn delay_feedback_darkens_each_repeat() {
+    // Level of the second echo of a tone burst relative to the first.
+    let decay = |freq| {
+        let mut delay = Delay::new(DelayTime::Seconds(0.1));
+        delay.feedback = 0.8;
+        delay.high_cut = 1_000.0;
+        let mut delay = prepared(delay);
+        let burst = common::sine(freq, f64::from(RATE), 2_400);
+        let mut buffer = vec![0.0; 2 * 14_400];
+        for (i, v) in burst.iter().enumerate() {
+            buffer[2 * i] = *v;
+        }
+        delay.process(&mut buffer);
+        let left = channel(&buffer, 0);
+        common::rms(&left[9_600..12_000]) / common::rms(&left[4_800..7_200])
+    };
+    let low = decay(200.0);
+    assert!((low - 0.8).abs() < 0.05, "{low}");
+    let high = decay(8_000.0);
+    assert!(high < 0.8 / 20.0, "{high}");
+}
+
+#[test]
+fn reverb_tails_grow_with_the_room() {
+    let tail = |room_size, width| {
+        let mut reverb = Reverb::new();
+        reverb.room_size = room_size;
+        reverb.width = width;
+        let mut reverb = prepared(reverb);
+        let mut buffer = impulse(2 * RATE as usize, &[0, 1]);
+        reverb.process(&mut buffer);
+        buffer
+    };
+    let late = |buffer: &[f32]| common::rms(&buffer[RATE as usize..]);
+    let early = |buffer: &[f32]| common::rms(&buffer[..RATE as usize / 2]);
+
+    let small = tail(0.3, 1.0);
+    let large = tail(0.9, 1.0);
+    assert!(late(&small) < early(&small) / 100.0);
+    assert!(late(&large) > 10.0 * late(&small));
+    assert!(small.iter().all(|v| v.is_finite() && v.abs() < 1.0));
+
+    // Full width decorrelates the channels; none folds them together.
+    let wide = tail(0.5, 1.0);
+    assert_ne!(channel(&wide, 0), channel(&wide, 1));
+    let narrow = tail(0.5, 0.0);
+    assert_eq!(channel(&narrow, 0), channel(&narrow, 1));
+
+    // Reset drops the tail.
+    let mut reverb = prepared(Reverb::new());
+    reverb.process(&mut impulse(1_000, &[0]));
+    reverb.reset();
+    let mut buffer = vec![0.0; 20_000];
+    reverb.process(&mut buffer);
+    assert!(buffer.iter().all(|&v| v == 0.0));
+}
+
+#[test]
+fn built_in_effects_describe_their_parameters() {
+    let mut reverb = Reverb::new();
+    let names: Vec<_> = reverb.params().iter().map(|p| p.name).collect();
+    assert_eq!(names, ["room_size", "damping", "width", "mix"]);
+    for (i, info) in reverb.params().to_vec().into_iter().enumerate() {
+        assert_eq!(reverb.param(i), info.default, "{}", info.name);
+    }
+    reverb.set_param(0, 0.9);
+    assert_eq!(reverb.room_size, 0.9);
+    // Values are kept in range, and indices past the last do nothing.
+    reverb.set_param(2, 3.0);
+    assert_eq!(reverb.param(2), 1.0);
+    reverb.set_param(4, 0.0);
+    assert_eq!(reverb.param(4), 0.0);
+
+    let mut delay = prepared(Delay::new(DelayTime::Seconds(0.1)));
+    let high_cut = delay.params()[2];
+    assert_eq!((high_cut.name, high_cut.unit), ("high_cut", "Hz"));
+    assert_eq!(delay.param(0), delay.feedback);
+    // The feedback filters follow their cutoffs straight away.
+    delay.set_param(0, 0.8);
+    delay.set_param(2, 1_000.0);
+    assert_eq!(delay.high_cut, 1_000.0);
+    let burst = common::sine(8_000.0, f64::from(RATE), 2_400);
+    let mut buffer = vec![0.0; 2 * 14_400];
+    for (i, v) in burst.iter().enumerate() {
+        buffer[2 * i] = *v;
+    }
+    delay.process(&mut buffer);
+    let left = channel(&buffer, 0);
+    assert!(common::rms(&left[9_600..12_000]) < common::rms(&left[4_800..7_200]) / 20.0);
+}
+
+/// An effect that scales what it is given.
+struct Gain(f32);
+
+const GAIN: [ParamInfo; 1] = [ParamInfo {
+    name: "gain",
+    unit: "",
+    min: 0.0,
+    max: 4.0,
+    default: 1.0,
+}];
+
+impl Effect for Gain {
+    fn prepare(&mut self, _sample_rate: u32, _max_block: usize) {}
+
+    fn process(&mut self, buffer: &mut [f32]) {
+        buffer.iter_mut().for_each(|v| *v *= self.0);
+    }
+
+    fn reset(&mut self) {}
+
+    fn params(&self) -> &[ParamInfo] {
+        &GAIN
+    }
+
+    fn param(&self, index: usize) -> f32 {
+        if index == 0 {
+            self.0
+        } else {
+            0.0
+        }
+    }
+
+    fn set_param(&mut self, index: usize, value: f32) {
+        if index == 0 {
+            self.0 = GAIN[0].clamp(value);
+        }
+    }
+}
+
+#[test]
+fn chains_run_in_series_with_their_parameters_in_order() {
+    let mut chain = prepared(EffectChain::new().with(Gain(2.0)).with(Gain(3.0)));
+    assert_eq!(chain.len(), 2);
+    let mut buffer = [1.0, -1.0];
+    chain.process(&mut buffer);
+    assert_eq!(buffer, [6.0, -6.0]);
+
+    assert_eq!(chain.params(), [GAIN[0], GAIN[0]]);
+    chain.set_param(1, 0.5);
+    assert_eq!((chain.param(0), chain.param(1)), (2.0, 0.5));
+    chain.set_param(0, 9.0);
+    assert_eq!(chain.param(0), 4.0);
+    let mut buffer = [1.0, -1.0];
+    chain.process(&mut buffer);
+    assert_eq!(buffer, [2.0, -2.0]);
+
+    let mut chain = EffectChain::new();
+    assert!(chain.is_empty());
+    chain.push(Box::new(Delay::new(DelayTime::Seconds(0.1))));
+    chain.push(Box::new(Reverb::new()));
+    assert_eq!(chain.params().len(), 8);
+    assert_eq!(chain.params()[4].name, "room_size");
+    chain.set_param(4, 0.2);
+    assert_eq!(chain.param(4), 0.2);
+    assert_eq!(chain.param(8), 0.0);
+}
+
+fn dc() -> Sample {
+    Sample::from_interleaved(vec![1.0; 10_000], 1, RATE).unwrap()
+}
+
+#[test]
+fn voices_feed_sends_that_return_to_the_output() {
+    for channels in [1, 2, 4] {
+        let (mut engine, mut ctl) = Engine::new(EngineConfig {
+            channels,
+            max_block: 64,
+            ..EngineConfig::default()
+        });
+        let id = ctl.add_sample(dc()).unwrap();
+        ctl.set_send(1, Box::new(Gain(2.0))).unwrap();
+        let mut sends = [0.0; SENDS];
+        sends[1] = 0.25;
+        ctl.trigger(
+            id,
+            TriggerParams {
+                sends,
+                ..TriggerParams::default()
+            },
+        )
+        .unwrap();
+        // A voice on another bus still returns on the first.
+        ctl.trigger(
+            id,
+            TriggerParams {
+                sends,
+                bus: Some(1),
+                ..TriggerParams::default()
+            },
+        )
+        .unwrap();
+        ctl.trigger(id, TriggerParams::default()).unwrap();
+
+        let mut out = vec![0.0; 200 * channels];
+        engine.render(&mut out);
+        let want: &[f32] = match channels {
+            1 => &[4.0],
+            2 => &[4.0, 4.0],
+            _ => &[3.0; 4],
+        };
+        for frame in out.chunks_exact(channels) {
+            assert_eq!(frame, want, "{channels}");
+        }
+
+        // Without an effect nothing returns.
+        ctl.clear_send(1).unwrap();
+        engine.render(&mut out);
+        let want: &[f32] = match channels {
+            1 => &[3.0],
+            2 => &[3.0, 3.0],
+            _ => &[2.0, 2.0, 3.0, 3.0],
+        };
+        assert_eq!(&out[out.len() - channels..], want, "{channels}");
+    }
+}
+
+#[test]
+fn voice_inserts_play_each_voice_onto_its_outputs() {
+    let (mut engine, mut ctl) = Engine::new(EngineConfig {
+        channels: 4,
+        max_block: 64,
+        ..EngineConfig::default()
+    });
+    let id = ctl.add_sample(dc()).unwrap();
+    ctl.set_voice_inserts(|| Box::new(Gain(2.0))).unwrap();
+    ctl.set_send(0, Box::new(Gain(1.0))).unwrap();
+    let mut sends = [0.0; SENDS];
+    sends[0] = 0.25;
+    // Sends take the voice after its insert.
+    ctl.trigger(
+        id,
+        TriggerParams {
+            sends,
+            bus: Some(1),
+            ..TriggerParams::default()
+        },
+    )
+    .unwrap();
+    let mut out = vec![0.0; 200 * 4];
+    engine.render(&mut out);
+    for frame in out.chunks_exact(4) {
+        assert_eq!(frame, [0.5, 0.5, 2.0, 2.0]);
+    }
+
+    ctl.set_param(EffectSlot::Voice, 0, 3.0).unwrap();
+    ctl.trigger(id, TriggerParams::default()).unwrap();
+    engine.render(&mut out);
+    assert_eq!(&out[out.len() - 4..], [3.75, 3.75, 6.0, 6.0]);
+
+    ctl.clear_voice_inserts().unwrap();
+    engine.render(&mut out);
+    assert_eq!(&out[out.len() - 4..], [1.25, 1.25, 2.0, 2.0]);
+
+    // A mono output takes the insert's two sides together.
+    let (mut engine, mut ctl) = Engine::new(EngineConfig {
+        channels: 1,
+        ..EngineConfig::default()
+    });
+    let id = ctl.add_sample(dc()).unwrap();
+    ctl.set_voice_inserts(|| Box::new(Gain(2.0))).unwrap();
+    ctl.trigger(id, TriggerParams::default()).unwrap();
+    let mut out = vec![0.0; 100];
+    engine.render(&mut out);
+    assert!(out.iter().all(|&v| v == 2.0));
+}
+
+#[test]
+fn stolen_voices_fade_out_through_their_inserts() {
+    const RATE: u32 = 1_000;
+    let (mut engine, mut ctl) = Engine::new(EngineConfig {
+        sample_rate: RATE,
+        voices: 1,
+        steal_fade: 0.05,
+        ..EngineConfig::default()
+    });
+    let click = {
+        let mut data = vec![0.0; 100];
+        data[0] = 1.0;
+        Sample::from_interleaved(data, 1, RATE).unwrap()
+    };
+    let id = ctl.add_sample(click).unwrap();
+    ctl.set_voice_inserts(|| {
+        let mut delay = Delay::new(DelayTime::Seconds(0.01));
+        delay.feedback = 0.0;
+        Box::new(delay)
+    })
+    .unwrap();
+    ctl.trigger(id, TriggerParams::default()).unwrap();
+    let mut out = vec![0.0; 2 * 5];
+    engine.render(&mut out);
+    ctl.trigger(id, TriggerParams::default()).unwrap();
+    let mut rest = vec![0.0; 2 * 25];
+    engine.render(&mut rest);
+    out.extend(rest);
+
+    // The first click echoes from the tail it was stolen into, while the
+    // second plays through a fresh delay.
+    let left = channel(&out, 0);
+    let echoes: Vec<_> = (0..left.len()).filter(|&i| left[i] != 0.0).collect();
+    assert_eq!(echoes, [10, 15]);
+    assert_eq!(left[10], 1.0);
+    assert_eq!(left[15], 1.0);
+}
+
+#[test]
+fn the_master_effect_processes_the_first_two_channels() {
+    for channels in [1, 2, 4] {
+        let (mut engine, mut ctl) = Engine::new(EngineConfig {
+            channels,
+            ..EngineConfig::default()
+        });
+        let id = ctl.add_sample(dc()).unwrap();
+        ctl.set_master(Box::new(Gain(2.0))).unwrap();
+        ctl.trigger(id, TriggerParams::default()).unwrap();
+        let mut out = vec![0.0; 100 * channels];
+        engine.render(&mut out);
+        let want: &[f32] = match channels {
+            1 => &[2.0],
+            2 => &[2.0, 2.0],
+            _ => &[2.0, 2.0, 1.0, 1.0],
+        };
+        for frame in out.chunks_exact(channels) {
+            assert_eq!(frame, want, "{channels}");
+        }
+
+        ctl.set_param(EffectSlot::Master, 0, 0.5).unwrap();
+        engine.render(&mut out);
+        assert_eq!(out[0], 0.5, "{channels}");
+        ctl.clear_master().unwrap();
+        engine.render(&mut out);
+        assert_eq!(out[0], 1.0, "{channels}");
+    }
+}
+
+#[test]
+fn output_buses_run_their_own_effects() {
+    // Three buses over five channels, the last a single channel.
+    let (mut engine, mut ctl) = Engine::new(EngineConfig {
+        channels: 5,
+        max_block: 64,
+        ..EngineConfig::default()
+    });
+    let id = ctl.add_sample(dc()).unwrap();
+    ctl.set_bus(0, Box::new(Gain(0.5))).unwrap();
+    ctl.set_bus(1, Box::new(Gain(2.0))).unwrap();
+    ctl.set_bus(2, Box::new(Gain(3.0))).unwrap();
+    ctl.set_send(0, Box::new(Gain(1.0))).unwrap();
+    let mut sends = [0.0; SENDS];
+    sends[0] = 1.0;
+    let on = |bus| TriggerParams {
+        bus,
+        ..TriggerParams::default()
+    };
+    ctl.trigger(
+        id,
+        TriggerParams {
+            sends,
+            ..on(Some(1))
+        },
+    )
+    .unwrap();
+    ctl.trigger(id, on(Some(2))).unwrap();
+    ctl.trigger(id, on(None)).unwrap();
+
+    // Each bus processes what plays on it, the spread voice included. The
+    // send returns after the first bus's effect has run.
+    let mut out = vec![0.0; 100 * 5];
+    engine.render(&mut out);
+    for frame in out.chunks_exact(5) {
+        assert_eq!(frame, [1.5, 1.5, 4.0, 4.0, 6.0]);
+    }
+
+    ctl.set_param(EffectSlot::Bus(2), 0, 1.0).unwrap();
+    ctl.set_param(EffectSlot::Bus(7), 0, 1.0).unwrap();
+    ctl.clear_bus(1).unwrap();
+    engine.render(&mut out);
+    assert_eq!(out[..5], [1.5, 1.5, 2.0, 2.0, 2.0]);
+    assert_eq!(ctl.collect_garbage(), 1);
+}
+
+#[test]
+fn send_effects_follow_the_engine_tempo() {
+    let (mut engine, mut ctl) = Engine::new(EngineConfig::default());
+    let click = {
+        let mut data = vec![0.0; 100];
+        data[0] = 1.0;
+        Sample::from_interleaved(data, 1, RATE).unwrap()
+    };
+    let id = ctl.add_sample(click).unwrap();
+    ctl.set_tempo(240.0).unwrap();
+    ctl.set_send(0, Box::new(Delay::new(DelayTime::Beats(1.0))))
+        .unwrap();
+    let mut sends = [0.0; SENDS];
+    sends[0] = 1.0;
+    ctl.trigger(
+        id,
+        TriggerParams {
+            sends,
+            ..TriggerParams::default()
+        },
+    )
+    .unwrap();
+    let mut out = vec![0.0; 2 * 13_000];
+    engine.render(&mut out);
+    // A beat at 240 bpm is a quarter of a second.
+    assert_eq!(out[..2], [1.0, 1.0]);
+    assert!(out[2..24_000].iter().all(|&v| v == 0.0));
+    assert_eq!(out[24_000..24_002], [1.0, 1.0]);
+}
+
+#[test]
+fn send_effects_are_swapped_and_returned() {
+    let (mut engine, mut ctl) = Engine::new(EngineConfig::default());
+    let mut effects = 0;
+    while ctl.set_send(0, Box::new(Gain(1.0))).is_ok() {
+        effects += 1;
+    }
+    assert_eq!(
+        ctl.set_send(1, Box::new(Reverb::new())),
+        Err(EngineError::QueueFull)
+    );
+    let mut out = vec![0.0; 64];
+    engine.render(&mut out);
+    // All but the last are retired on the way in.
+    assert_eq!(ctl.collect_garbage(), effects - 1);
+    ctl.clear_send(0).unwrap();
+    engine.render(&mut out);
+    assert_eq!(ctl.collect_garbage(), 1);
+
+    // Voice inserts go back as one set.
+    ctl.set_voice_inserts(|| Box::new(Reverb::new())).unwrap();
+    ctl.set_voice_inserts(|| Box::new(Gain(1.0))).unwrap();
+    ctl.set_master(Box::new(Gain(1.0))).unwrap();
+    ctl.set_bus(0, Box::new(Gain(1.0))).unwrap();
+    ctl.clear_voice_inserts().unwrap();
+    ctl.clear_master().unwrap();
+    ctl.clear_bus(0).unwrap();
+    engine.render(&mut out);
+    assert_eq!(ctl.collect_garbage(), 4);
+}
@@ -0,0 +1,127 @@
+use std::sync::Arc;
+
+use samplerust::{Engine, EngineConfig, EngineError, Sample, TriggerParams};
+
+fn mono_config() -> EngineConfig {
+    EngineConfig {
+        channels: 1,
+        voices: 4,
+        max_samples: 2,
+        ..EngineConfig::default()
+    }
+}
+
+fn ramp(frames: usize) -> Sample {
+    Sample::from_interleaved((1..=frames).map(|v| v as f32).collect(), 1, 48_000).unwrap()
+}
+
+#[test]
+fn triggered_voice_plays_sample_then_stops() {
+    let (mut engine, mut ctl) = Engine::new(mono_config());
+    let id = ctl.add_sample(ramp(5)).unwrap();
+    ctl.trigger(id, TriggerParams::default()).unwrap();
+
+    let mut out = [0.0; 4];
+    engine.render(&mut out);
+    assert_eq!(out, [1.0, 2.0, 3.0, 4.0]);
+    assert_eq!(engine.active_voices(), 1);
+    engine.render(&mut out);
+    assert_eq!(out, [5.0, 0.0, 0.0, 0.0]);
+    assert_eq!(engine.active_voices(), 0);
+}
+
+#[test]
+fn voices_mix_and_respond_to_stop_and_seek() {
+    let (mut engine, mut ctl) = Engine::new(mono_config());
+    let id = ctl.add_sample(ramp(100)).unwrap();
+    let a = ctl
+        .trigger(
+            id,
+            TriggerParams {
+                gain: 0.5,
+                ..TriggerParams::default()
+            },
+        )
+        .unwrap();
+    let b = ctl
+        .trigger(
+            id,
+            TriggerParams {
+                start: 10,
+                ..TriggerParams::default()
+            },
+        )
+        .unwrap();
+
+    let mut out = [0.0; 2];
+    engine.render(&mut out);
+    assert_eq!(out, [0.5 + 11.0, 1.0 + 12.0]);
+
+    ctl.seek(a, 50).unwrap();
+    ctl.stop(b).unwrap();
+    engine.render(&mut out);
+    assert_eq!(out, [25.5, 26.0]);
+
+    ctl.stop_all().unwrap();
+    engine.render(&mut out);
+    assert_eq!(out, [0.0, 0.0]);
+    assert_eq!(engine.active_voices(), 0);
+}
+
+#[test]
+fn mono_sample_fans_out_to_stereo() {
+    let (mut engine, mut ctl) = Engine::new(EngineConfig {
+        channels: 2,
+        ..mono_config()
+    });
+    let id = ctl.add_sample(ramp(2)).unwrap();
+    ctl.trigger(id, TriggerParams::default()).unwrap();
+    let mut out = [9.0; 6];
+    engine.render(&mut out);
+    assert_eq!(out, [1.0, 1.0, 2.0, 2.0, 0.0, 0.0]);
+}
+
+#[test]
+fn removed_samples_come_back_to_the_controller() {
+    let (mut engine, mut ctl) = Engine::new(mono_config());
+    let sample = Arc::new(ramp(10));
+    let id = ctl.add_sample(sample.clone()).unwrap();
+    ctl.add_sample(ramp(1)).unwrap();
+    assert_eq!(ctl.add_sample(ramp(1)), Err(EngineError::NoFreeSlot));
+
+    ctl.trigger(id, TriggerParams::default()).unwrap();
+    let mut out = [0.0; 4];
+    engine.render(&mut out);
+    assert_eq!(Arc::strong_count(&sample), 2);
+
+    ctl.remove_sample(id).unwrap();
+    assert_eq!(
+        ctl.trigger(id, TriggerParams::default()),
+        Err(EngineError::UnknownSample(id))
+    );
+    engine.render(&mut out);
+    assert_eq!(out, [0.0; 4], "voices on a removed sample stop");
+    // The engine handed its reference back rather than dropping it.
+    assert_eq!(Arc::strong_count(&sample), 2);
+    assert_eq!(ctl.collect_garbage(), 1);
+    assert_eq!(Arc::strong_count(&sample), 1);
+
+    // The slot is reusable once reclaimed.
+    assert_eq!(ctl.add_sample(ramp(1)), Ok(id));
+}
+
+#[test]
+fn full_queue_is_reported() {
+    let (_engine, mut ctl) = Engine::new(EngineConfig {
+        queue_capacity: 4,
+        ..mono_config()
+    });
+    let id = ctl.add_sample(ramp(1)).unwrap();
+    for _ in 0..3 {
+        ctl.trigger(id, TriggerParams::default()).unwrap();
+    }
+    assert_eq!(
+        ctl.trigger(id, TriggerParams::default()),
+        Err(EngineError::QueueFull)
+    );
+}
@@ -0,0 +1,149 @@
+use samplerust::{
+    Adsr, Controller, Curve, Engine, EngineConfig, Envelope, Sample, Stage, TriggerParams,
+};
+
+/// 1 kHz makes every millisecond one frame.
+const RATE: f32 = 1000.0;
+
+fn run(env: &mut Envelope, frames: usize) -> Vec<f32> {
+    let mut out = vec![0.0; frames];
+    env.process(RATE, &mut out);
+    out
+}
+
+fn close(a: &[f32], b: &[f32]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).all(|(x, y)| (x - y).abs() < 1e-6)
+}
+
+#[test]
+fn stages_land_on_exact_frames() {
+    let mut env = Envelope::default();
+    env.trigger(Adsr {
+        hold: 0.002,
+        ..Adsr::new(0.004, 0.002, 0.5, 0.004)
+    });
+
+    let out = run(&mut env, 10);
+    let expected = [0.25, 0.5, 0.75, 1.0, 1.0, 1.0, 0.75, 0.5, 0.5, 0.5];
+    assert!(close(&out, &expected), "{out:?}");
+    assert_eq!(env.stage(), Stage::Sustain);
+
+    env.release();
+    let out = run(&mut env, 5);
+    assert!(close(&out, &[0.375, 0.25, 0.125, 0.0, 0.0]), "{out:?}");
+    assert_eq!(env.stage(), Stage::Idle);
+    assert!(!env.is_active());
+}
+
+#[test]
+fn zero_length_stages_are_skipped() {
+    let mut env = Envelope::default();
+    env.trigger(Adsr::new(0.0, 0.0, 0.7, 0.0));
+    assert!(close(&run(&mut env, 2), &[0.7, 0.7]));
+    env.release();
+    assert_eq!(run(&mut env, 1), [0.0]);
+    assert!(!env.is_active());
+}
+
+#[test]
+fn curves_shape_each_stage() {
+    let exp = Adsr {
+        attack_curve: Curve::Exponential(5.0),
+        ..Adsr::new(0.1, 0.0, 1.0, 0.0)
+    };
+    let mut env = Envelope::default();
+    env.trigger(exp);
+    let out = run(&mut env, 100);
+    // A positive exponent front-loads the rise.
+    assert!(out[9] > 0.3, "{}", out[9]);
+    assert!(out.windows(2).all(|w| w[1] >= w[0]));
+    assert!((out[99] - 1.0).abs() < 1e-6);
+
+    let mut env = Envelope::default();
+    env.trigger(Adsr {
+        attack_curve: Curve::Exponential(-5.0),
+        ..exp
+    });
+    assert!(run(&mut env, 10)[9] < 0.02);
+
+    let mut env = Envelope::default();
+    env.trigger(Adsr {
+        release_curve: Curve::Custom(|t| t * t),
+        ..Adsr::new(0.0, 0.0, 1.0, 0.004)
+    });
+    run(&mut env, 1);
+    env.release();
+    let out = run(&mut env, 4);
+    assert!(close(&out, &[0.9375, 0.75, 0.4375, 0.0]), "{out:?}");
+}
+
+#[test]
+fn release_mid_attack_starts_from_current_level() {
+    let mut env = Envelope::default();
+    env.trigger(Adsr::new(0.010, 0.0, 1.0, 0.004));
+    let out = run(&mut env, 4);
+    assert!((out[3] - 0.4).abs() < 1e-6);
+
+    env.release();
+    let out = run(&mut env, 4);
+    assert!(close(&out, &[0.3, 0.2, 0.1, 0.0]), "{out:?}");
+}
+
+fn dc(frames: usize) -> Sample {
+    Sample::from_interleaved(vec![1.0; frames], 1, 1000).unwrap()
+}
+
+fn mono_engine() -> (Engine, Controller) {
+    Engine::new(EngineConfig {
+        sample_rate: 1000,
+        channels: 1,
+        voices: 2,
+        ..EngineConfig::default()
+    })
+}
+
+#[test]
+fn voice_frees_itself_when_release_ends() {
+    let (mut engine, mut ctl) = mono_engine();
+    let id = ctl.add_sample(dc(1000)).unwrap();
+    let voice = ctl
+        .trigger(
+            id,
+            TriggerParams {
+                gain: 0.5,
+                envelope: Adsr::new(0.002, 0.0, 1.0, 0.003),
+                ..TriggerParams::default()
+            },
+        )
+        .unwrap();
+
+    let mut out = [0.0; 4];
+    engine.render(&mut out);
+    assert!(close(&out, &[0.25, 0.5, 0.5, 0.5]), "{out:?}");
+
+    ctl.release(voice).unwrap();
+    let mut out = [0.0; 5];
+    engine.render(&mut out);
+    let third = 0.5 / 3.0;
+    assert!(close(&out, &[2.0 * third, third, 0.0, 0.0, 0.0]), "{out:?}");
+    assert_eq!(engine.active_voices(), 0);
+}
+
+#[test]
+fn zero_sustain_ends_the_voice_without_note_off() {
+    let (mut engine, mut ctl) = mono_engine();
+    let id = ctl.add_sample(dc(1000)).unwrap();
+    ctl.trigger(
+        id,
+        TriggerParams {
+            envelope: Adsr::new(0.0, 0.004, 0.0, 0.1),
+            ..TriggerParams::default()
+        },
+    )
+    .unwrap();
+
+    let mut out = [0.0; 6];
+    engine.render(&mut out);
+    assert!(close(&out, &[0.75, 0.5, 0.25, 0.0, 0.0, 0.0]), "{out:?}");
+    assert_eq!(engine.active_voices(), 0);
+}
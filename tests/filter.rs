@@ -0,0 +1,218 @@
+use std::f64::consts::PI;
+
+use samplerust::{
+    Adsr, Engine, EngineConfig, FilterMode, FilterParams, Sample, Svf, SvfCoefficients,
+    TriggerParams,
+};
+
+const RATE: f64 = 48_000.0;
+
+const MODES: [FilterMode; 5] = [
+    FilterMode::LowPass,
+    FilterMode::HighPass,
+    FilterMode::BandPass,
+    FilterMode::Notch,
+    FilterMode::Peak,
+];
+
+/// Magnitude of the response `x` at `freq` Hz: a single DFT bin, which for
+/// an impulse response is the filter's gain at that frequency.
+fn magnitude(x: &[f32], freq: f64) -> f64 {
+    let w = 2.0 * PI * freq / RATE;
+    let (re, im) = x.iter().enumerate().fold((0.0, 0.0), |(re, im), (n, &v)| {
+        let (s, c) = (w * n as f64).sin_cos();
+        (re + f64::from(v) * c, im - f64::from(v) * s)
+    });
+    re.hypot(im)
+}
+
+/// The frequency between 100 Hz and 12 kHz, in 0.25% steps, where `x`
+/// responds most.
+fn peak(x: &[f32]) -> f64 {
+    (0..2_000)
+        .map(|i| 100.0 * 1.0025f64.powi(i))
+        .take_while(|&f| f < 12_000.0)
+        .max_by(|&a, &b| magnitude(x, a).total_cmp(&magnitude(x, b)))
+        .unwrap()
+}
+
+/// The analog two-pole prototype's gain at `freq`, frequency-warped as the
+/// bilinear transform warps it.
+fn analog(mode: FilterMode, freq: f64, cutoff: f64, q: f64) -> f64 {
+    let w = (PI * freq / RATE).tan() / (PI * cutoff / RATE).tan();
+    // H(s) = N(s) / (s² + s/Q + 1) at s = jw.
+    let den = (1.0 - w * w).hypot(w / q);
+    let num = match mode {
+        FilterMode::LowPass => 1.0,
+        FilterMode::HighPass => w * w,
+        FilterMode::BandPass => w / q,
+        FilterMode::Notch => (1.0 - w * w).abs(),
+        FilterMode::Peak => 1.0 + w * w,
+    };
+    num / den
+}
+
+fn impulse_response(mode: FilterMode, cutoff: f32, q: f32, frames: usize) -> Vec<f32> {
+    let coefficients = SvfCoefficients::new(cutoff, q, RATE as f32);
+    let mut svf = Svf::default();
+    (0..frames)
+        .map(|i| svf.process(if i == 0 { 1.0 } else { 0.0 }, &coefficients, mode))
+        .collect()
+}
+
+#[test]
+fn every_mode_matches_the_analog_prototype() {
+    for (cutoff, q) in [(1_000.0, 0.707), (250.0, 4.0), (9_000.0, 1.5)] {
+        for mode in MODES {
+            let x = impulse_response(mode, cutoff as f32, q as f32, 48_000);
+            for freq in [50.0, 200.0, 900.0, 1_000.0, 1_100.0, 4_000.0, 15_000.0] {
+                let want = analog(mode, freq, cutoff, q);
+                let got = magnitude(&x, freq);
+                if want < 1e-3 {
+                    assert!(got < 2e-3, "{mode:?} {cutoff} Hz at {freq} Hz: {got}");
+                } else {
+                    let error = 20.0 * (got / want).log10();
+                    assert!(
+                        error.abs() < 0.01,
+                        "{mode:?} {cutoff} Hz at {freq} Hz: {error} dB"
+                    );
+                }
+            }
+        }
+    }
+
+    // The gains at the cutoff named on `FilterMode`.
+    let at = |mode| magnitude(&impulse_response(mode, 1_000.0, 5.0, 48_000), 1_000.0);
+    assert!((at(FilterMode::LowPass) - 5.0).abs() < 1e-3);
+    assert!((at(FilterMode::BandPass) - 1.0).abs() < 1e-3);
+    assert!(at(FilterMode::Notch) < 1e-3);
+    assert!((at(FilterMode::Peak) - 10.0).abs() < 1e-3);
+}
+
+#[test]
+fn per_sample_modulation_stays_stable() {
+    let mut rng = 0x2545_f491_4f6c_dd1du64;
+    let mut random = move || {
+        rng ^= rng << 13;
+        rng ^= rng >> 7;
+        rng ^= rng << 17;
+        (rng >> 40) as f32 / (1u64 << 24) as f32
+    };
+    for mode in MODES {
+        let mut svf = Svf::default();
+        let mut peak = 0.0f32;
+        for _ in 0..200_000 {
+            // Cutoff jumps anywhere in 20 Hz..20 kHz every frame, at high Q.
+            let cutoff = 20.0 * 1_000.0f32.powf(random());
+            let coefficients = SvfCoefficients::new(cutoff, 20.0, RATE as f32);
+            let y = svf.process(2.0 * random() - 1.0, &coefficients, mode);
+            assert!(y.is_finite(), "{mode:?}");
+            peak = peak.max(y.abs());
+        }
+        assert!(peak < 100.0, "{mode:?}: {peak}");
+    }
+
+    // The state holds steady through coefficient changes: a constant input
+    // comes out of a low pass untouched however its cutoff moves.
+    let mut svf = Svf::default();
+    let settle = SvfCoefficients::new(100.0, 0.707, RATE as f32);
+    for _ in 0..48_000 {
+        svf.process(0.5, &settle, FilterMode::LowPass);
+    }
+    for _ in 0..10_000 {
+        let coefficients = SvfCoefficients::new(20.0 + 20_000.0 * random(), 8.0, RATE as f32);
+        let y = svf.process(0.5, &coefficients, FilterMode::LowPass);
+        assert!((y - 0.5).abs() < 1e-5, "{y}");
+    }
+}
+
+/// Plays `sample` once through a band pass at 1 kHz, Q 8, with the given
+/// tracking, and returns the output.
+fn band_pass(
+    sample: &Sample,
+    channels: usize,
+    filter: FilterParams,
+    note: u8,
+    velocity: u8,
+) -> Vec<f32> {
+    let (mut engine, mut ctl) = Engine::new(EngineConfig {
+        channels,
+        ..EngineConfig::default()
+    });
+    let id = ctl.add_sample(sample.clone()).unwrap();
+    let filter = FilterParams {
+        mode: FilterMode::BandPass,
+        cutoff: 1_000.0,
+        resonance: 8.0,
+        ..filter
+    };
+    let params = TriggerParams {
+        note,
+        velocity,
+        filter: Some(filter),
+        ..TriggerParams::default()
+    };
+    ctl.trigger(id, params).unwrap();
+    let mut out = vec![0.0; sample.frames() * channels];
+    engine.render(&mut out);
+    out
+}
+
+fn impulses(at: &[usize], frames: usize) -> Sample {
+    let mut data = vec![0.0; frames];
+    for &i in at {
+        data[i] = 1.0;
+    }
+    Sample::from_interleaved(data, 1, 48_000).unwrap()
+}
+
+#[test]
+fn cutoff_tracks_key_velocity_and_envelope() {
+    let click = impulses(&[0], 4_800);
+    let within = |got: f64, want: f64| (got / want - 1.0).abs() < 0.01;
+
+    let keyed = FilterParams {
+        key_track: 100.0,
+        key_center: 60,
+        ..FilterParams::default()
+    };
+    assert!(within(peak(&band_pass(&click, 1, keyed, 60, 127)), 1_000.0));
+    assert!(within(peak(&band_pass(&click, 1, keyed, 72, 127)), 2_000.0));
+    assert!(within(peak(&band_pass(&click, 1, keyed, 53, 127)), 667.4));
+
+    let velocity = FilterParams {
+        velocity_track: 1_200.0,
+        ..FilterParams::default()
+    };
+    assert!(within(
+        peak(&band_pass(&click, 1, velocity, 60, 0)),
+        1_000.0
+    ));
+    assert!(within(
+        peak(&band_pass(&click, 1, velocity, 60, 127)),
+        2_000.0
+    ));
+
+    // An envelope rising over two seconds moves the cutoff up two octaves
+    // between a click at the start and one at the top, slowly enough that
+    // each rings at about one frequency.
+    let swept = FilterParams {
+        envelope: Adsr::new(2.0, 0.0, 1.0, 0.1),
+        envelope_depth: 2_400.0,
+        ..FilterParams::default()
+    };
+    let out = band_pass(&impulses(&[0, 96_000], 100_800), 1, swept, 60, 127);
+    assert!(within(peak(&out[..4_800]), 1_000.0));
+    assert!(within(peak(&out[96_000..]), 4_000.0));
+}
+
+#[test]
+fn mono_samples_are_filtered_once_for_every_output() {
+    let click = impulses(&[0], 4_800);
+    let mono = band_pass(&click, 1, FilterParams::default(), 60, 127);
+    let stereo = band_pass(&click, 2, FilterParams::default(), 60, 127);
+    for (frame, &v) in stereo.chunks_exact(2).zip(&mono) {
+        assert_eq!(frame, [v, v]);
+    }
+    assert!((magnitude(&mono, 1_000.0) - 1.0).abs() < 1e-3);
+}
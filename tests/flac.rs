@@ -0,0 +1,532 @@
+#![cfg(feature = "flac")]
+
+mod common;
+
+use std::fs;
+use std::num::Wrapping;
+
+use samplerust::codec::{self, flac, FileFormat};
+use samplerust::DecodeError;
+
+#[derive(Clone, Copy, Debug)]
+enum Subframe {
+    Constant,
+    Verbatim,
+    Fixed(usize),
+    Lpc(&'static [i64], u32),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Stereo {
+    Independent,
+    LeftSide,
+    RightSide,
+    MidSide,
+}
+
+/// Encoder settings. Only what the decoder needs to be exercised with.
+#[derive(Clone, Copy, Debug)]
+struct Options {
+    bits: u32,
+    block: usize,
+    subframe: Subframe,
+    stereo: Stereo,
+    /// Coded residual partition order; partitions are escaped when `escape`.
+    partition_order: u32,
+    escape: bool,
+    md5: [u8; 16],
+}
+
+impl Options {
+    fn new(bits: u32) -> Self {
+        Options {
+            bits,
+            block: 1000,
+            subframe: Subframe::Fixed(2),
+            stereo: Stereo::Independent,
+            partition_order: 1,
+            escape: false,
+            md5: [0; 16],
+        }
+    }
+}
+
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    acc: u64,
+    bits: u32,
+}
+
+impl BitWriter {
+    fn put(&mut self, value: u64, n: u32) {
+        for i in (0..n).rev() {
+            self.acc = (self.acc << 1) | ((value >> i) & 1);
+            self.bits += 1;
+            if self.bits == 8 {
+                self.bytes.push(self.acc as u8);
+                self.acc = 0;
+                self.bits = 0;
+            }
+        }
+    }
+
+    fn signed(&mut self, value: i64, n: u32) {
+        self.put(value as u64 & (u64::MAX >> (64 - n)), n);
+    }
+
+    fn unary(&mut self, zeros: u64) {
+        for _ in 0..zeros {
+            self.put(0, 1);
+        }
+        self.put(1, 1);
+    }
+
+    fn align(&mut self) {
+        while self.bits != 0 {
+            self.put(0, 1);
+        }
+    }
+}
+
+fn crc8(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |mut crc, &b| {
+        crc ^= b;
+        for _ in 0..8 {
+            crc = (crc << 1) ^ if crc & 0x80 != 0 { 0x07 } else { 0 };
+        }
+        crc
+    })
+}
+
+fn crc16(data: &[u8]) -> u16 {
+    data.iter().fold(0u16, |mut crc, &b| {
+        crc ^= u16::from(b) << 8;
+        for _ in 0..8 {
+            crc = (crc << 1) ^ if crc & 0x8000 != 0 { 0x8005 } else { 0 };
+        }
+        crc
+    })
+}
+
+/// Encodes planar integer channels as a FLAC file.
+fn encode(channels: &[Vec<i64>], rate: u32, opts: Options) -> Vec<u8> {
+    let frames = channels[0].len();
+    let mut out = b"fLaC".to_vec();
+    let mut w = BitWriter::default();
+    w.put(1, 1); // Last metadata block.
+    w.put(0, 7);
+    w.put(34, 24);
+    w.put(opts.block as u64, 16);
+    w.put(opts.block as u64, 16);
+    w.put(0, 24);
+    w.put(0, 24);
+    w.put(u64::from(rate), 20);
+    w.put(channels.len() as u64 - 1, 3);
+    w.put(u64::from(opts.bits) - 1, 5);
+    w.put(frames as u64, 36);
+    out.extend_from_slice(&w.bytes);
+    out.extend_from_slice(&opts.md5);
+
+    for (n, start) in (0..frames).step_by(opts.block).enumerate() {
+        let end = (start + opts.block).min(frames);
+        let block: Vec<&[i64]> = channels.iter().map(|c| &c[start..end]).collect();
+        out.extend_from_slice(&frame(n as u64, &block, opts));
+    }
+    out
+}
+
+fn frame(number: u64, block: &[&[i64]], opts: Options) -> Vec<u8> {
+    let len = block[0].len();
+    let mut w = BitWriter::default();
+    w.put(0x3ffe, 14);
+    w.put(0, 2);
+    w.put(7, 4); // 16-bit block size at the end of the header.
+    w.put(0, 4); // Rate from STREAMINFO.
+    let assignment = match opts.stereo {
+        Stereo::Independent => block.len() as u64 - 1,
+        Stereo::LeftSide => 8,
+        Stereo::RightSide => 9,
+        Stereo::MidSide => 10,
+    };
+    w.put(assignment, 4);
+    let bits_code = match opts.bits {
+        8 => 1,
+        12 => 2,
+        16 => 4,
+        20 => 5,
+        24 => 6,
+        32 => 7,
+        _ => 0,
+    };
+    w.put(bits_code, 3);
+    w.put(0, 1);
+    assert!(number < 128);
+    w.put(number, 8);
+    w.put(len as u64 - 1, 16);
+    let crc = crc8(&w.bytes);
+    w.put(u64::from(crc), 8);
+
+    let channels: Vec<(Vec<i64>, u32)> = match opts.stereo {
+        Stereo::Independent => block.iter().map(|c| (c.to_vec(), opts.bits)).collect(),
+        _ => {
+            let (l, r) = (block[0], block[1]);
+            let side: Vec<i64> = l.iter().zip(r).map(|(l, r)| l - r).collect();
+            match opts.stereo {
+                Stereo::LeftSide => vec![(l.to_vec(), opts.bits), (side, opts.bits + 1)],
+                Stereo::RightSide => vec![(side, opts.bits + 1), (r.to_vec(), opts.bits)],
+                _ => {
+                    let mid = l.iter().zip(r).map(|(l, r)| (l + r) >> 1).collect();
+                    vec![(mid, opts.bits), (side, opts.bits + 1)]
+                }
+            }
+        }
+    };
+    for (samples, bits) in &channels {
+        subframe(&mut w, samples, *bits, opts);
+    }
+    w.align();
+    let crc = crc16(&w.bytes);
+    w.put(u64::from(crc), 16);
+    w.bytes
+}
+
+fn subframe(w: &mut BitWriter, samples: &[i64], bits: u32, opts: Options) {
+    // Shift out trailing zero bits shared by every sample.
+    let wasted = samples
+        .iter()
+        .fold(0i64, |acc, &s| acc | s)
+        .trailing_zeros()
+        .min(bits - 1);
+    let wasted = if matches!(opts.subframe, Subframe::Constant) {
+        0
+    } else {
+        wasted
+    };
+    let s: Vec<i64> = samples.iter().map(|&x| x >> wasted).collect();
+    let bits = bits - wasted;
+
+    let kind = match opts.subframe {
+        Subframe::Constant => 0,
+        Subframe::Verbatim => 1,
+        Subframe::Fixed(order) => 8 + order as u64,
+        Subframe::Lpc(coefs, _) => 31 + coefs.len() as u64,
+    };
+    w.put(0, 1);
+    w.put(kind, 6);
+    if wasted > 0 {
+        w.put(1, 1);
+        w.unary(u64::from(wasted) - 1);
+    } else {
+        w.put(0, 1);
+    }
+
+    match opts.subframe {
+        Subframe::Constant => w.signed(s[0], bits),
+        Subframe::Verbatim => s.iter().for_each(|&x| w.signed(x, bits)),
+        Subframe::Fixed(order) => {
+            s[..order].iter().for_each(|&x| w.signed(x, bits));
+            // Wrapping, so a runaway prediction can be encoded too.
+            let residual: Vec<i64> = (order..s.len())
+                .map(|i| {
+                    let w = Wrapping;
+                    let p = match order {
+                        0 => w(0),
+                        1 => w(s[i - 1]),
+                        2 => w(2) * w(s[i - 1]) - w(s[i - 2]),
+                        3 => w(3) * w(s[i - 1]) - w(3) * w(s[i - 2]) + w(s[i - 3]),
+                        _ => {
+                            w(4) * w(s[i - 1]) - w(6) * w(s[i - 2]) + w(4) * w(s[i - 3])
+                                - w(s[i - 4])
+                        }
+                    };
+                    (w(s[i]) - p).0
+                })
+                .collect();
+            rice(w, order, &residual, opts);
+        }
+        Subframe::Lpc(coefs, shift) => {
+            let order = coefs.len();
+            s[..order].iter().for_each(|&x| w.signed(x, bits));
+            w.put(14, 4); // 15-bit coefficients.
+            w.signed(i64::from(shift), 5);
+            coefs.iter().for_each(|&c| w.signed(c, 15));
+            let residual: Vec<i64> = (order..s.len())
+                .map(|i| {
+                    let p: i64 = (0..order).map(|j| coefs[j] * s[i - 1 - j]).sum();
+                    s[i] - (p >> shift)
+                })
+                .collect();
+            rice(w, order, &residual, opts);
+        }
+    }
+}
+
+/// Writes `residual`, which starts `order` samples into the block.
+fn rice(w: &mut BitWriter, order: usize, residual: &[i64], opts: Options) {
+    let wide = opts.bits > 16;
+    w.put(u64::from(wide), 2);
+    w.put(u64::from(opts.partition_order), 4);
+    let per_partition = (residual.len() + order) >> opts.partition_order;
+    let mut rest = residual;
+    for p in 0..1usize << opts.partition_order {
+        let n = if p == 0 {
+            per_partition - order
+        } else {
+            per_partition
+        };
+        let (part, tail) = rest.split_at(n);
+        rest = tail;
+        let escape = if wide { 31 } else { 15 };
+        if opts.escape {
+            w.put(escape, if wide { 5 } else { 4 });
+            w.put(u64::from(opts.bits) + 4, 5);
+            part.iter().for_each(|&r| w.signed(r, opts.bits + 4));
+            continue;
+        }
+        let mean = part.iter().map(|r| r.unsigned_abs()).sum::<u64>() / n.max(1) as u64;
+        let k = (64 - mean.leading_zeros()).min(escape as u32 - 1);
+        w.put(u64::from(k), if wide { 5 } else { 4 });
+        for &r in part {
+            let u = ((r << 1) ^ (r >> 63)) as u64;
+            w.unary(u >> k);
+            w.put(u & ((1 << k) - 1), k);
+        }
+    }
+}
+
+/// A loud, noisy tone using most of the range of `bits`.
+fn signal(bits: u32, frames: usize, phase: f64) -> Vec<i64> {
+    let full = ((1i64 << (bits - 1)) - 1) as f64;
+    let mut seed = 0x2545_f491u32;
+    (0..frames)
+        .map(|i| {
+            seed ^= seed << 13;
+            seed ^= seed >> 17;
+            seed ^= seed << 5;
+            let noise = (f64::from(seed) / f64::from(u32::MAX) - 0.5) * 0.1;
+            let x = (i as f64 * 0.05 + phase).sin() * 0.85 + noise;
+            (x * full).round() as i64
+        })
+        .collect()
+}
+
+fn assert_decodes(channels: &[Vec<i64>], opts: Options) {
+    let sample =
+        flac::decode(&encode(channels, 44100, opts)).unwrap_or_else(|e| panic!("{opts:?}: {e}"));
+    assert_eq!(sample.channels(), channels.len());
+    assert_eq!(sample.frames(), channels[0].len());
+    assert_eq!(sample.sample_rate(), 44100);
+    let scale = (1u64 << (opts.bits - 1)) as f32;
+    for (ch, expected) in channels.iter().enumerate() {
+        for (frame, &e) in expected.iter().enumerate() {
+            assert_eq!(
+                sample.get(ch, frame),
+                e as f32 / scale,
+                "{opts:?}: channel {ch} frame {frame}"
+            );
+        }
+    }
+}
+
+#[test]
+fn decodes_every_bit_depth() {
+    for bits in [4, 8, 12, 16, 20, 24, 32] {
+        let mono = [signal(bits, 2500, 0.0)];
+        assert_decodes(&mono, Options::new(bits));
+        let surround: Vec<_> = (0..6).map(|c| signal(bits, 1100, c as f64)).collect();
+        assert_decodes(&surround, Options::new(bits));
+    }
+}
+
+#[test]
+fn decodes_every_subframe_type() {
+    let lpc: &[i64] = &[7000, -2500];
+    let long_lpc: &[i64] = &[3000, 1200, -600, 400, -200, 100, -50, 25];
+    let kinds = [
+        Subframe::Verbatim,
+        Subframe::Fixed(0),
+        Subframe::Fixed(1),
+        Subframe::Fixed(2),
+        Subframe::Fixed(3),
+        Subframe::Fixed(4),
+        Subframe::Lpc(lpc, 12),
+        Subframe::Lpc(long_lpc, 13),
+    ];
+    for bits in [16, 24] {
+        for subframe in kinds {
+            for (escape, partition_order) in [(false, 0), (false, 3), (true, 2)] {
+                let opts = Options {
+                    subframe,
+                    escape,
+                    partition_order,
+                    ..Options::new(bits)
+                };
+                assert_decodes(&[signal(bits, 2000, 0.3)], opts);
+            }
+        }
+    }
+    let constant = Options {
+        subframe: Subframe::Constant,
+        ..Options::new(16)
+    };
+    assert_decodes(&[vec![-1234; 700], vec![0; 700]], constant);
+}
+
+#[test]
+fn decodes_every_stereo_mode() {
+    for bits in [8, 16, 24, 32] {
+        let left = signal(bits, 1500, 0.0);
+        let right = signal(bits, 1500, 2.0);
+        for stereo in [
+            Stereo::Independent,
+            Stereo::LeftSide,
+            Stereo::RightSide,
+            Stereo::MidSide,
+        ] {
+            for subframe in [Subframe::Verbatim, Subframe::Fixed(1)] {
+                let opts = Options {
+                    stereo,
+                    subframe,
+                    ..Options::new(bits)
+                };
+                assert_decodes(&[left.clone(), right.clone()], opts);
+            }
+        }
+    }
+}
+
+#[test]
+fn restores_wasted_bits() {
+    // 24-bit container carrying 16-bit audio.
+    let padded: Vec<i64> = signal(16, 1000, 0.0).iter().map(|s| s << 8).collect();
+    assert_decodes(std::slice::from_ref(&padded), Options::new(24));
+    let opts = Options {
+        stereo: Stereo::MidSide,
+        ..Options::new(24)
+    };
+    assert_decodes(&[padded.clone(), padded], opts);
+}
+
+/// 16-bit stereo test signal whose MD5 was computed independently.
+fn md5_signal() -> (Vec<Vec<i64>>, [u8; 16]) {
+    let left = (0..300).map(|i| (i * 37) % 2001 - 1000).collect();
+    let right = (0..300).map(|i| (i * i * 13) % 30001 - 15000).collect();
+    let md5 = [
+        0x55, 0x76, 0x40, 0x2a, 0xba, 0x39, 0x2c, 0x2b, 0xd8, 0x49, 0x35, 0xaa, 0xfc, 0xde, 0x9d,
+        0x51,
+    ];
+    (vec![left, right], md5)
+}
+
+#[test]
+fn verifies_md5_signature() {
+    let (channels, md5) = md5_signal();
+    let opts = Options {
+        block: 128,
+        stereo: Stereo::MidSide,
+        md5,
+        ..Options::new(16)
+    };
+    assert_decodes(&channels, opts);
+
+    let mut wrong = md5;
+    wrong[7] ^= 1;
+    let err = flac::decode(&encode(&channels, 44100, Options { md5: wrong, ..opts })).unwrap_err();
+    assert!(
+        matches!(err, DecodeError::Malformed(m) if m.contains("MD5")),
+        "{err}"
+    );
+}
+
+#[test]
+fn rejects_corrupt_and_truncated_streams() {
+    let bytes = encode(&[signal(16, 3000, 0.0)], 48000, Options::new(16));
+    assert!(flac::decode(&bytes).is_ok());
+
+    // A flipped bit in the middle of the audio fails the frame CRC.
+    let mut corrupt = bytes.clone();
+    corrupt[bytes.len() / 2] ^= 0x10;
+    assert!(matches!(
+        flac::decode(&corrupt),
+        Err(DecodeError::Malformed(_))
+    ));
+
+    // So does one in a frame header.
+    let mut header = bytes.clone();
+    header[42 + 5] ^= 0x01;
+    assert!(matches!(
+        flac::decode(&header),
+        Err(DecodeError::Malformed(_))
+    ));
+
+    assert!(matches!(
+        flac::decode(&bytes[..bytes.len() - 100]),
+        Err(DecodeError::Truncated(_))
+    ));
+    assert!(matches!(
+        flac::decode(&bytes[..20]),
+        Err(DecodeError::Truncated(_))
+    ));
+    assert!(matches!(
+        flac::decode(b"RIFF"),
+        Err(DecodeError::Malformed(_))
+    ));
+}
+
+#[test]
+fn rejects_forged_frame_count() {
+    // Eight channels and no frames, but STREAMINFO claims 2^36 - 1 of them.
+    let mut bytes = encode(&vec![Vec::new(); 8], 48000, Options::new(16));
+    assert_eq!(bytes.len(), 42);
+    bytes[21] |= 0x0f;
+    bytes[22..26].fill(0xff);
+    assert!(matches!(
+        flac::decode(&bytes),
+        Err(DecodeError::Truncated(_))
+    ));
+}
+
+#[test]
+fn survives_runaway_prediction() {
+    // A constant 4th-order residual, which the prediction compounds well
+    // past `i64` before the block ends. The CRCs are valid throughout.
+    let residual = (1i64 << 34) + 1;
+    let mut samples = vec![0i64; 4];
+    for i in 4..1000 {
+        let w = Wrapping;
+        let next = w(4) * w(samples[i - 1]) - w(6) * w(samples[i - 2]) + w(4) * w(samples[i - 3])
+            - w(samples[i - 4])
+            + w(residual);
+        samples.push(next.0);
+    }
+    let opts = Options {
+        subframe: Subframe::Fixed(4),
+        ..Options::new(32)
+    };
+    assert!(flac::decode(&encode(&[samples], 48000, opts)).is_ok());
+}
+
+#[test]
+fn format_is_detected_from_contents() {
+    let flac_bytes = encode(&[vec![0, 64, -64, -128]], 8000, Options::new(8));
+    let wav_bytes = common::wav_bytes(&[0.25, -0.25], 1, 8000);
+    assert_eq!(codec::detect(&flac_bytes), Some(FileFormat::Flac));
+    assert_eq!(codec::detect(&wav_bytes), Some(FileFormat::Wav));
+    assert_eq!(codec::detect(b"OggS"), None);
+
+    // The extension is misleading on purpose.
+    let dir = common::temp_dir("flac-detect");
+    fs::write(dir.join("actually-flac.wav"), &flac_bytes).unwrap();
+    fs::write(dir.join("actually-wav.flac"), &wav_bytes).unwrap();
+    let sample = codec::load(dir.join("actually-flac.wav")).unwrap();
+    assert_eq!(sample.as_slice(), [0.0, 0.5, -0.5, -1.0]);
+    let sample = codec::load(dir.join("actually-wav.flac")).unwrap();
+    assert_eq!(sample.as_slice(), [0.25, -0.25]);
+    fs::remove_dir_all(dir).unwrap();
+
+    assert!(matches!(
+        codec::decode(b"not audio at all"),
+        Err(DecodeError::Unsupported(_))
+    ));
+}
@@ -0,0 +1,154 @@
+use samplerust::{
+    Adsr, Alternation, Controller, Engine, EngineConfig, Instrument, MidiMapper, Sample, SampleId,
+    TriggerParams, Variant, Zone,
+};
+
+/// Loads `n` constant samples whose value is their index plus one, so the
+/// output shows which one played.
+fn samples(n: usize) -> (Engine, Controller, Vec<SampleId>) {
+    let (engine, mut ctl) = Engine::new(EngineConfig {
+        sample_rate: 1000,
+        channels: 1,
+        ..EngineConfig::default()
+    });
+    let ids = (0..n)
+        .map(|i| {
+            let data = vec![(i + 1) as f32; 1000];
+            ctl.add_sample(Sample::from_interleaved(data, 1, 1000).unwrap())
+                .unwrap()
+        })
+        .collect();
+    (engine, ctl, ids)
+}
+
+fn picks(instrument: &mut Instrument, note: u8, velocity: u8) -> Vec<SampleId> {
+    instrument.select(note, velocity).map(|(s, _)| s).collect()
+}
+
+#[test]
+fn zones_split_by_key_and_velocity() {
+    let (_engine, _ctl, ids) = samples(4);
+    let mut inst = Instrument::new();
+    inst.add_zone(Zone::new(0..=59, Variant::new(ids[0], 48)));
+    inst.add_zone(Zone::new(60..=127, Variant::new(ids[1], 72)).with_velocities(0..=63));
+    inst.add_zone(Zone::new(60..=127, Variant::new(ids[2], 72)).with_velocities(64..=127));
+    // A layer over the top octave.
+    inst.add_zone(Zone::new(96..=127, Variant::new(ids[3], 100)));
+
+    assert_eq!(picks(&mut inst, 30, 100), [ids[0]]);
+    assert_eq!(picks(&mut inst, 59, 1), [ids[0]]);
+    assert_eq!(picks(&mut inst, 60, 63), [ids[1]]);
+    assert_eq!(picks(&mut inst, 60, 64), [ids[2]]);
+    assert_eq!(picks(&mut inst, 100, 127), [ids[2], ids[3]]);
+    assert_eq!(inst.zones().len(), 4);
+
+    let mut empty = Instrument::new();
+    assert!(picks(&mut empty, 60, 100).is_empty());
+}
+
+#[test]
+fn notes_are_pitched_from_the_zone_root() {
+    let (_engine, _ctl, ids) = samples(1);
+    let mut inst = Instrument::new();
+    let variant = Variant {
+        tune: -50.0,
+        ..Variant::new(ids[0], 60).with_params(TriggerParams {
+            rate: 0.5,
+            gain: 0.25,
+            ..TriggerParams::default()
+        })
+    };
+    inst.add_zone(Zone::new(0..=127, variant));
+
+    let (_, params) = inst.select(72, 100).next().unwrap();
+    assert_eq!(params.note, 72);
+    assert_eq!(params.gain, 0.25);
+    let expected = 0.5 * (11.5f64 / 12.0).exp2();
+    assert!((params.rate - expected).abs() < 1e-12);
+
+    let (_, params) = Instrument::single(ids[0], 69).select(57, 1).next().unwrap();
+    assert!((params.rate - 0.5).abs() < 1e-12);
+}
+
+#[test]
+fn round_robin_cycles_through_variants() {
+    let (_engine, _ctl, ids) = samples(3);
+    let mut inst = Instrument::new();
+    inst.add_zone(
+        Zone::new(60..=60, Variant::new(ids[0], 60)).with_alternates(
+            Alternation::RoundRobin,
+            [Variant::new(ids[1], 60), Variant::new(ids[2], 60)],
+        ),
+    );
+
+    let played: Vec<_> = (0..7).flat_map(|_| picks(&mut inst, 60, 100)).collect();
+    assert_eq!(
+        played,
+        [ids[0], ids[1], ids[2], ids[0], ids[1], ids[2], ids[0]]
+    );
+    // Notes outside the zone do not advance it.
+    assert!(picks(&mut inst, 61, 100).is_empty());
+    assert_eq!(picks(&mut inst, 60, 100), [ids[1]]);
+
+    inst.reset_alternation();
+    assert_eq!(picks(&mut inst, 60, 100), [ids[0]]);
+}
+
+#[test]
+fn random_alternation_avoids_repeats_and_is_seedable() {
+    let (_engine, _ctl, ids) = samples(4);
+    let zone = Zone::new(0..=127, Variant::new(ids[0], 60)).with_alternates(
+        Alternation::Random,
+        ids[1..].iter().map(|&id| Variant::new(id, 60)),
+    );
+    let run = |seed| {
+        let mut inst = Instrument::new();
+        inst.add_zone(zone.clone());
+        inst.set_seed(seed);
+        (0..400)
+            .flat_map(|_| picks(&mut inst, 60, 100))
+            .collect::<Vec<_>>()
+    };
+
+    let played = run(7);
+    assert!(played.windows(2).all(|w| w[0] != w[1]));
+    for id in &ids {
+        let count = played.iter().filter(|p| *p == id).count();
+        assert!((60..=140).contains(&count), "{id:?} played {count} times");
+    }
+    assert_eq!(run(7), played);
+    assert_ne!(run(8), played);
+}
+
+#[test]
+fn midi_notes_play_the_selected_zone() {
+    let (mut engine, mut ctl, ids) = samples(3);
+    let gate = TriggerParams {
+        envelope: Adsr::new(0.0, 0.0, 1.0, 0.0),
+        ..TriggerParams::default()
+    };
+    let mut inst = Instrument::new();
+    inst.add_zone(
+        Zone::new(0..=63, Variant::new(ids[0], 60).with_params(gate)).with_alternates(
+            Alternation::RoundRobin,
+            [Variant::new(ids[1], 60).with_params(gate)],
+        ),
+    );
+    inst.add_zone(Zone::new(
+        64..=127,
+        Variant::new(ids[2], 64).with_params(gate),
+    ));
+    let mut mapper = MidiMapper::new(inst);
+
+    let mut out = [0.0; 4];
+    let mut hit = |bytes: &[u8]| {
+        let now = ctl.now();
+        mapper.process(&mut ctl, now, bytes, |_| {}).unwrap();
+        engine.render(&mut out);
+        out[0]
+    };
+    assert_eq!(hit(&[0x90, 60, 127]), 1.0);
+    assert_eq!(hit(&[0x80, 60, 0, 0x90, 60, 127]), 2.0);
+    assert_eq!(hit(&[0x80, 60, 0, 0x90, 64, 127]), 3.0);
+    assert_eq!(hit(&[0x80, 64, 0, 0x90, 62, 127]), 1.0);
+}
@@ -0,0 +1,125 @@
+mod common;
+
+use common::{band_energy, db, power_spectrum, rms, sine, sweep};
+use samplerust::{Engine, EngineConfig, Interpolation, Sample, TriggerParams};
+
+const RATE: u32 = 48_000;
+
+/// Plays `data` once at `rate` through a mono engine and returns the output.
+fn play(data: Vec<f32>, sample_rate: u32, rate: f64, interpolation: Interpolation) -> Vec<f32> {
+    let frames = (data.len() as f64 / rate * f64::from(RATE) / f64::from(sample_rate)) as usize;
+    let (mut engine, mut ctl) = Engine::new(EngineConfig {
+        sample_rate: RATE,
+        channels: 1,
+        ..EngineConfig::default()
+    });
+    let id = ctl
+        .add_sample(Sample::from_interleaved(data, 1, sample_rate).unwrap())
+        .unwrap();
+    ctl.trigger(
+        id,
+        TriggerParams {
+            rate,
+            interpolation,
+            ..TriggerParams::default()
+        },
+    )
+    .unwrap();
+    let mut out = vec![0.0; frames + 64];
+    for block in out.chunks_mut(256) {
+        engine.render(block);
+    }
+    out.truncate(frames);
+    out
+}
+
+fn ramp(n: usize) -> Vec<f32> {
+    (0..n).map(|i| i as f32).collect()
+}
+
+#[test]
+fn polynomial_interpolators_read_between_frames() {
+    let none = play(ramp(8), RATE, 0.5, Interpolation::None);
+    assert_eq!(&none[..6], &[0.0, 0.0, 1.0, 1.0, 2.0, 2.0]);
+
+    let linear = play(ramp(8), RATE, 0.5, Interpolation::Linear);
+    assert_eq!(&linear[..6], &[0.0, 0.5, 1.0, 1.5, 2.0, 2.5]);
+
+    // Hermite reproduces a straight line exactly away from the edges.
+    let cubic = play(ramp(8), RATE, 0.5, Interpolation::Cubic);
+    assert_eq!(&cubic[2..10], &[1.0, 1.5, 2.0, 2.5, 3.0, 3.5, 4.0, 4.5]);
+}
+
+#[test]
+fn rate_and_sample_rate_set_playback_speed() {
+    let fast = play(ramp(8), RATE, 2.0, Interpolation::None);
+    assert_eq!(fast, [0.0, 2.0, 4.0, 6.0]);
+
+    // A 24 kHz sample in a 48 kHz engine advances half a frame per output.
+    let slow = play(ramp(4), 24_000, 1.0, Interpolation::None);
+    assert_eq!(slow, [0.0, 0.0, 1.0, 1.0, 2.0, 2.0, 3.0, 3.0]);
+}
+
+#[test]
+fn sinc_passes_in_band_content_at_unity_gain() {
+    let source = sine(1_000.0, f64::from(RATE), 8_192);
+    for rate in [0.5, 1.0, 1.5] {
+        let out = play(source.clone(), RATE, rate, Interpolation::Sinc);
+        let mid = &out[out.len() / 4..out.len() * 3 / 4];
+        let gain = db(rms(mid) / rms(&source));
+        assert!(gain.abs() < 0.05, "rate {rate}: {gain} dB");
+    }
+}
+
+/// Energy that aliases back into the audible band when a sweep lying
+/// entirely above the transposed Nyquist frequency is played 1.5x faster.
+fn aliasing_db(interpolation: Interpolation) -> f64 {
+    let source = sweep(18_000.0, 22_000.0, f64::from(RATE), 32_768);
+    let out = play(source.clone(), RATE, 1.5, interpolation);
+    db(rms(&out[64..out.len() - 64]) / rms(&source))
+}
+
+#[test]
+fn sinc_rejects_aliasing_on_upward_sweep() {
+    let sinc = aliasing_db(Interpolation::Sinc);
+    let cubic = aliasing_db(Interpolation::Cubic);
+    let linear = aliasing_db(Interpolation::Linear);
+    let none = aliasing_db(Interpolation::None);
+    assert!(sinc < -85.0, "sinc aliasing {sinc} dB");
+    assert!(cubic > -10.0, "cubic aliasing {cubic} dB");
+    assert!(linear > -10.0, "linear aliasing {linear} dB");
+    assert!(none > -3.0, "drop-sample aliasing {none} dB");
+}
+
+#[test]
+fn sinc_keeps_transposed_sweep_in_band() {
+    // 500 Hz..10 kHz at 1.5x lands on 750 Hz..15 kHz; nothing should appear
+    // above that.
+    let source = sweep(500.0, 10_000.0, f64::from(RATE), 49_152);
+    let out = play(source, RATE, 1.5, Interpolation::Sinc);
+    let spectrum = power_spectrum(&out);
+    let stray = band_energy(&spectrum, f64::from(RATE), 16_000.0, 24_000.0);
+    assert!(10.0 * stray.log10() < -100.0, "out-of-band {stray}");
+
+    let linear = power_spectrum(&play(
+        sweep(500.0, 10_000.0, f64::from(RATE), 49_152),
+        RATE,
+        1.5,
+        Interpolation::Linear,
+    ));
+    assert!(band_energy(&linear, f64::from(RATE), 16_000.0, 24_000.0) > stray * 1e3);
+}
+
+#[test]
+fn sinc_suppresses_images_when_pitching_down() {
+    // 10 kHz at half speed is 5 kHz, with its first image at 19 kHz.
+    let source = sine(10_000.0, f64::from(RATE), 16_384);
+    let images = |interp| {
+        let spectrum = power_spectrum(&play(source.clone(), RATE, 0.5, interp));
+        10.0 * band_energy(&spectrum, f64::from(RATE), 6_000.0, 24_000.0).log10()
+    };
+    let sinc = images(Interpolation::Sinc);
+    let linear = images(Interpolation::Linear);
+    assert!(sinc < -95.0, "sinc images {sinc} dB");
+    assert!(linear > -30.0, "linear images {linear} dB");
+}
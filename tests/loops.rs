@@ -0,0 +1,175 @@
+mod common;
+
+use samplerust::{
+    Adsr, Curve, Engine, EngineConfig, Interpolation, LoopMode, LoopRegion, Sample, TriggerParams,
+};
+
+fn ramp(n: usize) -> Sample {
+    Sample::from_interleaved((0..n).map(|i| i as f32).collect(), 1, 48_000).unwrap()
+}
+
+fn region(start: usize, end: usize, mode: LoopMode, crossfade: usize) -> LoopRegion {
+    LoopRegion {
+        start,
+        end,
+        mode,
+        crossfade,
+    }
+}
+
+/// Renders `frames` frames of `sample` with drop-sample reads. If `release`
+/// is set, the voice is released after that many frames; the envelope holds
+/// its level through the release so the raw sample values come through.
+fn play(sample: Sample, params: TriggerParams, frames: usize, release: Option<usize>) -> Vec<f32> {
+    let (mut engine, mut ctl) = Engine::new(EngineConfig {
+        channels: 1,
+        ..EngineConfig::default()
+    });
+    let id = ctl.add_sample(sample).unwrap();
+    let voice = ctl
+        .trigger(
+            id,
+            TriggerParams {
+                interpolation: Interpolation::None,
+                envelope: Adsr {
+                    release: 1.0,
+                    release_curve: Curve::Custom(|_| 0.0),
+                    ..Adsr::default()
+                },
+                ..params
+            },
+        )
+        .unwrap();
+    let mut out = vec![0.0; frames];
+    let split = release.unwrap_or(frames);
+    engine.render(&mut out[..split]);
+    ctl.release(voice).unwrap();
+    engine.render(&mut out[split..]);
+    out
+}
+
+fn looped(sample: Sample, region: LoopRegion, frames: usize) -> Vec<f32> {
+    play(
+        sample.with_loop_region(region),
+        TriggerParams::default(),
+        frames,
+        None,
+    )
+}
+
+#[test]
+fn forward_loop_repeats_region() {
+    let out = looped(ramp(10), LoopRegion::forward(4, 8), 16);
+    assert_eq!(
+        out,
+        [0., 1., 2., 3., 4., 5., 6., 7., 4., 5., 6., 7., 4., 5., 6., 7.]
+    );
+}
+
+#[test]
+fn ping_pong_loop_bounces() {
+    let out = looped(ramp(10), region(4, 8, LoopMode::PingPong, 0), 16);
+    assert_eq!(
+        out,
+        [0., 1., 2., 3., 4., 5., 6., 7., 6., 5., 4., 5., 6., 7., 6., 5.]
+    );
+}
+
+#[test]
+fn reverse_loop_plays_backwards() {
+    let out = looped(ramp(10), region(4, 8, LoopMode::Reverse, 0), 16);
+    assert_eq!(
+        out,
+        [0., 1., 2., 3., 4., 5., 6., 7., 6., 5., 4., 7., 6., 5., 4., 7.]
+    );
+}
+
+#[test]
+fn until_release_loop_plays_out_after_note_off() {
+    let sample = ramp(10).with_loop_region(region(4, 8, LoopMode::UntilRelease, 0));
+    let out = play(sample, TriggerParams::default(), 16, Some(10));
+    assert_eq!(
+        out,
+        [0., 1., 2., 3., 4., 5., 6., 7., 4., 5., 6., 7., 8., 9., 0., 0.]
+    );
+
+    // A plain forward loop keeps looping after release.
+    let sample = ramp(10).with_loop_region(LoopRegion::forward(4, 8));
+    let out = play(sample, TriggerParams::default(), 16, Some(10));
+    assert_eq!(&out[12..], &[4., 5., 6., 7.]);
+}
+
+#[test]
+fn trigger_can_override_or_disable_the_loop() {
+    let sample = ramp(10).with_loop_region(LoopRegion::forward(4, 8));
+    let params = TriggerParams {
+        loop_region: Some(region(0, 0, LoopMode::Off, 0)),
+        ..TriggerParams::default()
+    };
+    let out = play(sample.clone(), params, 12, None);
+    assert_eq!(&out[8..], &[8., 9., 0., 0.]);
+
+    let params = TriggerParams {
+        loop_region: Some(LoopRegion::forward(1, 3)),
+        ..TriggerParams::default()
+    };
+    let out = play(sample, params, 7, None);
+    assert_eq!(out, [0., 1., 2., 1., 2., 1., 2.]);
+}
+
+#[test]
+fn starting_past_the_loop_plays_to_the_end() {
+    let sample = ramp(10).with_loop_region(LoopRegion::forward(2, 4));
+    let params = TriggerParams {
+        start: 6,
+        ..TriggerParams::default()
+    };
+    assert_eq!(play(sample, params, 6, None), [6., 7., 8., 9., 0., 0.]);
+}
+
+#[test]
+fn loops_follow_fractional_rates() {
+    let sample = ramp(10).with_loop_region(LoopRegion::forward(4, 8));
+    let params = TriggerParams {
+        rate: 3.0,
+        ..TriggerParams::default()
+    };
+    assert_eq!(play(sample, params, 6, None), [0., 3., 6., 5., 4., 7.]);
+}
+
+#[test]
+fn crossfade_blends_the_seam() {
+    let out = looped(ramp(10), region(4, 8, LoopMode::Forward, 2), 12);
+    // Frames 6 and 7 blend towards frames 2 and 3 across the seam.
+    assert_eq!(out, [0., 1., 2., 3., 4., 5., 6., 5., 4., 5., 6., 5.]);
+
+    let out = looped(ramp(12), region(4, 8, LoopMode::Reverse, 2), 16);
+    // Backwards, frames 5 and 4 blend towards frames 9 and 8.
+    assert_eq!(
+        out,
+        [0., 1., 2., 3., 4., 5., 6., 7., 6., 7., 8., 7., 6., 7., 8., 7.]
+    );
+}
+
+#[test]
+fn crossfade_removes_the_click_from_a_mismatched_loop() {
+    // A 100 Hz sine looped over a length that is not a whole number of
+    // periods jumps at the seam unless crossfaded.
+    let sine = common::sine(100.0, 48_000.0, 4_800);
+    let max_jump = |crossfade| {
+        let sample = Sample::from_interleaved(sine.clone(), 1, 48_000).unwrap();
+        let out = looped(
+            sample,
+            region(1_000, 3_250, LoopMode::Forward, crossfade),
+            9_000,
+        );
+        out.windows(2)
+            .map(|w| (w[1] - w[0]).abs())
+            .fold(0.0f32, f32::max)
+    };
+    // The steepest a 100 Hz sine gets, plus the most a linear blend of two
+    // unit signals can add per frame.
+    let bound = 2.0 * std::f32::consts::PI * 100.0 / 48_000.0 + 2.0 / 900.0;
+    assert!(max_jump(0) > 0.5);
+    assert!(max_jump(900) < bound, "{}", max_jump(900));
+}
@@ -0,0 +1,276 @@
+use samplerust::midi::Parser;
+use samplerust::{
+    Adsr, Controller, Engine, EngineConfig, Instrument, Interpolation, MidiMapper, MidiMessage,
+    Sample, TriggerParams, Variant, Zone,
+};
+
+fn parse(bytes: &[u8]) -> Vec<MidiMessage> {
+    Parser::new().parse(bytes).collect()
+}
+
+#[test]
+fn parses_channel_messages_with_running_status() {
+    let msgs = parse(&[
+        0x91, 60, 100, 62, 90, 60, 0, 0xb1, 64, 127, 0xe2, 0x00, 0x40, 0x7f, 0x7f,
+    ]);
+    assert_eq!(
+        msgs,
+        [
+            MidiMessage::NoteOn {
+                channel: 1,
+                note: 60,
+                velocity: 100
+            },
+            MidiMessage::NoteOn {
+                channel: 1,
+                note: 62,
+                velocity: 90
+            },
+            MidiMessage::NoteOff {
+                channel: 1,
+                note: 60,
+                velocity: 0
+            },
+            MidiMessage::ControlChange {
+                channel: 1,
+                controller: 64,
+                value: 127
+            },
+            MidiMessage::PitchBend {
+                channel: 2,
+                value: 0
+            },
+            MidiMessage::PitchBend {
+                channel: 2,
+                value: 8191
+            },
+        ]
+    );
+    assert_eq!(
+        parse(&[0xe0, 0, 0, 0xc3, 5, 6]),
+        [
+            MidiMessage::PitchBend {
+                channel: 0,
+                value: -8192
+            },
+            MidiMessage::ProgramChange {
+                channel: 3,
+                program: 5
+            },
+            MidiMessage::ProgramChange {
+                channel: 3,
+                program: 6
+            },
+        ]
+    );
+}
+
+#[test]
+fn messages_may_span_chunks_and_interleave_real_time_bytes() {
+    let mut parser = Parser::new();
+    let mut msgs: Vec<_> = parser.parse(&[0x90, 60]).collect();
+    assert!(msgs.is_empty());
+    msgs.extend(parser.parse(&[0xf8, 100, 61]));
+    msgs.extend(parser.parse(&[0xfe, 80]));
+    assert_eq!(
+        msgs,
+        [
+            MidiMessage::TimingClock,
+            MidiMessage::NoteOn {
+                channel: 0,
+                note: 60,
+                velocity: 100
+            },
+            MidiMessage::ActiveSensing,
+            MidiMessage::NoteOn {
+                channel: 0,
+                note: 61,
+                velocity: 80
+            },
+        ]
+    );
+}
+
+#[test]
+fn sysex_passes_through_and_cancels_running_status() {
+    let mut parser = Parser::new();
+    let mut msgs: Vec<_> = parser.parse(&[0x90, 60, 100, 0xf0, 0x7e, 0x01]).collect();
+    msgs.extend(parser.parse(&[0xf8, 0x02, 0xf7, 61, 100]));
+    assert_eq!(
+        msgs,
+        [
+            MidiMessage::NoteOn {
+                channel: 0,
+                note: 60,
+                velocity: 100
+            },
+            MidiMessage::TimingClock,
+            MidiMessage::SysEx(vec![0x7e, 0x01, 0x02]),
+        ]
+    );
+
+    // An unterminated SysEx is dropped when the next status arrives.
+    assert_eq!(
+        parse(&[0xf0, 1, 2, 0x80, 60, 0]),
+        [MidiMessage::NoteOff {
+            channel: 0,
+            note: 60,
+            velocity: 0
+        }]
+    );
+}
+
+#[test]
+fn parses_system_common_and_ignores_stray_data() {
+    assert_eq!(
+        parse(&[5, 6, 0xf2, 0x01, 0x02, 0xf3, 7, 0xf1, 0x23, 0xf6, 9, 0xfa, 0xfc]),
+        [
+            MidiMessage::SongPosition(0x101),
+            MidiMessage::SongSelect(7),
+            MidiMessage::MtcQuarterFrame(0x23),
+            MidiMessage::TuneRequest,
+            MidiMessage::Start,
+            MidiMessage::Stop,
+        ]
+    );
+    // A new status byte abandons a half-finished message.
+    assert_eq!(
+        parse(&[0x90, 60, 0xb0, 7, 100]),
+        [MidiMessage::ControlChange {
+            channel: 0,
+            controller: 7,
+            value: 100
+        }]
+    );
+}
+
+fn setup() -> (Engine, Controller, MidiMapper) {
+    let (engine, mut ctl) = Engine::new(EngineConfig {
+        sample_rate: 1000,
+        channels: 1,
+        voices: 8,
+        ..EngineConfig::default()
+    });
+    let ramp = (0..1000).map(|i| i as f32).collect();
+    let id = ctl
+        .add_sample(Sample::from_interleaved(ramp, 1, 1000).unwrap())
+        .unwrap();
+    let variant = Variant::new(id, 60).with_params(TriggerParams {
+        interpolation: Interpolation::Linear,
+        envelope: Adsr::new(0.0, 0.0, 1.0, 0.0),
+        ..TriggerParams::default()
+    });
+    let mut instrument = Instrument::new();
+    instrument.add_zone(Zone::new(0..=127, variant));
+    (engine, ctl, MidiMapper::new(instrument))
+}
+
+#[test]
+fn notes_trigger_pitched_voices_scaled_by_velocity() {
+    let (mut engine, mut ctl, mut mapper) = setup();
+    mapper
+        .process(&mut ctl, 0, &[0x90, 72, 127], |_| {})
+        .unwrap();
+    mapper
+        .process(&mut ctl, 10, &[0x80, 72, 0], |_| {})
+        .unwrap();
+
+    let mut out = [0.0; 12];
+    engine.render(&mut out);
+    // An octave up reads every other frame.
+    assert_eq!(&out[..4], &[0.0, 2.0, 4.0, 6.0]);
+    assert_eq!(out[9], 18.0);
+    assert_eq!(out[10], 0.0);
+    assert_eq!(engine.active_voices(), 0);
+
+    mapper
+        .process(&mut ctl, 20, &[0x90, 60, 64], |_| {})
+        .unwrap();
+    engine.render(&mut out);
+    assert!((out[9] - 64.0 / 127.0).abs() < 1e-6);
+    assert_eq!(mapper.held_voices(), 1);
+}
+
+#[test]
+fn sustain_pedal_defers_note_off() {
+    let (mut engine, mut ctl, mut mapper) = setup();
+    let mut out = [0.0; 10];
+    mapper
+        .process(
+            &mut ctl,
+            0,
+            &[0x90, 60, 127, 0xb0, 64, 127, 0x80, 60, 0],
+            |_| {},
+        )
+        .unwrap();
+    engine.render(&mut out);
+    assert_eq!(engine.active_voices(), 1);
+    assert_eq!(mapper.held_voices(), 1);
+
+    // The pedal on another channel does nothing.
+    mapper
+        .process(&mut ctl, 10, &[0xb1, 64, 0], |_| {})
+        .unwrap();
+    engine.render(&mut out);
+    assert_eq!(engine.active_voices(), 1);
+
+    mapper
+        .process(&mut ctl, 25, &[0xb0, 64, 0], |_| {})
+        .unwrap();
+    engine.render(&mut out);
+    assert_eq!(out[4], 24.0);
+    assert_eq!(out[5], 0.0);
+    assert_eq!(engine.active_voices(), 0);
+    assert_eq!(mapper.held_voices(), 0);
+}
+
+#[test]
+fn pitch_bend_scales_rate_on_its_channel() {
+    let (mut engine, mut ctl, mut mapper) = setup();
+    mapper.set_bend_range(12.0);
+    mapper
+        .process(&mut ctl, 0, &[0x90, 60, 127, 0x91, 60, 127], |_| {})
+        .unwrap();
+    let mut out = [0.0; 4];
+    engine.render(&mut out);
+    assert_eq!(out, [0.0, 2.0, 4.0, 6.0]);
+
+    // Full bend up on channel 0 doubles only that voice's rate.
+    mapper
+        .process(&mut ctl, 4, &[0xe0, 0x7f, 0x7f], |_| {})
+        .unwrap();
+    engine.render(&mut out);
+    let up = 2f64.powf(8191.0 / 8192.0);
+    let expected = 4.0 + up;
+    assert!(
+        (f64::from(out[1]) - (expected + 5.0)).abs() < 1e-3,
+        "{out:?}"
+    );
+}
+
+#[test]
+fn unhandled_messages_are_passed_on() {
+    let (_engine, mut ctl, mut mapper) = setup();
+    mapper.set_channel(Some(2));
+    let mut other = Vec::new();
+    mapper
+        .process(
+            &mut ctl,
+            0,
+            &[0xf0, 1, 0xf7, 0x90, 60, 100, 0x92, 60, 100],
+            |m| other.push(m),
+        )
+        .unwrap();
+    assert_eq!(
+        other,
+        [
+            MidiMessage::SysEx(vec![1]),
+            MidiMessage::NoteOn {
+                channel: 0,
+                note: 60,
+                velocity: 100
+            },
+        ]
+    );
+    assert_eq!(mapper.held_voices(), 1);
+}
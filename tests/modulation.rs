@@ -0,0 +1,321 @@
+use samplerust::{
+    Command, Engine, EngineConfig, EngineError, FilterMode, FilterParams, Instrument,
+    Interpolation, Lfo, LfoRate, LfoShape, LoopRegion, MidiMapper, ModDestination, ModMatrix,
+    ModSource, Sample, TriggerParams, Variant, Zone,
+};
+
+const BLOCK: usize = samplerust::modulation::MOD_BLOCK;
+
+fn mono() -> EngineConfig {
+    EngineConfig {
+        channels: 1,
+        ..EngineConfig::default()
+    }
+}
+
+/// A sample whose every frame holds its own index, so linearly interpolated
+/// output reads back the playback position.
+fn ramp(frames: usize) -> Sample {
+    Sample::from_interleaved((0..frames).map(|v| v as f32).collect(), 1, 48_000).unwrap()
+}
+
+fn linear() -> TriggerParams {
+    TriggerParams {
+        interpolation: Interpolation::Linear,
+        ..TriggerParams::default()
+    }
+}
+
+/// Plays a constant 1.0 for `frames` frames under `matrix`.
+fn constant(matrix: ModMatrix, frames: usize, tempo: Option<f64>) -> Vec<f32> {
+    let (mut engine, mut ctl) = Engine::new(mono());
+    let id = ctl
+        .add_sample(Sample::from_interleaved(vec![1.0; frames], 1, 48_000).unwrap())
+        .unwrap();
+    ctl.set_mod_matrix(matrix).unwrap();
+    if let Some(bpm) = tempo {
+        ctl.set_tempo(bpm).unwrap();
+    }
+    ctl.trigger(id, TriggerParams::default()).unwrap();
+    let mut out = vec![0.0; frames];
+    engine.render(&mut out);
+    out
+}
+
+#[test]
+fn lfo_shapes_follow_their_cycles() {
+    for shape in [
+        LfoShape::Sine,
+        LfoShape::Triangle,
+        LfoShape::Saw,
+        LfoShape::Square,
+    ] {
+        // 50 Hz is 30 evaluations a cycle.
+        let lfo = Lfo {
+            phase: 0.1,
+            ..Lfo::new(shape, LfoRate::Hz(50.0))
+        };
+        let matrix =
+            ModMatrix::new()
+                .with_lfo(2, lfo)
+                .route(ModSource::Lfo(2), ModDestination::Gain, 6.0);
+        let out = constant(matrix, 4_800, None);
+        // Each evaluation is reached at the start of the next block.
+        for k in 0..4_800 / BLOCK - 1 {
+            let got = 20.0 * out[(k + 1) * BLOCK].log10() / 6.0;
+            let want = lfo.value(0.1 + (k * BLOCK) as f64 * 50.0 / 48_000.0);
+            assert!((got - want).abs() < 1e-4, "{shape:?} {k}: {got} {want}");
+        }
+        // Gain glides from one evaluation to the next.
+        let (from, to) = (out[BLOCK], out[2 * BLOCK]);
+        let (lo, hi) = (from.min(to) - 1e-6, from.max(to) + 1e-6);
+        assert!(out[BLOCK..2 * BLOCK].iter().all(|v| (lo..=hi).contains(v)));
+    }
+
+    // Sample-and-hold picks a level in range once a cycle.
+    let matrix = ModMatrix::new()
+        .with_lfo(0, Lfo::new(LfoShape::SampleAndHold, LfoRate::Hz(50.0)))
+        .route(ModSource::Lfo(0), ModDestination::Gain, 6.0);
+    let out = constant(matrix, 9_600, None);
+    let mut levels: Vec<f32> = (1..9_600 / BLOCK)
+        .map(|k| 20.0 * out[k * BLOCK].log10() / 6.0)
+        .collect();
+    assert!(levels.iter().all(|v| (-1.0..=1.0).contains(v)));
+    levels.dedup_by(|a, b| (*a - *b).abs() < 1e-4);
+    assert!((9..=11).contains(&levels.len()), "{}", levels.len());
+}
+
+#[test]
+fn synced_lfos_follow_the_tempo() {
+    // A square a quarter of a beat long turns negative half way through.
+    let first_dip = |tempo| {
+        let matrix = ModMatrix::new()
+            .with_lfo(0, Lfo::new(LfoShape::Square, LfoRate::Beats(0.25)))
+            .route(ModSource::Lfo(0), ModDestination::Gain, -12.0);
+        let out = constant(matrix, 9_600, tempo);
+        out.iter().position(|&v| v > 1.0).unwrap() as f64
+    };
+    // 120 bpm by default: 8 cycles a second.
+    assert!((first_dip(None) - 3_000.0).abs() <= 2.0 * BLOCK as f64);
+    assert!((first_dip(Some(240.0)) - 1_500.0).abs() <= 2.0 * BLOCK as f64);
+}
+
+/// Steps between successive output frames of a ramp.
+fn steps(out: &[f32]) -> Vec<f32> {
+    out.windows(2).map(|w| w[1] - w[0]).collect()
+}
+
+#[test]
+fn note_and_controller_sources_move_pitch() {
+    let matrix = ModMatrix::new()
+        .route(ModSource::Key, ModDestination::Pitch, 12.0)
+        .route(ModSource::Velocity, ModDestination::Pitch, -12.0)
+        .route(ModSource::ModWheel, ModDestination::Pitch, 7.0)
+        .route(ModSource::Aftertouch, ModDestination::Pitch, 5.0);
+    let (mut engine, mut ctl) = Engine::new(mono());
+    let id = ctl.add_sample(ramp(48_000)).unwrap();
+    ctl.set_mod_matrix(matrix).unwrap();
+    let mut instrument = Instrument::new();
+    instrument.add_zone(Zone::new(
+        0..=127,
+        Variant::new(id, 60).with_params(linear()),
+    ));
+    let mut mapper = MidiMapper::new(instrument);
+    let mut out = vec![0.0; 256];
+
+    // The instrument plays note 72 an octave up. The key adds an octave and
+    // full velocity takes one away.
+    mapper
+        .process(&mut ctl, 0, &[0x90, 72, 127], |_| {})
+        .unwrap();
+    engine.render(&mut out);
+    for step in steps(&out[BLOCK..]) {
+        assert!((step - 2.0).abs() < 1e-3, "{step}");
+    }
+
+    // Full mod wheel and aftertouch add a fifth and a fourth.
+    mapper
+        .process(&mut ctl, 256, &[0xb0, 1, 127, 0xd0, 127], |_| {})
+        .unwrap();
+    engine.render(&mut out);
+    engine.render(&mut out);
+    for step in steps(&out[BLOCK..]) {
+        assert!((step - 4.0).abs() < 1e-2, "{step}");
+    }
+}
+
+#[test]
+fn start_and_loop_position_move_the_playhead() {
+    let matrix = ModMatrix::new()
+        .route(ModSource::Velocity, ModDestination::Start, 1_000.0)
+        .route(ModSource::ModWheel, ModDestination::LoopPosition, 500.0);
+    let (mut engine, mut ctl) = Engine::new(mono());
+    let id = ctl
+        .add_sample(ramp(10_000).with_loop_region(LoopRegion::forward(100, 200)))
+        .unwrap();
+    ctl.set_mod_matrix(matrix).unwrap();
+    ctl.trigger(
+        id,
+        TriggerParams {
+            velocity: 127,
+            start: 50,
+            ..linear()
+        },
+    )
+    .unwrap();
+
+    // Full velocity starts past the loop, which the voice plays on out of.
+    let mut out = vec![0.0; 1_000];
+    engine.render(&mut out);
+    assert_eq!(out[0], 1_050.0);
+    assert_eq!(out[999], 2_049.0);
+    ctl.stop_all().unwrap();
+
+    ctl.trigger(
+        id,
+        TriggerParams {
+            velocity: 0,
+            ..linear()
+        },
+    )
+    .unwrap();
+    engine.render(&mut out);
+    assert!(out[200..].iter().all(|v| (100.0..200.0).contains(v)));
+
+    // With the wheel up the loop moves along, taking the voice with it.
+    ctl.send(Command::ModWheel {
+        channel: 0,
+        value: 1.0,
+    })
+    .unwrap();
+    engine.render(&mut out);
+    assert!(out.iter().all(|v| (600.0..700.0).contains(v)));
+}
+
+#[test]
+fn cutoff_and_pan_follow_their_routes() {
+    let click = {
+        let mut data = vec![0.0; 2_000];
+        data[0] = 1.0;
+        Sample::from_interleaved(data, 1, 48_000).unwrap()
+    };
+    let filter = |cutoff| FilterParams {
+        mode: FilterMode::BandPass,
+        cutoff,
+        resonance: 4.0,
+        ..FilterParams::default()
+    };
+    let play = |matrix: Option<ModMatrix>, cutoff| {
+        let (mut engine, mut ctl) = Engine::new(EngineConfig::default());
+        let id = ctl.add_sample(click.clone()).unwrap();
+        if let Some(matrix) = matrix {
+            ctl.set_mod_matrix(matrix).unwrap();
+        }
+        ctl.send(Command::ModWheel {
+            channel: 3,
+            value: 1.0,
+        })
+        .unwrap();
+        let params = TriggerParams {
+            channel: 3,
+            filter: Some(filter(cutoff)),
+            ..TriggerParams::default()
+        };
+        ctl.trigger(id, params).unwrap();
+        let mut out = vec![0.0; 4_000];
+        engine.render(&mut out);
+        out
+    };
+
+    // An octave of cutoff from the wheel is the same filter an octave up.
+    let routed = ModMatrix::new().route(ModSource::ModWheel, ModDestination::Cutoff, 1_200.0);
+    assert_eq!(play(Some(routed), 1_000.0), play(None, 2_000.0));
+
+    // Panned hard left, the right channel falls silent and the left keeps
+    // its level.
+    let panned = ModMatrix::new().route(ModSource::ModWheel, ModDestination::Pan, -1.0);
+    let centre = play(None, 1_000.0);
+    let left = play(Some(panned), 1_000.0);
+    for (l, c) in left.chunks_exact(2).zip(centre.chunks_exact(2)) {
+        assert_eq!(l, [c[0], 0.0]);
+    }
+    // Routed but left in the middle, the voice is panned to the centre,
+    // 3 dB down on both sides under the default constant-power law.
+    let still = ModMatrix::new().route(ModSource::ModWheel, ModDestination::Pan, 0.0);
+    let middle = play(Some(still), 1_000.0);
+    for (m, c) in middle.chunks_exact(2).zip(centre.chunks_exact(2)) {
+        let expected = c[0] * 10f32.powf(-3.0103 / 20.0);
+        assert!((m[0] - expected).abs() < 1e-6, "{} {}", m[0], c[0]);
+        assert_eq!(m[0], m[1]);
+    }
+}
+
+#[test]
+fn unfiltered_voices_read_a_silent_filter_envelope() {
+    let (mut engine, mut ctl) = Engine::new(EngineConfig {
+        voices: 1,
+        ..mono()
+    });
+    let id = ctl
+        .add_sample(Sample::from_interleaved(vec![1.0; 1_000], 1, 48_000).unwrap())
+        .unwrap();
+    let matrix = ModMatrix::new().route(ModSource::FilterEnvelope, ModDestination::Gain, -20.0);
+    ctl.set_mod_matrix(matrix).unwrap();
+
+    // A filtered note ends with its filter envelope held at full sustain...
+    let filtered = TriggerParams {
+        filter: Some(FilterParams {
+            mode: FilterMode::LowPass,
+            cutoff: 20_000.0,
+            ..FilterParams::default()
+        }),
+        ..TriggerParams::default()
+    };
+    ctl.trigger(id, filtered).unwrap();
+    let mut out = vec![0.0; 2_000];
+    engine.render(&mut out);
+    assert_eq!(engine.active_voices(), 0);
+
+    // ...which the next, unfiltered note in the same slot does not inherit.
+    ctl.trigger(id, TriggerParams::default()).unwrap();
+    engine.render(&mut out);
+    assert!(out[..1_000].iter().all(|&v| v == 1.0));
+}
+
+#[test]
+fn matrices_are_swapped_in_and_returned() {
+    let (mut engine, mut ctl) = Engine::new(mono());
+    let id = ctl
+        .add_sample(
+            Sample::from_interleaved(vec![1.0; 1_000], 1, 48_000)
+                .unwrap()
+                .with_loop_region(LoopRegion::forward(0, 1_000)),
+        )
+        .unwrap();
+    ctl.trigger(id, TriggerParams::default()).unwrap();
+    let mut out = vec![0.0; 256];
+    engine.render(&mut out);
+    assert!(out.iter().all(|&v| v == 1.0));
+
+    // The playing voice picks up a new matrix at the next block.
+    let quiet = |db| ModMatrix::new().route(ModSource::Velocity, ModDestination::Gain, db);
+    ctl.set_mod_matrix(quiet(-20.0)).unwrap();
+    engine.render(&mut out);
+    assert!((out[0] - 0.1).abs() < 1e-6, "{}", out[0]);
+
+    // Matrices the engine has not returned yet are limited.
+    for db in [-6.0, -12.0, -18.0] {
+        ctl.set_mod_matrix(quiet(db)).unwrap();
+    }
+    assert_eq!(ctl.set_mod_matrix(quiet(0.0)), Err(EngineError::QueueFull));
+    engine.render(&mut out);
+    assert_eq!(ctl.collect_garbage(), 3);
+    // The voice glides to the level of the last one.
+    assert!((out[BLOCK] - 0.125_9).abs() < 1e-4, "{}", out[BLOCK]);
+
+    // Without routes the voice glides back to where it started.
+    ctl.set_mod_matrix(ModMatrix::new()).unwrap();
+    engine.render(&mut out);
+    assert!(out[..BLOCK].windows(2).all(|w| w[0] < w[1]));
+    assert!(out[BLOCK..].iter().all(|&v| v == 1.0));
+}
@@ -0,0 +1,481 @@
+#![cfg(feature = "mp3")]
+
+mod common;
+
+use samplerust::codec::{self, mp3, FileFormat, StreamDecoder};
+use samplerust::DecodeError;
+
+/// MSB-first bit packer, as MP3 packs everything.
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    bits: usize,
+}
+
+impl BitWriter {
+    fn put(&mut self, value: u32, n: u32) {
+        for i in (0..n).rev() {
+            if self.bits.is_multiple_of(8) {
+                self.bytes.push(0);
+            }
+            let bit = ((value >> i) & 1) as u8;
+            *self.bytes.last_mut().unwrap() |= bit << (7 - self.bits % 8);
+            self.bits += 1;
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Stream {
+    mpeg1: bool,
+    /// Header bit-rate and sample-rate indices.
+    bitrate: u8,
+    rate: u8,
+    /// 0 stereo, 1 joint stereo, 3 mono.
+    mode: u8,
+    mode_extension: u8,
+}
+
+impl Stream {
+    /// 48 kHz at 128 kbit/s: 384-byte frames.
+    fn mono() -> Self {
+        Stream {
+            mpeg1: true,
+            bitrate: 9,
+            rate: 1,
+            mode: 3,
+            mode_extension: 0,
+        }
+    }
+
+    fn stereo(mode: u8, mode_extension: u8) -> Self {
+        Stream {
+            mode,
+            mode_extension,
+            ..Stream::mono()
+        }
+    }
+
+    /// MPEG-2 at 22.05 kHz and 64 kbit/s: 208-byte frames.
+    fn lsf() -> Self {
+        Stream {
+            mpeg1: false,
+            bitrate: 8,
+            rate: 0,
+            ..Stream::mono()
+        }
+    }
+
+    fn channels(&self) -> usize {
+        if self.mode == 3 {
+            1
+        } else {
+            2
+        }
+    }
+
+    fn granules(&self) -> usize {
+        if self.mpeg1 {
+            2
+        } else {
+            1
+        }
+    }
+
+    fn sample_rate(&self) -> f64 {
+        if self.mpeg1 {
+            48000.0
+        } else {
+            22050.0
+        }
+    }
+
+    fn frame_size(&self) -> usize {
+        if self.mpeg1 {
+            384
+        } else {
+            208
+        }
+    }
+
+    fn side_info_size(&self) -> usize {
+        match (self.mpeg1, self.channels()) {
+            (true, 1) => 17,
+            (true, _) => 32,
+            (false, 1) => 9,
+            (false, _) => 17,
+        }
+    }
+
+    fn header(&self) -> [u8; 4] {
+        let version = if self.mpeg1 { 3 } else { 2 };
+        [
+            0xff,
+            0xe0 | version << 3 | 1 << 1 | 1,
+            self.bitrate << 4 | self.rate << 2,
+            self.mode << 6 | self.mode_extension << 4,
+        ]
+    }
+}
+
+/// A long line, and the short line that sounds at the same frequency.
+const TONE: usize = 47;
+const SHORT_TONE: usize = 15;
+
+/// One channel of one granule: quantised values of -1, 0 or 1, coded
+/// entirely in the count1 region with table B.
+#[derive(Clone)]
+struct Granule {
+    values: [i8; 576],
+    block_type: u8,
+    global_gain: u32,
+}
+
+impl Granule {
+    fn silent() -> Self {
+        Granule {
+            values: [0; 576],
+            block_type: 0,
+            global_gain: 202,
+        }
+    }
+
+    /// A constant coefficient on a long-block line. Its phase turns a
+    /// quarter cycle each granule, so `TONE` sounds at `48 * fs / 1152` Hz.
+    fn long(line: usize) -> Self {
+        let mut g = Granule::silent();
+        g.values[line] = 1;
+        g
+    }
+
+    /// A constant coefficient on `line` of each short window, within the
+    /// 48 kHz band from 12 to 16. `SHORT_TONE` matches `TONE`.
+    fn short(line: usize) -> Self {
+        assert!((12..16).contains(&line));
+        let mut g = Granule::silent();
+        for w in 0..3 {
+            g.values[36 + 4 * w + line - 12] = 1;
+        }
+        g.block_type = 2;
+        g
+    }
+
+    fn with_block_type(mut self, block_type: u8) -> Self {
+        self.block_type = block_type;
+        self
+    }
+
+    fn main_data(&self, w: &mut BitWriter) -> usize {
+        let start = w.bits;
+        let end = self
+            .values
+            .iter()
+            .rposition(|&v| v != 0)
+            .map_or(0, |i| i / 4 * 4 + 4);
+        for quad in self.values[..end].chunks(4) {
+            let pattern = quad
+                .iter()
+                .fold(0, |acc, &v| (acc << 1) | u32::from(v != 0));
+            w.put(15 - pattern, 4);
+            for &v in quad.iter().filter(|&&v| v != 0) {
+                w.put(u32::from(v < 0), 1);
+            }
+        }
+        w.bits - start
+    }
+
+    fn side_info(&self, w: &mut BitWriter, stream: &Stream, bits: usize) {
+        w.put(bits as u32, 12);
+        w.put(0, 9);
+        w.put(self.global_gain, 8);
+        w.put(0, if stream.mpeg1 { 4 } else { 9 });
+        if self.block_type == 0 {
+            w.put(0, 1);
+            w.put(0, 15);
+            w.put(0, 7);
+        } else {
+            w.put(1, 1);
+            w.put(u32::from(self.block_type), 2);
+            w.put(0, 1);
+            w.put(0, 10);
+            w.put(0, 9);
+        }
+        if stream.mpeg1 {
+            w.put(0, 1);
+        }
+        w.put(0, 1);
+        w.put(1, 1);
+    }
+}
+
+/// Encodes `granules`, each a list of channels. Frame `k`'s main data
+/// starts up to `back(k)` bytes early, in the bit reservoir.
+fn encode(stream: Stream, granules: &[Vec<Granule>], back: impl Fn(usize) -> usize) -> Vec<u8> {
+    let per_frame = stream.granules();
+    assert!(granules.len().is_multiple_of(per_frame));
+    let slot = stream.frame_size() - 4 - stream.side_info_size();
+    let max_back = if stream.mpeg1 { 511 } else { 255 };
+
+    let mut main = Vec::new();
+    let mut sides = Vec::new();
+    for (k, frame) in granules.chunks(per_frame).enumerate() {
+        let mut data = BitWriter::default();
+        let mut lengths = Vec::new();
+        for granule in frame {
+            for channel in granule {
+                lengths.push(channel.main_data(&mut data));
+            }
+        }
+        let slot_start = k * slot;
+        let start = main
+            .len()
+            .max(slot_start.saturating_sub(back(k).min(max_back)));
+        assert!(start <= slot_start);
+        main.resize(start, 0);
+        main.extend_from_slice(&data.bytes);
+        assert!(main.len() <= slot_start + slot, "frame {k} overflows");
+
+        let mut side = BitWriter::default();
+        if stream.mpeg1 {
+            side.put((slot_start - start) as u32, 9);
+            side.put(0, if stream.channels() == 1 { 5 } else { 3 });
+            side.put(0, 4 * stream.channels() as u32);
+        } else {
+            side.put((slot_start - start) as u32, 8);
+            side.put(0, stream.channels() as u32);
+        }
+        let mut lengths = lengths.into_iter();
+        for granule in frame {
+            for channel in granule {
+                channel.side_info(&mut side, &stream, lengths.next().unwrap());
+            }
+        }
+        assert_eq!(side.bytes.len(), stream.side_info_size());
+        sides.push(side.bytes);
+    }
+
+    main.resize(sides.len() * slot, 0);
+    let mut out = Vec::new();
+    for (side, data) in sides.iter().zip(main.chunks(slot)) {
+        out.extend_from_slice(&stream.header());
+        out.extend_from_slice(side);
+        out.extend_from_slice(data);
+    }
+    out
+}
+
+/// An Info frame with a LAME tag recording `delay` and `padding`.
+fn info_frame(stream: Stream, frames: u32, delay: u32, padding: u32) -> Vec<u8> {
+    let mut frame = stream.header().to_vec();
+    frame.resize(4 + stream.side_info_size(), 0);
+    frame.extend_from_slice(b"Info");
+    frame.extend_from_slice(&1u32.to_be_bytes());
+    frame.extend_from_slice(&frames.to_be_bytes());
+    let mut lame = *b"LAME3.100\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0";
+    let gapless = delay << 12 | padding;
+    lame[21..24].copy_from_slice(&gapless.to_be_bytes()[1..]);
+    frame.extend_from_slice(&lame);
+    frame.resize(stream.frame_size(), 0);
+    frame
+}
+
+fn mono(granules: impl IntoIterator<Item = Granule>) -> Vec<Vec<Granule>> {
+    granules.into_iter().map(|g| vec![g]).collect()
+}
+
+fn assert_peak(samples: &[f32], sample_rate: f64, expected: f64) {
+    let spectrum = common::power_spectrum(samples);
+    let peak = common::peak_frequency(&spectrum, sample_rate);
+    assert!(
+        (peak - expected).abs() < 10.0,
+        "peak at {peak} Hz, expected {expected} Hz"
+    );
+    let band = common::band_energy(&spectrum, sample_rate, expected - 200.0, expected + 200.0);
+    assert!(band > 0.95, "only {band} of the energy near {expected} Hz");
+}
+
+#[test]
+fn decodes_a_long_block_tone() {
+    let bytes = encode(
+        Stream::mono(),
+        &mono((0..40).map(|_| Granule::long(TONE))),
+        |_| 0,
+    );
+    let sample = mp3::decode(&bytes).unwrap();
+    assert_eq!(sample.channels(), 1);
+    assert_eq!(sample.sample_rate(), 48000);
+    assert_eq!(sample.frames(), 20 * 1152);
+    assert_peak(&sample.as_slice()[2048..], 48000.0, 48.0 * 48000.0 / 1152.0);
+    assert!(common::rms(&sample.as_slice()[2048..]) > 0.05);
+}
+
+#[test]
+fn block_switching_keeps_the_tone() {
+    // Long, start, short, stop, repeated.
+    let pattern = (0..40).map(|i| match i % 8 {
+        0..=2 => Granule::long(TONE),
+        3 => Granule::long(TONE).with_block_type(1),
+        4..=6 => Granule::short(SHORT_TONE),
+        _ => Granule::long(TONE).with_block_type(3),
+    });
+    let bytes = encode(Stream::mono(), &mono(pattern), |_| 0);
+    let sample = mp3::decode(&bytes).unwrap();
+    assert_peak(&sample.as_slice()[2048..], 48000.0, 16.0 * 48000.0 / 384.0);
+}
+
+#[test]
+fn decodes_stereo_modes() {
+    let channel = |sample: &samplerust::Sample, ch: usize| -> Vec<f32> {
+        sample
+            .as_slice()
+            .iter()
+            .skip(ch)
+            .step_by(2)
+            .copied()
+            .collect()
+    };
+    let granules = |left: Granule, right: Granule| vec![vec![left, right]; 20];
+
+    // Independent channels.
+    let bytes = encode(
+        Stream::stereo(0, 0),
+        &granules(Granule::long(TONE), Granule::silent()),
+        |_| 0,
+    );
+    let sample = mp3::decode(&bytes).unwrap();
+    assert_eq!(sample.channels(), 2);
+    assert!(common::rms(&channel(&sample, 0)) > 0.05);
+    assert!(channel(&sample, 1).iter().all(|&v| v == 0.0));
+
+    // Mid/side: mid alone is centred, side alone is out of phase.
+    let bytes = encode(
+        Stream::stereo(1, 2),
+        &granules(Granule::long(TONE), Granule::silent()),
+        |_| 0,
+    );
+    let sample = mp3::decode(&bytes).unwrap();
+    assert!(common::rms(&channel(&sample, 0)) > 0.05);
+    assert_eq!(channel(&sample, 0), channel(&sample, 1));
+
+    let bytes = encode(
+        Stream::stereo(1, 2),
+        &granules(Granule::silent(), Granule::long(TONE)),
+        |_| 0,
+    );
+    let sample = mp3::decode(&bytes).unwrap();
+    let negated: Vec<f32> = channel(&sample, 1).iter().map(|v| -v).collect();
+    assert!(common::rms(&negated) > 0.05);
+    assert_eq!(channel(&sample, 0), negated);
+}
+
+#[test]
+fn decodes_mpeg2_low_sample_rates() {
+    let stream = Stream::lsf();
+    let bytes = encode(stream, &mono((0..40).map(|_| Granule::long(TONE))), |_| 0);
+    let sample = mp3::decode(&bytes).unwrap();
+    assert_eq!(sample.sample_rate(), 22050);
+    assert_eq!(sample.frames(), 40 * 576);
+    assert_peak(
+        &sample.as_slice()[2048..],
+        stream.sample_rate(),
+        48.0 * stream.sample_rate() / 1152.0,
+    );
+}
+
+#[test]
+fn reads_main_data_from_the_bit_reservoir() {
+    let tones = mono((0..24).map(|i| Granule::long(20 + 7 * i)));
+    let plain = mp3::decode(&encode(Stream::mono(), &tones, |_| 0)).unwrap();
+    let reservoir = encode(Stream::mono(), &tones, |k| k * 137 % 512);
+    assert_ne!(reservoir, encode(Stream::mono(), &tones, |_| 0));
+    let sample = mp3::decode(&reservoir).unwrap();
+    assert_eq!(sample.as_slice(), plain.as_slice());
+}
+
+#[test]
+fn trims_delay_and_padding_from_the_lame_tag() {
+    let stream = Stream::mono();
+    let audio = encode(stream, &mono((0..12).map(|_| Granule::long(TONE))), |_| 0);
+    let plain = mp3::decode(&audio).unwrap();
+    assert_eq!(plain.frames(), 6 * 1152);
+
+    let (delay, padding) = (576, 700);
+    let mut bytes = info_frame(stream, 6, delay, padding);
+    bytes.extend_from_slice(&audio);
+    let total = 6 * 1152 - (delay + padding) as usize;
+    let decoder = mp3::Decoder::new(&bytes[..]).unwrap();
+    assert_eq!(decoder.frames(), Some(total as u64));
+
+    // The decoder's own delay is trimmed along with the encoder's.
+    let start = delay as usize + 529;
+    let expected = &plain.as_slice()[start..start + total];
+    assert_eq!(mp3::decode(&bytes).unwrap().as_slice(), expected);
+
+    let mut decoder = mp3::Decoder::new(&bytes[..]).unwrap();
+    let mut streamed = Vec::new();
+    let mut block = [0.0; 77];
+    loop {
+        let n = decoder.read(&mut block).unwrap();
+        if n == 0 {
+            break;
+        }
+        streamed.extend_from_slice(&block[..n]);
+    }
+    assert_eq!(streamed, expected);
+
+    // A forged frame count is trusted only as far as the audio goes.
+    let mut bytes = info_frame(stream, u32::MAX, 0, 0);
+    bytes.extend_from_slice(&audio);
+    let decoder = mp3::Decoder::new(&bytes[..]).unwrap();
+    assert_eq!(decoder.frames(), Some(u64::from(u32::MAX) * 1152));
+    let sample = mp3::decode(&bytes).unwrap();
+    assert_eq!(sample.as_slice(), &plain.as_slice()[529..]);
+
+    // An Info frame without a LAME tag is skipped but trims nothing.
+    let mut bytes = info_frame(stream, 6, 0, 0);
+    bytes[4 + stream.side_info_size() + 12..][..4].copy_from_slice(b"\0\0\0\0");
+    bytes.extend_from_slice(&audio);
+    assert_eq!(mp3::decode(&bytes).unwrap().as_slice(), plain.as_slice());
+}
+
+#[test]
+fn skips_id3_tags_and_leading_junk() {
+    let audio = encode(
+        Stream::mono(),
+        &mono((0..8).map(|_| Granule::long(TONE))),
+        |_| 0,
+    );
+    let plain = mp3::decode(&audio).unwrap();
+    assert_eq!(codec::detect(&audio), Some(FileFormat::Mp3));
+
+    // A 300-byte tag body, its size in 7-bit bytes, then junk with a
+    // false sync before the first frame.
+    let mut bytes = b"ID3\x04\x00\x00\x00\x00\x02\x2c".to_vec();
+    bytes.resize(10 + 300, 0xff);
+    bytes.extend_from_slice(&[0x12, 0xff, 0xfb, 0x00, 0x34]);
+    bytes.extend_from_slice(&audio);
+    assert_eq!(codec::detect(&bytes), Some(FileFormat::Mp3));
+    let sample = codec::decode(&bytes).unwrap();
+    assert_eq!(sample.as_slice(), plain.as_slice());
+}
+
+#[test]
+fn rejects_truncated_and_invalid_streams() {
+    let bytes = encode(
+        Stream::mono(),
+        &mono((0..8).map(|_| Granule::long(TONE))),
+        |_| 0,
+    );
+    assert!(matches!(
+        mp3::decode(&bytes[..bytes.len() - 100]),
+        Err(DecodeError::Truncated(_))
+    ));
+    assert!(matches!(
+        mp3::decode(&[0x5a; 2000]),
+        Err(DecodeError::Malformed(_))
+    ));
+    assert!(matches!(
+        mp3::decode(b"ID3\x04\x00\x00\x00\x00\x02\x2c\x00"),
+        Err(DecodeError::Truncated(_))
+    ));
+}
@@ -0,0 +1,211 @@
+use samplerust::{Engine, EngineConfig, PanLaw, PanMode, Sample, TriggerParams};
+
+const LAWS: [PanLaw; 3] = [PanLaw::ConstantPower, PanLaw::Compromise, PanLaw::Linear];
+
+/// A sample holding `levels` in each frame, one per channel.
+fn constant(levels: &[f32]) -> Sample {
+    let data = levels.iter().copied().cycle().take(levels.len() * 1_000);
+    Sample::from_interleaved(data.collect(), levels.len(), 48_000).unwrap()
+}
+
+/// Plays each sample with its params for one block and returns the block.
+fn play(config: EngineConfig, voices: &[(Sample, TriggerParams)]) -> Vec<f32> {
+    let channels = config.channels;
+    let (mut engine, mut ctl) = Engine::new(config);
+    for (sample, params) in voices {
+        let id = ctl.add_sample(sample.clone()).unwrap();
+        ctl.trigger(id, *params).unwrap();
+    }
+    let mut out = vec![0.0; 256 * channels];
+    engine.render(&mut out);
+    out
+}
+
+fn panned(pan: f32, pan_mode: PanMode) -> TriggerParams {
+    TriggerParams {
+        pan: Some(pan),
+        pan_mode,
+        ..TriggerParams::default()
+    }
+}
+
+fn with_law(pan_law: PanLaw) -> EngineConfig {
+    EngineConfig {
+        pan_law,
+        ..EngineConfig::default()
+    }
+}
+
+fn db(v: f32) -> f32 {
+    20.0 * v.log10()
+}
+
+#[test]
+fn laws_attenuate_the_centre() {
+    for (law, centre) in LAWS.into_iter().zip([-3.01, -4.52, -6.02]) {
+        let (l, r) = law.gains(0.0);
+        assert_eq!(l, r, "{law:?}");
+        assert!((db(l) - centre).abs() < 0.01, "{law:?}: {}", db(l));
+        assert_eq!(law.gains(-1.0), (1.0, 0.0), "{law:?}");
+        assert_eq!(law.gains(1.0), (0.0, 1.0), "{law:?}");
+    }
+    for p in [-0.9, -0.4, 0.3, 0.8] {
+        let (l, r) = PanLaw::ConstantPower.gains(p);
+        assert!((l * l + r * r - 1.0).abs() < 1e-6, "{p}");
+        let (l, r) = PanLaw::Linear.gains(p);
+        assert!((l + r - 1.0).abs() < 1e-6, "{p}");
+    }
+}
+
+#[test]
+fn mono_voices_follow_the_law() {
+    let sample = constant(&[1.0]);
+    for law in LAWS {
+        // An unpanned voice plays at unity on both sides.
+        let out = play(with_law(law), &[(sample.clone(), TriggerParams::default())]);
+        assert!(out.iter().all(|&v| v == 1.0), "{law:?}");
+
+        for p in [-1.0, -0.5, 0.0, 0.25, 1.0] {
+            let out = play(
+                with_law(law),
+                &[(sample.clone(), panned(p, PanMode::Balance))],
+            );
+            let (l, r) = law.gains(p);
+            for frame in out.chunks_exact(2) {
+                assert!((frame[0] - l).abs() < 1e-6, "{law:?} {p}");
+                assert!((frame[1] - r).abs() < 1e-6, "{law:?} {p}");
+            }
+        }
+    }
+
+    // Centred, each law sits its attenuation below a hard pan, whose near
+    // side is at unity.
+    for (law, centre) in LAWS.into_iter().zip([-3.01, -4.52, -6.02]) {
+        let out = play(
+            with_law(law),
+            &[(sample.clone(), panned(0.0, PanMode::Balance))],
+        );
+        assert!(
+            (db(out[0]) - centre).abs() < 0.01,
+            "{law:?}: {}",
+            db(out[0])
+        );
+        assert_eq!(out[0], out[1], "{law:?}");
+        let out = play(
+            with_law(law),
+            &[(sample.clone(), panned(-1.0, PanMode::Balance))],
+        );
+        assert_eq!(out[..2], [1.0, 0.0], "{law:?}");
+    }
+
+    // Constant power keeps the summed power of a mono voice steady.
+    for p in [-1.0, -0.6, 0.0, 0.3, 1.0] {
+        let out = play(
+            EngineConfig::default(),
+            &[(sample.clone(), panned(p, PanMode::Balance))],
+        );
+        assert!(
+            (out[0] * out[0] + out[1] * out[1] - 1.0).abs() < 1e-6,
+            "{p}"
+        );
+    }
+}
+
+#[test]
+fn stereo_samples_balance_or_pan() {
+    let sample = constant(&[1.0, 0.5]);
+    let frame = |p, mode| {
+        let out = play(
+            EngineConfig::default(),
+            &[(sample.clone(), panned(p, mode))],
+        );
+        [out[510], out[511]]
+    };
+
+    // Balance gives each channel its side's gain from the law: centred,
+    // both sit 3 dB down, and panning brings neither over.
+    let c = PanLaw::ConstantPower.gains(0.0).0;
+    let [l, r] = frame(0.0, PanMode::Balance);
+    assert!(
+        (l - c).abs() < 1e-6 && (r - 0.5 * c).abs() < 1e-6,
+        "{l} {r}"
+    );
+    let [l, r] = frame(-0.5, PanMode::Balance);
+    let (gl, gr) = PanLaw::ConstantPower.gains(-0.5);
+    assert!((l - gl).abs() < 1e-6, "{l}");
+    assert!((r - 0.5 * gr).abs() < 1e-6, "{r}");
+    assert_eq!(frame(-1.0, PanMode::Balance), [1.0, 0.0]);
+
+    // True panning places each channel as a source of its own, hard to its
+    // side when the voice is centred.
+    assert_eq!(frame(0.0, PanMode::TruePan), [1.0, 0.5]);
+
+    // True panning moves both channels across: hard left, both play there.
+    assert_eq!(frame(-1.0, PanMode::TruePan), [1.5, 0.0]);
+    assert_eq!(frame(1.0, PanMode::TruePan), [0.0, 1.5]);
+    // Half way, the left channel is hard left and the right one centred.
+    let [l, r] = frame(-0.5, PanMode::TruePan);
+    let c = PanLaw::ConstantPower.gains(0.0).0;
+    assert!((l - (1.0 + 0.5 * c)).abs() < 1e-6, "{l}");
+    assert!((r - 0.5 * c).abs() < 1e-6, "{r}");
+}
+
+#[test]
+fn voices_play_on_their_bus() {
+    let six = EngineConfig {
+        channels: 6,
+        ..EngineConfig::default()
+    };
+    let on = |bus, pan| TriggerParams {
+        bus: Some(bus),
+        pan: Some(pan),
+        ..TriggerParams::default()
+    };
+    // A kick on the second pair, a snare on the third and a pad spread over
+    // every channel, none of them panned.
+    let unpanned = |bus| TriggerParams {
+        pan: None,
+        ..on(bus, 0.0)
+    };
+    let out = play(
+        six.clone(),
+        &[
+            (constant(&[1.0]), unpanned(1)),
+            (constant(&[0.5, 0.25]), unpanned(2)),
+            (constant(&[0.125]), TriggerParams::default()),
+        ],
+    );
+    for frame in out.chunks_exact(6) {
+        assert_eq!(frame, [0.125, 0.125, 1.125, 1.125, 0.625, 0.375]);
+    }
+
+    // Panning stays within the bus.
+    let out = play(six, &[(constant(&[1.0]), on(1, 1.0))]);
+    for frame in out.chunks_exact(6) {
+        assert_eq!(frame, [0.0, 0.0, 0.0, 1.0, 0.0, 0.0]);
+    }
+}
+
+#[test]
+fn bus_numbers_wrap() {
+    let config = |channels| EngineConfig {
+        channels,
+        ..EngineConfig::default()
+    };
+    let on = |bus| TriggerParams {
+        bus: Some(bus),
+        ..TriggerParams::default()
+    };
+    let sample = constant(&[1.0, 0.5]);
+
+    // Three buses: the fifth is the second.
+    let out = play(config(6), &[(sample.clone(), on(4))]);
+    assert_eq!(out[..6], [0.0, 0.0, 1.0, 0.5, 0.0, 0.0]);
+
+    // An odd channel out is a bus of its own, and a stereo sample folds
+    // onto it.
+    let out = play(config(5), &[(sample.clone(), on(2))]);
+    assert_eq!(out[..5], [0.0, 0.0, 0.0, 0.0, 1.5]);
+    let out = play(config(5), &[(sample, on(5))]);
+    assert_eq!(out[..5], [0.0, 0.0, 0.0, 0.0, 1.5]);
+}
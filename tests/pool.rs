@@ -0,0 +1,194 @@
+use samplerust::{
+    Command, Controller, Engine, EngineConfig, Sample, SampleId, StealPolicy, TriggerParams,
+    VoicePool,
+};
+
+fn dc(frames: usize) -> Sample {
+    Sample::from_interleaved(vec![1.0; frames], 1, 1_000).unwrap()
+}
+
+fn note(note: u8, gain: f32) -> TriggerParams {
+    TriggerParams {
+        note,
+        gain,
+        ..TriggerParams::default()
+    }
+}
+
+fn engine(voices: usize, policy: StealPolicy) -> (Engine, Controller, SampleId) {
+    let (engine, mut ctl) = Engine::new(EngineConfig {
+        sample_rate: 1_000,
+        channels: 1,
+        voices,
+        steal_policy: policy,
+        steal_fade: 0.004,
+        ..EngineConfig::default()
+    });
+    let id = ctl.add_sample(dc(1_000)).unwrap();
+    (engine, ctl, id)
+}
+
+#[test]
+fn oldest_voice_is_stolen_with_a_fade() {
+    let (mut engine, mut ctl, id) = engine(2, StealPolicy::Oldest);
+    let first = ctl.trigger(id, note(60, 1.0)).unwrap();
+    let mut out = [0.0; 1];
+    engine.render(&mut out);
+    ctl.trigger(id, note(61, 1.0)).unwrap();
+    engine.render(&mut out);
+    ctl.trigger(id, note(62, 1.0)).unwrap();
+
+    // The stolen voice ramps 1.0 -> 0 over four frames beside the two
+    // playing voices, instead of dropping out in one step.
+    let mut out = [0.0; 6];
+    engine.render(&mut out);
+    assert_eq!(out, [3.0, 2.75, 2.5, 2.25, 2.0, 2.0]);
+    assert_eq!(engine.active_voices(), 2);
+    assert_eq!(engine.pool().fading(), 0);
+
+    // The stolen id no longer addresses anything.
+    ctl.stop(first).unwrap();
+    engine.render(&mut out);
+    assert_eq!(out, [2.0; 6]);
+}
+
+#[test]
+fn quietest_voice_is_stolen() {
+    let (mut engine, mut ctl, id) = engine(2, StealPolicy::Quietest);
+    ctl.trigger(id, note(60, 0.8)).unwrap();
+    let quiet = ctl.trigger(id, note(61, 0.1)).unwrap();
+    let mut out = [0.0; 1];
+    engine.render(&mut out);
+    ctl.trigger(id, note(62, 0.5)).unwrap();
+    engine.render(&mut out);
+
+    // Stopping the quiet voice has no effect: it was the one stolen.
+    ctl.stop(quiet).unwrap();
+    let mut out = [0.0; 8];
+    engine.render(&mut out);
+    assert!((out[7] - 1.3).abs() < 1e-6, "{out:?}");
+}
+
+#[test]
+fn same_note_retriggers_in_place() {
+    let (mut engine, mut ctl, id) = engine(4, StealPolicy::SameNote);
+    ctl.trigger(id, note(36, 1.0)).unwrap();
+    ctl.trigger(id, note(36, 1.0)).unwrap();
+    ctl.trigger(id, note(38, 1.0)).unwrap();
+    let mut out = [0.0; 16];
+    engine.render(&mut out);
+    assert_eq!(engine.active_voices(), 2);
+    assert_eq!(out[15], 2.0);
+}
+
+#[test]
+fn refuse_drops_new_notes() {
+    let (mut engine, mut ctl, id) = engine(1, StealPolicy::Refuse);
+    ctl.trigger(id, note(60, 1.0)).unwrap();
+    ctl.trigger(id, note(61, 0.5)).unwrap();
+    let mut out = [0.0; 4];
+    engine.render(&mut out);
+    assert_eq!(out, [1.0; 4]);
+
+    ctl.send(Command::SetStealPolicy(StealPolicy::Oldest))
+        .unwrap();
+    ctl.trigger(id, note(61, 0.5)).unwrap();
+    let mut out = [0.0; 8];
+    engine.render(&mut out);
+    assert_eq!(out[7], 0.5);
+}
+
+#[test]
+fn pool_reports_refusal_and_tail_overflow() {
+    let (_engine, mut ctl, id) = engine(1, StealPolicy::Refuse);
+    let mut pool = VoicePool::new(1, StealPolicy::Refuse, 10);
+    assert!(pool.trigger(ctl.next_voice_id(), id, &note(60, 1.0)));
+    assert!(!pool.trigger(ctl.next_voice_id(), id, &note(60, 1.0)));
+
+    // With a single tail slot, each steal cuts the previous tail.
+    pool.set_policy(StealPolicy::Oldest);
+    for _ in 0..3 {
+        assert!(pool.trigger(ctl.next_voice_id(), id, &note(60, 1.0)));
+    }
+    assert_eq!(pool.active(), 1);
+    assert_eq!(pool.fading(), 1);
+}
+
+fn in_group(group: Option<u32>, choke_fade: f32) -> TriggerParams {
+    TriggerParams {
+        choke_group: group,
+        choked_by: group,
+        choke_fade,
+        ..note(60, 1.0)
+    }
+}
+
+#[test]
+fn choke_groups_cut_each_other_off() {
+    let (mut engine, mut ctl, id) = engine(4, StealPolicy::Oldest);
+    let open = ctl.trigger(id, in_group(Some(1), 0.004)).unwrap();
+    ctl.trigger(id, in_group(Some(2), 0.004)).unwrap();
+    ctl.trigger(id, in_group(None, 0.004)).unwrap();
+    let mut out = [0.0; 1];
+    engine.render(&mut out);
+
+    // Closing the hat fades the open one out over its choke time and
+    // leaves the other groups alone.
+    ctl.trigger(id, in_group(Some(1), 0.002)).unwrap();
+    let mut out = [0.0; 6];
+    engine.render(&mut out);
+    assert_eq!(out, [4.0, 3.75, 3.5, 3.25, 3.0, 3.0]);
+    assert_eq!(engine.active_voices(), 3);
+
+    // The choked id no longer addresses anything.
+    ctl.stop(open).unwrap();
+    engine.render(&mut out);
+    assert_eq!(out, [3.0; 6]);
+
+    // A group's own later note chokes it in turn, here at once.
+    ctl.send(Command::StopAll).unwrap();
+    ctl.trigger(id, in_group(Some(3), 0.0)).unwrap();
+    engine.render(&mut out);
+    ctl.trigger(id, in_group(Some(3), 0.0)).unwrap();
+    engine.render(&mut out);
+    assert_eq!(out, [1.0; 6]);
+    assert_eq!(engine.pool().fading(), 0);
+}
+
+#[test]
+fn one_shots_play_through_note_off() {
+    let (mut engine, mut ctl, id) = engine(2, StealPolicy::Oldest);
+    let gated = ctl.trigger(id, note(60, 1.0)).unwrap();
+    let shot = ctl
+        .trigger(
+            id,
+            TriggerParams {
+                one_shot: true,
+                ..note(61, 0.5)
+            },
+        )
+        .unwrap();
+    let mut out = [0.0; 100];
+    engine.render(&mut out);
+    ctl.release(gated).unwrap();
+    ctl.release(shot).unwrap();
+    engine.render(&mut out);
+    assert_eq!(out[99], 0.5);
+    assert_eq!(engine.active_voices(), 1);
+
+    // It still stops when told to, and at the end of the sample.
+    ctl.stop(shot).unwrap();
+    engine.render(&mut out);
+    assert_eq!(engine.active_voices(), 0);
+    ctl.trigger(
+        id,
+        TriggerParams {
+            one_shot: true,
+            ..note(61, 0.5)
+        },
+    )
+    .unwrap();
+    let mut out = [0.0; 1_100];
+    engine.render(&mut out);
+    assert_eq!(out[999..1_001], [0.5, 0.0]);
+}
@@ -0,0 +1,260 @@
+//! Checks that the render path never touches the heap.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+
+use samplerust::{
+    Adsr, Callback, Command, Delay, DelayTime, DeviceConfig, EffectChain, EffectSlot, Engine,
+    EngineConfig, FilterMode, FilterParams, Interpolation, Lfo, LfoRate, LfoShape, LoopMode,
+    LoopRegion, ModDestination, ModMatrix, ModSource, PanMode, Reverb, Sample, StreamedSample,
+    StretchMode, TimeStretch, TriggerParams,
+};
+
+/// Counts allocations and deallocations made by the current thread.
+struct CountingAllocator;
+
+thread_local! {
+    static HEAP_OPS: Cell<usize> = const { Cell::new(0) };
+}
+
+fn count() {
+    let _ = HEAP_OPS.try_with(|c| c.set(c.get() + 1));
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        count();
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        count();
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        count();
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+/// Runs `f` and returns how many heap operations it performed.
+fn heap_ops(f: impl FnOnce()) -> usize {
+    let before = HEAP_OPS.with(Cell::get);
+    f();
+    HEAP_OPS.with(Cell::get) - before
+}
+
+fn sine(frames: usize, channels: usize) -> Sample {
+    let data = (0..frames * channels)
+        .map(|i| ((i / channels) as f32 * 0.05).sin())
+        .collect();
+    Sample::from_interleaved(data, channels, 48_000).unwrap()
+}
+
+#[test]
+fn render_does_not_allocate() {
+    let (mut engine, mut ctl) = Engine::new(EngineConfig {
+        voices: 8,
+        max_block: 200,
+        ..EngineConfig::default()
+    });
+    ctl.set_send(1, Box::new(Delay::new(DelayTime::Beats(0.25))))
+        .unwrap();
+    let mono = ctl.add_sample(sine(4_800, 1)).unwrap();
+    let stereo = ctl
+        .add_sample(sine(2_400, 2).with_loop_region(LoopRegion {
+            start: 200,
+            end: 2_000,
+            mode: LoopMode::Forward,
+            crossfade: 100,
+        }))
+        .unwrap();
+    let mut out = vec![0.0; 512 * 2];
+
+    // Queue a busy mix of commands before each block, then count only the
+    // render call, which is what runs on the audio thread.
+    for block in 0..40 {
+        let sample = if block % 2 == 0 { mono } else { stereo };
+        let interpolation = [
+            Interpolation::None,
+            Interpolation::Linear,
+            Interpolation::Cubic,
+            Interpolation::Sinc,
+        ][block % 4];
+        let params = TriggerParams {
+            rate: 0.5 + block as f64 * 0.1,
+            interpolation,
+            filter: (block % 3 != 1).then_some(FilterParams {
+                mode: FilterMode::BandPass,
+                cutoff: 500.0,
+                envelope: Adsr::new(0.001, 0.005, 0.2, 0.01),
+                envelope_depth: 3_600.0,
+                ..FilterParams::default()
+            }),
+            pan: (block % 6 != 5).then_some((block % 5) as f32 / 2.0 - 1.0),
+            pan_mode: if block % 2 == 0 {
+                PanMode::TruePan
+            } else {
+                PanMode::Balance
+            },
+            bus: (block % 4 != 0).then_some(block % 4),
+            choke_group: (block % 3 == 0).then_some(1),
+            choked_by: (block % 3 != 2).then_some(1),
+            one_shot: block % 7 == 0,
+            sends: [(block % 3) as f32 * 0.3, 0.5],
+            stretch: (block % 4 == 1).then_some(TimeStretch {
+                mode: if block % 8 == 1 {
+                    StretchMode::Wsola
+                } else {
+                    StretchMode::PhaseVocoder
+                },
+                ratio: 1.25,
+                semitones: 2.0,
+            }),
+            ..TriggerParams::default()
+        };
+        // So do swapping and clearing effects, and moving their parameters.
+        match block % 10 {
+            1 => ctl
+                .set_voice_inserts(|| {
+                    let chain = EffectChain::new()
+                        .with(Delay::new(DelayTime::Beats(0.125)))
+                        .with(Reverb::new());
+                    Box::new(chain)
+                })
+                .unwrap(),
+            3 => ctl.set_send(0, Box::new(Reverb::new())).unwrap(),
+            4 => {
+                ctl.set_master(Box::new(Reverb::new())).unwrap();
+                ctl.set_bus(0, Box::new(Delay::new(DelayTime::Beats(0.5))))
+                    .unwrap();
+            }
+            5 => {
+                ctl.set_param(EffectSlot::Voice, 2, 2_000.0).unwrap();
+                ctl.set_param(EffectSlot::Send(1), 0, 0.7).unwrap();
+                ctl.set_param(EffectSlot::Bus(0), 0, 0.4).unwrap();
+                ctl.set_param(EffectSlot::Master, 3, 0.2).unwrap();
+            }
+            6 => ctl.set_tempo(90.0 + block as f64).unwrap(),
+            7 => {
+                ctl.clear_master().unwrap();
+                ctl.clear_bus(0).unwrap();
+            }
+            8 => ctl.clear_voice_inserts().unwrap(),
+            9 => ctl.clear_send(0).unwrap(),
+            _ => {}
+        }
+        // Swapping matrices retires the old ones from the audio thread.
+        if block % 8 == 2 {
+            let matrix = ModMatrix::new()
+                .with_lfo(1, Lfo::new(LfoShape::SampleAndHold, LfoRate::Beats(0.5)))
+                .route(ModSource::Lfo(0), ModDestination::Pan, 0.5)
+                .route(ModSource::Lfo(1), ModDestination::Pitch, 2.0)
+                .route(ModSource::Envelope, ModDestination::Cutoff, 1_200.0)
+                .route(ModSource::ModWheel, ModDestination::LoopPosition, 100.0)
+                .route(ModSource::Velocity, ModDestination::Start, 50.0)
+                .route(ModSource::Aftertouch, ModDestination::Gain, -6.0);
+            let matrix = if block % 16 == 2 {
+                matrix
+            } else {
+                ModMatrix::new()
+            };
+            ctl.set_mod_matrix(matrix).unwrap();
+            ctl.send(Command::ModWheel {
+                channel: 0,
+                value: block as f32 / 40.0,
+            })
+            .unwrap();
+        }
+        let voice = ctl.trigger(sample, params).unwrap();
+        if block % 3 == 0 {
+            ctl.seek(voice, 100).unwrap();
+        }
+        if block % 5 == 0 {
+            ctl.stop(voice).unwrap();
+        }
+        if block % 4 == 1 {
+            ctl.set_stretch(voice, 0.8).unwrap();
+            ctl.set_pitch_shift(voice, -3.0).unwrap();
+        }
+        let now = ctl.now();
+        let later = ctl
+            .trigger_at(now + 100 + block as u64, sample, params)
+            .unwrap();
+        ctl.release_at(now + 300, later).unwrap();
+        assert_eq!(heap_ops(|| engine.render(&mut out)), 0, "block {block}");
+    }
+}
+
+#[test]
+fn sample_removal_does_not_free_on_the_audio_thread() {
+    let (mut engine, mut ctl) = Engine::new(EngineConfig::default());
+    let id = ctl.add_sample(sine(1_000, 1)).unwrap();
+    ctl.trigger(id, TriggerParams::default()).unwrap();
+    let mut out = vec![0.0; 256];
+    engine.render(&mut out);
+
+    ctl.remove_sample(id).unwrap();
+    ctl.add_sample(sine(1_000, 2)).unwrap();
+    assert_eq!(heap_ops(|| engine.render(&mut out)), 0);
+    assert!(heap_ops(|| assert_eq!(ctl.collect_garbage(), 1)) > 0);
+}
+
+#[test]
+fn streamed_render_does_not_allocate() {
+    let (mut engine, mut ctl) = Engine::new(EngineConfig {
+        voices: 2,
+        stream_buffer: 1_024,
+        ..EngineConfig::default()
+    });
+    let mut streamer = ctl.streamer().unwrap();
+    let head = StreamedSample::new(sine(40_000, 2), 512).unwrap();
+    let id = ctl.add_streamed_sample(head).unwrap();
+    let mut out = vec![0.0; 256 * 2];
+
+    // Steals, seeks and starved blocks all go through the stream buffers.
+    for block in 0..60 {
+        if block % 7 == 0 {
+            let voice = ctl.trigger(id, TriggerParams::default()).unwrap();
+            if block % 2 == 0 {
+                ctl.seek(voice, 20_000).unwrap();
+            }
+        }
+        if block % 3 != 0 {
+            streamer.service();
+        }
+        assert_eq!(heap_ops(|| engine.render(&mut out)), 0, "block {block}");
+    }
+    assert!(engine.underruns() > 0);
+}
+
+#[test]
+fn device_callback_does_not_allocate() {
+    let (engine, mut ctl) = Engine::new(EngineConfig::default());
+    let id = ctl.add_sample(sine(48_000, 2)).unwrap();
+    let mut callback = Callback::new(
+        engine,
+        &DeviceConfig {
+            channels: 6,
+            buffer_frames: Some(256),
+            ..DeviceConfig::default()
+        },
+    );
+    // Oversized and ragged buffers, in floats and in integers.
+    let mut floats = vec![0.0f32; 1_000 * 6 + 5];
+    let mut ints = vec![0i16; 300 * 6];
+    for block in 0..20 {
+        ctl.trigger(id, TriggerParams::default()).unwrap();
+        assert_eq!(
+            heap_ops(|| callback.process(&mut floats)),
+            0,
+            "block {block}"
+        );
+        let ops = heap_ops(|| callback.process_with(&mut ints, |v| (v * 32_767.0) as i16));
+        assert_eq!(ops, 0, "block {block}");
+    }
+}
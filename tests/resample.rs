@@ -0,0 +1,184 @@
+mod common;
+
+use std::f64::consts::PI;
+
+use samplerust::{
+    resample, Engine, EngineConfig, Interpolation, LoopMode, LoopRegion, ResampleQuality, Sample,
+    TriggerParams,
+};
+
+const QUALITIES: [(ResampleQuality, f64, f64); 3] = [
+    // Preset, passband edge and stopband attenuation in dB.
+    (ResampleQuality::Fast, 0.80, 60.0),
+    (ResampleQuality::Balanced, 0.90, 100.0),
+    (ResampleQuality::Best, 0.94, 140.0),
+];
+
+fn tone(freq: f64, rate: u32, frames: usize) -> Sample {
+    Sample::from_interleaved(common::sine(freq, f64::from(rate), frames), 1, rate).unwrap()
+}
+
+/// Least-squares fit of a sine at `freq` to the middle half of `x`: its
+/// amplitude, and the RMS of what is left over.
+fn fit(x: &[f32], freq: f64, rate: u32) -> (f64, f64) {
+    let middle = x.len() / 4..x.len() * 3 / 4;
+    let w = 2.0 * PI * freq / f64::from(rate);
+    let (mut ss, mut sc, mut cc, mut xs, mut xc) = (0.0, 0.0, 0.0, 0.0, 0.0);
+    for i in middle.clone() {
+        let (s, c) = (w * i as f64).sin_cos();
+        let v = f64::from(x[i]);
+        ss += s * s;
+        sc += s * c;
+        cc += c * c;
+        xs += v * s;
+        xc += v * c;
+    }
+    let det = ss * cc - sc * sc;
+    let a = (xs * cc - xc * sc) / det;
+    let b = (xc * ss - xs * sc) / det;
+    let residual: f64 = middle
+        .clone()
+        .map(|i| {
+            let (s, c) = (w * i as f64).sin_cos();
+            (f64::from(x[i]) - a * s - b * c).powi(2)
+        })
+        .sum();
+    (
+        a.hypot(b),
+        (residual / middle.len() as f64).sqrt() * 2f64.sqrt(),
+    )
+}
+
+#[test]
+fn passband_is_flat_and_clean() {
+    for (from, to) in [(44_100, 48_000), (48_000, 44_100), (48_000, 96_000)] {
+        let nyquist = f64::from(from.min(to)) / 2.0;
+        for (quality, edge, attenuation) in QUALITIES {
+            for fraction in [0.01, 0.25, 0.5, edge] {
+                let freq = nyquist * fraction;
+                let out = resample(&tone(freq, from, 8_192), to, quality);
+                assert_eq!(out.sample_rate(), to);
+                let (amplitude, residual) = fit(out.as_slice(), freq, to);
+                let gain = common::db(amplitude);
+                assert!(
+                    gain.abs() < 0.01,
+                    "{quality:?} {from}->{to} at {freq:.0} Hz: gain {gain:.4} dB"
+                );
+                // Images and aliases of a passband tone are stopband content.
+                let noise = common::db(residual);
+                assert!(
+                    noise < -attenuation + 3.0,
+                    "{quality:?} {from}->{to} at {freq:.0} Hz: residual {noise:.1} dB"
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn stopband_is_attenuated() {
+    // Content between the new and old Nyquist frequencies must not alias.
+    for (from, to) in [(48_000, 44_100), (96_000, 44_100), (44_100, 32_000)] {
+        let nyquist = f64::from(to) / 2.0;
+        for (quality, _, attenuation) in QUALITIES {
+            for fraction in [1.02, 1.2, 1.6] {
+                let freq = nyquist * fraction;
+                if freq >= f64::from(from) / 2.0 {
+                    continue;
+                }
+                let out = resample(&tone(freq, from, 8_192), to, quality);
+                let x = out.as_slice();
+                let level = common::db(common::rms(&x[x.len() / 4..x.len() * 3 / 4]) * 2f64.sqrt());
+                assert!(
+                    level < -attenuation,
+                    "{quality:?} {from}->{to} at {freq:.0} Hz: {level:.1} dB"
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn awkward_ratios_use_interpolated_phases() {
+    let freq = 5_000.0;
+    let out = resample(&tone(freq, 44_100, 8_192), 44_101, ResampleQuality::Best);
+    let (amplitude, residual) = fit(out.as_slice(), freq, 44_101);
+    assert!(common::db(amplitude).abs() < 0.01);
+    assert!(common::db(residual) < -120.0);
+}
+
+#[test]
+fn length_layout_and_loop_points_follow_the_rate() {
+    let data: Vec<f32> = (0..8_820).map(|i| (i as f32 * 0.01).sin()).collect();
+    let sample = Sample::from_planar(data, 2, 44_100)
+        .unwrap()
+        .with_loop_region(LoopRegion {
+            start: 1_000,
+            end: 3_000,
+            mode: LoopMode::Forward,
+            crossfade: 147,
+        });
+    let out = resample(&sample, 48_000, ResampleQuality::Fast);
+    assert_eq!(out.frames(), 4_800);
+    assert_eq!(out.channels(), 2);
+    assert_eq!(out.layout(), sample.layout());
+    assert_eq!(
+        out.loop_region(),
+        Some(LoopRegion {
+            start: 1_088,
+            end: 3_265,
+            mode: LoopMode::Forward,
+            crossfade: 160,
+        })
+    );
+    assert_eq!(resample(&sample, 44_100, ResampleQuality::Best), sample);
+}
+
+#[test]
+fn engine_converts_samples_when_asked() {
+    let sample = tone(1_000.0, 24_000, 1_000);
+    let config = EngineConfig {
+        channels: 1,
+        sample_rate: 48_000,
+        resample: Some(ResampleQuality::Balanced),
+        ..EngineConfig::default()
+    };
+    let params = TriggerParams {
+        interpolation: Interpolation::None,
+        ..TriggerParams::default()
+    };
+    let (mut engine, mut ctl) = Engine::new(config.clone());
+    let id = ctl.add_sample(sample.clone()).unwrap();
+    let converted = resample(&sample, 48_000, ResampleQuality::Balanced);
+
+    // Played at its native pitch, frame for frame.
+    ctl.trigger(id, params).unwrap();
+    let mut out = vec![0.0; 2_100];
+    engine.render(&mut out);
+    assert_eq!(&out[..2_000], converted.as_slice());
+    assert_eq!(engine.active_voices(), 0);
+
+    // Start frames are counted in the sample's original rate.
+    ctl.trigger(
+        id,
+        TriggerParams {
+            start: 500,
+            ..params
+        },
+    )
+    .unwrap();
+    engine.render(&mut out);
+    assert_eq!(&out[..1_000], &converted.as_slice()[1_000..]);
+    assert!(out[1_000..].iter().all(|&v| v == 0.0));
+
+    // Without conversion the voice resamples as it plays instead.
+    let (mut engine, mut ctl) = Engine::new(EngineConfig {
+        resample: None,
+        ..config
+    });
+    let id = ctl.add_sample(sample).unwrap();
+    ctl.trigger(id, params).unwrap();
+    engine.render(&mut out);
+    assert!(out[1_990..2_000].iter().any(|&v| v != 0.0));
+    assert!(out[2_000..].iter().all(|&v| v == 0.0));
+}
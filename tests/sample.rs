@@ -0,0 +1,92 @@
+use samplerust::{Layout, Sample, SampleError};
+
+fn ramp(channels: usize, frames: usize) -> Vec<f32> {
+    // Value encodes (frame, channel) so any misplacement is visible.
+    (0..frames)
+        .flat_map(|f| (0..channels).map(move |c| (f * 10 + c) as f32))
+        .collect()
+}
+
+#[test]
+fn constructors_validate_shape() {
+    assert_eq!(
+        Sample::from_interleaved(vec![0.0; 5], 2, 48_000),
+        Err(SampleError::LengthMismatch {
+            len: 5,
+            channels: 2
+        })
+    );
+    assert_eq!(
+        Sample::from_interleaved(vec![], 0, 48_000),
+        Err(SampleError::NoChannels)
+    );
+    assert_eq!(
+        Sample::from_planar(vec![0.0; 4], 2, 0),
+        Err(SampleError::ZeroSampleRate)
+    );
+    assert_eq!(
+        Sample::from_channels(&[vec![0.0; 3], vec![0.0; 2]], 44_100),
+        Err(SampleError::RaggedChannels {
+            channel: 1,
+            expected: 3,
+            found: 2
+        })
+    );
+
+    let s = Sample::from_interleaved(ramp(2, 4), 2, 44_100).unwrap();
+    assert_eq!(s.channels(), 2);
+    assert_eq!(s.frames(), 4);
+    assert_eq!(s.sample_rate(), 44_100);
+    assert_eq!(s.layout(), Layout::Interleaved);
+}
+
+#[test]
+fn get_is_layout_independent() {
+    let interleaved = Sample::from_interleaved(ramp(3, 7), 3, 48_000).unwrap();
+    let planar = interleaved.clone().into_layout(Layout::Planar);
+    for ch in 0..3 {
+        for f in 0..7 {
+            assert_eq!(interleaved.get(ch, f), (f * 10 + ch) as f32);
+            assert_eq!(planar.get(ch, f), (f * 10 + ch) as f32);
+        }
+    }
+    assert_eq!(planar.channel(1).unwrap()[2], 21.0);
+    assert_eq!(interleaved.frame(2).unwrap(), &[20.0, 21.0, 22.0]);
+    assert!(interleaved.channel(0).is_none());
+    assert!(planar.frame(0).is_none());
+}
+
+#[test]
+fn layout_round_trip_in_place() {
+    for channels in 1..=6 {
+        for frames in [0, 1, 2, 3, 5, 16, 127, 1000] {
+            let original = ramp(channels, frames);
+            let mut s = Sample::from_interleaved(original.clone(), channels, 48_000).unwrap();
+            let ptr = s.as_slice().as_ptr();
+
+            s.set_layout(Layout::Planar);
+            assert_eq!(s.as_slice().as_ptr(), ptr, "conversion must not reallocate");
+            for ch in 0..channels {
+                let expected: Vec<f32> = (0..frames).map(|f| (f * 10 + ch) as f32).collect();
+                assert_eq!(s.channel(ch).unwrap(), expected.as_slice());
+            }
+
+            s.set_layout(Layout::Interleaved);
+            assert_eq!(s.as_slice(), original.as_slice());
+        }
+    }
+}
+
+#[test]
+fn copy_into_matches_conversion() {
+    let s = Sample::from_channels(&[vec![1.0, 2.0, 3.0], vec![4.0, 5.0, 6.0]], 48_000).unwrap();
+    let mut out = vec![0.0; 6];
+    s.copy_interleaved_into(&mut out);
+    assert_eq!(out, [1.0, 4.0, 2.0, 5.0, 3.0, 6.0]);
+
+    let inter = s.clone().into_layout(Layout::Interleaved);
+    let mut planar = vec![0.0; 6];
+    inter.copy_planar_into(&mut planar);
+    assert_eq!(planar, [1.0, 2.0, 3.0, 4.0, 5.0, 6.0]);
+    assert_eq!(inter.channel_iter(1).collect::<Vec<_>>(), [4.0, 5.0, 6.0]);
+}
@@ -0,0 +1,120 @@
+use samplerust::{Adsr, Command, Engine, EngineConfig, Sample, TriggerParams};
+
+fn mono_config() -> EngineConfig {
+    EngineConfig {
+        channels: 1,
+        voices: 8,
+        ..EngineConfig::default()
+    }
+}
+
+fn dc(frames: usize) -> Sample {
+    Sample::from_interleaved(vec![1.0; frames], 1, 48_000).unwrap()
+}
+
+fn gate() -> TriggerParams {
+    TriggerParams {
+        envelope: Adsr::new(0.0, 0.0, 1.0, 0.0),
+        ..TriggerParams::default()
+    }
+}
+
+/// Plays a pattern of 1-frame-accurate hits, rendering in blocks of `block`.
+fn render_pattern(block: usize) -> Vec<f32> {
+    let (mut engine, mut ctl) = Engine::new(mono_config());
+    let id = ctl.add_sample(dc(100)).unwrap();
+    for (i, &at) in [3u64, 700, 1023, 1024, 1500].iter().enumerate() {
+        let params = TriggerParams {
+            gain: (i + 1) as f32,
+            ..gate()
+        };
+        let voice = ctl.trigger_at(at, id, params).unwrap();
+        ctl.release_at(at + 10, voice).unwrap();
+    }
+
+    let mut out = vec![0.0; 2048];
+    for chunk in out.chunks_mut(block) {
+        engine.render(chunk);
+    }
+    out
+}
+
+#[test]
+fn events_land_on_their_frame_regardless_of_block_size() {
+    let reference = render_pattern(2048);
+    for (i, &at) in [3usize, 700, 1023, 1024, 1500].iter().enumerate() {
+        let gain = (i + 1) as f32;
+        assert_eq!(reference[at - 1], if at == 1024 { 3.0 } else { 0.0 });
+        assert!(reference[at] >= gain, "hit {i}");
+    }
+    // Each hit is released ten frames after it starts.
+    assert_eq!(reference[12], 1.0);
+    assert_eq!(reference[13], 0.0);
+    assert_eq!(reference[1509], 5.0);
+    assert_eq!(reference[1510], 0.0);
+
+    for block in [1, 64, 100, 512, 1000] {
+        assert_eq!(render_pattern(block), reference, "block size {block}");
+    }
+}
+
+#[test]
+fn events_on_the_same_frame_keep_their_order() {
+    let (mut engine, mut ctl) = Engine::new(mono_config());
+    let id = ctl.add_sample(dc(100)).unwrap();
+
+    let a = ctl.trigger_at(20, id, gate()).unwrap();
+    ctl.schedule(20, Command::Stop(a)).unwrap();
+    let b = ctl.next_voice_id();
+    ctl.schedule(30, Command::Stop(b)).unwrap();
+    ctl.schedule(
+        30,
+        Command::Trigger {
+            voice: b,
+            sample: id,
+            params: gate(),
+        },
+    )
+    .unwrap();
+
+    let mut out = vec![0.0; 64];
+    engine.render(&mut out);
+    assert!(out[..30].iter().all(|&v| v == 0.0));
+    assert!(out[30..].iter().all(|&v| v == 1.0));
+}
+
+#[test]
+fn late_events_apply_at_the_next_block() {
+    let (mut engine, mut ctl) = Engine::new(mono_config());
+    let id = ctl.add_sample(dc(100)).unwrap();
+    let mut out = vec![0.0; 32];
+    engine.render(&mut out);
+    assert_eq!(ctl.now(), 32);
+    assert_eq!(engine.frame(), 32);
+
+    ctl.trigger_at(10, id, gate()).unwrap();
+    ctl.trigger_at(40, id, gate()).unwrap();
+    engine.render(&mut out);
+    assert_eq!(out[0], 1.0);
+    assert_eq!(out[7], 1.0);
+    assert_eq!(out[8], 2.0);
+    assert_eq!(engine.pending_events(), 0);
+}
+
+#[test]
+fn future_events_wait_across_blocks() {
+    let (mut engine, mut ctl) = Engine::new(mono_config());
+    let id = ctl.add_sample(dc(100)).unwrap();
+    ctl.trigger_at(1000, id, gate()).unwrap();
+
+    let mut out = vec![0.0; 256];
+    for _ in 0..3 {
+        engine.render(&mut out);
+        assert!(out.iter().all(|&v| v == 0.0));
+        assert_eq!(engine.pending_events(), 1);
+    }
+    engine.render(&mut out);
+    assert_eq!(out[1000 - 768 - 1], 0.0);
+    assert_eq!(out[1000 - 768], 1.0);
+    assert_eq!(engine.active_voices(), 1);
+}
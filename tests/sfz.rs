@@ -0,0 +1,281 @@
+mod common;
+
+use std::fs;
+use std::path::Path;
+
+use samplerust::sfz::{Sfz, SfzError, SfzLoopMode};
+use samplerust::{Alternation, Engine, EngineConfig, LoopMode, LoopRegion};
+
+#[test]
+fn regions_inherit_from_every_level() {
+    let sfz = Sfz::parse_str(
+        r"
+        // Comments /* of */ both kinds.
+        <control> default_path=samples\drums/
+        <global> volume=-6 ampeg_release=0.5
+        <master> tune=10 /* a block
+        comment */ lovel=10
+        <group> lokey=c3 hikey=b3 pitch_keycenter=f#3
+        <region> sample=snare hit 1.wav
+        <region> sample=snare hit 2.wav hikey=60 volume=0 transpose=-1 <region>sample=kick.wav key=36
+        <group>
+        <region> sample=ride.wav
+        <master>
+        <region> sample=crash.wav
+        ",
+        "/lib",
+    )
+    .unwrap();
+    assert!(sfz.warnings().is_empty(), "{:?}", sfz.warnings());
+
+    let r = sfz.regions();
+    assert_eq!(r.len(), 5);
+    assert_eq!(r[0].sample, Path::new("/lib/samples/drums/snare hit 1.wav"));
+    assert_eq!(r[1].sample, Path::new("/lib/samples/drums/snare hit 2.wav"));
+    assert_eq!((r[0].lokey, r[0].hikey, r[0].pitch_keycenter), (48, 59, 54));
+    assert_eq!((r[0].volume, r[0].tune, r[0].lovel), (-6.0, 10.0, 10));
+    assert_eq!(r[0].ampeg.release, 0.5);
+    assert_eq!((r[1].hikey, r[1].volume, r[1].transpose), (60, 0.0, -1));
+    assert_eq!((r[2].lokey, r[2].hikey, r[2].pitch_keycenter), (36, 36, 36));
+    // A new group drops the previous group's opcodes but keeps the master's.
+    assert_eq!((r[3].lokey, r[3].tune, r[3].volume), (0, 10.0, -6.0));
+    // A new master drops the previous master's.
+    assert_eq!((r[4].tune, r[4].lovel, r[4].volume), (0.0, 0, -6.0));
+}
+
+#[test]
+fn parses_common_opcodes() {
+    let sfz = Sfz::parse_str(
+        "<region> sample=a.wav lovel=64 hivel=100 loop_mode=loop_sustain loop_start=10 \
+         loop_end=99 offset=5 ampeg_attack=0.01 ampeg_hold=0.02 ampeg_decay=0.3 \
+         ampeg_sustain=50 ampeg_release=1 amplitude=50 seq_length=3 seq_position=2 \
+         lorand=0.25 hirand=0.5 key=eb-1 pan=-50",
+        "",
+    )
+    .unwrap();
+    let r = &sfz.regions()[0];
+    assert_eq!((r.lovel, r.hivel), (64, 100));
+    assert_eq!(r.loop_mode, Some(SfzLoopMode::Sustain));
+    assert_eq!(
+        (r.loop_start, r.loop_end, r.offset),
+        (Some(10), Some(99), 5)
+    );
+    assert_eq!(
+        (r.ampeg.attack, r.ampeg.hold, r.ampeg.decay, r.ampeg.sustain),
+        (0.01, 0.02, 0.3, 0.5)
+    );
+    assert_eq!(r.ampeg.release, 1.0);
+    assert_eq!((r.seq_length, r.seq_position), (3, 2));
+    assert_eq!((r.lorand, r.hirand), (0.25, 0.5));
+    assert_eq!(r.pitch_keycenter, 3);
+
+    let params = r.params(None);
+    assert!((params.gain - 0.5).abs() < 1e-6);
+    assert_eq!(params.start, 5);
+    assert_eq!(params.pan, Some(-0.5));
+    assert_eq!(
+        params.loop_region,
+        Some(LoopRegion {
+            start: 10,
+            end: 100,
+            mode: LoopMode::UntilRelease,
+            crossfade: 0,
+        })
+    );
+
+    // A hi-hat that chokes itself and ignores note-off.
+    let sfz = Sfz::parse_str(
+        "<region> sample=a.wav loop_mode=one_shot group=3 off_by=3 off_time=0.05",
+        "",
+    )
+    .unwrap();
+    assert!(sfz.warnings().is_empty());
+    let params = sfz.regions()[0].params(None);
+    assert!(params.one_shot);
+    assert_eq!(
+        (params.choke_group, params.choked_by, params.choke_fade),
+        (Some(3), Some(3), 0.05)
+    );
+    assert_eq!(params.loop_region.map(|r| r.mode), Some(LoopMode::Off));
+}
+
+#[test]
+fn closed_hats_choke_open_ones() {
+    let dir = common::temp_dir("sfz-hats");
+    for (name, value) in [("open", 1.0), ("closed", 2.0)] {
+        fs::write(
+            dir.join(format!("{name}.wav")),
+            common::wav_bytes(&[value; 100], 1, 1000),
+        )
+        .unwrap();
+    }
+    // The usual setup: the closed hat's group cuts the open hat off, but
+    // not the other way round.
+    fs::write(
+        dir.join("hats.sfz"),
+        "<group> loop_mode=one_shot off_time=0
+         <region> sample=open.wav key=46 group=1 off_by=2
+         <region> sample=closed.wav key=42 group=2",
+    )
+    .unwrap();
+    let sfz = Sfz::parse_file(dir.join("hats.sfz")).unwrap();
+    assert!(sfz.warnings().is_empty());
+
+    let (mut engine, mut ctl) = Engine::new(EngineConfig {
+        sample_rate: 1000,
+        channels: 1,
+        ..EngineConfig::default()
+    });
+    let mut inst = sfz.load(&mut ctl).unwrap();
+    let mut hit = |key| {
+        let (sample, params) = inst.select(key, 100).next().unwrap();
+        ctl.trigger(sample, params).unwrap();
+        let mut out = [0.0; 2];
+        engine.render(&mut out);
+        out[1]
+    };
+    assert_eq!(hit(46), 1.0);
+    // Closing the hat cuts the open one off...
+    assert_eq!(hit(42), 2.0);
+    // ...but opening it again leaves the closed one ringing.
+    assert_eq!(hit(46), 3.0);
+    assert_eq!(engine.active_voices(), 2);
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn defines_are_substituted() {
+    let sfz = Sfz::parse_str(
+        "#define $KEY 62\n#define $KEYS 70\n<region> sample=$KEY.wav key=$KEY hikey=$KEYS",
+        "",
+    )
+    .unwrap();
+    let r = &sfz.regions()[0];
+    assert_eq!(r.sample, Path::new("62.wav"));
+    assert_eq!((r.lokey, r.hikey), (62, 70));
+}
+
+#[test]
+fn problems_are_reported_as_warnings() {
+    let sfz = Sfz::parse_str(
+        "<region> sample=a.wav cutoff=500 lokey=banana\n\
+         <region> sample=b.wav group=1 off_by=2\n\
+         <curve> v000=0\n\
+         <region> key=60\n\
+         stray",
+        "",
+    )
+    .unwrap();
+    assert_eq!(sfz.regions().len(), 2);
+    let lines: Vec<_> = sfz
+        .warnings()
+        .iter()
+        .map(|w| (w.line, w.message.as_str()))
+        .collect();
+    assert_eq!(
+        lines,
+        [
+            (1, "unsupported opcode `cutoff`"),
+            (1, "invalid value `banana` for `lokey`"),
+            (3, "unsupported header <curve>"),
+            (5, "unexpected text `stray`"),
+            (4, "region without a sample skipped"),
+        ]
+    );
+    assert_eq!(
+        sfz.warnings()[0].to_string(),
+        "<string>:1: unsupported opcode `cutoff`"
+    );
+}
+
+#[test]
+fn includes_resolve_relative_to_the_root_file() {
+    let dir = common::temp_dir("sfz-include");
+    fs::create_dir_all(dir.join("inc")).unwrap();
+    fs::write(
+        dir.join("inc/regions.sfzh"),
+        "<region> sample=$NAME.wav key=$KEY\n",
+    )
+    .unwrap();
+    fs::write(
+        dir.join("main.sfz"),
+        "#define $NAME piano\n#define $KEY 40\n<group> volume=-3\n#include \"inc/regions.sfzh\"\n",
+    )
+    .unwrap();
+    let sfz = Sfz::parse_file(dir.join("main.sfz")).unwrap();
+    let r = &sfz.regions()[0];
+    assert_eq!(r.sample, dir.join("piano.wav"));
+    assert_eq!((r.lokey, r.volume), (40, -3.0));
+
+    fs::write(dir.join("loop.sfz"), "#include \"loop.sfz\"\n").unwrap();
+    assert!(matches!(
+        Sfz::parse_file(dir.join("loop.sfz")),
+        Err(SfzError::IncludeDepth { .. })
+    ));
+    assert!(matches!(
+        Sfz::parse_file(dir.join("missing.sfz")),
+        Err(SfzError::Io { .. })
+    ));
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn loads_samples_into_an_instrument() {
+    let dir = common::temp_dir("sfz-load");
+    for (name, value) in [("soft", 1.0), ("hard1", 2.0), ("hard2", 3.0)] {
+        fs::write(
+            dir.join(format!("{name}.wav")),
+            common::wav_bytes(&[value; 100], 1, 1000),
+        )
+        .unwrap();
+    }
+    fs::write(
+        dir.join("kit.sfz"),
+        "<group> key=60 ampeg_release=0
+         <region> sample=soft.wav hivel=63
+         <group> key=60 lovel=64 seq_length=2 ampeg_release=0
+         <region> sample=hard2.wav seq_position=2
+         <region> sample=hard1.wav seq_position=1
+         <region> sample=soft.wav key=61 volume=-20
+         ",
+    )
+    .unwrap();
+    let sfz = Sfz::parse_file(dir.join("kit.sfz")).unwrap();
+
+    let (mut engine, mut ctl) = Engine::new(EngineConfig {
+        sample_rate: 1000,
+        channels: 1,
+        ..EngineConfig::default()
+    });
+    let mut inst = sfz.load(&mut ctl).unwrap();
+    assert_eq!(inst.zones().len(), 3);
+    assert_eq!(inst.zones()[1].alternation, Alternation::RoundRobin);
+    assert_eq!(inst.zones()[1].variants.len(), 2);
+    // `soft.wav` is shared, so only three samples were loaded.
+    let ids: Vec<_> = inst.zones().iter().map(|z| z.variants[0].sample).collect();
+    assert_eq!(ids[0], ids[2]);
+
+    let mut play = |velocity| {
+        let (sample, params) = inst.select(60, velocity).next().unwrap();
+        let voice = ctl.trigger(sample, params).unwrap();
+        let mut out = [0.0; 2];
+        engine.render(&mut out);
+        ctl.stop(voice).unwrap();
+        out[1]
+    };
+    assert_eq!(play(10), 1.0);
+    assert_eq!(play(100), 2.0);
+    assert_eq!(play(100), 3.0);
+    assert_eq!(play(100), 2.0);
+
+    let (_, quiet) = inst.select(61, 100).next().unwrap();
+    assert!((quiet.gain - 0.1).abs() < 1e-6);
+
+    fs::write(dir.join("bad.sfz"), "<region> sample=nope.wav").unwrap();
+    let err = Sfz::parse_file(dir.join("bad.sfz"))
+        .unwrap()
+        .load(&mut ctl)
+        .unwrap_err();
+    assert!(matches!(err, SfzError::Io { .. }), "{err}");
+    fs::remove_dir_all(dir).unwrap();
+}
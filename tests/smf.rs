@@ -0,0 +1,298 @@
+use samplerust::midi::{Division, SmfEvent, TimedEvent};
+use samplerust::{
+    Bounce, Engine, EngineConfig, Instrument, MidiMapper, MidiMessage, Sample, Sequence, Smf,
+    SmfError,
+};
+
+fn vlq(mut value: u32) -> Vec<u8> {
+    let mut out = vec![(value & 0x7f) as u8];
+    value >>= 7;
+    while value > 0 {
+        out.insert(0, (value & 0x7f) as u8 | 0x80);
+        value >>= 7;
+    }
+    out
+}
+
+/// A track chunk from `(delta, bytes)` events, with an end-of-track marker.
+fn track(events: &[(u32, &[u8])]) -> Vec<u8> {
+    let mut body = Vec::new();
+    for (delta, bytes) in events {
+        body.extend(vlq(*delta));
+        body.extend_from_slice(bytes);
+    }
+    body.extend([0x00, 0xff, 0x2f, 0x00]);
+    let mut chunk = b"MTrk".to_vec();
+    chunk.extend((body.len() as u32).to_be_bytes());
+    chunk.extend(body);
+    chunk
+}
+
+fn smf(format: u16, division: u16, tracks: &[Vec<u8>]) -> Vec<u8> {
+    let mut out = b"MThd".to_vec();
+    out.extend(6u32.to_be_bytes());
+    out.extend(format.to_be_bytes());
+    out.extend((tracks.len() as u16).to_be_bytes());
+    out.extend(division.to_be_bytes());
+    for t in tracks {
+        out.extend_from_slice(t);
+    }
+    out
+}
+
+fn on(channel: u8, note: u8) -> MidiMessage {
+    MidiMessage::NoteOn {
+        channel,
+        note,
+        velocity: 100,
+    }
+}
+
+fn off(channel: u8, note: u8) -> MidiMessage {
+    MidiMessage::NoteOff {
+        channel,
+        note,
+        velocity: 0,
+    }
+}
+
+/// Tempo meta event.
+fn tempo(micros: u32) -> Vec<u8> {
+    let [_, a, b, c] = micros.to_be_bytes();
+    vec![0xff, 0x51, 0x03, a, b, c]
+}
+
+#[test]
+fn type_0_follows_tempo_changes() {
+    let fast = tempo(250_000);
+    let bytes = smf(
+        0,
+        480,
+        &[track(&[
+            (0, &[0x90, 60, 100]),
+            // Running status, and a note-on at velocity 0 for note-off.
+            (480, &[60, 0]),
+            (0, &[62, 100]),
+            (480, &fast),
+            (480, &[0x80, 62, 0]),
+            (0, &[0xc1, 5]),
+        ])],
+    );
+    let file = Smf::parse(&bytes).unwrap();
+    assert_eq!(file.format(), 0);
+    assert_eq!(file.division(), Division::Ppq(480));
+    assert_eq!(file.length(), 1_440);
+
+    // 120 bpm for two beats, then 240 bpm.
+    let map = file.tempo_map();
+    assert_eq!(map.seconds(960), 1.0);
+    assert_eq!(map.seconds(1_440), 1.25);
+    assert_eq!(
+        file.events(48_000),
+        vec![
+            (0, on(0, 60)),
+            (24_000, off(0, 60)),
+            (24_000, on(0, 62)),
+            (60_000, off(0, 62)),
+            (
+                60_000,
+                MidiMessage::ProgramChange {
+                    channel: 1,
+                    program: 5
+                }
+            ),
+        ]
+    );
+}
+
+#[test]
+fn type_1_tracks_share_the_tempo_map() {
+    let slow = tempo(1_000_000);
+    let conductor = track(&[(0, &slow), (96, &tempo(500_000))]);
+    let bass = track(&[(96, &[0x91, 40, 100]), (96, &[0x81, 40, 0])]);
+    let lead = track(&[(0, &[0x92, 70, 100]), (96, &[0x82, 70, 0])]);
+    let file = Smf::parse(&smf(1, 96, &[conductor, bass, lead])).unwrap();
+    assert_eq!(file.tracks().len(), 3);
+    assert_eq!(
+        file.events(1_000),
+        vec![
+            (0, on(2, 70)),
+            // Same tick: the earlier track goes first.
+            (1_000, on(1, 40)),
+            (1_000, off(2, 70)),
+            (1_500, off(1, 40)),
+        ]
+    );
+}
+
+#[test]
+fn smpte_division_ignores_tempo() {
+    // 25 fps at 40 ticks a frame counts milliseconds.
+    let bytes = smf(
+        0,
+        0xe728,
+        &[track(&[(0, &tempo(100_000)), (1_000, &[0x90, 60, 1])])],
+    );
+    let file = Smf::parse(&bytes).unwrap();
+    assert_eq!(
+        file.division(),
+        Division::Smpte {
+            fps: 25,
+            ticks_per_frame: 40
+        }
+    );
+    assert_eq!(file.events(48_000)[0].0, 48_000);
+
+    // 29.97 drop-frame runs slow of 30 by 1000/1001.
+    let bytes = smf(0, 0xe301, &[track(&[(30_000, &[0x90, 60, 1])])]);
+    let file = Smf::parse(&bytes).unwrap();
+    assert_eq!(file.events(48_000)[0].0, 1_001 * 48_000);
+}
+
+#[test]
+fn long_files_do_not_drift() {
+    // Ten hours at 100 bpm is 28.8 million ticks of 1/480 of 0.6 s, and
+    // the last still lands on its exact frame.
+    let beats = 60_000u32;
+    let bytes = smf(
+        0,
+        480,
+        &[track(&[
+            (0, &tempo(600_000)),
+            (0, &[0x90, 60, 1]),
+            (beats * 480, &[0x80, 60, 0]),
+        ])],
+    );
+    let events = Smf::parse(&bytes).unwrap().events(44_100);
+    assert_eq!(events[1].0, 36_000 * 44_100);
+}
+
+#[test]
+fn meta_sysex_and_escapes_are_kept() {
+    let bytes = smf(
+        0,
+        96,
+        &[track(&[
+            (0, &[0xff, 0x03, 0x04, b'l', b'e', b'a', b'd']),
+            (0, &[0xf0, 0x04, 0x7e, 0x7f, 0x09, 0xf7]),
+            (10, &[0xf7, 0x02, 0xf8, 0xfa]),
+            // Running status survives the meta and SysEx events above.
+            (0, &[0x90, 60, 100]),
+            (0, &[0xff, 0x01, 0x00]),
+            (5, &[61, 100]),
+        ])],
+    );
+    let file = Smf::parse(&bytes).unwrap();
+    let timed = |tick, event| TimedEvent { tick, event };
+    assert_eq!(
+        file.tracks()[0],
+        [
+            timed(
+                0,
+                SmfEvent::Meta {
+                    kind: 3,
+                    data: b"lead".to_vec()
+                }
+            ),
+            timed(
+                0,
+                SmfEvent::Midi(MidiMessage::SysEx(vec![0x7e, 0x7f, 0x09]))
+            ),
+            timed(10, SmfEvent::Escape(vec![0xf8, 0xfa])),
+            timed(10, SmfEvent::Midi(on(0, 60))),
+            timed(
+                10,
+                SmfEvent::Meta {
+                    kind: 1,
+                    data: vec![]
+                }
+            ),
+            timed(15, SmfEvent::Midi(on(0, 61))),
+        ]
+    );
+    let events = file.events(9_600);
+    assert_eq!(events.len(), 3);
+    assert_eq!(events[2], (750, on(0, 61)));
+}
+
+#[test]
+fn bad_files_are_rejected() {
+    let note = track(&[(0, &[0x90, 60, 100])]);
+    let good = smf(0, 96, std::slice::from_ref(&note));
+    assert!(Smf::parse(&good).is_ok());
+
+    assert!(matches!(
+        Smf::parse(&good[..good.len() - 3]),
+        Err(SmfError::Truncated("track chunk"))
+    ));
+    assert!(matches!(
+        Smf::parse(&smf(2, 96, std::slice::from_ref(&note))),
+        Err(SmfError::Unsupported(_))
+    ));
+    assert!(matches!(
+        Smf::parse(&smf(0, 96, &[note.clone(), note.clone()])),
+        Err(SmfError::Malformed(_))
+    ));
+    assert!(matches!(
+        Smf::parse(&smf(0, 96, &[track(&[(0, &[60, 100])])])),
+        Err(SmfError::Malformed(_))
+    ));
+    assert!(matches!(
+        Smf::parse(&smf(0, 96, &[track(&[(0, &tempo(0))])])),
+        Err(SmfError::Malformed(_))
+    ));
+    assert!(matches!(
+        Smf::parse(b"RIFF\0\0\0\0"),
+        Err(SmfError::Malformed(_))
+    ));
+}
+
+/// A bouncy one-bar pattern on a 100-frame click.
+fn pattern() -> (Smf, Sample) {
+    let mut events: Vec<(u32, Vec<u8>)> = Vec::new();
+    for i in 0..16u8 {
+        events.push((if i == 0 { 0 } else { 37 }, vec![0x90, 60 + i % 5, 100]));
+        events.push((11, vec![0x80, 60 + i % 5, 0]));
+    }
+    let events: Vec<(u32, &[u8])> = events.iter().map(|(d, b)| (*d, b.as_slice())).collect();
+    let file = Smf::parse(&smf(0, 192, &[track(&events)])).unwrap();
+    let click = (0..100).map(|i| 1.0 - i as f32 / 100.0).collect();
+    (file, Sample::from_interleaved(click, 1, 48_000).unwrap())
+}
+
+#[test]
+fn realtime_sequence_matches_the_offline_bounce() {
+    let (file, click) = pattern();
+    let config = EngineConfig {
+        channels: 1,
+        ..EngineConfig::default()
+    };
+
+    let mut bounce = Bounce::new(config.clone());
+    let id = bounce.controller().add_sample(click.clone()).unwrap();
+    bounce.add_instrument(Instrument::single(id, 60), None);
+    bounce.add_smf(&file);
+    let offline = bounce.render().unwrap();
+
+    // Played a block at a time, scheduling a block ahead.
+    let (mut engine, mut ctl) = Engine::new(config);
+    let id = ctl.add_sample(click).unwrap();
+    let mut mappers = [MidiMapper::new(Instrument::single(id, 60))];
+    let mut sequence = Sequence::new(file.events(48_000));
+    sequence.start_at(1_000);
+    let mut live = Vec::new();
+    let mut block = vec![0.0; 256];
+    while !sequence.is_finished() || engine.active_voices() + engine.pending_events() > 0 {
+        let until = ctl.now() + 512;
+        let played = sequence.play(&mut mappers, &mut ctl, until).unwrap();
+        assert!(played <= 12);
+        engine.render(&mut block);
+        live.extend_from_slice(&block);
+    }
+    assert!(live[..1_000].iter().all(|&v| v == 0.0));
+    assert_eq!(&live[1_000..1_000 + offline.frames()], offline.as_slice());
+    assert_eq!(
+        sequence.end(),
+        1_000 + file.tempo_map().frame(file.length(), 48_000)
+    );
+}
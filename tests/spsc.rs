@@ -0,0 +1,59 @@
+use std::thread;
+
+use samplerust::spsc;
+
+#[test]
+fn preserves_order_and_reports_full() {
+    let (mut tx, mut rx) = spsc::channel(3);
+    assert_eq!(tx.capacity(), 4);
+    for i in 0..4 {
+        tx.push(i).unwrap();
+    }
+    assert_eq!(tx.push(4), Err(4));
+    assert_eq!(rx.len(), 4);
+    assert_eq!(rx.pop(), Some(0));
+    tx.push(4).unwrap();
+    assert_eq!(
+        (1..5).map(|_| rx.pop().unwrap()).collect::<Vec<_>>(),
+        [1, 2, 3, 4]
+    );
+    assert_eq!(rx.pop(), None);
+    assert!(tx.is_empty());
+}
+
+#[test]
+fn drops_unread_items() {
+    use std::sync::Arc;
+    let item = Arc::new(());
+    let (mut tx, rx) = spsc::channel(8);
+    tx.push(item.clone()).unwrap();
+    tx.push(item.clone()).unwrap();
+    drop((tx, rx));
+    assert_eq!(Arc::strong_count(&item), 1);
+}
+
+#[test]
+fn transfers_across_threads() {
+    const N: u64 = 200_000;
+    let (mut tx, mut rx) = spsc::channel(64);
+    let producer = thread::spawn(move || {
+        for i in 0..N {
+            let mut v = i;
+            while let Err(back) = tx.push(v) {
+                v = back;
+                thread::yield_now();
+            }
+        }
+    });
+    let mut expected = 0;
+    while expected < N {
+        match rx.pop() {
+            Some(v) => {
+                assert_eq!(v, expected);
+                expected += 1;
+            }
+            None => thread::yield_now(),
+        }
+    }
+    producer.join().unwrap();
+}
@@ -0,0 +1,372 @@
+mod common;
+
+use std::thread;
+use std::time::Duration;
+
+use samplerust::codec::{wav, DecodeError, StreamDecoder};
+use samplerust::stream::{self, DecoderSource};
+use samplerust::{
+    Engine, EngineConfig, EngineError, Interpolation, LoopMode, LoopRegion, Sample, StreamSource,
+    StreamedSample, TriggerParams,
+};
+
+/// A mono sample that is never zero, so silence in the output can only be
+/// an underrun.
+fn signal(frames: usize) -> Sample {
+    let data = (0..frames)
+        .map(|i| 0.5 + 0.4 * (i as f32 * 0.013).sin())
+        .collect();
+    Sample::from_interleaved(data, 1, 48_000).unwrap()
+}
+
+fn stereo(frames: usize) -> Sample {
+    let data = (0..frames * 2)
+        .map(|i| ((i * 7919) % 1013) as f32 / 1013.0 - 0.5)
+        .collect();
+    Sample::from_interleaved(data, 2, 48_000).unwrap()
+}
+
+fn config(stream_buffer: usize) -> EngineConfig {
+    EngineConfig {
+        channels: 1,
+        voices: 4,
+        stream_buffer,
+        ..EngineConfig::default()
+    }
+}
+
+/// Plays each sample frame once, so output frames are sample frames.
+fn straight() -> TriggerParams {
+    TriggerParams {
+        interpolation: Interpolation::None,
+        ..TriggerParams::default()
+    }
+}
+
+/// The non-silent output, which skips the gaps underruns leave.
+fn audible(out: &[f32]) -> Vec<f32> {
+    out.iter().copied().filter(|&v| v != 0.0).collect()
+}
+
+#[test]
+fn streamed_playback_matches_memory() {
+    let sample = stereo(60_000);
+    let params = TriggerParams {
+        rate: 1.3,
+        interpolation: Interpolation::Cubic,
+        ..TriggerParams::default()
+    };
+    let config = EngineConfig {
+        voices: 4,
+        stream_buffer: 4_096,
+        ..EngineConfig::default()
+    };
+
+    let (mut memory, mut ctl) = Engine::new(config.clone());
+    let id = ctl.add_sample(sample.clone()).unwrap();
+    ctl.trigger(id, params).unwrap();
+
+    let (mut streamed, mut sctl) = Engine::new(config);
+    let mut streamer = sctl.streamer().unwrap();
+    assert!(sctl.streamer().is_none());
+    let head = StreamedSample::new(sample, 2_000).unwrap();
+    assert_eq!(head.head().frames(), 2_000);
+    assert_eq!(head.frames(), 60_000);
+    let sid = sctl.add_streamed_sample(head).unwrap();
+    sctl.trigger(sid, params).unwrap();
+
+    let mut a = vec![0.0; 512];
+    let mut b = vec![0.0; 512];
+    for block in 0..250 {
+        streamer.service();
+        memory.render(&mut a);
+        streamed.render(&mut b);
+        assert_eq!(a, b, "block {block}");
+    }
+    assert_eq!(streamed.active_voices(), 0);
+    assert_eq!(sctl.underruns(), 0);
+    assert!(streamer.take_errors().is_empty());
+}
+
+#[test]
+fn starved_voice_holds_position_and_resumes() {
+    let sample = signal(20_000);
+    let (mut engine, mut ctl) = Engine::new(config(4_096));
+    let mut streamer = ctl.streamer().unwrap();
+    let id = ctl
+        .add_streamed_sample(StreamedSample::new(sample.clone(), 1_000).unwrap())
+        .unwrap();
+    ctl.trigger(id, straight()).unwrap();
+
+    // Nothing services the buffer: the head plays, then the voice waits.
+    let mut out = Vec::new();
+    let mut block = vec![0.0; 256];
+    for _ in 0..10 {
+        engine.render(&mut block);
+        out.extend_from_slice(&block);
+    }
+    assert_eq!(audible(&out).len(), 1_000);
+    assert!(engine.underruns() >= 6);
+    assert_eq!(ctl.underruns(), engine.underruns());
+    assert_eq!(engine.active_voices(), 1);
+
+    while engine.active_voices() > 0 {
+        streamer.service();
+        engine.render(&mut block);
+        out.extend_from_slice(&block);
+    }
+    assert_eq!(audible(&out), sample.as_slice());
+}
+
+#[test]
+fn slow_source_underruns_without_glitching() {
+    /// Takes a millisecond over every read.
+    struct Slow(Sample);
+
+    impl StreamSource for Slow {
+        fn channels(&self) -> usize {
+            self.0.channels()
+        }
+
+        fn sample_rate(&self) -> u32 {
+            self.0.sample_rate()
+        }
+
+        fn frames(&self) -> usize {
+            self.0.frames()
+        }
+
+        fn read(&mut self, frame: usize, out: &mut [f32]) -> Result<usize, DecodeError> {
+            thread::sleep(Duration::from_millis(1));
+            self.0.read(frame, out)
+        }
+    }
+
+    let sample = signal(30_000);
+    let (mut engine, mut ctl) = Engine::new(config(1_024));
+    let streamer = ctl.streamer().unwrap().spawn();
+    let id = ctl
+        .add_streamed_sample(StreamedSample::new(Slow(sample.clone()), 256).unwrap())
+        .unwrap();
+    ctl.trigger(id, straight()).unwrap();
+
+    // Render far faster than real time so the reader cannot keep up.
+    let mut out = Vec::new();
+    let mut block = vec![0.0; 512];
+    for _ in 0..100_000 {
+        engine.render(&mut block);
+        out.extend_from_slice(&block);
+        if engine.active_voices() == 0 {
+            break;
+        }
+        thread::sleep(Duration::from_micros(50));
+    }
+    let mut streamer = streamer.stop();
+    assert_eq!(engine.active_voices(), 0);
+    assert!(engine.underruns() > 0);
+    assert_eq!(audible(&out), sample.as_slice());
+    assert!(streamer.take_errors().is_empty());
+}
+
+#[test]
+fn loops_play_from_memory() {
+    let sample = signal(20_000).with_loop_region(LoopRegion {
+        start: 1_000,
+        end: 3_000,
+        mode: LoopMode::UntilRelease,
+        crossfade: 0,
+    });
+    let (mut engine, mut ctl) = Engine::new(config(4_096));
+    let mut streamer = ctl.streamer().unwrap();
+    let streamed = StreamedSample::new(sample.clone(), 500).unwrap();
+    assert_eq!(streamed.head().frames(), 3_000);
+    assert_eq!(streamed.head().loop_region(), sample.loop_region());
+    let id = ctl.add_streamed_sample(streamed).unwrap();
+    let voice = ctl.trigger(id, straight()).unwrap();
+
+    let mut block = vec![0.0; 256];
+    for _ in 0..100 {
+        engine.render(&mut block);
+        assert!(block.iter().all(|&v| v != 0.0));
+    }
+    assert_eq!(engine.underruns(), 0);
+
+    // After note-off the voice plays on past the loop, from the disk.
+    ctl.release(voice).unwrap();
+    streamer.service();
+    let mut out = Vec::new();
+    while engine.active_voices() > 0 {
+        engine.render(&mut block);
+        out.extend_from_slice(&block);
+        streamer.service();
+    }
+    assert_eq!(engine.underruns(), 0);
+    assert!(audible(&out).len() > 200);
+}
+
+#[test]
+fn seeking_past_the_head_refetches() {
+    let sample = signal(20_000);
+    let (mut engine, mut ctl) = Engine::new(config(2_048));
+    let mut streamer = ctl.streamer().unwrap();
+    let id = ctl
+        .add_streamed_sample(StreamedSample::new(sample.clone(), 1_000).unwrap())
+        .unwrap();
+    let voice = ctl.trigger(id, straight()).unwrap();
+    let mut block = vec![0.0; 256];
+    engine.render(&mut block);
+
+    ctl.seek(voice, 15_000).unwrap();
+    engine.render(&mut block);
+    assert!(block.iter().all(|&v| v == 0.0));
+    assert_eq!(engine.underruns(), 1);
+
+    let mut out = Vec::new();
+    while engine.active_voices() > 0 {
+        streamer.service();
+        engine.render(&mut block);
+        out.extend_from_slice(&block);
+    }
+    assert_eq!(audible(&out), &sample.as_slice()[15_000..]);
+}
+
+#[test]
+fn stolen_voices_keep_streaming_while_they_fade() {
+    let (mut engine, mut ctl) = Engine::new(EngineConfig {
+        voices: 1,
+        ..config(2_048)
+    });
+    let mut streamer = ctl.streamer().unwrap();
+    let id = ctl
+        .add_streamed_sample(StreamedSample::new(signal(20_000), 100).unwrap())
+        .unwrap();
+    ctl.trigger(id, straight()).unwrap();
+    let mut block = vec![0.0; 64];
+    for _ in 0..10 {
+        streamer.service();
+        engine.render(&mut block);
+    }
+
+    // The victim fades out past its head while the new note starts.
+    ctl.trigger(id, straight()).unwrap();
+    for _ in 0..20 {
+        streamer.service();
+        engine.render(&mut block);
+    }
+    assert_eq!(engine.underruns(), 0);
+    assert_eq!(engine.pool().fading(), 0);
+    assert_eq!(engine.active_voices(), 1);
+}
+
+#[test]
+fn wav_files_stream_from_disk() {
+    let data = common::sine(440.0, 48_000.0, 30_000);
+    let interleaved: Vec<f32> = data.iter().flat_map(|&v| [v, -v]).collect();
+    let path = common::temp_dir("stream").join("long.wav");
+    std::fs::write(&path, common::wav_bytes(&interleaved, 2, 48_000)).unwrap();
+    let loaded = wav::load(&path).unwrap();
+
+    let mut reader = wav::Reader::open(&path).unwrap();
+    assert_eq!(reader.channels(), 2);
+    assert_eq!(reader.frames(), 30_000);
+    let mut frames = vec![0.0; 200];
+    assert_eq!(reader.read(29_950, &mut frames).unwrap(), 50);
+    assert_eq!(&frames[..100], &loaded.as_slice()[59_900..]);
+    assert_eq!(reader.read(10, &mut frames).unwrap(), 100);
+    assert_eq!(&frames[..], &loaded.as_slice()[20..220]);
+
+    let params = TriggerParams {
+        rate: 0.75,
+        interpolation: Interpolation::Linear,
+        ..TriggerParams::default()
+    };
+    let config = EngineConfig {
+        voices: 2,
+        stream_buffer: 4_096,
+        ..EngineConfig::default()
+    };
+    let (mut memory, mut ctl) = Engine::new(config.clone());
+    let id = ctl.add_sample(loaded).unwrap();
+    ctl.trigger(id, params).unwrap();
+    let (mut streamed, mut sctl) = Engine::new(config);
+    let mut streamer = sctl.streamer().unwrap();
+    let sid = sctl
+        .add_streamed_sample(stream::open(&path, 1_024).unwrap())
+        .unwrap();
+    sctl.trigger(sid, params).unwrap();
+
+    let mut a = vec![0.0; 256];
+    let mut b = vec![0.0; 256];
+    while memory.active_voices() > 0 {
+        streamer.service();
+        memory.render(&mut a);
+        streamed.render(&mut b);
+        assert_eq!(a, b);
+    }
+    assert_eq!(streamed.underruns(), 0);
+}
+
+#[test]
+fn decoder_sources_reopen_to_read_backwards() {
+    /// Counts up from zero, one frame at a time.
+    struct Counter {
+        next: usize,
+        frames: usize,
+    }
+
+    impl StreamDecoder for Counter {
+        fn channels(&self) -> usize {
+            1
+        }
+
+        fn sample_rate(&self) -> u32 {
+            48_000
+        }
+
+        fn frames(&self) -> Option<u64> {
+            None
+        }
+
+        fn read(&mut self, out: &mut [f32]) -> Result<usize, DecodeError> {
+            let n = out.len().min(self.frames - self.next);
+            for v in &mut out[..n] {
+                *v = self.next as f32;
+                self.next += 1;
+            }
+            Ok(n)
+        }
+    }
+
+    let mut opened = 0;
+    let mut source = DecoderSource::new(|| {
+        opened += 1;
+        Ok(Counter {
+            next: 0,
+            frames: 10_000,
+        })
+    })
+    .unwrap();
+    assert_eq!(source.frames(), 10_000);
+
+    let mut out = [0.0; 4];
+    source.read(5_000, &mut out).unwrap();
+    assert_eq!(out, [5_000.0, 5_001.0, 5_002.0, 5_003.0]);
+    source.read(9_000, &mut out).unwrap();
+    assert_eq!(out[0], 9_000.0);
+    source.read(100, &mut out).unwrap();
+    assert_eq!(out[0], 100.0);
+    assert_eq!(source.read(9_998, &mut out).unwrap(), 2);
+    drop(source);
+    // Once to count the frames, once to play, once to go back.
+    assert_eq!(opened, 3);
+}
+
+#[test]
+fn streaming_can_be_disabled() {
+    let (_engine, mut ctl) = Engine::new(config(0));
+    let sample = StreamedSample::new(signal(100), 10).unwrap();
+    assert_eq!(
+        ctl.add_streamed_sample(sample).unwrap_err(),
+        EngineError::StreamingDisabled
+    );
+}
@@ -0,0 +1,170 @@
+mod common;
+
+use samplerust::{
+    Controller, Engine, EngineConfig, Sample, SampleId, StretchMode, TimeStretch, TriggerParams,
+};
+
+const RATE: u32 = 48_000;
+const MODES: [StretchMode; 2] = [StretchMode::Wsola, StretchMode::PhaseVocoder];
+
+fn engine(stretch_window: usize) -> (Engine, Controller) {
+    Engine::new(EngineConfig {
+        channels: 1,
+        stretch_window,
+        ..EngineConfig::default()
+    })
+}
+
+fn tone(freq: f64, frames: usize) -> Sample {
+    Sample::from_interleaved(common::sine(freq, f64::from(RATE), frames), 1, RATE).unwrap()
+}
+
+fn stretched(mode: StretchMode, ratio: f64, semitones: f32) -> TriggerParams {
+    TriggerParams {
+        stretch: Some(TimeStretch {
+            mode,
+            ratio,
+            semitones,
+        }),
+        ..TriggerParams::default()
+    }
+}
+
+/// Renders until the engine falls silent, with `between` run before each
+/// block of 64 frames, and returns the output.
+fn play(engine: &mut Engine, mut between: impl FnMut(usize)) -> Vec<f32> {
+    let mut out = Vec::new();
+    let mut block = [0.0; 64];
+    loop {
+        between(out.len());
+        engine.render(&mut block);
+        out.extend_from_slice(&block);
+        if engine.active_voices() == 0 {
+            return out;
+        }
+    }
+}
+
+/// Fundamental of the middle of `x`, in Hz.
+fn pitch(x: &[f32]) -> f64 {
+    let middle = &x[x.len() / 2 - 8_192..];
+    common::peak_frequency(&common::power_spectrum(&middle[..16_384]), f64::from(RATE))
+}
+
+fn assert_near(actual: f64, expected: f64, tolerance: f64, what: &str) {
+    assert!(
+        (actual / expected - 1.0).abs() < tolerance,
+        "{what}: {actual} vs {expected}"
+    );
+}
+
+#[test]
+fn stretch_and_shift_are_independent() {
+    for mode in MODES {
+        for (ratio, semitones) in [(1.0, 0.0), (1.5, 0.0), (1.0, 7.0), (0.75, -5.0), (2.0, 3.0)] {
+            let (mut engine, mut ctl) = engine(2_048);
+            let id = ctl.add_sample(tone(440.0, RATE as usize)).unwrap();
+            ctl.trigger(id, stretched(mode, ratio, semitones)).unwrap();
+            let out = play(&mut engine, |_| {});
+            let what = format!("{mode:?} x{ratio} {semitones:+} st");
+            assert_near(out.len() as f64, f64::from(RATE) * ratio, 0.01, &what);
+            let shifted = 440.0 * f64::from(semitones / 12.0).exp2();
+            assert_near(pitch(&out), shifted, 0.01, &what);
+            // The rebuilt tone keeps its level.
+            let level = common::rms(&out[out.len() / 4..out.len() * 3 / 4]);
+            assert!((0.65..0.75).contains(&level), "{what}: {level}");
+        }
+    }
+}
+
+#[test]
+fn wsola_keeps_drum_hits_sharp_and_in_time() {
+    // A decaying 1 kHz hit every quarter second.
+    let mut data = vec![0.0; RATE as usize];
+    for hit in 0..4 {
+        let start = hit * RATE as usize / 4;
+        let burst = common::sine(1_000.0, f64::from(RATE), 4_800);
+        for (i, v) in burst.iter().enumerate() {
+            data[start + i] = v * (-(i as f32) / 600.0).exp();
+        }
+    }
+    let (mut engine, mut ctl) = engine(1_024);
+    let id = ctl
+        .add_sample(Sample::from_interleaved(data, 1, RATE).unwrap())
+        .unwrap();
+    ctl.trigger(id, stretched(StretchMode::Wsola, 1.5, 0.0))
+        .unwrap();
+    let out = play(&mut engine, |_| {});
+    assert_near(out.len() as f64, 1.5 * f64::from(RATE), 0.01, "length");
+
+    // Each hit starts where the stretch puts it, and its energy stays
+    // bunched up at the start.
+    let spacing = 3 * RATE as usize / 8;
+    for hit in 0..4 {
+        let expected = hit * spacing;
+        let onset = (expected.saturating_sub(1_000)..)
+            .find(|&i| out[i].abs() > 0.2)
+            .unwrap();
+        assert!(onset.abs_diff(expected) < 240, "hit {hit} at {onset}");
+        let attack = common::rms(&out[expected..expected + 2_400]);
+        let tail = common::rms(&out[expected + 4_800..expected + 7_200]);
+        assert!(attack > 20.0 * tail, "hit {hit}: {attack} vs {tail}");
+        let spectrum = common::power_spectrum(&out[expected..expected + 2_048]);
+        assert_near(
+            common::peak_frequency(&spectrum, f64::from(RATE)),
+            1_000.0,
+            0.03,
+            "hit pitch",
+        );
+    }
+}
+
+#[test]
+fn stretch_and_shift_follow_commands_while_playing() {
+    for mode in MODES {
+        let (mut engine, mut ctl) = engine(2_048);
+        let id = ctl.add_sample(tone(440.0, 2 * RATE as usize)).unwrap();
+        let voice = ctl.trigger(id, stretched(mode, 1.0, 0.0)).unwrap();
+        // Play the first second as it is, then the rest twice as long and
+        // an octave down.
+        let out = play(&mut engine, |frame| {
+            if frame == RATE as usize {
+                ctl.set_stretch(voice, 2.0).unwrap();
+                ctl.set_pitch_shift(voice, -12.0).unwrap();
+            }
+        });
+        let what = format!("{mode:?}");
+        assert_near(out.len() as f64, 3.0 * f64::from(RATE), 0.01, &what);
+        assert_near(pitch(&out[..RATE as usize]), 440.0, 0.01, &what);
+        assert_near(pitch(&out[RATE as usize + 4_096..]), 220.0, 0.01, &what);
+    }
+}
+
+#[test]
+fn stretched_voices_loop_and_play_unstretched_without_buffers() {
+    let looped = |window| {
+        let (mut engine, mut ctl) = engine(window);
+        let sample = tone(500.0, 4_800).with_loop_region(samplerust::LoopRegion {
+            start: 0,
+            end: 4_800,
+            mode: samplerust::LoopMode::Forward,
+            crossfade: 0,
+        });
+        let id: SampleId = ctl.add_sample(sample).unwrap();
+        let params = TriggerParams {
+            rate: 2.0,
+            ..stretched(StretchMode::PhaseVocoder, 1.0, -12.0)
+        };
+        ctl.trigger(id, params).unwrap();
+        let mut out = vec![0.0; RATE as usize];
+        engine.render(&mut out);
+        (engine.active_voices(), pitch(&out))
+    };
+    // The rate still speeds up the pitch, and the shift brings it down.
+    let (active, f0) = looped(2_048);
+    assert_eq!(active, 1);
+    assert_near(f0, 500.0, 0.01, "stretched");
+    let (active, f0) = looped(0);
+    assert_eq!(active, 1);
+    assert_near(f0, 1_000.0, 0.01, "unstretched");
+}
@@ -0,0 +1,689 @@
+#![cfg(feature = "vorbis")]
+
+use std::f64::consts::PI;
+
+use samplerust::codec::{self, vorbis, FileFormat, StreamDecoder};
+use samplerust::DecodeError;
+
+/// LSB-first bit packer, as Vorbis packs everything but codewords.
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    bits: usize,
+}
+
+impl BitWriter {
+    fn put(&mut self, value: u32, n: u32) {
+        for i in 0..n {
+            if self.bits.is_multiple_of(8) {
+                self.bytes.push(0);
+            }
+            let bit = ((value >> i) & 1) as u8;
+            *self.bytes.last_mut().unwrap() |= bit << (self.bits % 8);
+            self.bits += 1;
+        }
+    }
+
+    /// Huffman codewords go out most significant bit first.
+    fn code(&mut self, code: u32, len: u32) {
+        for i in (0..len).rev() {
+            self.put((code >> i) & 1, 1);
+        }
+    }
+
+    fn bytes(&mut self, data: &[u8]) {
+        for &b in data {
+            self.put(u32::from(b), 8);
+        }
+    }
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    data.iter().fold(0u32, |mut crc, &b| {
+        crc ^= u32::from(b) << 24;
+        for _ in 0..8 {
+            crc = (crc << 1)
+                ^ if crc & 0x8000_0000 != 0 {
+                    0x04c1_1db7
+                } else {
+                    0
+                };
+        }
+        crc
+    })
+}
+
+#[derive(Default)]
+struct OggWriter {
+    out: Vec<u8>,
+    sequence: u32,
+}
+
+impl OggWriter {
+    fn page(&mut self, packets: &[&[u8]], granule: i64, flags: u8) {
+        let mut lacing = Vec::new();
+        for p in packets {
+            lacing.extend(std::iter::repeat_n(255u8, p.len() / 255));
+            lacing.push((p.len() % 255) as u8);
+        }
+        assert!(lacing.len() <= 255, "packets too large for one page");
+        let mut page = b"OggS".to_vec();
+        page.push(0);
+        page.push(flags);
+        page.extend_from_slice(&granule.to_le_bytes());
+        page.extend_from_slice(&0x5eed_u32.to_le_bytes());
+        page.extend_from_slice(&self.sequence.to_le_bytes());
+        page.extend_from_slice(&[0; 4]);
+        page.push(lacing.len() as u8);
+        page.extend_from_slice(&lacing);
+        for p in packets {
+            page.extend_from_slice(p);
+        }
+        let crc = crc32(&page);
+        page[22..26].copy_from_slice(&crc.to_le_bytes());
+        self.out.extend_from_slice(&page);
+        self.sequence += 1;
+    }
+}
+
+// Codebooks shared by every test stream.
+const CLASS_BOOK: u32 = 0;
+const COARSE_BOOK: u32 = 1;
+const FINE_BOOK: u32 = 2;
+const Y_BOOK: u32 = 3;
+const MASTER_BOOK: u32 = 4;
+
+/// Floor 1 layout: two posts at the ends plus two partitions of three.
+const RANGE_BITS: u32 = 9;
+const XS: [i32; 8] = [0, 512, 256, 128, 384, 64, 192, 320];
+const MULTIPLIER: i32 = 2;
+const RANGE: i32 = 128;
+/// Target floor shape, indices into the dB table once multiplied.
+const TARGET_YS: [i32; 8] = [52, 56, 50, 54, 49, 58, 51, 55];
+
+#[derive(Clone, Copy, Debug)]
+struct Config {
+    channels: usize,
+    short: usize,
+    long: usize,
+    residue: u16,
+    coupled: bool,
+    /// Priming frames before the signal starts.
+    delay: usize,
+}
+
+impl Config {
+    fn mono() -> Self {
+        Config {
+            channels: 1,
+            short: 128,
+            long: 1024,
+            residue: 1,
+            coupled: false,
+            delay: 0,
+        }
+    }
+}
+
+fn float32(v: i32) -> u32 {
+    let sign = if v < 0 { 1 << 31 } else { 0 };
+    sign | (788 << 21) | v.unsigned_abs()
+}
+
+fn codebook(
+    w: &mut BitWriter,
+    dims: u32,
+    entries: u32,
+    len: u32,
+    lattice: Option<(i32, i32, u32)>,
+) {
+    w.put(0x564342, 24);
+    w.put(dims, 16);
+    w.put(entries, 24);
+    if entries <= 8 {
+        // Unordered; the smallest book is also marked sparse.
+        w.put(0, 1);
+        let sparse = entries == 4;
+        w.put(u32::from(sparse), 1);
+        for _ in 0..entries {
+            if sparse {
+                w.put(1, 1);
+            }
+            w.put(len - 1, 5);
+        }
+    } else {
+        w.put(1, 1);
+        w.put(len - 1, 5);
+        w.put(entries, 32 - entries.leading_zeros());
+    }
+    match lattice {
+        None => w.put(0, 4),
+        Some((min, delta, values)) => {
+            w.put(1, 4);
+            w.put(float32(min), 32);
+            w.put(float32(delta), 32);
+            let bits = 32 - (values - 1).leading_zeros();
+            w.put(bits - 1, 4);
+            w.put(0, 1);
+            for m in 0..values {
+                w.put(m, bits);
+            }
+        }
+    }
+}
+
+fn headers(cfg: &Config) -> [Vec<u8>; 3] {
+    let mut id = BitWriter::default();
+    id.put(1, 8);
+    id.bytes(b"vorbis");
+    id.put(0, 32);
+    id.put(cfg.channels as u32, 8);
+    id.put(44100, 32);
+    id.put(0, 32);
+    id.put(128_000, 32);
+    id.put(0, 32);
+    id.put(cfg.short.trailing_zeros(), 4);
+    id.put(cfg.long.trailing_zeros(), 4);
+    id.put(1, 8);
+
+    let mut comment = BitWriter::default();
+    comment.put(3, 8);
+    comment.bytes(b"vorbis");
+    comment.put(4, 32);
+    comment.bytes(b"test");
+    comment.put(0, 32);
+    comment.put(1, 8);
+
+    let mut w = BitWriter::default();
+    w.put(5, 8);
+    w.bytes(b"vorbis");
+    w.put(4, 8);
+    codebook(&mut w, 2, 4, 2, None);
+    codebook(&mut w, 2, 4096, 12, Some((-8192, 256, 64)));
+    codebook(&mut w, 2, 65536, 16, Some((-128, 1, 256)));
+    codebook(&mut w, 1, 256, 8, None);
+    codebook(&mut w, 1, 8, 3, None);
+    w.put(0, 6);
+    w.put(0, 16);
+
+    // Floor 1.
+    w.put(0, 6);
+    w.put(1, 16);
+    w.put(2, 5);
+    w.put(0, 4);
+    w.put(0, 4);
+    w.put(2, 3);
+    w.put(1, 2);
+    w.put(MASTER_BOOK, 8);
+    w.put(0, 8);
+    w.put(Y_BOOK + 1, 8);
+    w.put(MULTIPLIER as u32 - 1, 2);
+    w.put(RANGE_BITS, 4);
+    for &x in &XS[2..] {
+        w.put(x as u32, RANGE_BITS);
+    }
+
+    // Residue.
+    let end = cfg.long / 2 * if cfg.residue == 2 { cfg.channels } else { 1 };
+    w.put(0, 6);
+    w.put(u32::from(cfg.residue), 16);
+    w.put(0, 24);
+    w.put(end as u32, 24);
+    w.put(15, 24);
+    w.put(1, 6);
+    w.put(CLASS_BOOK, 8);
+    w.put(0, 3);
+    w.put(0, 1);
+    w.put(3, 3);
+    w.put(0, 1);
+    w.put(COARSE_BOOK, 8);
+    w.put(FINE_BOOK, 8);
+
+    // Mapping.
+    w.put(0, 6);
+    w.put(0, 16);
+    w.put(0, 1);
+    w.put(u32::from(cfg.coupled), 1);
+    if cfg.coupled {
+        let bits = 32 - (cfg.channels as u32 - 1).leading_zeros();
+        w.put(0, 8);
+        w.put(0, bits);
+        w.put(1, bits);
+    }
+    w.put(0, 2);
+    w.put(0, 8);
+    w.put(0, 8);
+    w.put(0, 8);
+
+    // Modes: short, then long.
+    w.put(1, 6);
+    for long in [0, 1] {
+        w.put(long, 1);
+        w.put(0, 16);
+        w.put(0, 16);
+        w.put(0, 8);
+    }
+    w.put(1, 1);
+    [id.bytes, comment.bytes, w.bytes]
+}
+
+fn render_point(x0: i32, y0: i32, x1: i32, y1: i32, x: i32) -> i32 {
+    let dy = y1 - y0;
+    let off = dy.abs() * (x - x0) / (x1 - x0);
+    if dy < 0 {
+        y0 - off
+    } else {
+        y0 + off
+    }
+}
+
+fn neighbors(i: usize) -> (usize, usize) {
+    let low = (0..i)
+        .filter(|&j| XS[j] < XS[i])
+        .max_by_key(|&j| XS[j])
+        .unwrap();
+    let high = (0..i)
+        .filter(|&j| XS[j] > XS[i])
+        .min_by_key(|&j| XS[j])
+        .unwrap();
+    (low, high)
+}
+
+/// Codes `TARGET_YS` the way floor 1 predicts them: each post as an offset
+/// from the line between its neighbours.
+fn coded_ys() -> [i32; 8] {
+    let mut coded = [0; 8];
+    coded[0] = TARGET_YS[0];
+    coded[1] = TARGET_YS[1];
+    for i in 2..8 {
+        let (lo, hi) = neighbors(i);
+        let predicted = render_point(XS[lo], TARGET_YS[lo], XS[hi], TARGET_YS[hi], XS[i]);
+        let diff = TARGET_YS[i] - predicted;
+        assert!(diff.abs() < predicted.min(RANGE - predicted));
+        coded[i] = if diff >= 0 { 2 * diff } else { -2 * diff - 1 };
+    }
+    coded
+}
+
+/// The floor curve the decoder will compute for `half` bins.
+fn floor_curve(half: usize) -> Vec<f64> {
+    let min = 1.064_986_3e-7f64;
+    let db = |y: i32| min * (-min.ln() * f64::from(y) / 255.0).exp();
+    let coded = coded_ys();
+    // Every post is coded non-zero or is a neighbour of one that is.
+    let mut sorted: Vec<usize> = (0..8).collect();
+    sorted.sort_by_key(|&i| XS[i]);
+    let mut curve = vec![0.0; half];
+    let mut line = |x0: i32, y0: i32, x1: i32, y1: i32| {
+        let dy = y1 - y0;
+        let adx = x1 - x0;
+        let base = dy / adx;
+        let sy = if dy < 0 { base - 1 } else { base + 1 };
+        let ady = dy.abs() - base.abs() * adx;
+        let (mut y, mut err) = (y0, 0);
+        for x in x0..x1 {
+            if x > x0 {
+                err += ady;
+                if err >= adx {
+                    err -= adx;
+                    y += sy;
+                } else {
+                    y += base;
+                }
+            }
+            if (x as usize) < half {
+                curve[x as usize] = db(y);
+            }
+        }
+    };
+    let (mut lx, mut ly) = (0, TARGET_YS[0] * MULTIPLIER);
+    for &i in &sorted[1..] {
+        assert!(i < 2 || coded[i] != 0);
+        let (hx, hy) = (XS[i], TARGET_YS[i] * MULTIPLIER);
+        line(lx, ly, hx, hy);
+        (lx, ly) = (hx, hy);
+    }
+    if (lx as usize) < half {
+        line(lx, ly, half as i32, ly);
+    }
+    curve
+}
+
+fn write_floor(w: &mut BitWriter) {
+    let coded = coded_ys();
+    w.put(1, 1);
+    w.put(coded[0] as u32, 7);
+    w.put(coded[1] as u32, 7);
+    for part in coded[2..].chunks(3) {
+        let mask = part
+            .iter()
+            .enumerate()
+            .fold(0, |m, (j, &y)| m | (u32::from(y != 0) << j));
+        w.code(mask, 3);
+        for &y in part {
+            if y != 0 {
+                w.code(y as u32, 8);
+            }
+        }
+    }
+}
+
+/// The sine window slope for a block size.
+fn slope(n: usize, i: usize) -> f64 {
+    let s = ((i as f64 + 0.5) / (n / 2) as f64 * PI / 2.0).sin();
+    (PI / 2.0 * s * s).sin()
+}
+
+fn window(n: usize, left: usize, right: usize) -> Vec<f64> {
+    let left_start = n / 4 - left / 4;
+    let right_start = 3 * n / 4 - right / 4;
+    (0..n)
+        .map(|i| {
+            if i < left_start {
+                0.0
+            } else if i < left_start + left / 2 {
+                slope(left, i - left_start)
+            } else if i < right_start {
+                1.0
+            } else if i < right_start + right / 2 {
+                slope(right, right / 2 - 1 - (i - right_start))
+            } else {
+                0.0
+            }
+        })
+        .collect()
+}
+
+/// Forward MDCT scaled so the decoder's inverse reconstructs the input.
+fn mdct(block: &[f64]) -> Vec<f64> {
+    let n = block.len();
+    (0..n / 2)
+        .map(|k| {
+            let sum: f64 = block
+                .iter()
+                .enumerate()
+                .map(|(i, &x)| {
+                    x * (2.0 * PI / n as f64 * (i as f64 + 0.5 + n as f64 / 4.0) * (k as f64 + 0.5))
+                        .cos()
+                })
+                .sum();
+            sum * 4.0 / n as f64
+        })
+        .collect()
+}
+
+fn quantise(q: i32) -> (i32, i32) {
+    let coarse = ((f64::from(q) / 256.0).round() as i32).clamp(-32, 31);
+    let fine = (q - coarse * 256).clamp(-128, 127);
+    (coarse, fine)
+}
+
+fn write_residue(w: &mut BitWriter, cfg: &Config, vectors: &[Vec<i32>]) {
+    // Type 2 codes every channel as one interleaved vector.
+    let interleaved;
+    let vectors = if cfg.residue == 2 {
+        let half = vectors[0].len();
+        interleaved = vec![(0..half * vectors.len())
+            .map(|i| vectors[i % vectors.len()][i / vectors.len()])
+            .collect::<Vec<_>>()];
+        &interleaved[..]
+    } else {
+        vectors
+    };
+    let psize = 16;
+    let partitions = vectors[0].len() / psize;
+    let class = |ch: usize, p: usize| -> u32 {
+        let part = vectors[ch].get(p * psize..(p + 1) * psize);
+        u32::from(part.is_some_and(|v| v.iter().any(|&x| x != 0)))
+    };
+    for pass in 0..2 {
+        for group in (0..partitions).step_by(2) {
+            if pass == 0 {
+                for ch in 0..vectors.len() {
+                    w.code(class(ch, group) * 2 + class(ch, group + 1), 2);
+                }
+            }
+            for p in group..(group + 2).min(partitions) {
+                for (ch, v) in vectors.iter().enumerate() {
+                    if class(ch, p) == 0 {
+                        continue;
+                    }
+                    let part = &v[p * psize..(p + 1) * psize];
+                    let pairs: Vec<(i32, i32)> = if cfg.residue == 0 {
+                        (0..psize / 2)
+                            .map(|j| (part[j], part[j + psize / 2]))
+                            .collect()
+                    } else {
+                        part.chunks(2).map(|c| (c[0], c[1])).collect()
+                    };
+                    for (a, b) in pairs {
+                        let (ca, fa) = quantise(a);
+                        let (cb, fb) = quantise(b);
+                        if pass == 0 {
+                            w.code(((ca + 32) + 64 * (cb + 32)) as u32, 12);
+                        } else {
+                            w.code(((fa + 128) + 256 * (fb + 128)) as u32, 16);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Square polar coupling, the inverse of the decoder's.
+fn couple(l: i32, r: i32) -> (i32, i32) {
+    if l > 0 && r < l {
+        (l, l - r)
+    } else if r > 0 && l <= r {
+        (r, l - r)
+    } else if l <= 0 && r > l {
+        (l, r - l)
+    } else {
+        (r, r - l)
+    }
+}
+
+/// Encodes planar `signal`, starting with the block sequence in `pattern`
+/// (true = long) and continuing with long blocks until it is covered.
+fn encode(cfg: Config, signal: &[Vec<f64>], pattern: &[bool]) -> Vec<u8> {
+    let len = signal[0].len() as i64;
+    let size = |long: bool| if long { cfg.long } else { cfg.short };
+    let mut blocks = pattern.to_vec();
+    let covered = |blocks: &[bool]| {
+        let frames: usize = blocks
+            .windows(2)
+            .map(|w| size(w[0]) / 4 + size(w[1]) / 4)
+            .sum();
+        frames as i64 - cfg.delay as i64 >= len
+    };
+    assert!(!covered(pattern), "pattern runs past the signal");
+    while !covered(&blocks) {
+        blocks.push(true);
+    }
+    let sample = |ch: usize, t: i64| {
+        if (0..len).contains(&t) {
+            signal[ch][t as usize]
+        } else {
+            0.0
+        }
+    };
+
+    let mut packets = Vec::new();
+    let mut granules = Vec::new();
+    // The first block's centre sits `delay` frames before the signal.
+    let mut start = -(size(blocks[0]) as i64 / 2) - cfg.delay as i64;
+    let mut produced = 0i64;
+    for (j, &long) in blocks.iter().enumerate() {
+        let n = size(long);
+        if j > 0 {
+            let prev = size(blocks[j - 1]);
+            start += (3 * prev / 4) as i64 - (n / 4) as i64;
+            produced += (prev / 4 + n / 4) as i64;
+        }
+        let prev_long = j > 0 && blocks[j - 1];
+        let next_long = blocks.get(j + 1).copied().unwrap_or(false);
+        let left = if long && !prev_long { cfg.short } else { n };
+        let right = if long && !next_long { cfg.short } else { n };
+        let win = window(n, left, right);
+        let curve = floor_curve(n / 2);
+
+        let mut w = BitWriter::default();
+        w.put(0, 1);
+        w.put(u32::from(long), 1);
+        if long {
+            w.put(u32::from(prev_long), 1);
+            w.put(u32::from(next_long), 1);
+        }
+        let mut vectors: Vec<Vec<i32>> = (0..cfg.channels)
+            .map(|ch| {
+                let block: Vec<f64> = (0..n)
+                    .map(|i| win[i] * sample(ch, start + i as i64))
+                    .collect();
+                mdct(&block)
+                    .iter()
+                    .zip(&curve)
+                    .map(|(x, f)| (x / f).round() as i32)
+                    .collect()
+            })
+            .collect();
+        if cfg.coupled {
+            let [left, right] = &mut vectors[..] else {
+                unreachable!()
+            };
+            for (l, r) in left.iter_mut().zip(right.iter_mut()) {
+                (*l, *r) = couple(*l, *r);
+            }
+        }
+        for _ in 0..cfg.channels {
+            write_floor(&mut w);
+        }
+        write_residue(&mut w, &cfg, &vectors);
+        packets.push(w.bytes);
+        granules.push(produced - cfg.delay as i64);
+    }
+
+    let mut ogg = OggWriter::default();
+    let [id, comment, setup] = headers(&cfg);
+    ogg.page(&[&id], 0, 2);
+    ogg.page(&[&comment, &setup], 0, 0);
+    let count = packets.len();
+    for (i, chunk) in packets.chunks(3).enumerate() {
+        let last = (i + 1) * 3 >= count;
+        let granule = if last { len } else { granules[(i + 1) * 3 - 1] };
+        let refs: Vec<&[u8]> = chunk.iter().map(|p| &p[..]).collect();
+        ogg.page(&refs, granule, if last { 4 } else { 0 });
+    }
+    ogg.out
+}
+
+fn tone(freq: f64, len: usize, amplitude: f64) -> Vec<f64> {
+    (0..len)
+        .map(|i| amplitude * (2.0 * PI * freq * i as f64 / 44100.0).sin())
+        .collect()
+}
+
+fn assert_matches(decoded: &[f32], channels: usize, signal: &[Vec<f64>]) {
+    assert_eq!(decoded.len(), signal[0].len() * channels);
+    for (i, frame) in decoded.chunks(channels).enumerate() {
+        for (ch, &v) in frame.iter().enumerate() {
+            let expected = signal[ch][i];
+            assert!(
+                (f64::from(v) - expected).abs() < 4e-3,
+                "channel {ch} frame {i}: {v} != {expected}"
+            );
+        }
+    }
+}
+
+const BLOCKS: [bool; 11] = [
+    true, true, false, false, false, true, false, true, true, true, false,
+];
+
+#[test]
+fn decodes_mono_with_block_switching() {
+    let cfg = Config {
+        delay: 300,
+        ..Config::mono()
+    };
+    let signal = vec![tone(440.0, 7000, 0.25)];
+    let sample = vorbis::decode(&encode(cfg, &signal, &BLOCKS)).unwrap();
+    assert_eq!((sample.channels(), sample.sample_rate()), (1, 44100));
+    assert_matches(sample.as_slice(), 1, &signal);
+}
+
+#[test]
+fn decodes_every_residue_type() {
+    let signal = vec![tone(440.0, 6000, 0.25), tone(1250.0, 6000, 0.2)];
+    for (residue, coupled) in [(0, false), (1, false), (2, false), (1, true), (2, true)] {
+        let cfg = Config {
+            channels: 2,
+            residue,
+            coupled,
+            ..Config::mono()
+        };
+        let sample = vorbis::decode(&encode(cfg, &signal, &BLOCKS)).unwrap();
+        assert_eq!(sample.channels(), 2);
+        assert_matches(sample.as_slice(), 2, &signal);
+    }
+}
+
+#[test]
+fn trims_priming_and_padding_exactly() {
+    for (delay, len) in [(0, 5000), (1, 4321), (576, 2000)] {
+        let cfg = Config {
+            delay,
+            ..Config::mono()
+        };
+        let signal = vec![tone(300.0, len, 0.2)];
+        let bytes = encode(cfg, &signal, &[true]);
+        let sample = vorbis::decode(&bytes).unwrap();
+        assert_eq!(sample.frames(), len, "delay {delay}");
+        assert_matches(sample.as_slice(), 1, &signal);
+
+        // Reading in small, odd-sized blocks gives the same audio.
+        let mut decoder = vorbis::Decoder::new(&bytes[..]).unwrap();
+        assert_eq!(decoder.frames(), None);
+        let mut streamed = Vec::new();
+        let mut block = [0.0; 77];
+        loop {
+            let n = decoder.read(&mut block).unwrap();
+            if n == 0 {
+                break;
+            }
+            streamed.extend_from_slice(&block[..n]);
+        }
+        assert_eq!(streamed, sample.as_slice());
+    }
+}
+
+#[test]
+fn rejects_damaged_streams() {
+    let signal = vec![tone(440.0, 3000, 0.25)];
+    let bytes = encode(Config::mono(), &signal, &[true]);
+
+    let mut corrupt = bytes.clone();
+    let last = corrupt.len() - 10;
+    corrupt[last] ^= 0x40;
+    assert!(matches!(
+        vorbis::decode(&corrupt),
+        Err(DecodeError::Malformed(_))
+    ));
+    assert!(matches!(
+        vorbis::decode(&bytes[..bytes.len() - 10]),
+        Err(DecodeError::Truncated(_))
+    ));
+    assert!(matches!(
+        vorbis::decode(&bytes[..40]),
+        Err(DecodeError::Truncated(_))
+    ));
+}
+
+#[test]
+fn format_is_detected_from_contents() {
+    let signal = vec![tone(440.0, 1000, 0.25)];
+    let bytes = encode(Config::mono(), &signal, &[true]);
+    assert_eq!(codec::detect(&bytes), Some(FileFormat::Vorbis));
+    let sample = codec::decode(&bytes).unwrap();
+    assert_matches(sample.as_slice(), 1, &signal);
+}
@@ -0,0 +1,246 @@
+use samplerust::codec::wav;
+use samplerust::{DecodeError, LoopMode, LoopRegion};
+
+/// Builds a RIFF/WAVE file from a `fmt ` chunk body, raw `data` bytes and any
+/// extra chunks to place between them.
+fn riff(fmt: &[u8], extra: &[(&[u8; 4], &[u8])], data: &[u8]) -> Vec<u8> {
+    let mut body = b"WAVE".to_vec();
+    let mut chunk = |id: &[u8; 4], bytes: &[u8]| {
+        body.extend_from_slice(id);
+        body.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+        body.extend_from_slice(bytes);
+        if bytes.len() % 2 == 1 {
+            body.push(0);
+        }
+    };
+    chunk(b"fmt ", fmt);
+    for (id, bytes) in extra {
+        chunk(id, bytes);
+    }
+    chunk(b"data", data);
+
+    let mut out = b"RIFF".to_vec();
+    out.extend_from_slice(&(body.len() as u32).to_le_bytes());
+    out.extend_from_slice(&body);
+    out
+}
+
+fn fmt_chunk(tag: u16, channels: u16, rate: u32, bits: u16) -> Vec<u8> {
+    let align = channels * bits / 8;
+    let mut f = Vec::new();
+    f.extend_from_slice(&tag.to_le_bytes());
+    f.extend_from_slice(&channels.to_le_bytes());
+    f.extend_from_slice(&rate.to_le_bytes());
+    f.extend_from_slice(&(rate * u32::from(align)).to_le_bytes());
+    f.extend_from_slice(&align.to_le_bytes());
+    f.extend_from_slice(&bits.to_le_bytes());
+    f
+}
+
+fn extensible_chunk(sub_format: u16, channels: u16, rate: u32, bits: u16, valid: u16) -> Vec<u8> {
+    let mut f = fmt_chunk(0xFFFE, channels, rate, bits);
+    f.extend_from_slice(&22u16.to_le_bytes());
+    f.extend_from_slice(&valid.to_le_bytes());
+    f.extend_from_slice(&0x3u32.to_le_bytes());
+    f.extend_from_slice(&sub_format.to_le_bytes());
+    f.extend_from_slice(&[
+        0x00, 0x00, 0x00, 0x00, 0x10, 0x00, 0x80, 0x00, 0x00, 0xAA, 0x00, 0x38, 0x9B, 0x71,
+    ]);
+    f
+}
+
+const EXPECTED: [f32; 4] = [0.0, 0.5, -0.5, -1.0];
+
+fn assert_close(actual: &[f32], expected: &[f32], tolerance: f32) {
+    assert_eq!(actual.len(), expected.len());
+    for (a, e) in actual.iter().zip(expected) {
+        assert!((a - e).abs() <= tolerance, "{actual:?} != {expected:?}");
+    }
+}
+
+#[test]
+fn decodes_pcm_8_bit() {
+    let data = [128u8, 192, 64, 0];
+    let s = wav::decode(&riff(&fmt_chunk(1, 1, 8_000, 8), &[], &data)).unwrap();
+    assert_eq!(s.sample_rate(), 8_000);
+    assert_close(s.as_slice(), &EXPECTED, 0.0);
+}
+
+#[test]
+fn decodes_pcm_16_bit_stereo() {
+    let data: Vec<u8> = [0i16, 16_384, -16_384, -32_768]
+        .iter()
+        .flat_map(|v| v.to_le_bytes())
+        .collect();
+    let s = wav::decode(&riff(&fmt_chunk(1, 2, 44_100, 16), &[], &data)).unwrap();
+    assert_eq!(s.channels(), 2);
+    assert_eq!(s.frames(), 2);
+    assert_close(s.as_slice(), &EXPECTED, 0.0);
+}
+
+#[test]
+fn decodes_pcm_24_bit() {
+    let data: Vec<u8> = [0i32, 4_194_304, -4_194_304, -8_388_608]
+        .iter()
+        .flat_map(|v| v.to_le_bytes()[..3].to_vec())
+        .collect();
+    let s = wav::decode(&riff(&fmt_chunk(1, 1, 48_000, 24), &[], &data)).unwrap();
+    assert_close(s.as_slice(), &EXPECTED, 0.0);
+}
+
+#[test]
+fn decodes_pcm_32_bit() {
+    let data: Vec<u8> = [0i32, 1 << 30, -(1 << 30), i32::MIN]
+        .iter()
+        .flat_map(|v| v.to_le_bytes())
+        .collect();
+    let s = wav::decode(&riff(&fmt_chunk(1, 1, 96_000, 32), &[], &data)).unwrap();
+    assert_close(s.as_slice(), &EXPECTED, 0.0);
+}
+
+#[test]
+fn decodes_float_32_and_64_bit() {
+    let f32_data: Vec<u8> = EXPECTED.iter().flat_map(|v| v.to_le_bytes()).collect();
+    let s = wav::decode(&riff(&fmt_chunk(3, 1, 48_000, 32), &[], &f32_data)).unwrap();
+    assert_close(s.as_slice(), &EXPECTED, 0.0);
+
+    let f64_data: Vec<u8> = EXPECTED
+        .iter()
+        .flat_map(|&v| f64::from(v).to_le_bytes())
+        .collect();
+    let s = wav::decode(&riff(&fmt_chunk(3, 2, 48_000, 64), &[], &f64_data)).unwrap();
+    assert_eq!(s.channels(), 2);
+    assert_close(s.as_slice(), &EXPECTED, 0.0);
+}
+
+#[test]
+fn decodes_extensible_pcm_and_float() {
+    // 20 valid bits in a 24-bit container.
+    let data: Vec<u8> = [0i32, 4_194_304, -4_194_304, -8_388_608]
+        .iter()
+        .flat_map(|v| v.to_le_bytes()[..3].to_vec())
+        .collect();
+    let fmt = extensible_chunk(1, 2, 48_000, 24, 20);
+    let s = wav::decode(&riff(&fmt, &[], &data)).unwrap();
+    assert_close(s.as_slice(), &EXPECTED, 0.0);
+
+    let data: Vec<u8> = EXPECTED.iter().flat_map(|v| v.to_le_bytes()).collect();
+    let fmt = extensible_chunk(3, 1, 48_000, 32, 32);
+    let s = wav::decode(&riff(&fmt, &[], &data)).unwrap();
+    assert_close(s.as_slice(), &EXPECTED, 0.0);
+}
+
+#[test]
+fn skips_unknown_and_odd_sized_chunks() {
+    let data: Vec<u8> = [0i16, 16_384, -16_384, -32_768]
+        .iter()
+        .flat_map(|v| v.to_le_bytes())
+        .collect();
+    let extra: [(&[u8; 4], &[u8]); 2] = [(b"LIST", b"abc"), (b"junk", &[0; 6])];
+    let s = wav::decode(&riff(&fmt_chunk(1, 1, 44_100, 16), &extra, &data)).unwrap();
+    assert_close(s.as_slice(), &EXPECTED, 0.0);
+}
+
+#[test]
+fn reports_truncation() {
+    let data: Vec<u8> = [0i16, 1, 2, 3]
+        .iter()
+        .flat_map(|v| v.to_le_bytes())
+        .collect();
+    let file = riff(&fmt_chunk(1, 1, 44_100, 16), &[], &data);
+
+    let cut = &file[..file.len() - 3];
+    assert!(matches!(
+        wav::decode(cut),
+        Err(DecodeError::Truncated("data chunk"))
+    ));
+    assert!(matches!(
+        wav::decode(&file[..10]),
+        Err(DecodeError::Truncated("RIFF header"))
+    ));
+    assert!(matches!(
+        wav::decode(&file[..30]),
+        Err(DecodeError::Truncated("fmt chunk"))
+    ));
+
+    let odd = riff(&fmt_chunk(1, 2, 44_100, 16), &[], &data[..6]);
+    assert!(matches!(
+        wav::decode(&odd),
+        Err(DecodeError::Truncated("data chunk: partial frame"))
+    ));
+}
+
+#[test]
+fn reports_malformed_and_unsupported_input() {
+    let data = [0u8; 4];
+    let mut file = riff(&fmt_chunk(1, 1, 44_100, 16), &[], &data);
+    file[8..12].copy_from_slice(b"AVI ");
+    assert!(matches!(wav::decode(&file), Err(DecodeError::Malformed(_))));
+
+    let mut bad_align = fmt_chunk(1, 2, 44_100, 16);
+    bad_align[12] = 3;
+    assert!(matches!(
+        wav::decode(&riff(&bad_align, &[], &data)),
+        Err(DecodeError::Malformed(_))
+    ));
+
+    assert!(matches!(
+        wav::decode(&riff(&fmt_chunk(2, 1, 44_100, 16), &[], &data)),
+        Err(DecodeError::Unsupported(_))
+    ));
+    assert!(matches!(
+        wav::decode(&riff(&fmt_chunk(3, 1, 44_100, 16), &[], &data)),
+        Err(DecodeError::Unsupported(_))
+    ));
+
+    let mut no_data = b"RIFF\x0c\0\0\0WAVE".to_vec();
+    no_data.extend_from_slice(b"fmt \x10\0\0\0");
+    no_data.extend_from_slice(&fmt_chunk(1, 1, 44_100, 16));
+    assert!(matches!(
+        wav::decode(&no_data),
+        Err(DecodeError::MissingChunk("data chunk"))
+    ));
+}
+
+#[test]
+fn loads_from_disk() {
+    let data: Vec<u8> = EXPECTED.iter().flat_map(|v| v.to_le_bytes()).collect();
+    let path = std::env::temp_dir().join(format!("samplerust-wav-{}.wav", std::process::id()));
+    std::fs::write(&path, riff(&fmt_chunk(3, 1, 22_050, 32), &[], &data)).unwrap();
+    let s = wav::load(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(s.sample_rate(), 22_050);
+    assert_close(s.as_slice(), &EXPECTED, 0.0);
+}
+
+#[test]
+fn reads_loop_points_from_smpl_chunk() {
+    let data = [0u8; 20];
+    let mut smpl = vec![0u8; 28];
+    smpl.extend_from_slice(&1u32.to_le_bytes());
+    smpl.extend_from_slice(&0u32.to_le_bytes());
+    for v in [0u32, 1, 2, 7, 0, 0] {
+        smpl.extend_from_slice(&v.to_le_bytes());
+    }
+    let file = riff(&fmt_chunk(1, 1, 44_100, 16), &[(b"smpl", &smpl)], &data);
+    let s = wav::decode(&file).unwrap();
+    assert_eq!(
+        s.loop_region(),
+        Some(LoopRegion {
+            start: 2,
+            end: 8,
+            mode: LoopMode::PingPong,
+            crossfade: 0,
+        })
+    );
+
+    let file = riff(
+        &fmt_chunk(1, 1, 44_100, 16),
+        &[(b"smpl", &smpl[..40])],
+        &data,
+    );
+    assert!(matches!(
+        wav::decode(&file),
+        Err(DecodeError::Truncated("smpl loop"))
+    ));
+}